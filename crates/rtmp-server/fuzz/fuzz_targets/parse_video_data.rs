@@ -0,0 +1,18 @@
+//! Arbitrary RTMP video message bodies through the FLV parser.
+
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use rtmp_server::flv::{self, VideoPacket};
+
+fuzz_target!(|data: &[u8]| {
+    let data = Bytes::copy_from_slice(data);
+    for nalu_length_size in [1, 2, 4] {
+        if let VideoPacket::NaluData { avcc_payload, .. } =
+            flv::parse_video_data(&data, 0, nalu_length_size)
+        {
+            flv::is_keyframe(&avcc_payload, nalu_length_size);
+        }
+    }
+});
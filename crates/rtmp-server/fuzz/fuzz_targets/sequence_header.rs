@@ -0,0 +1,23 @@
+//! Arbitrary AVCDecoderConfigurationRecords, behind a valid sequence header
+//! tag so every input reaches the record parser.
+
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use rtmp_server::flv::{self, VideoPacket};
+
+fuzz_target!(|record: &[u8]| {
+    let mut tag = vec![0x17, 0x00, 0x00, 0x00, 0x00];
+    tag.extend_from_slice(record);
+    let VideoPacket::SequenceHeader(config) = flv::parse_video_data(&Bytes::from(tag), 0, 4) else {
+        return;
+    };
+
+    // Anything accepted survives a round trip through the tag builder
+    let rebuilt = flv::sequence_header_tag(&config);
+    match flv::parse_video_data(&rebuilt, 0, 4) {
+        VideoPacket::SequenceHeader(reparsed) => assert_eq!(reparsed, config),
+        other => panic!("rebuilt sequence header rejected: {other:?}"),
+    }
+});
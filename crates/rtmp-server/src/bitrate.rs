@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Span of input the ingest bitrate is averaged over.
+pub const BITRATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Reads landing within this long of each other share a bucket, so the
+/// window holds at most `BITRATE_WINDOW / BUCKET` entries.
+const BUCKET: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+struct Window {
+    /// Start of each bucket and the bytes counted in it, oldest first.
+    buckets: VecDeque<(Instant, u64)>,
+    /// When the first bytes were counted.
+    started: Option<Instant>,
+}
+
+/// Measures the bytes received on a connection over the last
+/// `BITRATE_WINDOW`.
+///
+/// Cloning yields another handle to the same measurement.
+#[derive(Debug, Clone, Default)]
+pub struct IngestMeter {
+    window: Arc<Mutex<Window>>,
+}
+
+impl IngestMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count `len` bytes received now.
+    pub fn record(&self, len: usize) {
+        self.record_at(len, Instant::now());
+    }
+
+    /// Bitrate over the last `BITRATE_WINDOW`, or since the first bytes if
+    /// that was more recent, in kilobits per second.
+    pub fn kbps(&self) -> u64 {
+        self.kbps_at(Instant::now())
+    }
+
+    fn record_at(&self, len: usize, now: Instant) {
+        let mut window = self.window.lock().unwrap();
+        window.started.get_or_insert(now);
+        match window.buckets.back_mut() {
+            Some((start, bytes)) if now.saturating_duration_since(*start) < BUCKET => {
+                *bytes += len as u64;
+            }
+            _ => window.buckets.push_back((now, len as u64)),
+        }
+        while window
+            .buckets
+            .front()
+            .is_some_and(|(start, _)| now.saturating_duration_since(*start) > BITRATE_WINDOW)
+        {
+            window.buckets.pop_front();
+        }
+    }
+
+    fn kbps_at(&self, now: Instant) -> u64 {
+        let window = self.window.lock().unwrap();
+        let Some(started) = window.started else {
+            return 0;
+        };
+        let bytes: u64 = window
+            .buckets
+            .iter()
+            .filter(|(start, _)| now.saturating_duration_since(*start) <= BITRATE_WINDOW)
+            .map(|(_, bytes)| bytes)
+            .sum();
+        // Less than a second in, a single burst would read as a huge rate
+        let span = now
+            .saturating_duration_since(started)
+            .clamp(Duration::from_secs(1), BITRATE_WINDOW);
+        (bytes as f64 * 8.0 / 1000.0 / span.as_secs_f64()).round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steady_stream() {
+        let meter = IngestMeter::new();
+        let start = Instant::now();
+        assert_eq!(meter.kbps_at(start), 0);
+
+        // 5 Mbps in 4 KiB reads: 625,000 bytes a second
+        let mut received = 0u64;
+        for ms in 0..10_000u64 {
+            let due = 625 * (ms + 1);
+            if due >= received + 4096 {
+                meter.record_at(4096, start + Duration::from_millis(ms));
+                received += 4096;
+            }
+        }
+        let kbps = meter.kbps_at(start + Duration::from_secs(10));
+        assert!((4900..=5100).contains(&kbps), "{kbps}");
+    }
+
+    #[test]
+    fn test_rate_falls_to_zero_when_idle() {
+        let meter = IngestMeter::new();
+        let start = Instant::now();
+        meter.record_at(125_000, start);
+        // Averaged over the first second, not less
+        assert_eq!(meter.kbps_at(start + Duration::from_millis(100)), 1000);
+        assert_eq!(meter.kbps_at(start + Duration::from_secs(2)), 500);
+        assert_eq!(meter.kbps_at(start + Duration::from_secs(6)), 0);
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+use std::io;
+
+/// Why an RTMP connection, or the server accepting them, failed.
+#[derive(Debug)]
+pub enum RtmpError {
+    /// The client's handshake was malformed or used an unsupported version.
+    Handshake(String),
+    /// rml_rtmp couldn't parse the client's data or build a response.
+    Session(String),
+    /// The client was refused: an unknown app or an invalid stream key.
+    Auth(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for RtmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RtmpError::Handshake(msg) => write!(f, "handshake error: {msg}"),
+            RtmpError::Session(msg) => write!(f, "session error: {msg}"),
+            RtmpError::Auth(msg) => write!(f, "rejected: {msg}"),
+            RtmpError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for RtmpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RtmpError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RtmpError {
+    fn from(e: io::Error) -> Self {
+        RtmpError::Io(e)
+    }
+}
+
+/// For callers that only deal in `io::Error`: rejections become
+/// `PermissionDenied` and protocol errors `InvalidData`.
+impl From<RtmpError> for io::Error {
+    fn from(e: RtmpError) -> Self {
+        match e {
+            RtmpError::Io(e) => e,
+            RtmpError::Auth(_) => io::Error::new(io::ErrorKind::PermissionDenied, e),
+            RtmpError::Handshake(_) | RtmpError::Session(_) => {
+                io::Error::new(io::ErrorKind::InvalidData, e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_error_round_trip() {
+        let e = RtmpError::from(io::Error::from(io::ErrorKind::UnexpectedEof));
+        assert!(matches!(&e, RtmpError::Io(inner) if inner.kind() == io::ErrorKind::UnexpectedEof));
+        assert_eq!(io::Error::from(e).kind(), io::ErrorKind::UnexpectedEof);
+
+        let e = io::Error::from(RtmpError::Auth("invalid stream key".into()));
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(e.to_string(), "rejected: invalid stream key");
+
+        let e = io::Error::from(RtmpError::Handshake("unsupported RTMP version 6".into()));
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,23 +1,103 @@
 use bytes::Bytes;
+use rml_rtmp::amf0;
+use std::io::Cursor;
 use tracing::{debug, trace, warn};
+use video_pipeline::nal::{AvccNalIter, NAL_TYPE_IDR};
+
+use crate::metadata::ColorInfo;
 
 /// Parsed H.264 decoder configuration (SPS + PPS).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AvcDecoderConfig {
     pub sps: Vec<Vec<u8>>,
     pub pps: Vec<Vec<u8>>,
     pub nalu_length_size: u8,
 }
 
+impl AvcDecoderConfig {
+    /// Whether there's at least one SPS and one PPS to build a decoder from.
+    /// Some encoders send a sequence header without them and carry the
+    /// parameter sets in-band instead; only its NAL length size applies then.
+    pub fn has_parameter_sets(&self) -> bool {
+        !self.sps.is_empty() && !self.pps.is_empty()
+    }
+}
+
+pub(crate) const FLV_TAG_HEADER_SIZE: usize = 11;
+/// Size of the PreviousTagSize field that follows the header and every tag.
+pub(crate) const PREVIOUS_TAG_SIZE: usize = 4;
+
+/// One FLV tag, from an FLV stream or an RTMP aggregate message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlvTag {
+    pub tag_type: u8,
+    /// Timestamp in milliseconds, including the extended byte.
+    pub timestamp: u32,
+    pub data: Bytes,
+}
+
+/// Tag type, data size and timestamp from an 11-byte FLV tag header.
+pub(crate) fn parse_tag_header(header: &[u8]) -> (u8, usize, u32) {
+    // The top bits of the type byte are reserved/filter flags
+    let tag_type = header[0] & 0x1F;
+    let data_size = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+    let timestamp = u32::from_be_bytes([header[7], header[4], header[5], header[6]]);
+    (tag_type, data_size, timestamp)
+}
+
+/// Split the body of an RTMP aggregate message into its FLV tags.
+///
+/// Each sub-message is a whole FLV tag: header, body and PreviousTagSize.
+/// Their timestamps count from the first one, which stands for `timestamp`,
+/// the aggregate message's own. A truncated tag ends the split; the tags
+/// before it are still returned.
+pub fn split_aggregate(data: &Bytes, timestamp: u32) -> Vec<FlvTag> {
+    let mut tags = Vec::new();
+    let mut first_timestamp = None;
+    let mut pos = 0;
+    while data.len() - pos >= FLV_TAG_HEADER_SIZE {
+        let (tag_type, data_size, tag_timestamp) =
+            parse_tag_header(&data[pos..pos + FLV_TAG_HEADER_SIZE]);
+        let start = pos + FLV_TAG_HEADER_SIZE;
+        if data.len() - start < data_size {
+            warn!(
+                data_size,
+                available = data.len() - start,
+                "truncated tag in aggregate message"
+            );
+            break;
+        }
+        let first = *first_timestamp.get_or_insert(tag_timestamp);
+        tags.push(FlvTag {
+            tag_type,
+            timestamp: timestamp.wrapping_add(tag_timestamp.wrapping_sub(first)),
+            data: data.slice(start..start + data_size),
+        });
+        // Some muxers leave out the last PreviousTagSize
+        pos = (start + data_size + PREVIOUS_TAG_SIZE).min(data.len());
+    }
+    tags
+}
+
 /// Result of parsing an RTMP video data packet.
 #[derive(Debug)]
 pub enum VideoPacket {
     /// AVC sequence header containing SPS/PPS
     SequenceHeader(AvcDecoderConfig),
     /// AVCC-framed video data: [4-byte len][NAL1][4-byte len][NAL2]...
-    NaluData { avcc_payload: Bytes, timestamp: u32 },
+    /// `is_keyframe` is set when the payload contains an IDR slice.
+    /// `composition_time` is the offset of the presentation timestamp from
+    /// `timestamp` in ms, clamped to ±`MAX_COMPOSITION_TIME_MS`.
+    NaluData {
+        avcc_payload: Bytes,
+        timestamp: u32,
+        composition_time: i32,
+        is_keyframe: bool,
+    },
     /// End of sequence
     EndOfSequence,
+    /// Enhanced-RTMP metadata packet carrying `colorInfo`
+    ColorInfo(ColorInfo),
     /// Not H.264 or not AVC — skip
     Unsupported,
 }
@@ -30,23 +110,32 @@ pub enum VideoPacket {
 ///     byte 1: AVC packet type (0=seq header, 1=NALU, 2=end of seq)
 ///     bytes 2-4: composition time offset (signed, 24-bit)
 ///     bytes 5+: AVC data
-pub fn parse_video_data(data: &Bytes, timestamp: u32) -> VideoPacket {
-    if data.len() < 2 {
+///
+/// Enhanced RTMP sets the top bit of byte 0 (IsExHeader), in which case the
+/// low 4 bits are the packet type and bytes 1-4 a codec FourCC. Only the
+/// metadata packet type (4) is understood there; its body is AMF0.
+///
+/// `nalu_length_size` comes from the most recent sequence header and is used
+/// to validate the length prefixes of NALU packets.
+pub fn parse_video_data(data: &Bytes, timestamp: u32, nalu_length_size: u8) -> VideoPacket {
+    let [header, avc_packet_type, ..] = data[..] else {
         return VideoPacket::Unsupported;
+    };
+
+    if header & 0x80 != 0 {
+        return parse_enhanced(data);
     }
 
-    let codec_id = data[0] & 0x0F;
+    let codec_id = header & 0x0F;
     if codec_id != 7 {
         // Not H.264/AVC
         trace!(codec_id, "non-AVC video codec, skipping");
         return VideoPacket::Unsupported;
     }
 
-    let avc_packet_type = data[1];
-
     match avc_packet_type {
         0 => parse_sequence_header(data),
-        1 => parse_nalu_data(data, timestamp),
+        1 => parse_nalu_data(data, timestamp, nalu_length_size),
         2 => VideoPacket::EndOfSequence,
         _ => {
             warn!(avc_packet_type, "unknown AVC packet type");
@@ -55,6 +144,77 @@ pub fn parse_video_data(data: &Bytes, timestamp: u32) -> VideoPacket {
     }
 }
 
+/// Parse an enhanced-RTMP video packet. Only metadata is handled: H.264
+/// publishers still use the legacy header for their video.
+fn parse_enhanced(data: &Bytes) -> VideoPacket {
+    let packet_type = data.first().map_or(0, |header| header & 0x0F);
+    if packet_type != ENHANCED_PACKET_TYPE_METADATA {
+        trace!(packet_type, "enhanced video packet, skipping");
+        return VideoPacket::Unsupported;
+    }
+    // Skip: header byte + FourCC (4 bytes)
+    let Some(body) = data.get(5..) else {
+        return VideoPacket::Unsupported;
+    };
+    let values = match amf0::deserialize(&mut Cursor::new(body)) {
+        Ok(values) => values,
+        Err(e) => {
+            debug!(?e, "failed to decode video metadata packet");
+            return VideoPacket::Unsupported;
+        }
+    };
+    match ColorInfo::from_metadata_packet(&values) {
+        Some(info) => VideoPacket::ColorInfo(info),
+        None => {
+            trace!(?values, "unhandled video metadata");
+            VideoPacket::Unsupported
+        }
+    }
+}
+
+/// Result of parsing an RTMP audio data packet.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AudioPacket<'a> {
+    /// AAC sequence header carrying the AudioSpecificConfig
+    AacSequenceHeader(&'a [u8]),
+    /// One raw AAC frame
+    AacRaw(&'a [u8]),
+    /// Not AAC — skip
+    Unsupported,
+}
+
+/// Parse an RTMP audio data payload (FLV audio tag body).
+///
+/// FLV audio tag format:
+///   byte 0: sound format (4 bits) | rate, size and type (4 bits)
+///   For AAC (sound format 10):
+///     byte 1: AAC packet type (0=seq header, 1=raw)
+///     bytes 2+: AudioSpecificConfig or AAC frame
+pub fn parse_audio_data(data: &[u8]) -> AudioPacket<'_> {
+    let [header, aac_packet_type, ref body @ ..] = data[..] else {
+        return AudioPacket::Unsupported;
+    };
+    if header >> 4 != SOUND_FORMAT_AAC {
+        return AudioPacket::Unsupported;
+    }
+    match aac_packet_type {
+        0 => AudioPacket::AacSequenceHeader(body),
+        1 => AudioPacket::AacRaw(body),
+        _ => AudioPacket::Unsupported,
+    }
+}
+
+/// FLV `SoundFormat` of AAC audio.
+const SOUND_FORMAT_AAC: u8 = 10;
+
+/// Largest composition time offset, in ms, taken from a NALU packet. B-frame
+/// reordering needs a few frames at most; anything beyond this is an encoder
+/// bug and would push presentation timestamps far into the future.
+pub const MAX_COMPOSITION_TIME_MS: i32 = 10_000;
+
+/// Enhanced-RTMP `PacketTypeMetadata`.
+const ENHANCED_PACKET_TYPE_METADATA: u8 = 4;
+
 /// Parse AVCDecoderConfigurationRecord from sequence header.
 ///
 /// Format (ISO 14496-15):
@@ -73,91 +233,261 @@ pub fn parse_video_data(data: &Bytes, timestamp: u32) -> VideoPacket {
 ///     pps_length bytes: PPS data
 fn parse_sequence_header(data: &Bytes) -> VideoPacket {
     // Skip: video tag header (1 byte) + avc packet type (1 byte) + composition time (3 bytes)
-    let offset = 5;
-    if data.len() < offset + 6 {
-        warn!("sequence header too short");
-        return VideoPacket::Unsupported;
+    let config = data.get(5..).unwrap_or_default();
+    match parse_avc_config(config) {
+        Ok(config) => {
+            debug!(
+                num_sps = config.sps.len(),
+                num_pps = config.pps.len(),
+                "parsed AVC decoder config"
+            );
+            if !config.has_parameter_sets() {
+                debug!("sequence header without SPS/PPS, expecting them in-band");
+            }
+            VideoPacket::SequenceHeader(config)
+        }
+        Err(e @ AvcConfigError::ReservedBits { .. }) => {
+            debug!(%e, "not a real AVCDecoderConfigurationRecord, rejecting");
+            VideoPacket::Unsupported
+        }
+        Err(e) => {
+            warn!(%e, "invalid AVC sequence header");
+            VideoPacket::Unsupported
+        }
     }
+}
 
-    let config = &data[offset..];
+/// Parse the AVCDecoderConfigurationRecord body of a sequence header.
+pub(crate) fn parse_avc_config(config: &[u8]) -> Result<AvcDecoderConfig, AvcConfigError> {
+    let mut reader = ConfigReader { data: config, pos: 0 };
 
-    let version = config[0];
+    let version = reader.u8("version")?;
     if version != 1 {
-        warn!(version, "unexpected AVCDecoderConfigurationRecord version");
-        return VideoPacket::Unsupported;
+        return Err(AvcConfigError::UnsupportedVersion(version));
+    }
+    let profile = reader.u8("profile")?;
+    reader.u8("profile compatibility")?;
+    let level = reader.u8("level")?;
+    let length_size_byte = reader.u8("lengthSizeMinusOne")?;
+    let num_sps_byte = reader.u8("numOfSequenceParameterSets")?;
+
+    // Reserved bits must be all ones; anything else isn't a real config record
+    if length_size_byte & 0xFC != 0xFC || num_sps_byte & 0xE0 != 0xE0 {
+        return Err(AvcConfigError::ReservedBits {
+            length_size_byte,
+            num_sps_byte,
+        });
     }
 
-    let profile = config[1];
-    let level = config[3];
-    let nalu_length_size = (config[4] & 0x03) + 1;
+    let nalu_length_size = (length_size_byte & 0x03) + 1;
     debug!(profile, level, nalu_length_size, "AVC decoder config");
 
-    let num_sps = (config[5] & 0x1F) as usize;
-    let mut pos = 6;
-    let mut sps = Vec::with_capacity(num_sps);
+    let num_sps = (num_sps_byte & 0x1F) as usize;
+    let sps = (0..num_sps)
+        .map(|_| reader.parameter_set("SPS"))
+        .collect::<Result<Vec<_>, _>>()?;
+    let num_pps = reader.u8("numOfPictureParameterSets")? as usize;
+    let pps = (0..num_pps)
+        .map(|_| reader.parameter_set("PPS"))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    for _ in 0..num_sps {
-        if pos + 2 > config.len() {
-            warn!("truncated SPS length");
-            return VideoPacket::Unsupported;
-        }
-        let sps_len = u16::from_be_bytes([config[pos], config[pos + 1]]) as usize;
-        pos += 2;
-        if pos + sps_len > config.len() {
-            warn!("truncated SPS data");
-            return VideoPacket::Unsupported;
-        }
-        sps.push(config[pos..pos + sps_len].to_vec());
-        pos += sps_len;
-    }
+    Ok(AvcDecoderConfig {
+        sps,
+        pps,
+        nalu_length_size,
+    })
+}
 
-    if pos >= config.len() {
-        warn!("truncated PPS count");
-        return VideoPacket::Unsupported;
+/// Why a sequence header's AVCDecoderConfigurationRecord was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AvcConfigError {
+    /// The record ends before `field`, which starts at `offset`.
+    Truncated { field: &'static str, offset: usize },
+    /// `configurationVersion` isn't 1.
+    UnsupportedVersion(u8),
+    /// The reserved bits around the NAL length size and SPS count aren't
+    /// all ones.
+    ReservedBits {
+        length_size_byte: u8,
+        num_sps_byte: u8,
+    },
+}
+
+impl std::fmt::Display for AvcConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AvcConfigError::Truncated { field, offset } => {
+                write!(f, "record truncated at {field} (offset {offset})")
+            }
+            AvcConfigError::UnsupportedVersion(version) => {
+                write!(f, "unsupported AVCDecoderConfigurationRecord version {version}")
+            }
+            AvcConfigError::ReservedBits {
+                length_size_byte,
+                num_sps_byte,
+            } => write!(
+                f,
+                "reserved bits not set (length size byte {length_size_byte:#04x}, \
+                 SPS count byte {num_sps_byte:#04x})"
+            ),
+        }
     }
+}
 
-    let num_pps = config[pos] as usize;
-    pos += 1;
-    let mut pps = Vec::with_capacity(num_pps);
+/// Bounds-checked reads through an AVCDecoderConfigurationRecord.
+struct ConfigReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
 
-    for _ in 0..num_pps {
-        if pos + 2 > config.len() {
-            warn!("truncated PPS length");
-            return VideoPacket::Unsupported;
-        }
-        let pps_len = u16::from_be_bytes([config[pos], config[pos + 1]]) as usize;
-        pos += 2;
-        if pos + pps_len > config.len() {
-            warn!("truncated PPS data");
-            return VideoPacket::Unsupported;
-        }
-        pps.push(config[pos..pos + pps_len].to_vec());
-        pos += pps_len;
+impl<'a> ConfigReader<'a> {
+    fn bytes(&mut self, len: usize, field: &'static str) -> Result<&'a [u8], AvcConfigError> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or(AvcConfigError::Truncated {
+                field,
+                offset: self.pos,
+            })?;
+        self.pos += len;
+        Ok(bytes)
     }
 
-    debug!(num_sps = sps.len(), num_pps = pps.len(), "parsed AVC decoder config");
+    fn u8(&mut self, field: &'static str) -> Result<u8, AvcConfigError> {
+        self.bytes(1, field).map(|bytes| bytes[0])
+    }
 
-    VideoPacket::SequenceHeader(AvcDecoderConfig {
-        sps,
-        pps,
-        nalu_length_size,
-    })
+    /// A parameter set with its 16-bit length prefix.
+    fn parameter_set(&mut self, field: &'static str) -> Result<Vec<u8>, AvcConfigError> {
+        let len = self.bytes(2, field)?;
+        let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+        self.bytes(len, field).map(<[u8]>::to_vec)
+    }
 }
 
 /// Extract AVCC-formatted payload from a video data packet.
 ///
 /// Returns the raw AVCC payload (length-prefixed NAL units) for direct
-/// submission to VideoToolbox as a single CMSampleBuffer.
-fn parse_nalu_data(data: &Bytes, timestamp: u32) -> VideoPacket {
+/// submission to VideoToolbox as a single CMSampleBuffer. Payloads whose
+/// length prefixes don't add up to the payload size are skipped, as are
+/// payloads that only make sense with a different prefix width than the
+/// sequence header declared.
+fn parse_nalu_data(data: &Bytes, timestamp: u32, nalu_length_size: u8) -> VideoPacket {
     // Skip: video tag header (1 byte) + avc packet type (1 byte) + composition time (3 bytes)
     let offset = 5;
     if data.len() <= offset {
         return VideoPacket::Unsupported;
     }
 
+    let composition_time = parse_composition_time([data[2], data[3], data[4]], timestamp);
     let avcc_payload = data.slice(offset..);
-    trace!(len = avcc_payload.len(), timestamp, "AVCC payload");
-    VideoPacket::NaluData { avcc_payload, timestamp }
+    let mut nal_count = 0;
+    let mut is_keyframe = false;
+    let mut nals = AvccNalIter::new(&avcc_payload, nalu_length_size);
+    for (nal_type, _) in nals.by_ref() {
+        is_keyframe |= nal_type == NAL_TYPE_IDR;
+        nal_count += 1;
+    }
+    if let Some(e) = nals.error() {
+        if let Some(actual) = other_nalu_length_size(&avcc_payload, nalu_length_size) {
+            warn!(
+                len = avcc_payload.len(),
+                timestamp,
+                declared = nalu_length_size,
+                actual,
+                "NAL length prefixes don't match the sequence header, skipping"
+            );
+            return VideoPacket::Unsupported;
+        }
+        warn!(len = avcc_payload.len(), timestamp, %e, "malformed AVCC payload, skipping");
+        return VideoPacket::Unsupported;
+    }
+    trace!(
+        len = avcc_payload.len(),
+        nal_count,
+        timestamp,
+        composition_time,
+        is_keyframe,
+        "AVCC payload"
+    );
+    VideoPacket::NaluData {
+        avcc_payload,
+        timestamp,
+        composition_time,
+        is_keyframe,
+    }
+}
+
+/// The NAL length size other than `declared` under which `avcc_payload`
+/// splits into non-empty NAL units with nothing left over, if there is one.
+///
+/// A payload written with a different prefix width than the sequence header
+/// declares either fails to parse or, for a wider prefix read as a narrower
+/// one, turns up zero-length NALs where the high bytes of a length were.
+fn other_nalu_length_size(avcc_payload: &[u8], declared: u8) -> Option<u8> {
+    [4, 2, 1].into_iter().filter(|&size| size != declared).find(|&size| {
+        let mut nals = AvccNalIter::new(avcc_payload, size);
+        nals.by_ref().count() > 0 && !nals.is_truncated()
+    })
+}
+
+/// Sign-extend the 24-bit composition time offset and clamp it to
+/// ±`MAX_COMPOSITION_TIME_MS`.
+fn parse_composition_time(bytes: [u8; 3], timestamp: u32) -> i32 {
+    let raw = i32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) << 8 >> 8;
+    let clamped = raw.clamp(-MAX_COMPOSITION_TIME_MS, MAX_COMPOSITION_TIME_MS);
+    if clamped != raw {
+        warn!(
+            timestamp,
+            composition_time = raw,
+            clamped,
+            "composition time out of range, clamping"
+        );
+    }
+    clamped
+}
+
+/// Build an FLV video tag body carrying `config` as an AVC sequence header
+/// (the inverse of the sequence header parsing above).
+pub fn sequence_header_tag(config: &AvcDecoderConfig) -> Bytes {
+    let first_sps = config.sps.first().map(Vec::as_slice).unwrap_or_default();
+    let profile_bytes = |i: usize| first_sps.get(i).copied().unwrap_or(0);
+
+    let mut tag = vec![0x17, 0x00, 0x00, 0x00, 0x00];
+    tag.extend_from_slice(&[
+        0x01,
+        profile_bytes(1),
+        profile_bytes(2),
+        profile_bytes(3),
+        0xFC | (config.nalu_length_size.saturating_sub(1) & 0x03),
+        0xE0 | (config.sps.len() as u8 & 0x1F),
+    ]);
+    for sps in &config.sps {
+        tag.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        tag.extend_from_slice(sps);
+    }
+    tag.push(config.pps.len() as u8);
+    for pps in &config.pps {
+        tag.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+        tag.extend_from_slice(pps);
+    }
+    Bytes::from(tag)
+}
+
+/// Build an FLV video tag body carrying an AVCC payload, with a composition
+/// time offset of 0.
+pub fn nalu_tag(avcc_payload: &[u8], keyframe: bool) -> Bytes {
+    let frame_type = if keyframe { 0x17 } else { 0x27 };
+    let mut tag = Vec::with_capacity(5 + avcc_payload.len());
+    tag.extend_from_slice(&[frame_type, 0x01, 0x00, 0x00, 0x00]);
+    tag.extend_from_slice(avcc_payload);
+    Bytes::from(tag)
+}
+
+/// Whether an AVCC payload contains an IDR slice.
+pub fn is_keyframe(avcc_payload: &[u8], nalu_length_size: u8) -> bool {
+    AvccNalIter::new(avcc_payload, nalu_length_size).any(|(nal_type, _)| nal_type == NAL_TYPE_IDR)
 }
 
 #[cfg(test)]
@@ -167,14 +497,14 @@ mod tests {
     #[test]
     fn test_parse_non_avc() {
         let data = Bytes::from_static(&[0x22, 0x00]); // codec_id = 2 (Sorenson H.263)
-        assert!(matches!(parse_video_data(&data, 0), VideoPacket::Unsupported));
+        assert!(matches!(parse_video_data(&data, 0, 4), VideoPacket::Unsupported));
     }
 
     #[test]
     fn test_parse_end_of_sequence() {
         // frame_type=1 (keyframe) | codec_id=7 (AVC), packet_type=2 (end of seq)
         let data = Bytes::from_static(&[0x17, 0x02]);
-        assert!(matches!(parse_video_data(&data, 0), VideoPacket::EndOfSequence));
+        assert!(matches!(parse_video_data(&data, 0, 4), VideoPacket::EndOfSequence));
     }
 
     #[test]
@@ -201,7 +531,7 @@ mod tests {
         buf.extend_from_slice(&[0x68, 0xEB, 0xE3]); // PPS data
 
         let data = Bytes::from(buf);
-        match parse_video_data(&data, 0) {
+        match parse_video_data(&data, 0, 4) {
             VideoPacket::SequenceHeader(config) => {
                 assert_eq!(config.sps.len(), 1);
                 assert_eq!(config.pps.len(), 1);
@@ -228,9 +558,16 @@ mod tests {
         buf.extend_from_slice(&[0x06, 0x05, 0x00]);
 
         let data = Bytes::from(buf);
-        match parse_video_data(&data, 100) {
-            VideoPacket::NaluData { avcc_payload, timestamp } => {
+        match parse_video_data(&data, 100, 4) {
+            VideoPacket::NaluData {
+                avcc_payload,
+                timestamp,
+                composition_time,
+                is_keyframe,
+            } => {
                 assert_eq!(timestamp, 100);
+                assert_eq!(composition_time, 0);
+                assert!(is_keyframe);
                 // AVCC payload should contain both NAL units with length prefixes
                 let expected: &[u8] = &[
                     0x00, 0x00, 0x00, 0x05, 0x65, 0x88, 0x80, 0x40, 0x00,
@@ -241,4 +578,276 @@ mod tests {
             other => panic!("expected NaluData, got {:?}", other),
         }
     }
+
+    #[test]
+    fn test_parse_composition_time() {
+        let tag = |cto: [u8; 3]| {
+            let mut tag = vec![0x27, 0x01, cto[0], cto[1], cto[2]];
+            tag.extend_from_slice(&[0x00, 0x00, 0x00, 0x02, 0x41, 0x9A]);
+            Bytes::from(tag)
+        };
+        let composition_time = |cto| match parse_video_data(&tag(cto), 0, 4) {
+            VideoPacket::NaluData {
+                composition_time, ..
+            } => composition_time,
+            other => panic!("expected NaluData, got {other:?}"),
+        };
+        assert_eq!(composition_time([0x00, 0x00, 0x43]), 67);
+        assert_eq!(composition_time([0xFF, 0xFF, 0xDF]), -33);
+        // 0x7FFFFF ms is over two hours ahead
+        assert_eq!(composition_time([0x7F, 0xFF, 0xFF]), MAX_COMPOSITION_TIME_MS);
+        assert_eq!(composition_time([0x80, 0x00, 0x00]), -MAX_COMPOSITION_TIME_MS);
+    }
+
+    #[test]
+    fn test_parse_audio_data() {
+        // AAC-LC, 44.1 kHz, mono
+        assert_eq!(
+            parse_audio_data(&[0xAF, 0x00, 0x12, 0x08]),
+            AudioPacket::AacSequenceHeader(&[0x12, 0x08])
+        );
+        assert_eq!(
+            parse_audio_data(&[0xAF, 0x01, 0x21, 0x10]),
+            AudioPacket::AacRaw(&[0x21, 0x10])
+        );
+        // MP3
+        assert_eq!(parse_audio_data(&[0x2F, 0xFF, 0xFB]), AudioPacket::Unsupported);
+        assert_eq!(parse_audio_data(&[0xAF]), AudioPacket::Unsupported);
+    }
+
+    #[test]
+    fn test_split_aggregate() {
+        let tag = |tag_type: u8, timestamp: u32, data: &[u8]| {
+            let mut out = vec![tag_type];
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+            out.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+            out.push((timestamp >> 24) as u8);
+            out.extend_from_slice(&[0, 0, 0]);
+            out.extend_from_slice(data);
+            out.extend_from_slice(&((FLV_TAG_HEADER_SIZE + data.len()) as u32).to_be_bytes());
+            out
+        };
+        let mut body = tag(9, 0x0100_0000, &[0x17, 0x01, 0, 0, 0]);
+        body.extend(tag(8, 0x0100_000A, &[0xAF, 0x01]));
+        body.extend(tag(9, 0x0100_0021, &[0x27, 0x01, 0, 0, 0]));
+        let tags = split_aggregate(&Bytes::from(body.clone()), 5000);
+        let summary: Vec<(u8, u32, usize)> = tags
+            .iter()
+            .map(|t| (t.tag_type, t.timestamp, t.data.len()))
+            .collect();
+        assert_eq!(summary, [(9, 5000, 5), (8, 5010, 2), (9, 5033, 5)]);
+
+        // Missing final PreviousTagSize, then a truncated tag
+        let tags = split_aggregate(&Bytes::from(body[..body.len() - 4].to_vec()), 0);
+        assert_eq!(tags.len(), 3);
+        let tags = split_aggregate(&Bytes::from(body[..body.len() - 6].to_vec()), 0);
+        assert_eq!(tags.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_truncated_nalu_data() {
+        let mut buf = vec![
+            0x27, // inter frame + AVC
+            0x01, // NALU
+            0x00, 0x00, 0x00, // composition time
+        ];
+        // NAL unit declares 8 bytes but only 5 follow
+        buf.extend_from_slice(&[0x00, 0x00, 0x00, 0x08]);
+        buf.extend_from_slice(&[0x65, 0x88, 0x80, 0x40, 0x00]);
+
+        let data = Bytes::from(buf);
+        assert!(matches!(parse_video_data(&data, 0, 4), VideoPacket::Unsupported));
+    }
+
+    #[test]
+    fn test_mismatched_nalu_length_size() {
+        // Two NALs with 4-byte length prefixes
+        let four = [
+            0x00, 0x00, 0x00, 0x05, 0x65, 0x88, 0x80, 0x40, 0x00, 0x00, 0x00, 0x00, 0x03, 0x06,
+            0x05, 0x00,
+        ];
+        assert_eq!(other_nalu_length_size(&four, 4), None);
+        // Read with 2-byte prefixes these split cleanly, but into empty NALs
+        assert_eq!(other_nalu_length_size(&four, 2), Some(4));
+
+        // The same NALs with 2-byte prefixes don't parse as 4-byte ones
+        let two = [0x00, 0x05, 0x65, 0x88, 0x80, 0x40, 0x00, 0x00, 0x03, 0x06, 0x05, 0x00];
+        assert_eq!(other_nalu_length_size(&two, 4), Some(2));
+
+        for (payload, declared) in [(&four[..], 2), (&two[..], 4)] {
+            let mut tag = vec![0x17, 0x01, 0x00, 0x00, 0x00];
+            tag.extend_from_slice(payload);
+            let packet = parse_video_data(&Bytes::from(tag), 0, declared);
+            assert!(matches!(packet, VideoPacket::Unsupported));
+        }
+    }
+
+    #[test]
+    fn test_sequence_header_without_parameter_sets() {
+        // numOfSequenceParameterSets = 0, numOfPictureParameterSets = 0
+        let data = Bytes::from_static(&[
+            0x17, 0x00, 0x00, 0x00, 0x00, 0x01, 0x64, 0x00, 0x1F, 0xFD, 0xE0, 0x00,
+        ]);
+        let VideoPacket::SequenceHeader(config) = parse_video_data(&data, 0, 4) else {
+            panic!("expected a sequence header");
+        };
+        assert!(!config.has_parameter_sets());
+        assert_eq!(config.nalu_length_size, 2);
+    }
+
+    #[test]
+    fn test_sequence_header_truncated_at_every_field() {
+        let record = [
+            0x01, 0x64, 0x00, 0x1F, 0xFF, 0xE1, // version .. SPS count
+            0x00, 0x02, 0x67, 0x64, // SPS
+            0x01, 0x00, 0x02, 0x68, 0xEB, // PPS count, PPS
+        ];
+        assert!(parse_avc_config(&record).is_ok());
+
+        let fields = [
+            "version",
+            "profile",
+            "profile compatibility",
+            "level",
+            "lengthSizeMinusOne",
+            "numOfSequenceParameterSets",
+            "SPS",
+            "SPS",
+            "SPS",
+            "SPS",
+            "numOfPictureParameterSets",
+            "PPS",
+            "PPS",
+            "PPS",
+            "PPS",
+        ];
+        for (len, expected) in fields.into_iter().enumerate() {
+            match parse_avc_config(&record[..len]) {
+                Err(AvcConfigError::Truncated { field, .. }) => assert_eq!(field, expected),
+                other => panic!("{len} bytes: expected truncation, got {other:?}"),
+            }
+
+            // The same cut through a whole video tag is skipped, not a panic
+            let mut tag = vec![0x17, 0x00, 0x00, 0x00, 0x00];
+            tag.extend_from_slice(&record[..len]);
+            let packet = parse_video_data(&Bytes::from(tag), 0, 4);
+            assert!(matches!(packet, VideoPacket::Unsupported));
+        }
+
+        // A parameter set length running far past the end
+        let record = [0x01, 0x64, 0x00, 0x1F, 0xFF, 0xE1, 0xFF, 0xFF, 0x67];
+        assert_eq!(
+            parse_avc_config(&record),
+            Err(AvcConfigError::Truncated {
+                field: "SPS",
+                offset: 8
+            })
+        );
+    }
+
+    #[test]
+    fn test_truncated_tags_are_skipped() {
+        let tags: [&[u8]; 4] = [
+            &[],
+            &[0x17],
+            &[0x17, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x65],
+            &[0x84, b'a', b'v', b'c'], // enhanced metadata, cut in the FourCC
+        ];
+        for tag in tags {
+            let packet = parse_video_data(&Bytes::copy_from_slice(tag), 0, 4);
+            assert!(matches!(packet, VideoPacket::Unsupported), "{tag:02x?}");
+        }
+    }
+
+    #[test]
+    fn test_sequence_header_reserved_bits() {
+        let header = |length_size_byte: u8, num_sps_byte: u8| {
+            let mut buf = vec![0x17, 0x00, 0x00, 0x00, 0x00, 0x01, 0x64, 0x00, 0x1F];
+            buf.extend_from_slice(&[length_size_byte, num_sps_byte]);
+            buf.extend_from_slice(&[0x00, 0x02, 0x67, 0x64]); // SPS
+            buf.extend_from_slice(&[0x01, 0x00, 0x02, 0x68, 0xEB]); // PPS
+            Bytes::from(buf)
+        };
+        assert!(matches!(
+            parse_video_data(&header(0xFF, 0xE1), 0, 4),
+            VideoPacket::SequenceHeader(_)
+        ));
+        // lengthSizeMinusOne without its reserved ones
+        assert!(matches!(
+            parse_video_data(&header(0x03, 0xE1), 0, 4),
+            VideoPacket::Unsupported
+        ));
+        // numOfSequenceParameterSets without its reserved ones
+        assert!(matches!(
+            parse_video_data(&header(0xFF, 0x01), 0, 4),
+            VideoPacket::Unsupported
+        ));
+    }
+
+    #[test]
+    fn test_tags_round_trip() {
+        let config = AvcDecoderConfig {
+            sps: vec![vec![0x67, 0x64, 0x00, 0x1F, 0xAC]],
+            pps: vec![vec![0x68, 0xEB, 0xE3]],
+            nalu_length_size: 4,
+        };
+        let tag = sequence_header_tag(&config);
+        match parse_video_data(&tag, 0, 4) {
+            VideoPacket::SequenceHeader(parsed) => assert_eq!(parsed, config),
+            other => panic!("expected sequence header, got {other:?}"),
+        }
+
+        let avcc = [0x00, 0x00, 0x00, 0x02, 0x65, 0x88];
+        assert!(is_keyframe(&avcc, 4));
+        let tag = nalu_tag(&avcc, true);
+        assert_eq!(tag[0], 0x17);
+        match parse_video_data(&tag, 40, 4) {
+            VideoPacket::NaluData {
+                avcc_payload,
+                timestamp,
+                is_keyframe,
+                ..
+            } => {
+                assert_eq!(&avcc_payload[..], &avcc);
+                assert_eq!(timestamp, 40);
+                assert!(is_keyframe);
+            }
+            other => panic!("expected NALU data, got {other:?}"),
+        }
+        assert!(!is_keyframe(&[0x00, 0x00, 0x00, 0x02, 0x41, 0x9A], 4));
+    }
+
+    #[test]
+    fn test_parse_enhanced_color_info() {
+        use rml_rtmp::amf0::Amf0Value;
+        use std::collections::HashMap;
+
+        let color_config = HashMap::from([
+            ("bitDepth".to_string(), Amf0Value::Number(10.0)),
+            ("transferCharacteristics".to_string(), Amf0Value::Number(16.0)),
+        ]);
+        let body = amf0::serialize(&vec![
+            Amf0Value::Utf8String("colorInfo".into()),
+            Amf0Value::Object(HashMap::from([(
+                "colorConfig".to_string(),
+                Amf0Value::Object(color_config),
+            )])),
+        ])
+        .unwrap();
+        // IsExHeader | frame type 5 (command) | packet type 4 (metadata), 'avc1'
+        let mut tag = vec![0xD4, b'a', b'v', b'c', b'1'];
+        tag.extend_from_slice(&body);
+        match parse_video_data(&Bytes::from(tag), 0, 4) {
+            VideoPacket::ColorInfo(info) => {
+                assert_eq!(info.bit_depth, Some(10));
+                assert_eq!(info.transfer_characteristics, Some(16));
+                assert!(info.mastering_display.is_none());
+            }
+            other => panic!("expected color info, got {other:?}"),
+        }
+
+        // Other enhanced packet types (here coded frames) are skipped
+        let frames = Bytes::from_static(&[0x91, b'h', b'v', b'c', b'1', 0x00]);
+        assert!(matches!(parse_video_data(&frames, 0, 4), VideoPacket::Unsupported));
+    }
 }
@@ -1,52 +1,238 @@
 use bytes::Bytes;
 use rml_rtmp::handshake::{Handshake, HandshakeProcessResult, PeerType};
-use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+use crate::error::RtmpError;
+
+/// RTMP version byte sent in C0/S0.
+const RTMP_VERSION: u8 = 3;
+
+/// Size of each of C1, C2, S1 and S2.
+const PACKET_SIZE: usize = 1536;
+
+/// Which handshake the server answered the client with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeMode {
+    /// Flash Player 9+ handshake, where C1/S1 carry an HMAC-SHA256 digest.
+    /// Handled by rml_rtmp.
+    Digest,
+    /// Original RTMP handshake: S2 echoes C1 and nothing is validated. Used
+    /// for clients that send a zero version in C1, or whose C1 carries no
+    /// valid digest.
+    Simple,
+}
+
+enum Phase {
+    /// Collecting C0 and C1 to see which handshake the client speaks.
+    AwaitingC1(Vec<u8>),
+    Digest(Handshake),
+    /// S0-S2 sent, reading the client's C2.
+    Simple {
+        c2_received: usize,
+    },
+    Completed,
+}
 
 /// Drives the RTMP handshake to completion, returning any leftover bytes
 /// that belong to the RTMP session (post-handshake data).
+///
+/// Clients advertising a player version in C1 are handed to rml_rtmp, which
+/// does the digest handshake. The rest, and any whose digest rml_rtmp can't
+/// find, get the simple handshake instead, as other servers do.
 pub struct HandshakeState {
-    inner: Handshake,
-    completed: bool,
+    phase: Phase,
+    mode: Option<HandshakeMode>,
 }
 
 impl HandshakeState {
     pub fn new() -> Self {
         Self {
-            inner: Handshake::new(PeerType::Server),
-            completed: false,
+            phase: Phase::AwaitingC1(Vec::with_capacity(1 + PACKET_SIZE)),
+            mode: None,
         }
     }
 
     /// Process incoming bytes. Returns (response_bytes_to_send, maybe_remaining).
     /// If `maybe_remaining` is Some, the handshake is complete and the bytes
     /// are leftover RTMP data to feed into ServerSession.
-    pub fn process(&mut self, data: &[u8]) -> io::Result<(Bytes, Option<Bytes>)> {
-        if self.completed {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "handshake already completed",
-            ));
+    pub fn process(&mut self, data: &[u8]) -> Result<(Bytes, Option<Bytes>), RtmpError> {
+        match &mut self.phase {
+            Phase::AwaitingC1(received) => {
+                received.extend_from_slice(data);
+                if received.len() < 1 + PACKET_SIZE {
+                    return Ok((Bytes::new(), None));
+                }
+                let received = std::mem::take(received);
+                self.negotiate(&received)
+            }
+            Phase::Digest(handshake) => {
+                let result = handshake.process_bytes(data).map_err(handshake_error)?;
+                Ok(self.digest_progress(result))
+            }
+            Phase::Simple { c2_received } => {
+                let needed = PACKET_SIZE - *c2_received;
+                if data.len() < needed {
+                    *c2_received += data.len();
+                    return Ok((Bytes::new(), None));
+                }
+                self.phase = Phase::Completed;
+                Ok((Bytes::new(), Some(Bytes::copy_from_slice(&data[needed..]))))
+            }
+            Phase::Completed => Err(RtmpError::Handshake(
+                "handshake already completed".to_string(),
+            )),
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        matches!(self.phase, Phase::Completed)
+    }
+
+    /// The handshake the client was answered with, once C1 has arrived.
+    pub fn mode(&self) -> Option<HandshakeMode> {
+        self.mode
+    }
+
+    /// Pick a handshake for `received` (C0, C1 and anything after them).
+    fn negotiate(&mut self, received: &[u8]) -> Result<(Bytes, Option<Bytes>), RtmpError> {
+        if received[0] != RTMP_VERSION {
+            return Err(RtmpError::Handshake(format!(
+                "unsupported RTMP version {}",
+                received[0]
+            )));
+        }
+
+        // C1 is time (4 bytes), then zero, or the client version for the digest handshake
+        let c1 = &received[1..1 + PACKET_SIZE];
+        if c1[4..8] != [0; 4] {
+            let mut handshake = Handshake::new(PeerType::Server);
+            match handshake.process_bytes(received) {
+                Ok(result) => {
+                    self.mode = Some(HandshakeMode::Digest);
+                    self.phase = Phase::Digest(handshake);
+                    return Ok(self.digest_progress(result));
+                }
+                Err(e) => debug!(error = ?e, "no usable digest in C1, using the simple handshake"),
+            }
         }
 
-        match self.inner.process_bytes(data) {
-            Ok(HandshakeProcessResult::InProgress { response_bytes }) => {
-                Ok((Bytes::from(response_bytes), None))
+        self.mode = Some(HandshakeMode::Simple);
+        self.phase = Phase::Simple { c2_received: 0 };
+        let response = simple_response(c1);
+        let (_, remaining) = self.process(&received[1 + PACKET_SIZE..])?;
+        Ok((response, remaining))
+    }
+
+    fn digest_progress(&mut self, result: HandshakeProcessResult) -> (Bytes, Option<Bytes>) {
+        match result {
+            HandshakeProcessResult::InProgress { response_bytes } => {
+                (Bytes::from(response_bytes), None)
             }
-            Ok(HandshakeProcessResult::Completed {
+            HandshakeProcessResult::Completed {
                 response_bytes,
                 remaining_bytes,
-            }) => {
-                self.completed = true;
-                Ok((Bytes::from(response_bytes), Some(Bytes::from(remaining_bytes))))
+            } => {
+                self.phase = Phase::Completed;
+                (
+                    Bytes::from(response_bytes),
+                    Some(Bytes::from(remaining_bytes)),
+                )
             }
-            Err(e) => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("handshake error: {e:?}"),
-            )),
         }
     }
+}
 
-    pub fn is_completed(&self) -> bool {
-        self.completed
+/// S0, S1 and S2 for the simple handshake: S1 is our timestamp, four zero
+/// bytes and filler; S2 echoes C1.
+fn simple_response(c1: &[u8]) -> Bytes {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut response = Vec::with_capacity(1 + 2 * PACKET_SIZE);
+    response.push(RTMP_VERSION);
+    response.extend_from_slice(&(now.as_millis() as u32).to_be_bytes());
+    response.extend_from_slice(&[0; 4]);
+
+    // The filler only has to differ between connections; xorshift will do
+    let mut state = now.as_nanos() as u64 | 1;
+    while response.len() < 1 + PACKET_SIZE {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        response.push(state as u8);
+    }
+    response.extend_from_slice(c1);
+    Bytes::from(response)
+}
+
+fn handshake_error(e: impl std::fmt::Debug) -> RtmpError {
+    RtmpError::Handshake(format!("{e:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a handshake between `client` and a fresh `HandshakeState`,
+    /// returning the server's leftover session bytes.
+    fn run_handshake(client: &mut Handshake, trailing: &[u8]) -> (HandshakeState, Bytes) {
+        let mut server = HandshakeState::new();
+        let mut to_server = client.generate_outbound_p0_and_p1().unwrap();
+        loop {
+            let (response, remaining) = server.process(&to_server).unwrap();
+            if let Some(remaining) = remaining {
+                return (server, remaining);
+            }
+            match client.process_bytes(&response).unwrap() {
+                HandshakeProcessResult::InProgress { response_bytes } => {
+                    to_server = response_bytes;
+                }
+                HandshakeProcessResult::Completed { response_bytes, .. } => {
+                    to_server = [&response_bytes[..], trailing].concat();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_handshake_digest_client() {
+        let mut client = Handshake::new(PeerType::Client);
+        let (server, remaining) = run_handshake(&mut client, &[0x02, 0x00]);
+        assert!(server.is_completed());
+        assert_eq!(server.mode(), Some(HandshakeMode::Digest));
+        assert_eq!(&remaining[..], [0x02, 0x00]);
+    }
+
+    #[test]
+    fn test_handshake_simple_client() {
+        // Zero version field and no digest, C0+C1 split across reads
+        let mut c0_c1 = vec![RTMP_VERSION, 0, 0, 0, 1, 0, 0, 0, 0];
+        c0_c1.resize(1 + PACKET_SIZE, 0x5A);
+        let mut server = HandshakeState::new();
+        let (response, _) = server.process(&c0_c1[..100]).unwrap();
+        assert!(response.is_empty());
+        let (response, remaining) = server.process(&c0_c1[100..]).unwrap();
+        assert!(remaining.is_none());
+        assert_eq!(server.mode(), Some(HandshakeMode::Simple));
+        assert_eq!(response.len(), 1 + 2 * PACKET_SIZE);
+        assert_eq!(response[0], RTMP_VERSION);
+        assert_eq!(&response[1 + PACKET_SIZE..], &c0_c1[1..]);
+
+        // C2 echoes S1, followed by the first session bytes
+        let mut c2 = response[1..1 + PACKET_SIZE].to_vec();
+        c2.extend_from_slice(&[0x03, 0x00]);
+        let (_, remaining) = server.process(&c2[..1000]).unwrap();
+        assert!(remaining.is_none());
+        let (_, remaining) = server.process(&c2[1000..]).unwrap();
+        assert_eq!(&remaining.unwrap()[..], [0x03, 0x00]);
+        assert!(server.is_completed());
+    }
+
+    #[test]
+    fn test_handshake_rejects_bad_version() {
+        let mut server = HandshakeState::new();
+        let c0_c1 = vec![6u8; 1 + PACKET_SIZE];
+        assert!(server.process(&c0_c1).is_err());
     }
 }
@@ -0,0 +1,652 @@
+//! HTTP-FLV push ingest, for interop testing against tools that POST an FLV
+//! file or stream (e.g. `curl --data-binary @test.flv http://host:port/ingest`).
+//!
+//! The request body is read as an FLV file: header, then tags. Video tags go
+//! through the same parser as RTMP video messages, and `onMetaData` script
+//! tags become `on_stream_info` calls. Bodies may be sent with a
+//! Content-Length or chunked.
+
+use std::collections::VecDeque;
+use std::io::{self, Cursor, Read};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use rml_rtmp::amf0;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::flv::{self, VideoPacket};
+use crate::metadata::{ColorInfo, StreamInfo};
+use crate::recording::{TAG_TYPE_SCRIPT, TAG_TYPE_VIDEO};
+use crate::sei;
+pub use crate::flv::FlvTag;
+use crate::flv::{parse_tag_header, FLV_TAG_HEADER_SIZE, PREVIOUS_TAG_SIZE};
+use crate::session::{ConnectionContext, SinkFactory, VideoSink};
+
+const FLV_SIGNATURE: &[u8; 3] = b"FLV";
+
+/// Request headers larger than this are rejected.
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+
+/// Splits an FLV byte stream into tags, however the bytes are chunked.
+#[derive(Default)]
+pub struct FlvTagReader {
+    pending: Vec<u8>,
+    header_read: bool,
+}
+
+impl FlvTagReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed body bytes; returns the tags completed by them.
+    pub fn push(&mut self, data: &[u8]) -> io::Result<Vec<FlvTag>> {
+        self.pending.extend_from_slice(data);
+        let mut tags = Vec::new();
+        let mut pos = 0;
+
+        if !self.header_read {
+            // Signature, version, flags, header size (u32), PreviousTagSize0
+            if self.pending.len() < 9 {
+                return Ok(tags);
+            }
+            if &self.pending[..3] != FLV_SIGNATURE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not an FLV stream",
+                ));
+            }
+            let header_size = u32::from_be_bytes([
+                self.pending[5],
+                self.pending[6],
+                self.pending[7],
+                self.pending[8],
+            ]) as usize;
+            if header_size < 9 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("FLV header size {header_size} too small"),
+                ));
+            }
+            if self.pending.len() < header_size + PREVIOUS_TAG_SIZE {
+                return Ok(tags);
+            }
+            debug!(
+                version = self.pending[3],
+                flags = self.pending[4],
+                "FLV header"
+            );
+            self.header_read = true;
+            pos = header_size + PREVIOUS_TAG_SIZE;
+        }
+
+        while self.pending.len() - pos >= FLV_TAG_HEADER_SIZE {
+            let (tag_type, data_size, timestamp) =
+                parse_tag_header(&self.pending[pos..pos + FLV_TAG_HEADER_SIZE]);
+            let end = pos + FLV_TAG_HEADER_SIZE + data_size + PREVIOUS_TAG_SIZE;
+            if self.pending.len() < end {
+                break;
+            }
+            let data_start = pos + FLV_TAG_HEADER_SIZE;
+            tags.push(FlvTag {
+                tag_type,
+                timestamp,
+                data: Bytes::copy_from_slice(&self.pending[data_start..data_start + data_size]),
+            });
+            pos = end;
+        }
+
+        self.pending.drain(..pos);
+        Ok(tags)
+    }
+}
+
+/// Iterator over the tags of an FLV file or other blocking reader.
+///
+/// A file that ends partway through a tag yields an `UnexpectedEof` error
+/// after its last complete tag.
+pub struct FlvFileTags<R> {
+    input: R,
+    reader: FlvTagReader,
+    ready: VecDeque<FlvTag>,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> FlvFileTags<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            reader: FlvTagReader::new(),
+            ready: VecDeque::new(),
+            buf: vec![0u8; 64 * 1024],
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for FlvFileTags<R> {
+    type Item = io::Result<FlvTag>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(tag) = self.ready.pop_front() {
+                return Some(Ok(tag));
+            }
+            if self.done {
+                return None;
+            }
+            let n = match self.input.read(&mut self.buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            if n == 0 {
+                self.done = true;
+                let leftover = self.reader.pending.len();
+                if leftover > 0 || !self.reader.header_read {
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("FLV file ends with {leftover} bytes of incomplete header or tag"),
+                    )));
+                }
+                return None;
+            }
+            match self.reader.push(&self.buf[..n]) {
+                Ok(tags) => self.ready.extend(tags),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Delivers the tags of one pushed stream to its sink.
+struct FlvIngest {
+    sink: Box<dyn VideoSink>,
+    nalu_length_size: u8,
+    color_info: Option<ColorInfo>,
+}
+
+impl FlvIngest {
+    fn deliver(&mut self, tag: FlvTag) {
+        match tag.tag_type {
+            TAG_TYPE_VIDEO => self.deliver_video(&tag.data, tag.timestamp),
+            TAG_TYPE_SCRIPT => {
+                let values = match amf0::deserialize(&mut Cursor::new(&tag.data[..])) {
+                    Ok(values) => values,
+                    Err(e) => {
+                        debug!(?e, "failed to decode FLV script tag");
+                        return;
+                    }
+                };
+                if let Some(info) = StreamInfo::from_data_message(&values) {
+                    debug!(?info, "stream metadata from script tag");
+                    self.sink.on_stream_info(info);
+                }
+            }
+            _ => trace!(tag_type = tag.tag_type, "non-video FLV tag (ignored)"),
+        }
+    }
+
+    fn deliver_video(&mut self, data: &Bytes, timestamp: u32) {
+        match flv::parse_video_data(data, timestamp, self.nalu_length_size) {
+            VideoPacket::SequenceHeader(config) => {
+                info!("received AVC sequence header");
+                self.nalu_length_size = config.nalu_length_size;
+                self.sink.on_decoder_config(config);
+            }
+            VideoPacket::NaluData {
+                avcc_payload,
+                timestamp,
+                ..
+            } => {
+                sei::forward_sei(self.sink.as_mut(), &avcc_payload, self.nalu_length_size);
+                self.sink.on_video_data(avcc_payload, timestamp);
+            }
+            VideoPacket::EndOfSequence => {
+                info!("received end of sequence");
+                self.sink.on_end_of_sequence();
+            }
+            VideoPacket::ColorInfo(info) => {
+                if self.color_info.as_ref() != Some(&info) {
+                    self.color_info = Some(info.clone());
+                    self.sink.on_color_info(info);
+                }
+            }
+            VideoPacket::Unsupported => {}
+        }
+    }
+}
+
+/// How the request body is delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyLength {
+    Fixed(u64),
+    Chunked,
+    /// Neither header: the body runs until the client closes its side.
+    UntilClose,
+}
+
+/// The parts of the request head the ingest uses.
+#[derive(Debug, PartialEq, Eq)]
+struct RequestHead {
+    method: String,
+    path: String,
+    body: BodyLength,
+    expect_continue: bool,
+}
+
+fn parse_request_head(head: &str) -> io::Result<RequestHead> {
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(path), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("malformed request line '{request_line}'"),
+        ));
+    };
+
+    let mut body = BodyLength::UntilClose;
+    let mut expect_continue = false;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => {
+                let length = value.parse().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length")
+                })?;
+                body = BodyLength::Fixed(length);
+            }
+            "transfer-encoding" if value.eq_ignore_ascii_case("chunked") => {
+                body = BodyLength::Chunked;
+            }
+            "expect" if value.eq_ignore_ascii_case("100-continue") => expect_continue = true,
+            _ => {}
+        }
+    }
+    Ok(RequestHead {
+        method: method.to_string(),
+        path: path.to_string(),
+        body,
+        expect_continue,
+    })
+}
+
+/// Start the HTTP-FLV ingest server on the given address.
+/// Each POST is treated as one publish; `sink_factory` is called with an
+/// empty app name and the last segment of the request path as the stream
+/// key ("/live/cam1" publishes "cam1").
+pub async fn run<F>(addr: SocketAddr, sink_factory: F) -> io::Result<()>
+where
+    F: Fn(&ConnectionContext) -> io::Result<Box<dyn VideoSink>> + Send + Sync + 'static,
+{
+    let sink_factory: SinkFactory = Arc::new(sink_factory);
+    let listener = TcpListener::bind(addr).await?;
+    info!(%addr, "HTTP-FLV ingest listening");
+
+    loop {
+        let (stream, peer_addr) = listener.accept().await?;
+        info!(%peer_addr, "new HTTP-FLV connection");
+
+        let factory = Arc::clone(&sink_factory);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, peer_addr, factory).await {
+                error!(%peer_addr, %e, "HTTP-FLV connection error");
+            }
+            info!(%peer_addr, "HTTP-FLV connection closed");
+        });
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    sink_factory: SinkFactory,
+) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let head = match read_request_head(&mut stream).await? {
+        Some(head) => head,
+        None => return Ok(()),
+    };
+    let head = match parse_request_head(&head) {
+        Ok(head) => head,
+        Err(e) => {
+            respond(&mut stream, "400 Bad Request").await?;
+            return Err(e);
+        }
+    };
+    if head.method != "POST" {
+        warn!(%peer_addr, method = head.method, "HTTP-FLV ingest only accepts POST");
+        return respond(&mut stream, "405 Method Not Allowed").await;
+    }
+
+    let stream_key = head
+        .path
+        .split('?')
+        .next()
+        .and_then(|path| path.trim_end_matches('/').rsplit('/').next())
+        .filter(|key| !key.is_empty())
+        .map_or_else(|| peer_addr.to_string(), str::to_string);
+    let context = ConnectionContext {
+        peer_addr,
+        app_name: String::new(),
+        stream_key: stream_key.clone(),
+    };
+    let sink = match sink_factory(&context) {
+        Ok(sink) => sink,
+        Err(e) => {
+            warn!(%peer_addr, stream_key, %e, "publish rejected: no sink available");
+            respond(&mut stream, "503 Service Unavailable").await?;
+            return Err(e);
+        }
+    };
+    info!(%peer_addr, stream_key, body = ?head.body, "HTTP-FLV publish");
+    if head.expect_continue {
+        stream
+            .get_mut()
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+            .await?;
+    }
+
+    let mut ingest = FlvIngest {
+        sink,
+        nalu_length_size: 4,
+        color_info: None,
+    };
+    let result = read_body(&mut stream, head.body, &mut ingest).await;
+    ingest.sink.on_stream_end();
+    match result {
+        Ok(()) => respond(&mut stream, "200 OK").await,
+        Err(e) => {
+            respond(&mut stream, "400 Bad Request").await.ok();
+            Err(e)
+        }
+    }
+}
+
+/// Read the request line and headers, without the blank line ending them.
+/// Returns `None` if the client closed the connection before sending any.
+async fn read_request_head<R: AsyncRead + Unpin>(
+    stream: &mut BufReader<R>,
+) -> io::Result<Option<String>> {
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            if head.is_empty() {
+                return Ok(None);
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if line == "\r\n" || line == "\n" {
+            return Ok(Some(head.trim_end().to_string()));
+        }
+        head.push_str(line.trim_end_matches(['\r', '\n']));
+        head.push_str("\r\n");
+        if head.len() > MAX_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head too large",
+            ));
+        }
+    }
+}
+
+async fn read_body<R: AsyncRead + Unpin>(
+    stream: &mut BufReader<R>,
+    body: BodyLength,
+    ingest: &mut FlvIngest,
+) -> io::Result<()> {
+    let mut reader = FlvTagReader::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut feed = |data: &[u8]| -> io::Result<()> {
+        for tag in reader.push(data)? {
+            ingest.deliver(tag);
+        }
+        Ok(())
+    };
+
+    match body {
+        BodyLength::Fixed(length) => {
+            let mut remaining = length;
+            while remaining > 0 {
+                let max = buf.len().min(remaining as usize);
+                let n = stream.read(&mut buf[..max]).await?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                remaining -= n as u64;
+                feed(&buf[..n])?;
+            }
+        }
+        BodyLength::UntilClose => loop {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            feed(&buf[..n])?;
+        },
+        BodyLength::Chunked => loop {
+            let mut size_line = String::new();
+            if stream.read_line(&mut size_line).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            // Chunk extensions follow a ';'
+            let size = size_line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))?;
+            if size == 0 {
+                // Skip trailers up to the final blank line
+                loop {
+                    let mut line = String::new();
+                    if stream.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                        break;
+                    }
+                }
+                break;
+            }
+            let mut remaining = size;
+            while remaining > 0 {
+                let max = buf.len().min(remaining);
+                let n = stream.read(&mut buf[..max]).await?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                remaining -= n;
+                feed(&buf[..n])?;
+            }
+            let mut crlf = [0u8; 2];
+            stream.read_exact(&mut crlf).await?;
+        },
+    }
+    Ok(())
+}
+
+async fn respond(stream: &mut BufReader<TcpStream>, status: &str) -> io::Result<()> {
+    let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    let stream = stream.get_mut();
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flv_header() -> Vec<u8> {
+        let mut out = b"FLV\x01\x01".to_vec();
+        out.extend_from_slice(&9u32.to_be_bytes());
+        out.extend_from_slice(&0u32.to_be_bytes()); // PreviousTagSize0
+        out
+    }
+
+    fn flv_tag(tag_type: u8, timestamp: u32, data: &[u8]) -> Vec<u8> {
+        let ts = timestamp.to_be_bytes();
+        let size = (data.len() as u32).to_be_bytes();
+        let mut out = vec![
+            tag_type, size[1], size[2], size[3], ts[1], ts[2], ts[3], ts[0],
+        ];
+        out.extend_from_slice(&[0, 0, 0]); // stream id
+        out.extend_from_slice(data);
+        out.extend_from_slice(&((FLV_TAG_HEADER_SIZE + data.len()) as u32).to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn test_tag_reader_splits_tags_across_pushes() {
+        let mut stream = flv_header();
+        stream.extend(flv_tag(TAG_TYPE_VIDEO, 0, &[0x17, 0x00, 0x00, 0x00, 0x00]));
+        stream.extend(flv_tag(8, 10, &[0xAF, 0x01, 0x21]));
+        // Timestamp past 24 bits uses the extended byte
+        stream.extend(flv_tag(TAG_TYPE_VIDEO, 0x0100_0021, &[0x27, 0x01]));
+
+        let mut reader = FlvTagReader::new();
+        let mut tags = Vec::new();
+        for chunk in stream.chunks(7) {
+            tags.extend(reader.push(chunk).unwrap());
+        }
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[0].tag_type, TAG_TYPE_VIDEO);
+        assert_eq!(&tags[0].data[..], &[0x17, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!((tags[1].tag_type, tags[1].timestamp), (8, 10));
+        assert_eq!(tags[2].timestamp, 0x0100_0021);
+    }
+
+    #[test]
+    fn test_tag_reader_rejects_non_flv() {
+        let mut reader = FlvTagReader::new();
+        assert!(reader.push(b"GIF89a\x01\x00\x01\x00").is_err());
+    }
+
+    #[test]
+    fn test_flv_file_tags() {
+        let mut file = b"FLV\x01\x05\x00\x00\x00\x09\x00\x00\x00\x00".to_vec();
+        let tags = [(9u8, 0u32, &[0x17, 0x00][..]), (8, 0x0100_0021, &[0xAF])];
+        for (tag_type, timestamp, data) in tags {
+            let ts = timestamp.to_be_bytes();
+            file.extend_from_slice(&[tag_type, 0, 0, data.len() as u8, ts[1], ts[2], ts[3], ts[0]]);
+            file.extend_from_slice(&[0, 0, 0]);
+            file.extend_from_slice(data);
+            file.extend_from_slice(&(11 + data.len() as u32).to_be_bytes());
+        }
+        let tags: Vec<_> = FlvFileTags::new(&file[..])
+            .map(|tag| tag.map(|tag| (tag.tag_type, tag.timestamp)))
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(tags, [(9, 0), (8, 0x0100_0021)]);
+
+        // Cut off inside the second tag
+        let mut tags = FlvFileTags::new(&file[..file.len() - 2]);
+        assert!(tags.next().unwrap().is_ok());
+        let err = tags.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(tags.next().is_none());
+    }
+
+    #[test]
+    fn test_parse_request_head() {
+        let head = parse_request_head(
+            "POST /live/cam1 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 1234\r\n\
+             Expect: 100-continue",
+        )
+        .unwrap();
+        assert_eq!(head.method, "POST");
+        assert_eq!(head.path, "/live/cam1");
+        assert_eq!(head.body, BodyLength::Fixed(1234));
+        assert!(head.expect_continue);
+
+        let head =
+            parse_request_head("POST /ingest HTTP/1.1\r\nTransfer-Encoding: chunked").unwrap();
+        assert_eq!(head.body, BodyLength::Chunked);
+        assert!(parse_request_head("garbage").is_err());
+    }
+
+    struct RecordingSink(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl VideoSink for RecordingSink {
+        fn on_decoder_config(&mut self, config: crate::AvcDecoderConfig) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("config {}", config.sps.len()));
+        }
+
+        fn on_video_data(&mut self, data: Bytes, timestamp: u32) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("video {} {timestamp}", data.len()));
+        }
+
+        fn on_stream_end(&mut self) {
+            self.0.lock().unwrap().push("end".into());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chunked_post_reaches_sink() {
+        let config = crate::AvcDecoderConfig {
+            sps: vec![vec![0x67, 0x64, 0x00, 0x1F]],
+            pps: vec![vec![0x68, 0xEB]],
+            nalu_length_size: 4,
+        };
+        let mut body = flv_header();
+        body.extend(flv_tag(
+            TAG_TYPE_VIDEO,
+            0,
+            &flv::sequence_header_tag(&config),
+        ));
+        let nalu = flv::nalu_tag(&[0x00, 0x00, 0x00, 0x02, 0x65, 0x88], true);
+        body.extend(flv_tag(TAG_TYPE_VIDEO, 40, &nalu));
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink_events = Arc::clone(&events);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        tokio::spawn(run(
+            addr,
+            move |context: &ConnectionContext| -> io::Result<Box<dyn VideoSink>> {
+                assert_eq!(context.stream_key, "cam1");
+                assert_eq!(context.app_name, "");
+                assert!(context.peer_addr.ip().is_loopback());
+                Ok(Box::new(RecordingSink(Arc::clone(&sink_events))))
+            },
+        ));
+
+        let mut stream = loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        let mut request =
+            b"POST /live/cam1 HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        for chunk in body.chunks(20) {
+            request.extend_from_slice(format!("{:x};ext=1\r\n", chunk.len()).as_bytes());
+            request.extend_from_slice(chunk);
+            request.extend_from_slice(b"\r\n");
+        }
+        request.extend_from_slice(b"0\r\n\r\n");
+        stream.write_all(&request).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert_eq!(*events.lock().unwrap(), ["config 1", "video 6 40", "end"]);
+    }
+}
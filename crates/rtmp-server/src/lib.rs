@@ -1,7 +1,37 @@
+pub mod bitrate;
+pub mod error;
 pub mod flv;
 pub mod handshake;
+pub mod http_flv;
+pub mod message_counts;
+pub mod metadata;
+pub mod mpegts;
+pub mod publishers;
+pub mod rate_limit;
+pub mod raw_avcc;
+pub mod recording;
+pub mod relay;
+pub mod sei;
 pub mod server;
 pub mod session;
+pub mod shutdown;
+pub mod tls;
 
-pub use flv::{AvcDecoderConfig, VideoPacket};
-pub use session::VideoSink;
+pub use bitrate::IngestMeter;
+pub use error::RtmpError;
+pub use flv::{AudioPacket, AvcConfigError, AvcDecoderConfig, VideoPacket};
+pub use handshake::HandshakeMode;
+pub use message_counts::{MessageCounter, MessageCounts};
+pub use metadata::{ColorInfo, MasteringDisplay, StreamInfo, VideoCodec};
+pub use publishers::{ConnectionInfo, PublisherRegistry};
+pub use rate_limit::ConnectionRateLimiter;
+pub use raw_avcc::{RawAvccReader, RawAvccRecorder};
+pub use recording::{FlvRecorder, RecorderOptions};
+pub use relay::RelaySink;
+pub use sei::SeiMessage;
+pub use server::{Listener, Server};
+pub use session::{
+    sanitize_stream_key, ConnectionContext, SinkFactory, VideoSink, KEYFRAME_REQUEST_COMMAND,
+};
+pub use shutdown::Shutdown;
+pub use tls::TlsConfig;
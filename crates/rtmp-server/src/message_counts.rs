@@ -0,0 +1,116 @@
+//! Per-connection counts of RTMP messages by type, for protocol debugging.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+pub const TYPE_ID_SET_CHUNK_SIZE: u8 = 1;
+pub const TYPE_ID_ACKNOWLEDGEMENT: u8 = 3;
+pub const TYPE_ID_USER_CONTROL: u8 = 4;
+pub const TYPE_ID_AUDIO: u8 = 8;
+pub const TYPE_ID_VIDEO: u8 = 9;
+/// AMF0 data messages (`@setDataFrame`, `onMetaData`).
+pub const TYPE_ID_AMF0_DATA: u8 = 18;
+pub const TYPE_ID_AMF0_COMMAND: u8 = 20;
+/// Aggregate messages, a run of FLV tags in one message.
+pub const TYPE_ID_AGGREGATE: u8 = 22;
+
+/// Name of an RTMP message type, or `None` for ones we don't name.
+pub fn message_type_name(type_id: u8) -> Option<&'static str> {
+    Some(match type_id {
+        TYPE_ID_SET_CHUNK_SIZE => "set chunk size",
+        2 => "abort",
+        TYPE_ID_ACKNOWLEDGEMENT => "acknowledgement",
+        TYPE_ID_USER_CONTROL => "user control",
+        5 => "window ack size",
+        6 => "set peer bandwidth",
+        TYPE_ID_AUDIO => "audio",
+        TYPE_ID_VIDEO => "video",
+        15 => "amf3 data",
+        17 => "amf3 command",
+        TYPE_ID_AMF0_DATA => "data",
+        TYPE_ID_AMF0_COMMAND => "command",
+        TYPE_ID_AGGREGATE => "aggregate",
+        _ => return None,
+    })
+}
+
+/// Number of messages received of each RTMP message type.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageCounts(BTreeMap<u8, u64>);
+
+impl MessageCounts {
+    /// Messages received with `type_id`.
+    pub fn get(&self, type_id: u8) -> u64 {
+        self.0.get(&type_id).copied().unwrap_or(0)
+    }
+
+    /// Message types seen, in ascending order, with their counts.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, u64)> + '_ {
+        self.0.iter().map(|(&type_id, &count)| (type_id, count))
+    }
+
+    pub fn total(&self) -> u64 {
+        self.0.values().sum()
+    }
+}
+
+/// Lists the counts as e.g. `audio=212 video=120 command=6 type 30=1`.
+impl fmt::Display for MessageCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (type_id, count)) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            match message_type_name(type_id) {
+                Some(name) => write!(f, "{name}={count}")?,
+                None => write!(f, "type {type_id}={count}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Counts the RTMP messages received on a connection.
+///
+/// Cloning yields another handle to the same counts.
+#[derive(Debug, Clone, Default)]
+pub struct MessageCounter {
+    counts: Arc<Mutex<MessageCounts>>,
+}
+
+impl MessageCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one message of `type_id`.
+    pub fn record(&self, type_id: u8) {
+        *self.counts.lock().unwrap().0.entry(type_id).or_default() += 1;
+    }
+
+    /// The counts so far.
+    pub fn counts(&self) -> MessageCounts {
+        self.counts.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_by_type() {
+        let counter = MessageCounter::new();
+        let handle = counter.clone();
+        for type_id in [TYPE_ID_VIDEO, TYPE_ID_AUDIO, TYPE_ID_VIDEO, TYPE_ID_AMF0_COMMAND, 30] {
+            handle.record(type_id);
+        }
+        let counts = counter.counts();
+        assert_eq!(counts.get(TYPE_ID_VIDEO), 2);
+        assert_eq!(counts.get(TYPE_ID_AUDIO), 1);
+        assert_eq!(counts.get(TYPE_ID_AMF0_DATA), 0);
+        assert_eq!(counts.total(), 5);
+        assert_eq!(counts.to_string(), "audio=1 video=2 command=1 type 30=1");
+    }
+}
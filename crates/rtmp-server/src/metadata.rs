@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+
+use rml_rtmp::amf0::Amf0Value;
+use rml_rtmp::sessions::StreamMetadata;
+
+/// Video codec declared in a publisher's `onMetaData`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VideoCodec {
+    Avc,
+    Hevc,
+    Other(String),
+}
+
+impl VideoCodec {
+    /// Interpret a `videocodecid` value. Encoders send either the FLV codec
+    /// id (7 = AVC, 12 = HEVC), an enhanced-RTMP FourCC as a number, or the
+    /// FourCC as a string.
+    pub fn from_metadata(value: &str) -> Self {
+        let value = value.trim();
+        let id = value
+            .parse::<f64>()
+            .ok()
+            .filter(|id| id.fract() == 0.0 && *id >= 0.0)
+            .map(|id| id as u32);
+        match id {
+            Some(7) => return VideoCodec::Avc,
+            Some(12) => return VideoCodec::Hevc,
+            Some(fourcc) if fourcc > 0xFFFF => {
+                return Self::from_fourcc(&fourcc.to_be_bytes())
+                    .unwrap_or_else(|| VideoCodec::Other(value.to_string()));
+            }
+            _ => {}
+        }
+        Self::from_fourcc(value.as_bytes()).unwrap_or_else(|| VideoCodec::Other(value.to_string()))
+    }
+
+    fn from_fourcc(fourcc: &[u8]) -> Option<Self> {
+        match fourcc {
+            b"avc1" | b"avc3" => Some(VideoCodec::Avc),
+            b"hvc1" | b"hev1" => Some(VideoCodec::Hevc),
+            _ => None,
+        }
+    }
+}
+
+/// Publisher-declared stream properties, taken from `onMetaData`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamInfo {
+    /// Encoder name, e.g. "obs-output module (libobs version 30.0.0)".
+    pub encoder: Option<String>,
+    pub video_codec: Option<VideoCodec>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_rate: Option<f32>,
+    pub video_bitrate_kbps: Option<u32>,
+}
+
+impl From<&StreamMetadata> for StreamInfo {
+    fn from(metadata: &StreamMetadata) -> Self {
+        StreamInfo {
+            encoder: metadata.encoder.clone(),
+            video_codec: metadata
+                .video_codec
+                .as_deref()
+                .map(VideoCodec::from_metadata),
+            width: metadata.video_width,
+            height: metadata.video_height,
+            frame_rate: metadata.video_frame_rate,
+            video_bitrate_kbps: metadata.video_bitrate_kbps,
+        }
+    }
+}
+
+impl StreamInfo {
+    /// Parse the values of an AMF0 data message carrying `onMetaData`,
+    /// either bare or wrapped as `@setDataFrame, onMetaData, {...}`.
+    /// Returns `None` for any other data message.
+    pub fn from_data_message(values: &[Amf0Value]) -> Option<Self> {
+        let mut values = values;
+        if let [Amf0Value::Utf8String(name), rest @ ..] = values {
+            if name == "@setDataFrame" {
+                values = rest;
+            }
+        }
+        match values {
+            [Amf0Value::Utf8String(name), Amf0Value::Object(properties), ..]
+                if name == "onMetaData" =>
+            {
+                Some(Self::from_properties(properties))
+            }
+            _ => None,
+        }
+    }
+
+    fn from_properties(properties: &HashMap<String, Amf0Value>) -> Self {
+        let number = |key: &str| match properties.get(key) {
+            Some(Amf0Value::Number(n)) if *n >= 0.0 => Some(*n),
+            _ => None,
+        };
+        let video_codec = match properties.get("videocodecid") {
+            Some(Amf0Value::Number(id)) => Some(VideoCodec::from_metadata(&id.to_string())),
+            Some(Amf0Value::Utf8String(id)) => Some(VideoCodec::from_metadata(id)),
+            _ => None,
+        };
+        StreamInfo {
+            encoder: match properties.get("encoder") {
+                Some(Amf0Value::Utf8String(encoder)) => Some(encoder.clone()),
+                _ => None,
+            },
+            video_codec,
+            width: number("width").map(|n| n as u32),
+            height: number("height").map(|n| n as u32),
+            frame_rate: number("framerate")
+                .or_else(|| number("videoframerate"))
+                .map(|n| n as f32),
+            video_bitrate_kbps: number("videodatarate").map(|n| n as u32),
+        }
+    }
+}
+
+/// SMPTE ST 2086 mastering display color volume, from `hdrMdcv`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MasteringDisplay {
+    /// CIE 1931 xy chromaticity of the red, green and blue primaries.
+    pub primaries: [(f32, f32); 3],
+    pub white_point: (f32, f32),
+    /// Luminance range in cd/m².
+    pub max_luminance: f32,
+    pub min_luminance: f32,
+}
+
+/// Color description sent in an enhanced-RTMP metadata video packet
+/// (`colorInfo`). Code points are the ISO/IEC 23091-4 ones, as in the SPS VUI.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColorInfo {
+    pub bit_depth: Option<u8>,
+    pub color_primaries: Option<u8>,
+    pub transfer_characteristics: Option<u8>,
+    pub matrix_coefficients: Option<u8>,
+    /// Content light levels in cd/m², from `hdrCll`.
+    pub max_cll: Option<u16>,
+    pub max_fall: Option<u16>,
+    pub mastering_display: Option<MasteringDisplay>,
+}
+
+impl ColorInfo {
+    /// Parse the AMF0 values of a metadata video packet:
+    /// `"colorInfo", { colorConfig, hdrCll, hdrMdcv }`. Returns `None` for
+    /// any other metadata.
+    pub fn from_metadata_packet(values: &[Amf0Value]) -> Option<Self> {
+        let [Amf0Value::Utf8String(name), Amf0Value::Object(properties), ..] = values else {
+            return None;
+        };
+        if name != "colorInfo" {
+            return None;
+        }
+        let object = |key: &str| match properties.get(key) {
+            Some(Amf0Value::Object(object)) => Some(object),
+            _ => None,
+        };
+        let config = object("colorConfig");
+        let config_code = |key| config.and_then(|config| number(config, key)).map(|n| n as u8);
+        let cll = object("hdrCll");
+        let light_level = |key| cll.and_then(|cll| number(cll, key)).map(|n| n as u16);
+        Some(ColorInfo {
+            bit_depth: config_code("bitDepth"),
+            color_primaries: config_code("colorPrimaries"),
+            transfer_characteristics: config_code("transferCharacteristics"),
+            matrix_coefficients: config_code("matrixCoefficients"),
+            max_cll: light_level("maxCLL"),
+            max_fall: light_level("maxFall"),
+            mastering_display: object("hdrMdcv").and_then(MasteringDisplay::from_properties),
+        })
+    }
+}
+
+impl MasteringDisplay {
+    /// All ten values are needed; a partial `hdrMdcv` is ignored.
+    fn from_properties(properties: &HashMap<String, Amf0Value>) -> Option<Self> {
+        let value = |key| number(properties, key).map(|n| n as f32);
+        let point = |x, y| Some((value(x)?, value(y)?));
+        Some(MasteringDisplay {
+            primaries: [
+                point("redX", "redY")?,
+                point("greenX", "greenY")?,
+                point("blueX", "blueY")?,
+            ],
+            white_point: point("whitePointX", "whitePointY")?,
+            max_luminance: value("maxLuminance")?,
+            min_luminance: value("minLuminance")?,
+        })
+    }
+}
+
+/// A non-negative number property.
+fn number(properties: &HashMap<String, Amf0Value>, key: &str) -> Option<f64> {
+    match properties.get(key) {
+        Some(Amf0Value::Number(n)) if *n >= 0.0 => Some(*n),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec_from_flv_id() {
+        assert_eq!(VideoCodec::from_metadata("7"), VideoCodec::Avc);
+        assert_eq!(VideoCodec::from_metadata("12"), VideoCodec::Hevc);
+        assert_eq!(
+            VideoCodec::from_metadata("2"),
+            VideoCodec::Other("2".into())
+        );
+    }
+
+    #[test]
+    fn test_codec_from_fourcc() {
+        assert_eq!(VideoCodec::from_metadata("avc1"), VideoCodec::Avc);
+        assert_eq!(VideoCodec::from_metadata("hvc1"), VideoCodec::Hevc);
+        // 'hvc1' sent as a number by enhanced-RTMP encoders
+        assert_eq!(VideoCodec::from_metadata("1752589105"), VideoCodec::Hevc);
+    }
+
+    fn metadata_object() -> Amf0Value {
+        Amf0Value::Object(HashMap::from([
+            ("width".to_string(), Amf0Value::Number(1280.0)),
+            ("height".to_string(), Amf0Value::Number(720.0)),
+            ("framerate".to_string(), Amf0Value::Number(30.0)),
+            ("videocodecid".to_string(), Amf0Value::Number(7.0)),
+            (
+                "encoder".to_string(),
+                Amf0Value::Utf8String("Lavf60".into()),
+            ),
+        ]))
+    }
+
+    #[test]
+    fn test_data_message_with_set_data_frame() {
+        let values = [
+            Amf0Value::Utf8String("@setDataFrame".into()),
+            Amf0Value::Utf8String("onMetaData".into()),
+            metadata_object(),
+        ];
+        let info = StreamInfo::from_data_message(&values).unwrap();
+        assert_eq!(info.width, Some(1280));
+        assert_eq!(info.height, Some(720));
+        assert_eq!(info.frame_rate, Some(30.0));
+        assert_eq!(info.video_codec, Some(VideoCodec::Avc));
+        assert_eq!(info.encoder.as_deref(), Some("Lavf60"));
+    }
+
+    #[test]
+    fn test_data_message_bare_and_unrelated() {
+        let bare = [
+            Amf0Value::Utf8String("onMetaData".into()),
+            metadata_object(),
+        ];
+        assert_eq!(
+            StreamInfo::from_data_message(&bare).unwrap().width,
+            Some(1280)
+        );
+
+        let other = [
+            Amf0Value::Utf8String("onTextData".into()),
+            metadata_object(),
+        ];
+        assert!(StreamInfo::from_data_message(&other).is_none());
+    }
+
+
+    #[test]
+    fn test_color_info_hdr10() {
+        let numbers = |pairs: &[(&str, f64)]| {
+            Amf0Value::Object(
+                pairs
+                    .iter()
+                    .map(|(key, n)| (key.to_string(), Amf0Value::Number(*n)))
+                    .collect(),
+            )
+        };
+        let values = [
+            Amf0Value::Utf8String("colorInfo".into()),
+            Amf0Value::Object(HashMap::from([
+                (
+                    "colorConfig".to_string(),
+                    numbers(&[
+                        ("bitDepth", 10.0),
+                        ("colorPrimaries", 9.0),
+                        ("transferCharacteristics", 16.0),
+                        ("matrixCoefficients", 9.0),
+                    ]),
+                ),
+                (
+                    "hdrCll".to_string(),
+                    numbers(&[("maxCLL", 1000.0), ("maxFall", 400.0)]),
+                ),
+                (
+                    "hdrMdcv".to_string(),
+                    numbers(&[
+                        ("redX", 0.708),
+                        ("redY", 0.292),
+                        ("greenX", 0.17),
+                        ("greenY", 0.797),
+                        ("blueX", 0.131),
+                        ("blueY", 0.046),
+                        ("whitePointX", 0.3127),
+                        ("whitePointY", 0.329),
+                        ("maxLuminance", 1000.0),
+                        ("minLuminance", 0.0001),
+                    ]),
+                ),
+            ])),
+        ];
+        let info = ColorInfo::from_metadata_packet(&values).unwrap();
+        assert_eq!(info.bit_depth, Some(10));
+        assert_eq!(info.color_primaries, Some(9));
+        assert_eq!(info.transfer_characteristics, Some(16));
+        assert_eq!(info.max_cll, Some(1000));
+        assert_eq!(info.max_fall, Some(400));
+        let mastering = info.mastering_display.unwrap();
+        assert_eq!(mastering.primaries[1], (0.17, 0.797));
+        assert_eq!(mastering.white_point, (0.3127, 0.329));
+        assert_eq!(mastering.max_luminance, 1000.0);
+
+        // Without hdrMdcv, and with an unrelated metadata name
+        let sdr = [
+            Amf0Value::Utf8String("colorInfo".into()),
+            Amf0Value::Object(HashMap::new()),
+        ];
+        assert_eq!(
+            ColorInfo::from_metadata_packet(&sdr),
+            Some(ColorInfo::default())
+        );
+        let other = [
+            Amf0Value::Utf8String("somethingElse".into()),
+            Amf0Value::Object(HashMap::new()),
+        ];
+        assert!(ColorInfo::from_metadata_packet(&other).is_none());
+    }
+}
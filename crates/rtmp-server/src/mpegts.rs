@@ -0,0 +1,480 @@
+//! MPEG-TS over TCP ingest, for cameras that push a transport stream instead of RTMP.
+//!
+//! Demuxes 188-byte TS packets, follows PAT → PMT to the first H.264 elementary
+//! stream, reassembles its PES packets, and converts each access unit from
+//! Annex-B to AVCC so it can be fed to the same `VideoSink` as RTMP input.
+//! In-band SPS/PPS are turned into `on_decoder_config` calls.
+//!
+//! PSI sections are assumed to fit in a single TS packet, which holds for the
+//! single-program streams produced by ffmpeg and typical hardware encoders.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::flv::AvcDecoderConfig;
+use crate::sei;
+use crate::session::{ConnectionContext, SinkFactory, VideoSink};
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0x0000;
+const STREAM_TYPE_H264: u8 = 0x1B;
+
+/// One reassembled H.264 access unit in Annex-B form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessUnit {
+    /// Presentation timestamp in milliseconds (90 kHz PTS / 90, wrapping).
+    pub pts_ms: u32,
+    pub annexb: Vec<u8>,
+}
+
+/// Transport stream demuxer for the first H.264 stream of the first program.
+#[derive(Default)]
+pub struct TsDemuxer {
+    /// Bytes of an incomplete TS packet carried over between `push` calls.
+    pending: Vec<u8>,
+    pmt_pid: Option<u16>,
+    video_pid: Option<u16>,
+    pes: Vec<u8>,
+    pes_pts: Option<u64>,
+}
+
+impl TsDemuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed raw bytes from the socket; returns the access units completed by them.
+    pub fn push(&mut self, data: &[u8]) -> Vec<AccessUnit> {
+        self.pending.extend_from_slice(data);
+        let mut units = Vec::new();
+        let mut pos = 0;
+
+        while self.pending.len() - pos >= TS_PACKET_SIZE {
+            if self.pending[pos] != TS_SYNC_BYTE {
+                // Lost sync — scan forward for the next sync byte
+                pos += 1;
+                continue;
+            }
+            let mut packet = [0u8; TS_PACKET_SIZE];
+            packet.copy_from_slice(&self.pending[pos..pos + TS_PACKET_SIZE]);
+            pos += TS_PACKET_SIZE;
+            if let Some(unit) = self.handle_packet(&packet) {
+                units.push(unit);
+            }
+        }
+
+        self.pending.drain(..pos);
+        units
+    }
+
+    /// Flush the PES being assembled, e.g. when the connection closes.
+    pub fn finish(&mut self) -> Option<AccessUnit> {
+        self.take_pes()
+    }
+
+    fn handle_packet(&mut self, packet: &[u8; TS_PACKET_SIZE]) -> Option<AccessUnit> {
+        if packet[1] & 0x80 != 0 {
+            trace!("TS packet with transport error indicator, skipping");
+            return None;
+        }
+        let payload_start = packet[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([packet[1] & 0x1F, packet[2]]);
+        let adaptation = (packet[3] >> 4) & 0x03;
+
+        let mut offset = 4;
+        if adaptation & 0x02 != 0 {
+            offset += 1 + packet[4] as usize;
+        }
+        if adaptation & 0x01 == 0 || offset >= TS_PACKET_SIZE {
+            return None;
+        }
+        let payload = &packet[offset..];
+
+        if pid == PAT_PID && payload_start {
+            self.parse_pat(payload);
+            None
+        } else if Some(pid) == self.pmt_pid && payload_start {
+            self.parse_pmt(payload);
+            None
+        } else if Some(pid) == self.video_pid {
+            self.handle_video_payload(payload, payload_start)
+        } else {
+            None
+        }
+    }
+
+    /// Strip the pointer field and return the section up to (not including) its CRC.
+    fn section(payload: &[u8], table_id: u8) -> Option<&[u8]> {
+        let pointer = *payload.first()? as usize;
+        let section = payload.get(1 + pointer..)?;
+        if *section.first()? != table_id || section.len() < 3 {
+            return None;
+        }
+        let section_length = (u16::from_be_bytes([section[1] & 0x0F, section[2]])) as usize;
+        // section_length counts everything after itself, including the 4-byte CRC
+        if section_length < 4 || 3 + section_length > section.len() {
+            return None;
+        }
+        Some(&section[..3 + section_length - 4])
+    }
+
+    fn parse_pat(&mut self, payload: &[u8]) {
+        let Some(section) = Self::section(payload, 0x00) else {
+            warn!("malformed PAT");
+            return;
+        };
+        // 8-byte header: table_id, length (2), ts_id (2), version, section, last_section
+        for entry in section.get(8..).unwrap_or_default().chunks_exact(4) {
+            let program = u16::from_be_bytes([entry[0], entry[1]]);
+            if program == 0 {
+                continue; // network PID
+            }
+            let pid = u16::from_be_bytes([entry[2] & 0x1F, entry[3]]);
+            if self.pmt_pid != Some(pid) {
+                debug!(program, pid, "PAT: selected program");
+                self.pmt_pid = Some(pid);
+            }
+            return;
+        }
+    }
+
+    fn parse_pmt(&mut self, payload: &[u8]) {
+        let Some(section) = Self::section(payload, 0x02) else {
+            warn!("malformed PMT");
+            return;
+        };
+        if section.len() < 12 {
+            return;
+        }
+        let program_info_length = u16::from_be_bytes([section[10] & 0x0F, section[11]]) as usize;
+        let mut pos = 12 + program_info_length;
+
+        while pos + 5 <= section.len() {
+            let stream_type = section[pos];
+            let pid = u16::from_be_bytes([section[pos + 1] & 0x1F, section[pos + 2]]);
+            let es_info_length =
+                u16::from_be_bytes([section[pos + 3] & 0x0F, section[pos + 4]]) as usize;
+            if stream_type == STREAM_TYPE_H264 {
+                if self.video_pid != Some(pid) {
+                    info!(pid, "PMT: selected H.264 stream");
+                    self.video_pid = Some(pid);
+                    self.pes.clear();
+                    self.pes_pts = None;
+                }
+                return;
+            }
+            trace!(stream_type, pid, "PMT: skipping non-H.264 stream");
+            pos += 5 + es_info_length;
+        }
+    }
+
+    fn handle_video_payload(&mut self, payload: &[u8], payload_start: bool) -> Option<AccessUnit> {
+        let mut completed = None;
+        if payload_start {
+            // Video PES usually has an unbounded length, so a new PES start
+            // is what completes the previous one
+            completed = self.take_pes();
+            self.pes_pts = parse_pes_pts(payload);
+            match pes_payload_offset(payload) {
+                Some(offset) => self.pes.extend_from_slice(&payload[offset..]),
+                None => warn!("malformed PES header"),
+            }
+        } else if self.pes_pts.is_some() {
+            self.pes.extend_from_slice(payload);
+        }
+        completed
+    }
+
+    fn take_pes(&mut self) -> Option<AccessUnit> {
+        let pts = self.pes_pts.take()?;
+        if self.pes.is_empty() {
+            return None;
+        }
+        Some(AccessUnit {
+            pts_ms: (pts / 90) as u32,
+            annexb: std::mem::take(&mut self.pes),
+        })
+    }
+}
+
+/// Offset of the elementary stream data within a PES packet starting at `pes`.
+fn pes_payload_offset(pes: &[u8]) -> Option<usize> {
+    if pes.len() < 9 || pes[..3] != [0x00, 0x00, 0x01] {
+        return None;
+    }
+    let offset = 9 + pes[8] as usize;
+    (offset <= pes.len()).then_some(offset)
+}
+
+/// 33-bit PTS from a PES header, if present.
+fn parse_pes_pts(pes: &[u8]) -> Option<u64> {
+    pes_payload_offset(pes)?;
+    if pes[7] & 0x80 == 0 || pes.len() < 14 {
+        // Keep assembling even without a PTS; timestamps will be 0
+        return Some(0);
+    }
+    let p = &pes[9..14];
+    Some(
+        ((p[0] as u64 >> 1) & 0x07) << 30
+            | (p[1] as u64) << 22
+            | ((p[2] as u64) >> 1) << 15
+            | (p[3] as u64) << 7
+            | (p[4] as u64) >> 1,
+    )
+}
+
+/// Split an Annex-B byte stream into NAL units (start codes removed).
+pub fn split_annexb(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    let mut units = Vec::with_capacity(starts.len());
+    for (n, &start) in starts.iter().enumerate() {
+        let mut end = starts.get(n + 1).map(|&next| next - 3).unwrap_or(data.len());
+        // Trailing zeros belong to the next 4-byte start code (or are padding)
+        while end > start && data[end - 1] == 0 {
+            end -= 1;
+        }
+        if end > start {
+            units.push(&data[start..end]);
+        }
+    }
+    units
+}
+
+/// Converts Annex-B access units to AVCC and tracks in-band parameter sets.
+#[derive(Default)]
+pub struct AnnexBConverter {
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+}
+
+impl AnnexBConverter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Convert one access unit. Returns a new decoder config if the SPS/PPS
+    /// changed, and the AVCC payload (4-byte lengths) of the remaining NALs.
+    pub fn convert(&mut self, annexb: &[u8]) -> (Option<AvcDecoderConfig>, Option<Bytes>) {
+        let mut avcc = Vec::with_capacity(annexb.len() + 16);
+        let mut config_changed = false;
+
+        for nal in split_annexb(annexb) {
+            match nal[0] & 0x1F {
+                7 => config_changed |= replace_if_changed(&mut self.sps, nal),
+                8 => config_changed |= replace_if_changed(&mut self.pps, nal),
+                9 => {} // access unit delimiter, not needed by VideoToolbox
+                _ => {
+                    avcc.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                    avcc.extend_from_slice(nal);
+                }
+            }
+        }
+
+        let config = match (&self.sps, &self.pps) {
+            (Some(sps), Some(pps)) if config_changed => Some(AvcDecoderConfig {
+                sps: vec![sps.clone()],
+                pps: vec![pps.clone()],
+                nalu_length_size: 4,
+            }),
+            _ => None,
+        };
+        let payload = (!avcc.is_empty()).then(|| Bytes::from(avcc));
+        (config, payload)
+    }
+}
+
+fn replace_if_changed(slot: &mut Option<Vec<u8>>, nal: &[u8]) -> bool {
+    if slot.as_deref() == Some(nal) {
+        return false;
+    }
+    *slot = Some(nal.to_vec());
+    true
+}
+
+/// Start the MPEG-TS ingest server on the given address.
+/// Each connection is treated as one publish; `sink_factory` is called with
+/// an empty app name and the peer address as the stream key.
+pub async fn run<F>(addr: SocketAddr, sink_factory: F) -> io::Result<()>
+where
+    F: Fn(&ConnectionContext) -> io::Result<Box<dyn VideoSink>> + Send + Sync + 'static,
+{
+    let sink_factory: SinkFactory = Arc::new(sink_factory);
+    let listener = TcpListener::bind(addr).await?;
+    info!(%addr, "MPEG-TS ingest listening");
+
+    loop {
+        let (stream, peer_addr) = listener.accept().await?;
+        info!(%peer_addr, "new MPEG-TS connection");
+
+        let factory = Arc::clone(&sink_factory);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, peer_addr, factory).await {
+                error!(%peer_addr, %e, "MPEG-TS connection error");
+            }
+            info!(%peer_addr, "MPEG-TS connection closed");
+        });
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    peer_addr: SocketAddr,
+    sink_factory: SinkFactory,
+) -> io::Result<()> {
+    let mut sink = sink_factory(&ConnectionContext {
+        peer_addr,
+        app_name: String::new(),
+        stream_key: peer_addr.to_string(),
+    })?;
+    let mut demuxer = TsDemuxer::new();
+    let mut converter = AnnexBConverter::new();
+    let mut buf = vec![0u8; TS_PACKET_SIZE * 64];
+
+    let result = loop {
+        let n = match stream.read(&mut buf).await {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(e) => break Err(e),
+        };
+        for unit in demuxer.push(&buf[..n]) {
+            deliver(&mut converter, &mut *sink, unit);
+        }
+        if sink.is_failed() {
+            warn!(%peer_addr, "sink gave up on the stream, closing connection");
+            break Ok(());
+        }
+    };
+
+    if let Some(unit) = demuxer.finish() {
+        deliver(&mut converter, &mut *sink, unit);
+    }
+    sink.on_stream_end();
+    result
+}
+
+fn deliver(converter: &mut AnnexBConverter, sink: &mut dyn VideoSink, unit: AccessUnit) {
+    let (config, payload) = converter.convert(&unit.annexb);
+    if let Some(config) = config {
+        info!("received in-band SPS/PPS");
+        sink.on_decoder_config(config);
+    }
+    if let Some(payload) = payload {
+        sei::forward_sei(sink, &payload, 4);
+        sink.on_video_data(payload, unit.pts_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts_packet(pid: u16, payload_start: bool, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![
+            TS_SYNC_BYTE,
+            ((payload_start as u8) << 6) | (pid >> 8) as u8,
+            pid as u8,
+            0x10, // payload only
+        ];
+        packet.extend_from_slice(payload);
+        packet.resize(TS_PACKET_SIZE, 0xFF);
+        packet
+    }
+
+    fn psi(table_id: u8, body: &[u8]) -> Vec<u8> {
+        let section_length = body.len() + 4; // + CRC (not checked)
+        let mut out = vec![0x00, table_id, 0xB0 | (section_length >> 8) as u8, section_length as u8];
+        out.extend_from_slice(body);
+        out.extend_from_slice(&[0; 4]);
+        out
+    }
+
+    fn pes(pts: u64, es: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x00, 0x00, 0x01, 0xE0, 0x00, 0x00, 0x80, 0x80, 0x05];
+        out.extend_from_slice(&[
+            0x21 | ((pts >> 29) & 0x0E) as u8,
+            (pts >> 22) as u8,
+            0x01 | ((pts >> 14) & 0xFE) as u8,
+            (pts >> 7) as u8,
+            0x01 | ((pts << 1) & 0xFE) as u8,
+        ]);
+        out.extend_from_slice(es);
+        out
+    }
+
+    fn stream_with_two_units() -> Vec<u8> {
+        // PAT: program 1 -> PMT PID 0x1000
+        let pat = psi(0x00, &[0x00, 0x01, 0xC1, 0x00, 0x00, 0x00, 0x01, 0xF0, 0x00]);
+        // PMT: PCR PID 0x100, H.264 on PID 0x100
+        let pmt = psi(
+            0x02,
+            &[0x00, 0x01, 0xC1, 0x00, 0x00, 0xE1, 0x00, 0xF0, 0x00, 0x1B, 0xE1, 0x00, 0xF0, 0x00],
+        );
+        let au1 = [
+            0x00, 0x00, 0x00, 0x01, 0x09, 0xF0, // AUD
+            0x00, 0x00, 0x00, 0x01, 0x67, 0x64, 0x00, 0x1F, // SPS
+            0x00, 0x00, 0x00, 0x01, 0x68, 0xEB, 0xE3, // PPS
+            0x00, 0x00, 0x01, 0x65, 0x88, 0x80, // IDR
+        ];
+        let au2 = [0x00, 0x00, 0x00, 0x01, 0x41, 0x9A, 0x02];
+
+        let mut out = ts_packet(PAT_PID, true, &pat);
+        out.extend(ts_packet(0x1000, true, &pmt));
+        out.extend(ts_packet(0x100, true, &pes(90_000, &au1)));
+        out.extend(ts_packet(0x100, true, &pes(93_000, &au2)));
+        out
+    }
+
+    #[test]
+    fn test_demux_reassembles_access_units() {
+        let stream = stream_with_two_units();
+        let mut demuxer = TsDemuxer::new();
+
+        // Feed in odd-sized chunks to exercise packet reassembly
+        let mut units = Vec::new();
+        for chunk in stream.chunks(100) {
+            units.extend(demuxer.push(chunk));
+        }
+        units.extend(demuxer.finish());
+
+        assert_eq!(units.len(), 2);
+        assert_eq!(units[0].pts_ms, 1000);
+        assert_eq!(units[1].pts_ms, 1033);
+        assert!(units[1].annexb.starts_with(&[0x00, 0x00, 0x00, 0x01, 0x41, 0x9A, 0x02]));
+    }
+
+    #[test]
+    fn test_annexb_to_avcc_extracts_parameter_sets() {
+        let au = [
+            0x00, 0x00, 0x00, 0x01, 0x09, 0xF0, 0x00, 0x00, 0x00, 0x01, 0x67, 0x64, 0x00, 0x1F,
+            0x00, 0x00, 0x00, 0x01, 0x68, 0xEB, 0xE3, 0x00, 0x00, 0x01, 0x65, 0x88, 0x80,
+        ];
+        let mut converter = AnnexBConverter::new();
+        let (config, payload) = converter.convert(&au);
+
+        let config = config.expect("config from in-band SPS/PPS");
+        assert_eq!(config.sps, vec![vec![0x67, 0x64, 0x00, 0x1F]]);
+        assert_eq!(config.pps, vec![vec![0x68, 0xEB, 0xE3]]);
+        assert_eq!(&payload.unwrap()[..], &[0x00, 0x00, 0x00, 0x03, 0x65, 0x88, 0x80]);
+
+        // Same parameter sets again: no new config
+        let (config, _) = converter.convert(&au);
+        assert!(config.is_none());
+    }
+}
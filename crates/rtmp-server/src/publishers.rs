@@ -0,0 +1,208 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::bitrate::IngestMeter;
+use crate::message_counts::{MessageCounter, MessageCounts};
+
+/// Details of a client that is currently publishing.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub peer_addr: SocketAddr,
+    pub app_name: String,
+    pub started_at: SystemTime,
+    /// Bitrate the connection received at over the last `BITRATE_WINDOW`,
+    /// in kbps, as of the snapshot.
+    pub ingest_kbps: u64,
+    /// RTMP messages received on the connection so far, by type.
+    pub messages: MessageCounts,
+}
+
+#[derive(Debug)]
+struct Publisher {
+    info: ConnectionInfo,
+    ingest: IngestMeter,
+    messages: MessageCounter,
+}
+
+/// Shared table of active publishers, keyed by stream key.
+///
+/// Cloning yields another handle to the same table.
+#[derive(Debug, Clone, Default)]
+pub struct PublisherRegistry {
+    inner: Arc<Mutex<HashMap<String, Publisher>>>,
+    /// Stream keys whose publisher should be asked for a keyframe.
+    keyframe_requests: Arc<Mutex<HashSet<String>>>,
+}
+
+impl PublisherRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `stream_key` is now being published by `peer_addr`.
+    pub fn register(&self, stream_key: &str, app_name: &str, peer_addr: SocketAddr) {
+        self.register_with_ingest(stream_key, app_name, peer_addr, IngestMeter::new());
+    }
+
+    /// Like `register`, reporting the bitrate measured by `ingest`.
+    pub fn register_with_ingest(
+        &self,
+        stream_key: &str,
+        app_name: &str,
+        peer_addr: SocketAddr,
+        ingest: IngestMeter,
+    ) {
+        self.register_with_stats(stream_key, app_name, peer_addr, ingest, MessageCounter::new());
+    }
+
+    /// Like `register_with_ingest`, also reporting the message counts kept
+    /// by `messages`.
+    pub fn register_with_stats(
+        &self,
+        stream_key: &str,
+        app_name: &str,
+        peer_addr: SocketAddr,
+        ingest: IngestMeter,
+        messages: MessageCounter,
+    ) {
+        let info = ConnectionInfo {
+            peer_addr,
+            app_name: app_name.to_string(),
+            started_at: SystemTime::now(),
+            ingest_kbps: 0,
+            messages: MessageCounts::default(),
+        };
+        let publisher = Publisher {
+            info,
+            ingest,
+            messages,
+        };
+        self.inner
+            .lock()
+            .unwrap()
+            .insert(stream_key.to_string(), publisher);
+    }
+
+    /// Remove `stream_key`, but only if it is still owned by `peer_addr` —
+    /// a newer publisher may have taken the key over in the meantime.
+    pub fn unregister(&self, stream_key: &str, peer_addr: SocketAddr) {
+        let mut publishers = self.inner.lock().unwrap();
+        if publishers.get(stream_key).map(|p| p.info.peer_addr) == Some(peer_addr) {
+            publishers.remove(stream_key);
+            self.keyframe_requests.lock().unwrap().remove(stream_key);
+        }
+    }
+
+    /// Ask the publisher of `stream_key` to send a keyframe, e.g. because a
+    /// consumer joined mid-GOP. The request goes out the next time the
+    /// publisher's connection handles input. Returns false if nothing is
+    /// publishing with that key.
+    pub fn request_keyframe(&self, stream_key: &str) -> bool {
+        if !self.inner.lock().unwrap().contains_key(stream_key) {
+            return false;
+        }
+        self.keyframe_requests
+            .lock()
+            .unwrap()
+            .insert(stream_key.to_string());
+        true
+    }
+
+    /// Clear and return a pending keyframe request for `stream_key`, if
+    /// `peer_addr` is still the one publishing it.
+    pub(crate) fn take_keyframe_request(&self, stream_key: &str, peer_addr: SocketAddr) -> bool {
+        let publishers = self.inner.lock().unwrap();
+        if publishers.get(stream_key).map(|p| p.info.peer_addr) != Some(peer_addr) {
+            return false;
+        }
+        self.keyframe_requests.lock().unwrap().remove(stream_key)
+    }
+
+    /// Snapshot of all active publishers.
+    pub fn snapshot(&self) -> Vec<(String, ConnectionInfo)> {
+        self.inner
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, p)| {
+                let info = ConnectionInfo {
+                    ingest_kbps: p.ingest.kbps(),
+                    messages: p.messages.counts(),
+                    ..p.info.clone()
+                };
+                (k.clone(), info)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_and_unregister() {
+        let registry = PublisherRegistry::new();
+        let a: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        registry.register("cam1", "live", a);
+        registry.register("cam2", "live", b);
+
+        let mut keys: Vec<_> = registry.snapshot().into_iter().map(|(k, _)| k).collect();
+        keys.sort();
+        assert_eq!(keys, ["cam1", "cam2"]);
+
+        registry.unregister("cam1", a);
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].1.peer_addr, b);
+    }
+
+    #[test]
+    fn test_unregister_ignores_other_peer() {
+        let registry = PublisherRegistry::new();
+        let old: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let new: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        registry.register("cam", "live", old);
+        registry.register("cam", "live", new);
+
+        // The old connection closing must not remove the new publisher
+        registry.unregister("cam", old);
+        assert_eq!(registry.snapshot()[0].1.peer_addr, new);
+    }
+
+    #[test]
+    fn test_snapshot_reports_ingest() {
+        let registry = PublisherRegistry::new();
+        let a: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let ingest = IngestMeter::new();
+        registry.register_with_ingest("cam", "live", a, ingest.clone());
+        assert_eq!(registry.snapshot()[0].1.ingest_kbps, 0);
+
+        ingest.record(125_000);
+        assert!(registry.snapshot()[0].1.ingest_kbps > 0);
+    }
+
+    #[test]
+    fn test_keyframe_request() {
+        let registry = PublisherRegistry::new();
+        let a: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        assert!(!registry.request_keyframe("cam"));
+
+        registry.register("cam", "live", a);
+        assert!(registry.request_keyframe("cam"));
+        assert!(!registry.take_keyframe_request("cam", b));
+        assert!(registry.take_keyframe_request("cam", a));
+        // Taken once
+        assert!(!registry.take_keyframe_request("cam", a));
+
+        // Dropped with the publisher
+        registry.request_keyframe("cam");
+        registry.unregister("cam", a);
+        registry.register("cam", "live", a);
+        assert!(!registry.take_keyframe_request("cam", a));
+    }
+}
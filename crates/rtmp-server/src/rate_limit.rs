@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Connections a single IP may open per `CONNECTION_RATE_WINDOW` by default.
+pub const CONNECTION_BURST: u32 = 5;
+pub const CONNECTION_RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// How often buckets of IPs that have gone quiet are dropped.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token bucket for one source IP: each connection takes a token, and
+/// tokens refill continuously up to the burst size.
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    by_ip: HashMap<IpAddr, TokenBucket>,
+    last_prune: Instant,
+}
+
+/// Limits how often each source IP may connect, so one client can't flood
+/// the server with connections.
+///
+/// Cloning yields another handle to the same buckets.
+#[derive(Debug, Clone)]
+pub struct ConnectionRateLimiter {
+    buckets: Arc<Mutex<Buckets>>,
+    burst: u32,
+    window: Duration,
+}
+
+impl Default for ConnectionRateLimiter {
+    fn default() -> Self {
+        Self::new(CONNECTION_BURST, CONNECTION_RATE_WINDOW)
+    }
+}
+
+impl ConnectionRateLimiter {
+    /// Allow `burst` connections per IP within any `window`.
+    pub fn new(burst: u32, window: Duration) -> Self {
+        Self {
+            buckets: Arc::new(Mutex::new(Buckets {
+                by_ip: HashMap::new(),
+                last_prune: Instant::now(),
+            })),
+            burst,
+            window,
+        }
+    }
+
+    /// Take a token for a connection from `ip`. Returns false if the IP is
+    /// over its limit and the connection should be dropped.
+    pub fn check(&self, ip: IpAddr) -> bool {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let burst = self.burst as f64;
+        let refill_per_sec = burst / self.window.as_secs_f64();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // A bucket untouched for a whole window is full again, the same as
+        // having none
+        if now.saturating_duration_since(buckets.last_prune) >= PRUNE_INTERVAL {
+            let window = self.window;
+            buckets
+                .by_ip
+                .retain(|_, bucket| now.saturating_duration_since(bucket.updated) < window);
+            buckets.last_prune = now;
+        }
+
+        let bucket = buckets.by_ip.entry(ip).or_insert(TokenBucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_sec).min(burst);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    #[cfg(test)]
+    fn tracked_ips(&self) -> usize {
+        self.buckets.lock().unwrap().by_ip.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttles_one_ip_only() {
+        let limiter = ConnectionRateLimiter::default();
+        let flooder: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let start = Instant::now();
+
+        for i in 0..CONNECTION_BURST {
+            assert!(limiter.check_at(flooder, start), "connection {i}");
+        }
+        assert!(!limiter.check_at(flooder, start));
+        assert!(!limiter.check_at(flooder, start + Duration::from_millis(500)));
+        assert!(limiter.check_at(other, start + Duration::from_millis(500)));
+
+        // One token comes back every 2 seconds
+        assert!(limiter.check_at(flooder, start + Duration::from_secs(2)));
+        assert!(!limiter.check_at(flooder, start + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_prunes_quiet_ips() {
+        let limiter = ConnectionRateLimiter::new(2, Duration::from_secs(1));
+        let start = Instant::now();
+        for host in 1..=10u8 {
+            limiter.check_at(IpAddr::from([192, 0, 2, host]), start);
+        }
+        assert_eq!(limiter.tracked_ips(), 10);
+
+        let later = start + PRUNE_INTERVAL;
+        assert!(limiter.check_at(IpAddr::from([192, 0, 2, 1]), later));
+        assert_eq!(limiter.tracked_ips(), 1);
+    }
+}
@@ -0,0 +1,276 @@
+//! Raw AVCC recording: the sequence header and frame payloads of a stream,
+//! without FLV framing, for replaying through a decoder in tests.
+//!
+//! File layout, all integers big-endian:
+//!
+//! ```text
+//! "RAVC" version (u8)
+//! records: kind (u8) timestamp (u32) length (u32) payload
+//! ```
+//!
+//! A `RECORD_CONFIG` payload is an AVCDecoderConfigurationRecord, a
+//! `RECORD_FRAME` payload the AVCC NAL units of one frame, and a
+//! `RECORD_END_OF_SEQUENCE` has none.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use tracing::error;
+
+use crate::flv::{self, AvcDecoderConfig};
+use crate::session::VideoSink;
+
+const MAGIC: [u8; 4] = *b"RAVC";
+const VERSION: u8 = 1;
+const RECORD_HEADER_SIZE: usize = 9;
+
+pub const RECORD_CONFIG: u8 = 1;
+pub const RECORD_FRAME: u8 = 2;
+pub const RECORD_END_OF_SEQUENCE: u8 = 3;
+
+/// One entry of a raw AVCC recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RawAvccRecord {
+    Config(AvcDecoderConfig),
+    Frame { data: Bytes, timestamp: u32 },
+    EndOfSequence,
+}
+
+/// VideoSink that writes the stream to a raw AVCC file.
+///
+/// A write error is logged once and ends the recording; the stream itself
+/// carries on.
+pub struct RawAvccRecorder {
+    out: Option<BufWriter<File>>,
+    path: PathBuf,
+    frames: u64,
+}
+
+impl RawAvccRecorder {
+    /// Start a recording at `path`, replacing any file already there.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&MAGIC)?;
+        out.write_all(&[VERSION])?;
+        Ok(Self {
+            out: Some(out),
+            path: path.to_path_buf(),
+            frames: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Frames written so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Flush buffered records to the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.out.take() {
+            Some(mut out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn write_record(&mut self, kind: u8, timestamp: u32, payload: &[u8]) {
+        let Some(out) = self.out.as_mut() else {
+            return;
+        };
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        header[0] = kind;
+        header[1..5].copy_from_slice(&timestamp.to_be_bytes());
+        header[5..].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+        if let Err(e) = out.write_all(&header).and_then(|()| out.write_all(payload)) {
+            error!(path = %self.path.display(), %e, "raw AVCC recording failed, stopping");
+            self.out = None;
+        }
+    }
+}
+
+impl VideoSink for RawAvccRecorder {
+    fn on_decoder_config(&mut self, config: AvcDecoderConfig) {
+        // Without the 5-byte FLV video tag header
+        let tag = flv::sequence_header_tag(&config);
+        self.write_record(RECORD_CONFIG, 0, &tag[5..]);
+    }
+
+    fn on_end_of_sequence(&mut self) {
+        self.write_record(RECORD_END_OF_SEQUENCE, 0, &[]);
+    }
+
+    fn on_video_data(&mut self, data: Bytes, timestamp: u32) {
+        self.write_record(RECORD_FRAME, timestamp, &data);
+        self.frames += 1;
+    }
+
+    fn on_stream_end(&mut self) {
+        if let Some(Err(e)) = self.out.as_mut().map(Write::flush) {
+            error!(path = %self.path.display(), %e, "failed to flush raw AVCC recording");
+        }
+    }
+}
+
+/// Reads back a file written by `RawAvccRecorder`, one record at a time.
+pub struct RawAvccReader<R = BufReader<File>> {
+    input: R,
+}
+
+impl RawAvccReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> RawAvccReader<R> {
+    /// Check the file header and position `input` at the first record.
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0u8; 5];
+        input.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(invalid("not a raw AVCC recording".to_string()));
+        }
+        if header[4] != VERSION {
+            return Err(invalid(format!("unsupported raw AVCC version {}", header[4])));
+        }
+        Ok(Self { input })
+    }
+
+    /// The next record, or `None` at the end of the file.
+    pub fn next_record(&mut self) -> io::Result<Option<RawAvccRecord>> {
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        match self.input.read_exact(&mut header[..1]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        self.input.read_exact(&mut header[1..])?;
+        let timestamp = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+        let len = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as usize;
+        let mut payload = vec![0u8; len];
+        self.input.read_exact(&mut payload)?;
+
+        let record = match header[0] {
+            RECORD_CONFIG => RawAvccRecord::Config(
+                flv::parse_avc_config(&payload).map_err(|e| invalid(e.to_string()))?,
+            ),
+            RECORD_FRAME => RawAvccRecord::Frame {
+                data: Bytes::from(payload),
+                timestamp,
+            },
+            RECORD_END_OF_SEQUENCE => RawAvccRecord::EndOfSequence,
+            kind => return Err(invalid(format!("unknown raw AVCC record kind {kind}"))),
+        };
+        Ok(Some(record))
+    }
+
+    /// Feed every remaining record to `sink`, as a publish would, and end
+    /// the stream. Returns the number of frames passed on.
+    pub fn replay(&mut self, sink: &mut dyn VideoSink) -> io::Result<u64> {
+        let mut frames = 0;
+        while let Some(record) = self.next_record()? {
+            match record {
+                RawAvccRecord::Config(config) => sink.on_decoder_config(config),
+                RawAvccRecord::Frame { data, timestamp } => {
+                    sink.on_video_data(data, timestamp);
+                    frames += 1;
+                }
+                RawAvccRecord::EndOfSequence => sink.on_end_of_sequence(),
+            }
+        }
+        sink.on_stream_end();
+        Ok(frames)
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct CollectingSink {
+        records: Vec<RawAvccRecord>,
+        ended: bool,
+    }
+
+    impl VideoSink for CollectingSink {
+        fn on_decoder_config(&mut self, config: AvcDecoderConfig) {
+            self.records.push(RawAvccRecord::Config(config));
+        }
+
+        fn on_end_of_sequence(&mut self) {
+            self.records.push(RawAvccRecord::EndOfSequence);
+        }
+
+        fn on_video_data(&mut self, data: Bytes, timestamp: u32) {
+            self.records.push(RawAvccRecord::Frame { data, timestamp });
+        }
+
+        fn on_stream_end(&mut self) {
+            self.ended = true;
+        }
+    }
+
+    #[test]
+    fn test_record_then_replay() {
+        let path = std::env::temp_dir().join(format!("rtmp-raw-{}.avcc", std::process::id()));
+        let config = AvcDecoderConfig {
+            sps: vec![vec![0x67, 0x42, 0xC0, 0x0A]],
+            pps: vec![vec![0x68, 0xCE]],
+            nalu_length_size: 4,
+        };
+        let frames = [
+            (Bytes::from_static(&[0x00, 0x00, 0x00, 0x02, 0x65, 0x88]), 0),
+            (Bytes::from_static(&[0x00, 0x00, 0x00, 0x02, 0x41, 0x9A]), 33),
+        ];
+
+        let mut recorder = RawAvccRecorder::create(&path).unwrap();
+        recorder.on_decoder_config(config.clone());
+        for (data, timestamp) in &frames {
+            recorder.on_video_data(data.clone(), *timestamp);
+        }
+        recorder.on_end_of_sequence();
+        assert_eq!(recorder.frames(), 2);
+        recorder.finish().unwrap();
+
+        let mut sink = CollectingSink::default();
+        let replayed = RawAvccReader::open(&path).unwrap().replay(&mut sink).unwrap();
+        assert_eq!(replayed, 2);
+        assert!(sink.ended);
+        assert_eq!(
+            sink.records,
+            [
+                RawAvccRecord::Config(config),
+                RawAvccRecord::Frame {
+                    data: frames[0].0.clone(),
+                    timestamp: 0
+                },
+                RawAvccRecord::Frame {
+                    data: frames[1].0.clone(),
+                    timestamp: 33
+                },
+                RawAvccRecord::EndOfSequence,
+            ]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        assert!(RawAvccReader::new(&b"FLV\x01\x05"[..]).is_err());
+        assert!(RawAvccReader::new(&b"RAVC\x02"[..]).is_err());
+
+        // A record cut off mid-payload is an error, not the end of the file
+        let mut reader = RawAvccReader::new(&b"RAVC\x01\x02\0\0\0\0\0\0\0\x04\x65"[..]).unwrap();
+        assert!(reader.next_record().is_err());
+    }
+}
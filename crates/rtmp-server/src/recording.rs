@@ -0,0 +1,265 @@
+//! FLV recording of publishes made in RTMP `record` or `append` mode.
+//!
+//! The video and audio messages of the publish are written as FLV tags as
+//! they arrive, without remuxing.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::flv::VideoPacket;
+
+pub const TAG_TYPE_AUDIO: u8 = 8;
+pub const TAG_TYPE_VIDEO: u8 = 9;
+/// Script data, e.g. `onMetaData`.
+pub const TAG_TYPE_SCRIPT: u8 = 18;
+
+/// Signature, version 1, audio + video flags, header size.
+const FLV_HEADER: [u8; 9] = [b'F', b'L', b'V', 1, 0x05, 0, 0, 0, 9];
+const FLV_TAG_HEADER_SIZE: usize = 11;
+/// Size of the PreviousTagSize field that follows the header and every tag.
+const PREVIOUS_TAG_SIZE: usize = 4;
+
+/// What an `FlvRecorder` keeps of a publish.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecorderOptions {
+    /// Record only keyframes (and the sequence header and other non-frame
+    /// video tags), for a sparse archive of the stream. Inter frames and
+    /// audio are left out.
+    pub keyframes_only: bool,
+}
+
+/// Writes a publish to an FLV file.
+pub struct FlvRecorder {
+    out: BufWriter<File>,
+    path: PathBuf,
+    /// Added to stream timestamps, so appended tags follow the file's last one.
+    timestamp_offset: u32,
+    options: RecorderOptions,
+}
+
+impl FlvRecorder {
+    /// Start a new recording at `path`, replacing any file already there.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write_header(&mut out)?;
+        Ok(Self {
+            out,
+            path: path.to_path_buf(),
+            timestamp_offset: 0,
+            options: RecorderOptions::default(),
+        })
+    }
+
+    /// Continue the recording at `path`, or start one if there's none.
+    /// Timestamps carry on from the file's last tag.
+    pub fn append(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let timestamp_offset = if file.metadata()?.len() == 0 {
+            write_header(&mut file)?;
+            0
+        } else {
+            last_timestamp(&mut file)?
+        };
+        Ok(Self {
+            out: BufWriter::new(file),
+            path: path.to_path_buf(),
+            timestamp_offset,
+            options: RecorderOptions::default(),
+        })
+    }
+
+    /// Replace the default `RecorderOptions`.
+    pub fn with_options(mut self, options: RecorderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record a video message, given how `flv::parse_video_data` parsed it.
+    pub fn write_video(
+        &mut self,
+        timestamp: u32,
+        data: &[u8],
+        packet: &VideoPacket,
+    ) -> io::Result<()> {
+        let inter_frame = matches!(
+            packet,
+            VideoPacket::NaluData {
+                is_keyframe: false,
+                ..
+            }
+        );
+        if self.options.keyframes_only && inter_frame {
+            return Ok(());
+        }
+        self.write_tag(TAG_TYPE_VIDEO, timestamp, data)
+    }
+
+    /// Record an audio message.
+    pub fn write_audio(&mut self, timestamp: u32, data: &[u8]) -> io::Result<()> {
+        if self.options.keyframes_only {
+            return Ok(());
+        }
+        self.write_tag(TAG_TYPE_AUDIO, timestamp, data)
+    }
+
+    /// Append one tag holding an RTMP message body.
+    pub fn write_tag(&mut self, tag_type: u8, timestamp: u32, data: &[u8]) -> io::Result<()> {
+        if data.len() > 0xFF_FFFF {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} byte message too large for an FLV tag", data.len()),
+            ));
+        }
+        let timestamp = timestamp.wrapping_add(self.timestamp_offset);
+        let size = (data.len() as u32).to_be_bytes();
+        let ts = timestamp.to_be_bytes();
+        let mut header = [0u8; FLV_TAG_HEADER_SIZE];
+        header[0] = tag_type;
+        header[1..4].copy_from_slice(&size[1..]);
+        // Lower 24 bits, then the extended byte; stream ID stays zero
+        header[4..7].copy_from_slice(&ts[1..]);
+        header[7] = ts[0];
+        self.out.write_all(&header)?;
+        self.out.write_all(data)?;
+        self.out
+            .write_all(&((FLV_TAG_HEADER_SIZE + data.len()) as u32).to_be_bytes())
+    }
+
+    /// Flush buffered tags to the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn write_header(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(&FLV_HEADER)?;
+    // PreviousTagSize0
+    out.write_all(&[0; PREVIOUS_TAG_SIZE])
+}
+
+/// Timestamp of the last tag in an FLV file, found through the trailing
+/// PreviousTagSize. A file with only a header yields 0.
+fn last_timestamp(file: &mut File) -> io::Result<u32> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a complete FLV file");
+    let len = file.metadata()?.len();
+    if len < (FLV_HEADER.len() + PREVIOUS_TAG_SIZE) as u64 {
+        return Err(invalid());
+    }
+    let mut word = [0u8; 4];
+    file.seek(SeekFrom::Start(len - PREVIOUS_TAG_SIZE as u64))?;
+    file.read_exact(&mut word)?;
+    let tag_size = u32::from_be_bytes(word) as u64;
+    if tag_size == 0 {
+        return Ok(0);
+    }
+    let tag_start = (len - PREVIOUS_TAG_SIZE as u64)
+        .checked_sub(tag_size)
+        .filter(|&start| start >= (FLV_HEADER.len() + PREVIOUS_TAG_SIZE) as u64)
+        .ok_or_else(invalid)?;
+    file.seek(SeekFrom::Start(tag_start + 4))?;
+    file.read_exact(&mut word)?;
+    Ok(u32::from_be_bytes([word[3], word[0], word[1], word[2]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flv::{nalu_tag, parse_video_data};
+    use bytes::Bytes;
+    use crate::http_flv::FlvTagReader;
+
+    fn read_tags(path: &Path) -> Vec<(u8, u32, Vec<u8>)> {
+        FlvTagReader::new()
+            .push(&std::fs::read(path).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|tag| (tag.tag_type, tag.timestamp, tag.data.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_record_then_append() {
+        let path = std::env::temp_dir().join(format!("rtmp-record-{}.flv", std::process::id()));
+
+        let mut recorder = FlvRecorder::create(&path).unwrap();
+        recorder.write_tag(TAG_TYPE_VIDEO, 0, &[0x17, 0]).unwrap();
+        recorder
+            .write_tag(TAG_TYPE_AUDIO, 0x0100_0010, &[0xAF])
+            .unwrap();
+        recorder.finish().unwrap();
+        assert_eq!(
+            read_tags(&path),
+            [
+                (TAG_TYPE_VIDEO, 0, vec![0x17, 0]),
+                (TAG_TYPE_AUDIO, 0x0100_0010, vec![0xAF]),
+            ]
+        );
+
+        // The appended publish restarts at 0 but lands after the last tag
+        let mut recorder = FlvRecorder::append(&path).unwrap();
+        recorder.write_tag(TAG_TYPE_VIDEO, 33, &[0x27]).unwrap();
+        recorder.finish().unwrap();
+        let tags = read_tags(&path);
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[2], (TAG_TYPE_VIDEO, 0x0100_0010 + 33, vec![0x27]));
+
+        // Recording again starts over
+        FlvRecorder::create(&path).unwrap().finish().unwrap();
+        assert!(read_tags(&path).is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_keyframes_only() {
+        let path = std::env::temp_dir().join(format!("rtmp-keyframes-{}.flv", std::process::id()));
+        let options = RecorderOptions {
+            keyframes_only: true,
+        };
+        let mut recorder = FlvRecorder::create(&path).unwrap().with_options(options);
+        let header = Bytes::from_static(&[
+            0x17, 0x00, 0x00, 0x00, 0x00, 0x01, 0x42, 0xC0, 0x0A, 0xFF, 0xE1, 0x00, 0x02, 0x67,
+            0x42, 0x01, 0x00, 0x02, 0x68, 0xCE,
+        ]);
+        let idr = nalu_tag(&[0x00, 0x00, 0x00, 0x02, 0x65, 0x88], true);
+        let p_frame = nalu_tag(&[0x00, 0x00, 0x00, 0x02, 0x41, 0x9A], false);
+        for (timestamp, tag) in [(0, &header), (0, &idr), (33, &p_frame), (66, &idr)] {
+            let packet = parse_video_data(tag, timestamp, 4);
+            recorder.write_video(timestamp, tag, &packet).unwrap();
+        }
+        recorder.write_audio(10, &[0xAF, 0x01]).unwrap();
+        recorder.finish().unwrap();
+
+        assert_eq!(
+            read_tags(&path),
+            [
+                (TAG_TYPE_VIDEO, 0, header.to_vec()),
+                (TAG_TYPE_VIDEO, 0, idr.to_vec()),
+                (TAG_TYPE_VIDEO, 66, idr.to_vec()),
+            ]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_append_to_missing_or_damaged_file() {
+        let path = std::env::temp_dir().join(format!("rtmp-append-{}.flv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut recorder = FlvRecorder::append(&path).unwrap();
+        recorder.write_tag(TAG_TYPE_VIDEO, 5, &[0x17]).unwrap();
+        recorder.finish().unwrap();
+        assert_eq!(read_tags(&path), [(TAG_TYPE_VIDEO, 5, vec![0x17])]);
+
+        std::fs::write(&path, b"FLV\x01").unwrap();
+        assert!(FlvRecorder::append(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+//! Re-publishes an ingested stream to another RTMP server without decoding.
+
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+
+use bytes::Bytes;
+use rml_rtmp::handshake::{Handshake, HandshakeProcessResult, PeerType};
+use rml_rtmp::sessions::{
+    ClientSession, ClientSessionConfig, ClientSessionEvent, ClientSessionResult, PublishRequestType,
+};
+use rml_rtmp::time::RtmpTimestamp;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use crate::flv::{self, AvcDecoderConfig};
+use crate::session::VideoSink;
+
+/// Video messages queued for the downstream connection before frames are dropped.
+const RELAY_QUEUE_CAPACITY: usize = 256;
+
+/// Wait before the first reconnect to a lost downstream; doubled after each
+/// failed attempt up to `RELAY_MAX_BACKOFF`.
+const RELAY_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const RELAY_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Frames held while the downstream is away, about two seconds at 30 fps.
+/// A longer gap keeps only what follows the next keyframe.
+const RELAY_GAP_FRAMES: usize = 60;
+
+enum RelayMessage {
+    Config(AvcDecoderConfig),
+    Video(Bytes, u32),
+}
+
+/// VideoSink that forwards the stream to a downstream RTMP server.
+///
+/// The sequence header and each AVCC payload are re-wrapped as FLV video tags
+/// and published on a client connection owned by a background task. If the
+/// downstream falls behind, frames are dropped rather than stalling ingest.
+///
+/// If the downstream connection is lost, the task reconnects with
+/// exponential backoff (`RELAY_INITIAL_BACKOFF` to `RELAY_MAX_BACKOFF`),
+/// holding up to `RELAY_GAP_FRAMES` frames from the last keyframe meanwhile.
+/// Each new connection gets the sequence header before any frames. A
+/// downstream that rejects the publish is not retried.
+pub struct RelaySink {
+    tx: mpsc::Sender<RelayMessage>,
+    dropped: u64,
+}
+
+impl RelaySink {
+    /// Start relaying to `addr` ("host:port"), publishing as `app`/`stream_key`.
+    /// Must be called from within a tokio runtime. The connection is made in
+    /// the background; frames sent before it is up are queued.
+    pub fn connect(addr: &str, app: &str, stream_key: &str) -> Self {
+        let (tx, rx) = mpsc::channel(RELAY_QUEUE_CAPACITY);
+        let addr = addr.to_string();
+        let app = app.to_string();
+        let stream_key = stream_key.to_string();
+        tokio::spawn(async move {
+            match relay(&addr, &app, &stream_key, rx).await {
+                Ok(()) => info!(addr, app, stream_key, "relay finished"),
+                Err(e) => error!(addr, app, stream_key, %e, "relay failed"),
+            }
+        });
+        Self { tx, dropped: 0 }
+    }
+
+    fn send(&mut self, message: RelayMessage) {
+        match self.tx.try_send(message) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.dropped += 1;
+                if self.dropped.is_power_of_two() {
+                    warn!(
+                        dropped = self.dropped,
+                        "relay falling behind, dropping frames"
+                    );
+                }
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+impl VideoSink for RelaySink {
+    fn on_decoder_config(&mut self, config: AvcDecoderConfig) {
+        self.send(RelayMessage::Config(config));
+    }
+
+    fn on_video_data(&mut self, data: Bytes, timestamp: u32) {
+        self.send(RelayMessage::Video(data, timestamp));
+    }
+}
+
+/// Connection to the downstream server.
+struct Downstream {
+    stream: TcpStream,
+    session: ClientSession,
+    buf: Vec<u8>,
+}
+
+impl Downstream {
+    async fn connect(addr: &str) -> io::Result<Self> {
+        let mut stream = TcpStream::connect(addr).await?;
+
+        let mut handshake = Handshake::new(PeerType::Client);
+        let p0_p1 = handshake
+            .generate_outbound_p0_and_p1()
+            .map_err(|e| relay_error("handshake", e))?;
+        stream.write_all(&p0_p1).await?;
+
+        let mut buf = vec![0u8; 4096];
+        let remaining = loop {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed during handshake",
+                ));
+            }
+            match handshake
+                .process_bytes(&buf[..n])
+                .map_err(|e| relay_error("handshake", e))?
+            {
+                HandshakeProcessResult::InProgress { response_bytes } => {
+                    stream.write_all(&response_bytes).await?;
+                }
+                HandshakeProcessResult::Completed {
+                    response_bytes,
+                    remaining_bytes,
+                } => {
+                    stream.write_all(&response_bytes).await?;
+                    break remaining_bytes;
+                }
+            }
+        };
+        debug!(addr, "relay handshake complete");
+
+        let (session, results) = ClientSession::new(ClientSessionConfig::new())
+            .map_err(|e| relay_error("session", e))?;
+        let mut downstream = Self {
+            stream,
+            session,
+            buf,
+        };
+        downstream.send(results).await?;
+        if !remaining.is_empty() {
+            downstream.handle_input(&remaining).await?;
+        }
+        Ok(downstream)
+    }
+
+    /// Connect to `addr` and start publishing as `app`/`stream_key`.
+    async fn publish(addr: &str, app: &str, stream_key: &str) -> io::Result<Self> {
+        let mut downstream = Self::connect(addr).await?;
+
+        let result = downstream
+            .session
+            .request_connection(app.to_string())
+            .map_err(|e| relay_error("connect", e))?;
+        downstream.send(vec![result]).await?;
+        downstream
+            .wait_for(ClientSessionEvent::ConnectionRequestAccepted)
+            .await?;
+
+        let result = downstream
+            .session
+            .request_publishing(stream_key.to_string(), PublishRequestType::Live)
+            .map_err(|e| relay_error("publish", e))?;
+        downstream.send(vec![result]).await?;
+        downstream
+            .wait_for(ClientSessionEvent::PublishRequestAccepted)
+            .await?;
+        Ok(downstream)
+    }
+
+    /// Write outbound packets and return raised events.
+    async fn send(
+        &mut self,
+        results: Vec<ClientSessionResult>,
+    ) -> io::Result<Vec<ClientSessionEvent>> {
+        let mut events = Vec::new();
+        for result in results {
+            match result {
+                ClientSessionResult::OutboundResponse(packet) => {
+                    self.stream.write_all(&packet.bytes).await?;
+                }
+                ClientSessionResult::RaisedEvent(event) => events.push(event),
+                ClientSessionResult::UnhandleableMessageReceived(_) => {}
+            }
+        }
+        self.stream.flush().await?;
+        Ok(events)
+    }
+
+    async fn handle_input(&mut self, data: &[u8]) -> io::Result<Vec<ClientSessionEvent>> {
+        let results = self
+            .session
+            .handle_input(data)
+            .map_err(|e| relay_error("session input", e))?;
+        self.send(results).await
+    }
+
+    /// Read from the server until `wanted` is raised.
+    async fn wait_for(&mut self, wanted: ClientSessionEvent) -> io::Result<()> {
+        loop {
+            let n = self.stream.read(&mut self.buf).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let data = self.buf[..n].to_vec();
+            for event in self.handle_input(&data).await? {
+                if let ClientSessionEvent::ConnectionRequestRejected { description } = &event {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("downstream rejected connection: {description}"),
+                    ));
+                }
+                if event == wanted {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn publish_video(&mut self, tag: Bytes, timestamp: u32) -> io::Result<()> {
+        let result = self
+            .session
+            .publish_video_data(tag, RtmpTimestamp::new(timestamp), false)
+            .map_err(|e| relay_error("publish video", e))?;
+        self.send(vec![result]).await?;
+        Ok(())
+    }
+}
+
+/// What carries over from one downstream connection to the next.
+struct RelayState {
+    /// Last sequence header, sent first on every connection.
+    config: Option<AvcDecoderConfig>,
+    nalu_length_size: u8,
+    /// Frames received while disconnected, starting at a keyframe.
+    gap: VecDeque<(Bytes, u32)>,
+    /// Drop frames until the next keyframe, since the downstream can't
+    /// decode from the middle of a GOP.
+    wait_for_keyframe: bool,
+}
+
+impl RelayState {
+    fn new() -> Self {
+        Self {
+            config: None,
+            nalu_length_size: 4,
+            gap: VecDeque::new(),
+            wait_for_keyframe: false,
+        }
+    }
+
+    fn set_config(&mut self, config: AvcDecoderConfig) {
+        self.nalu_length_size = config.nalu_length_size;
+        self.config = Some(config);
+    }
+
+    /// Whether a frame can go downstream after the ones before it.
+    fn accept(&mut self, data: &Bytes) -> bool {
+        if flv::is_keyframe(data, self.nalu_length_size) {
+            self.wait_for_keyframe = false;
+        }
+        !self.wait_for_keyframe
+    }
+
+    /// Hold a message that arrived while the downstream is away.
+    fn hold(&mut self, message: RelayMessage) {
+        match message {
+            RelayMessage::Config(config) => self.set_config(config),
+            RelayMessage::Video(data, timestamp) => {
+                if flv::is_keyframe(&data, self.nalu_length_size) {
+                    self.gap.clear();
+                } else if self.gap.len() >= RELAY_GAP_FRAMES {
+                    self.gap.clear();
+                    self.wait_for_keyframe = true;
+                }
+                if self.accept(&data) {
+                    self.gap.push_back((data, timestamp));
+                }
+            }
+        }
+    }
+
+    /// Hold the messages arriving over the next `delay`. Returns false if
+    /// the ingest ended meanwhile.
+    async fn hold_for(&mut self, delay: Duration, rx: &mut mpsc::Receiver<RelayMessage>) -> bool {
+        let sleep = tokio::time::sleep(delay);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => return true,
+                message = rx.recv() => match message {
+                    Some(message) => self.hold(message),
+                    None => return false,
+                },
+            }
+        }
+    }
+}
+
+async fn relay(
+    addr: &str,
+    app: &str,
+    stream_key: &str,
+    mut rx: mpsc::Receiver<RelayMessage>,
+) -> io::Result<()> {
+    let mut state = RelayState::new();
+    let mut backoff = RELAY_INITIAL_BACKOFF;
+    loop {
+        match Downstream::publish(addr, app, stream_key).await {
+            Ok(mut downstream) => {
+                info!(addr, app, stream_key, "relay publishing");
+                backoff = RELAY_INITIAL_BACKOFF;
+                match forward(&mut downstream, &mut state, &mut rx).await {
+                    Ok(()) => {
+                        // Ingest ended: unpublish cleanly
+                        let results = downstream
+                            .session
+                            .stop_publishing()
+                            .map_err(|e| relay_error("stop publishing", e))?;
+                        downstream.send(results).await?;
+                        return Ok(());
+                    }
+                    Err(e) => warn!(addr, %e, "relay connection lost"),
+                }
+                // Whatever follows the loss starts at the next keyframe
+                state.wait_for_keyframe = true;
+            }
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return Err(e),
+            Err(e) => warn!(addr, %e, "relay connection failed"),
+        }
+        info!(
+            addr,
+            retry_ms = backoff.as_millis() as u64,
+            "relay reconnecting"
+        );
+        if !state.hold_for(backoff, &mut rx).await {
+            return Ok(());
+        }
+        backoff = (backoff * 2).min(RELAY_MAX_BACKOFF);
+    }
+}
+
+/// Publish the stream on `downstream` until the ingest ends (`Ok`) or the
+/// connection fails. Starts with the sequence header and any frames held
+/// while disconnected.
+async fn forward(
+    downstream: &mut Downstream,
+    state: &mut RelayState,
+    rx: &mut mpsc::Receiver<RelayMessage>,
+) -> io::Result<()> {
+    if let Some(config) = &state.config {
+        downstream
+            .publish_video(flv::sequence_header_tag(config), 0)
+            .await?;
+    }
+    while let Some((data, timestamp)) = state.gap.pop_front() {
+        let keyframe = flv::is_keyframe(&data, state.nalu_length_size);
+        downstream
+            .publish_video(flv::nalu_tag(&data, keyframe), timestamp)
+            .await?;
+    }
+
+    loop {
+        tokio::select! {
+            message = rx.recv() => match message {
+                Some(RelayMessage::Config(config)) => {
+                    downstream.publish_video(flv::sequence_header_tag(&config), 0).await?;
+                    state.set_config(config);
+                }
+                Some(RelayMessage::Video(data, timestamp)) => {
+                    if !state.accept(&data) {
+                        continue;
+                    }
+                    let keyframe = flv::is_keyframe(&data, state.nalu_length_size);
+                    downstream.publish_video(flv::nalu_tag(&data, keyframe), timestamp).await?;
+                }
+                None => return Ok(()),
+            },
+            // Keep servicing acknowledgements and pings from the server
+            read = downstream.stream.read(&mut downstream.buf) => {
+                let n = read?;
+                if n == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "downstream closed the connection",
+                    ));
+                }
+                let data = downstream.buf[..n].to_vec();
+                downstream.handle_input(&data).await?;
+            }
+        }
+    }
+}
+
+fn relay_error(context: &str, e: impl std::fmt::Debug) -> io::Error {
+    io::Error::other(format!("relay {context} error: {e:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(nal_header: u8, timestamp: u32) -> RelayMessage {
+        RelayMessage::Video(Bytes::from(vec![0, 0, 0, 2, nal_header, 0x88]), timestamp)
+    }
+
+    fn held_timestamps(state: &RelayState) -> Vec<u32> {
+        state.gap.iter().map(|(_, timestamp)| *timestamp).collect()
+    }
+
+    #[test]
+    fn test_gap_starts_at_keyframe() {
+        let mut state = RelayState::new();
+        state.wait_for_keyframe = true;
+        state.hold(frame(0x41, 0));
+        assert!(state.gap.is_empty());
+        state.hold(frame(0x65, 33));
+        state.hold(frame(0x41, 66));
+        assert_eq!(held_timestamps(&state), [33, 66]);
+
+        // A newer keyframe makes the earlier frames unnecessary
+        state.hold(frame(0x65, 100));
+        assert_eq!(held_timestamps(&state), [100]);
+    }
+
+    #[test]
+    fn test_gap_overflow_waits_for_keyframe() {
+        let mut state = RelayState::new();
+        state.hold(frame(0x65, 0));
+        for i in 1..RELAY_GAP_FRAMES as u32 {
+            state.hold(frame(0x41, i));
+        }
+        assert_eq!(state.gap.len(), RELAY_GAP_FRAMES);
+
+        state.hold(frame(0x41, 1000));
+        assert!(state.gap.is_empty());
+        assert!(state.wait_for_keyframe);
+        state.hold(frame(0x65, 2000));
+        assert_eq!(held_timestamps(&state), [2000]);
+        assert!(!state.wait_for_keyframe);
+    }
+}
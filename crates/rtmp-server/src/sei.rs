@@ -0,0 +1,137 @@
+//! SEI (NAL type 6) parsing, so timecodes and captions embedded in the
+//! H.264 stream can be passed through to the sink before decoding drops them.
+
+use tracing::trace;
+use video_pipeline::nal::{AvccNalIter, NAL_TYPE_SEI};
+
+use crate::session::VideoSink;
+
+/// `pic_timing`: carries SMPTE clock timestamps. Interpreting them requires
+/// the SPS VUI/HRD parameters, so the payload is passed through as-is.
+pub const SEI_PIC_TIMING: u32 = 1;
+/// `user_data_registered_itu_t_t35`: carries CEA-608/708 closed captions.
+pub const SEI_USER_DATA_REGISTERED: u32 = 4;
+
+/// One SEI message with its payload (emulation prevention bytes removed).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeiMessage {
+    pub payload_type: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Parse the messages of an SEI NAL unit (including its one-byte header).
+/// Stops at the RBSP trailing bits or at the first truncated message.
+pub fn parse_sei(nal: &[u8]) -> Vec<SeiMessage> {
+    let rbsp = unescape_rbsp(nal.get(1..).unwrap_or_default());
+    let mut messages = Vec::new();
+    let mut pos = 0;
+
+    // A lone 0x80 left is the rbsp_stop_one_bit plus alignment
+    while pos < rbsp.len() && rbsp[pos..] != [0x80] {
+        let Some(payload_type) = read_ff_coded(&rbsp, &mut pos) else {
+            break;
+        };
+        let Some(size) = read_ff_coded(&rbsp, &mut pos) else {
+            break;
+        };
+        let Some(payload) = rbsp.get(pos..pos + size as usize) else {
+            trace!(payload_type, size, "truncated SEI message");
+            break;
+        };
+        messages.push(SeiMessage {
+            payload_type,
+            payload: payload.to_vec(),
+        });
+        pos += size as usize;
+    }
+    messages
+}
+
+/// Pass the SEI messages sinks care about (`SEI_PIC_TIMING`,
+/// `SEI_USER_DATA_REGISTERED`) from an AVCC payload to `sink`.
+pub(crate) fn forward_sei(sink: &mut dyn VideoSink, avcc: &[u8], nalu_length_size: u8) {
+    for (nal_type, nal) in AvccNalIter::new(avcc, nalu_length_size) {
+        if nal_type != NAL_TYPE_SEI {
+            continue;
+        }
+        for message in parse_sei(nal) {
+            if matches!(
+                message.payload_type,
+                SEI_PIC_TIMING | SEI_USER_DATA_REGISTERED
+            ) {
+                sink.on_sei(message.payload_type, &message.payload);
+            }
+        }
+    }
+}
+
+/// payloadType / payloadSize encoding: a run of 0xFF bytes each adding 255,
+/// then a final byte.
+fn read_ff_coded(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut value = 0u32;
+    loop {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value = value.checked_add(byte as u32)?;
+        if byte != 0xFF {
+            return Some(value);
+        }
+    }
+}
+
+/// Remove emulation prevention bytes (00 00 03 -> 00 00).
+fn unescape_rbsp(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sei_messages() {
+        let nal = [
+            0x06, // SEI header
+            0x01, 0x04, 0xAA, 0x00, 0x00, 0x03, 0x01, // pic_timing, escaped 00 00 03 01
+            0x04, 0x02, 0xB5, 0x00, // user_data_registered
+            0x80, // trailing bits
+        ];
+        let messages = parse_sei(&nal);
+        assert_eq!(
+            messages,
+            vec![
+                SeiMessage {
+                    payload_type: SEI_PIC_TIMING,
+                    payload: vec![0xAA, 0x00, 0x00, 0x01],
+                },
+                SeiMessage {
+                    payload_type: SEI_USER_DATA_REGISTERED,
+                    payload: vec![0xB5, 0x00],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_sei_large_type_and_truncation() {
+        // payloadType 255 + 5 = 260, size 4 but only 2 bytes present
+        let nal = [0x06, 0xFF, 0x05, 0x04, 0x01, 0x02];
+        assert!(parse_sei(&nal).is_empty());
+
+        let nal = [0x06, 0xFF, 0x05, 0x01, 0x09, 0x80];
+        let messages = parse_sei(&nal);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload_type, 260);
+        assert_eq!(messages[0].payload, [0x09]);
+    }
+}
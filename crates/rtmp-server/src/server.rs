@@ -1,37 +1,392 @@
+use std::future::Future;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::io::AsyncReadExt;
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{error, info, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
 
+use crate::bitrate::IngestMeter;
+use crate::error::RtmpError;
 use crate::handshake::HandshakeState;
-use crate::session::{RtmpSession, VideoSink};
+use crate::publishers::{ConnectionInfo, PublisherRegistry};
+use crate::rate_limit::ConnectionRateLimiter;
+use crate::recording::RecorderOptions;
+use crate::session::{ConnectionContext, RtmpSession, SinkFactory, VideoSink};
+use crate::shutdown::Shutdown;
+use crate::tls::TlsConfig;
+
+/// How long `Server::shutdown` waits for open connections to close before
+/// aborting them.
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Bytes read from a connection at a time. Independent of the client's
+/// chunk size: rml_rtmp applies its Set Chunk Size messages and reassembles
+/// chunks and messages spread over any number of reads.
+const READ_BUFFER_SIZE: usize = 4096;
 
 /// Start the RTMP server on the given address.
-/// Calls `sink_factory` for each new connection to get a VideoSink.
+/// Calls `sink_factory` with the peer address, app name and stream key of
+/// each accepted publish to get a VideoSink for it; an error from the
+/// factory rejects the publish.
 /// If `stream_key` is `Some`, only clients publishing with that key are accepted.
-pub async fn run<F>(addr: SocketAddr, sink_factory: F, stream_key: Option<String>) -> io::Result<()>
+pub async fn run<F>(
+    addr: SocketAddr,
+    sink_factory: F,
+    stream_key: Option<String>,
+) -> Result<(), RtmpError>
 where
-    F: Fn() -> Box<dyn VideoSink> + Send + Sync + 'static,
+    F: Fn(&ConnectionContext) -> io::Result<Box<dyn VideoSink>> + Send + Sync + 'static,
 {
-    let listener = TcpListener::bind(addr).await?;
-    if stream_key.is_some() {
-        info!(%addr, "RTMP server listening (stream key required)");
-    } else {
-        info!(%addr, "RTMP server listening (no stream key — accepting all)");
+    Server::new().run(addr, sink_factory, stream_key).await
+}
+
+/// Start the RTMP server on a Unix domain socket at `path`, for local-only
+/// ingest. See [`run`] for the meaning of the other arguments.
+pub async fn run_unix<F>(
+    path: impl AsRef<Path>,
+    sink_factory: F,
+    stream_key: Option<String>,
+) -> Result<(), RtmpError>
+where
+    F: Fn(&ConnectionContext) -> io::Result<Box<dyn VideoSink>> + Send + Sync + 'static,
+{
+    Server::new().run_unix(path, sink_factory, stream_key).await
+}
+
+/// A TCP address for [`Server::serve`] to accept connections on.
+#[derive(Debug, Clone)]
+pub enum Listener {
+    /// Plain RTMP.
+    Tcp(SocketAddr),
+    /// RTMPS: RTMP inside TLS, presenting the certificate in the config.
+    Tls(SocketAddr, TlsConfig),
+}
+
+impl Listener {
+    pub fn addr(&self) -> SocketAddr {
+        match self {
+            Listener::Tcp(addr) | Listener::Tls(addr, _) => *addr,
+        }
+    }
+}
+
+/// RTMP server with state that can be queried while it runs.
+///
+/// Cloning yields another handle to the same server state.
+#[derive(Debug, Clone, Default)]
+pub struct Server {
+    publishers: PublisherRegistry,
+    app_allowlist: Option<Arc<[String]>>,
+    rate_limiter: ConnectionRateLimiter,
+    dual_stack: bool,
+    recording_dir: Option<Arc<Path>>,
+    recorder_options: RecorderOptions,
+    shutdown: Shutdown,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    loop {
-        let (stream, peer_addr) = listener.accept().await?;
-        info!(%peer_addr, "new connection");
+    /// Only accept connections to these RTMP app names (e.g. "live");
+    /// others are rejected at connect. By default any app name is accepted.
+    pub fn with_app_allowlist<I, S>(mut self, apps: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.app_allowlist = Some(apps.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Allow each source IP `burst` TCP connections within any `window`;
+    /// connections beyond that are dropped on accept. Defaults to
+    /// `CONNECTION_BURST` per `CONNECTION_RATE_WINDOW`.
+    pub fn with_connection_rate_limit(mut self, burst: u32, window: Duration) -> Self {
+        self.rate_limiter = ConnectionRateLimiter::new(burst, window);
+        self
+    }
+
+    /// Clear `IPV6_V6ONLY` when listening on an IPv6 address, so a single
+    /// listener on `[::]` also accepts IPv4 clients (as IPv4-mapped
+    /// addresses). Otherwise the OS default applies, which varies.
+    pub fn with_dual_stack(mut self, enabled: bool) -> Self {
+        self.dual_stack = enabled;
+        self
+    }
+
+    /// Record publishes made in RTMP `record` or `append` mode to
+    /// `<stream key>.flv` files in `dir`. Without this every publish is
+    /// treated as `live`.
+    pub fn with_recording_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.recording_dir = Some(dir.as_ref().into());
+        self
+    }
+
+    /// Options for the recordings made under `with_recording_dir`, such as
+    /// keeping only keyframes.
+    pub fn with_recorder_options(mut self, options: RecorderOptions) -> Self {
+        self.recorder_options = options;
+        self
+    }
+
+    /// Stream keys currently being published, with their connection details
+    /// and ingest bitrate.
+    pub fn active_publishers(&self) -> Vec<(String, ConnectionInfo)> {
+        self.publishers.snapshot()
+    }
+
+    /// Ask the publisher of `stream_key` for a keyframe so a newly attached
+    /// consumer needn't wait for the next scheduled one. Returns false if
+    /// nothing is publishing with that key. See
+    /// [`PublisherRegistry::request_keyframe`].
+    pub fn request_keyframe(&self, stream_key: &str) -> bool {
+        self.publishers.request_keyframe(stream_key)
+    }
+
+    /// Stop accepting connections and close the open ones: each stops
+    /// reading, ends its publish and returns. `run` and `run_unix` then
+    /// return `Ok(())` once they're closed, or after `SHUTDOWN_GRACE`. The
+    /// server can't be run again afterwards.
+    pub fn shutdown(&self) {
+        info!("RTMP server shutting down");
+        self.shutdown.trigger();
+    }
+
+    /// Accept connections on `addr` until an I/O error occurs or
+    /// `shutdown` is called. See [`run`] for the meaning of the arguments.
+    pub async fn run<F>(
+        &self,
+        addr: SocketAddr,
+        sink_factory: F,
+        stream_key: Option<String>,
+    ) -> Result<(), RtmpError>
+    where
+        F: Fn(&ConnectionContext) -> io::Result<Box<dyn VideoSink>> + Send + Sync + 'static,
+    {
+        self.run_all(&[addr], sink_factory, stream_key).await
+    }
 
-        let mut sink = sink_factory();
+    /// Accept plain RTMP connections on all of `addrs` at once. See
+    /// [`Server::serve`].
+    pub async fn run_all<F>(
+        &self,
+        addrs: &[SocketAddr],
+        sink_factory: F,
+        stream_key: Option<String>,
+    ) -> Result<(), RtmpError>
+    where
+        F: Fn(&ConnectionContext) -> io::Result<Box<dyn VideoSink>> + Send + Sync + 'static,
+    {
+        let listeners: Vec<Listener> = addrs.iter().map(|&addr| Listener::Tcp(addr)).collect();
+        self.serve(&listeners, sink_factory, stream_key).await
+    }
+
+    /// Accept connections on all of `listeners` at once, e.g. RTMP on one
+    /// port and RTMPS on another, with one sink factory and server state
+    /// shared between them. Every address is bound before any connection
+    /// is accepted, so one that is in use fails the call. Runs until
+    /// `shutdown` is called or an I/O error on any listener, which stops
+    /// the others too. A failed TLS handshake only closes that connection.
+    pub async fn serve<F>(
+        &self,
+        listeners: &[Listener],
+        sink_factory: F,
+        stream_key: Option<String>,
+    ) -> Result<(), RtmpError>
+    where
+        F: Fn(&ConnectionContext) -> io::Result<Box<dyn VideoSink>> + Send + Sync + 'static,
+    {
+        if listeners.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no listen address").into());
+        }
+        let sink_factory: SinkFactory = Arc::new(sink_factory);
+        let bound = listeners
+            .iter()
+            .map(|listener| self.bind_tcp(listener.addr()))
+            .collect::<io::Result<Vec<_>>>()?;
+        for listener in listeners {
+            let addr = listener.addr();
+            let tls = matches!(listener, Listener::Tls(..));
+            if stream_key.is_some() {
+                info!(%addr, tls, "RTMP server listening (stream key required)");
+            } else {
+                info!(%addr, tls, "RTMP server listening (no stream key — accepting all)");
+            }
+        }
+
+        let mut accept_loops = JoinSet::new();
+        for (tcp, listener) in bound.into_iter().zip(listeners) {
+            let server = self.clone();
+            let tls = match listener {
+                Listener::Tcp(_) => None,
+                Listener::Tls(_, config) => Some(config.clone()),
+            };
+            let sink_factory = Arc::clone(&sink_factory);
+            let stream_key = stream_key.clone();
+            accept_loops.spawn(async move {
+                server.accept_tcp(tcp, tls, sink_factory, stream_key).await
+            });
+        }
+        while let Some(result) = accept_loops.join_next().await {
+            result.map_err(io::Error::other)??;
+        }
+        Ok(())
+    }
+
+    /// Accept connections on `listener` until an I/O error occurs or
+    /// `shutdown` is called, then wait for them to close. With `tls`, each
+    /// connection does a TLS handshake in its own task before RTMP's.
+    async fn accept_tcp(
+        &self,
+        listener: TcpListener,
+        tls: Option<TlsConfig>,
+        sink_factory: SinkFactory,
+        stream_key: Option<String>,
+    ) -> io::Result<()> {
+        let mut connections = JoinSet::new();
+        loop {
+            let (stream, peer_addr) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                Some(_) = connections.join_next() => continue,
+                _ = self.shutdown.wait() => break,
+            };
+            // Report IPv4 clients of a dual-stack listener as plain IPv4
+            let peer_addr = SocketAddr::new(peer_addr.ip().to_canonical(), peer_addr.port());
+            if !self.rate_limiter.check(peer_addr.ip()) {
+                debug!(%peer_addr, "connection rate limit exceeded, dropping");
+                continue;
+            }
+            info!(%peer_addr, "new connection");
+            match &tls {
+                None => self.spawn_connection(
+                    &mut connections,
+                    std::future::ready(Ok(stream)),
+                    peer_addr,
+                    &sink_factory,
+                    &stream_key,
+                ),
+                Some(tls) => self.spawn_connection(
+                    &mut connections,
+                    tls.acceptor().accept(stream),
+                    peer_addr,
+                    &sink_factory,
+                    &stream_key,
+                ),
+            }
+        }
+        drain_connections(connections).await;
+        Ok(())
+    }
+
+    /// Bind a TCP listener on `addr`, set up as `TcpListener::bind` would
+    /// but with `IPV6_V6ONLY` cleared if dual-stack is enabled.
+    fn bind_tcp(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_reuse_address(true)?;
+        if self.dual_stack && addr.is_ipv6() {
+            socket.set_only_v6(false)?;
+        }
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        TcpListener::from_std(socket.into())
+    }
+
+    /// Accept connections on a Unix domain socket at `path` until an I/O
+    /// error occurs or `shutdown` is called. A stale socket file left by a
+    /// previous run is replaced.
+    ///
+    /// Unix peers have no network address; they are reported as 127.0.0.1
+    /// with a per-connection number in place of the port.
+    pub async fn run_unix<F>(
+        &self,
+        path: impl AsRef<Path>,
+        sink_factory: F,
+        stream_key: Option<String>,
+    ) -> Result<(), RtmpError>
+    where
+        F: Fn(&ConnectionContext) -> io::Result<Box<dyn VideoSink>> + Send + Sync + 'static,
+    {
+        let path = path.as_ref();
+        let sink_factory: SinkFactory = Arc::new(sink_factory);
+        if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        info!(path = %path.display(), "RTMP server listening on Unix socket");
+
+        let mut connection_id: u16 = 0;
+        let mut connections = JoinSet::new();
+        loop {
+            let (stream, _) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                Some(_) = connections.join_next() => continue,
+                _ = self.shutdown.wait() => break,
+            };
+            connection_id = connection_id.wrapping_add(1);
+            let peer_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, connection_id));
+            info!(%peer_addr, "new Unix socket connection");
+            self.spawn_connection(
+                &mut connections,
+                std::future::ready(Ok(stream)),
+                peer_addr,
+                &sink_factory,
+                &stream_key,
+            );
+        }
+        drain_connections(connections).await;
+        Ok(())
+    }
+
+    /// Handle a connection in a task of its own. `stream` resolves to the
+    /// connection once any TLS handshake is done.
+    fn spawn_connection<S>(
+        &self,
+        connections: &mut JoinSet<()>,
+        stream: impl Future<Output = io::Result<S>> + Send + 'static,
+        peer_addr: SocketAddr,
+        sink_factory: &SinkFactory,
+        stream_key: &Option<String>,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let factory = Arc::clone(sink_factory);
         let key = stream_key.clone();
-        tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, peer_addr, &mut *sink, key).await {
-                if e.kind() == io::ErrorKind::PermissionDenied {
-                    warn!(%peer_addr, "connection rejected: {e}");
+        let apps = self.app_allowlist.clone();
+        let publishers = self.publishers.clone();
+        let recording_dir = self.recording_dir.clone();
+        let recorder_options = self.recorder_options;
+        let shutdown = self.shutdown.clone();
+        connections.spawn(async move {
+            let result = match stream.await {
+                Ok(stream) => {
+                    handle_connection(
+                        stream,
+                        peer_addr,
+                        factory,
+                        key,
+                        apps,
+                        publishers,
+                        recording_dir,
+                        recorder_options,
+                        shutdown,
+                    )
+                    .await
+                }
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+                if let RtmpError::Auth(reason) = &e {
+                    warn!(%peer_addr, "connection rejected: {reason}");
                 } else {
                     error!(%peer_addr, %e, "connection error");
                 }
@@ -41,23 +396,45 @@ where
     }
 }
 
-async fn handle_connection(
-    mut stream: TcpStream,
+/// Wait up to `SHUTDOWN_GRACE` for connection tasks to finish after a
+/// shutdown, then abort the rest.
+async fn drain_connections(mut connections: JoinSet<()>) {
+    let open = connections.len();
+    let drained = async { while connections.join_next().await.is_some() {} };
+    if tokio::time::timeout(SHUTDOWN_GRACE, drained).await.is_err() {
+        warn!(
+            remaining = connections.len(),
+            "connections still open after shutdown grace period, aborting"
+        );
+        connections.shutdown().await;
+    }
+    info!(connections = open, "RTMP server stopped");
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
     peer_addr: SocketAddr,
-    sink: &mut dyn VideoSink,
+    sink_factory: SinkFactory,
     stream_key: Option<String>,
-) -> io::Result<()> {
-    let mut buf = vec![0u8; 4096];
+    app_allowlist: Option<Arc<[String]>>,
+    publishers: PublisherRegistry,
+    recording_dir: Option<Arc<Path>>,
+    recorder_options: RecorderOptions,
+    shutdown: Shutdown,
+) -> Result<(), RtmpError> {
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    let ingest = IngestMeter::new();
 
     // Phase 1: RTMP Handshake
     let mut handshake = HandshakeState::new();
     let remaining = loop {
         let n = stream.read(&mut buf).await?;
         if n == 0 {
-            return Err(io::Error::new(
+            return Err(RtmpError::Io(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
                 "connection closed during handshake",
-            ));
+            )));
         }
 
         let (response, maybe_remaining) = handshake.process(&buf[..n])?;
@@ -68,29 +445,57 @@ async fn handle_connection(
         }
 
         if let Some(remaining) = maybe_remaining {
-            info!(%peer_addr, "handshake complete");
+            info!(%peer_addr, mode = ?handshake.mode(), "handshake complete");
             break remaining;
         }
     };
 
     // Phase 2: RTMP Session
-    let mut session = RtmpSession::new(&mut stream, stream_key).await?;
+    let mut session = RtmpSession::new(
+        &mut stream,
+        peer_addr,
+        stream_key,
+        app_allowlist,
+        publishers,
+        sink_factory,
+        recording_dir,
+    )
+    .await?
+    .with_recorder_options(recorder_options)
+    .with_ingest_meter(ingest.clone());
 
     // Process any leftover bytes from the handshake
     if !remaining.is_empty() {
-        session
-            .handle_input(&remaining, &mut stream, sink)
-            .await?;
+        ingest.record(remaining.len());
+        session.handle_input(&remaining, &mut stream).await?;
     }
 
-    // Main read loop
-    loop {
-        let n = stream.read(&mut buf).await?;
-        if n == 0 {
-            break;
+    // Main read loop, until the client leaves or the server shuts down
+    let result = loop {
+        let read = tokio::select! {
+            read = stream.read(&mut buf) => read,
+            _ = shutdown.wait() => {
+                info!(%peer_addr, "server shutting down, closing connection");
+                break Ok(());
+            }
+        };
+        let n = match read {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(e) => break Err(e.into()),
+        };
+        ingest.record(n);
+        if let Err(e) = session.handle_input(&buf[..n], &mut stream).await {
+            break Err(e);
         }
-        session.handle_input(&buf[..n], &mut stream, sink).await?;
-    }
+        if session.close_requested() {
+            break Ok(());
+        }
+    };
+
+    // Tear down the publish if the client vanished without closing the stream
+    session.end_publish();
+    debug!(%peer_addr, messages = %session.message_counts(), "RTMP messages received");
 
-    Ok(())
+    result
 }
@@ -1,41 +1,178 @@
 use bytes::Bytes;
+use rml_rtmp::amf0::{self, Amf0Value};
+use rml_rtmp::messages::{MessagePayload, RtmpMessage};
 use rml_rtmp::sessions::{
-    ServerSession, ServerSessionConfig, ServerSessionEvent, ServerSessionResult,
+    PublishMode, ServerSession, ServerSessionConfig, ServerSessionEvent, ServerSessionResult,
 };
-use std::io;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
+use rml_rtmp::time::RtmpTimestamp;
+use std::collections::HashMap;
+use std::io::{self, Cursor};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tracing::{debug, info, trace, warn};
+#[cfg(feature = "audio-level")]
+use video_pipeline::audio::AacLevelMeter;
 
+use crate::bitrate::IngestMeter;
+use crate::error::RtmpError;
+#[cfg(feature = "audio-level")]
+use crate::flv::AudioPacket;
 use crate::flv::{self, AvcDecoderConfig, VideoPacket};
+use crate::message_counts::{
+    MessageCounter, MessageCounts, TYPE_ID_ACKNOWLEDGEMENT, TYPE_ID_AGGREGATE, TYPE_ID_AMF0_COMMAND,
+    TYPE_ID_AMF0_DATA, TYPE_ID_AUDIO, TYPE_ID_SET_CHUNK_SIZE, TYPE_ID_USER_CONTROL, TYPE_ID_VIDEO,
+};
+use crate::metadata::{ColorInfo, StreamInfo, VideoCodec};
+use crate::publishers::PublisherRegistry;
+use crate::recording::{FlvRecorder, RecorderOptions, TAG_TYPE_AUDIO, TAG_TYPE_VIDEO};
+use crate::sei;
 
 /// Callback for receiving decoded video data from the RTMP session.
 pub trait VideoSink: Send + 'static {
     /// Called when an AVC sequence header (SPS/PPS) is received.
     fn on_decoder_config(&mut self, config: AvcDecoderConfig);
 
+    /// Called right after the sink is created, before any stream data, with
+    /// the query parameters of the URL the publisher connected to, e.g.
+    /// `token` for `rtmp://host/live?token=xyz`. Not called when there are
+    /// none.
+    fn on_connect_params(&mut self, _params: HashMap<String, String>) {}
+
+    /// Called when the publisher sends an AVC end of sequence, which some
+    /// encoders do before reconfiguring. Decoding sinks should flush and
+    /// drop their decoder, so the next sequence header starts afresh with no
+    /// reference frames carried over.
+    fn on_end_of_sequence(&mut self) {}
+
     /// Called with AVCC-framed NAL units for a single video frame.
     /// Data is already in AVCC format: [4-byte len][NAL1][4-byte len][NAL2]...
     fn on_video_data(&mut self, data: Bytes, timestamp: u32);
+
+    /// Called when the publisher sends (or updates) its `onMetaData`.
+    fn on_stream_info(&mut self, _info: StreamInfo) {}
+
+    /// Called when an enhanced-RTMP publisher describes the stream's color
+    /// (HDR) properties. Repeats of the same description aren't passed on.
+    fn on_color_info(&mut self, _info: ColorInfo) {}
+
+    /// Called with the RMS and peak level, in dBFS, of each AAC frame the
+    /// publisher sends, for a VU meter. Only raised with the `audio-level`
+    /// feature, which decodes the audio just to measure it.
+    fn on_audio_level(&mut self, _rms_db: f32, _peak_db: f32) {}
+
+    /// Called with SEI messages found in the video before it's decoded:
+    /// `sei::SEI_PIC_TIMING` (timecodes) and `sei::SEI_USER_DATA_REGISTERED`
+    /// (closed captions). `data` is the raw message payload.
+    fn on_sei(&mut self, _payload_type: u32, _data: &[u8]) {}
+
+    /// Called once per publish, when the first frame has been decoded and is
+    /// ready to display. The session itself doesn't decode, so this is raised
+    /// by decoding sinks once their decoder reports a frame.
+    fn on_first_frame(&mut self, _width: usize, _height: usize) {}
+
+    /// Called when a decoder can't be created for the stream's sequence
+    /// header, after which the publish produces no frames. Like
+    /// `on_first_frame`, this is raised by decoding sinks, and sinks that
+    /// wrap another forward it. `status` is the OSStatus behind the
+    /// failure, or 0 if there was none.
+    fn on_decoder_create_error(&mut self, _status: i32, _message: &str) {}
+
+    /// Called when a decoding sink gives up on the stream, e.g. after too
+    /// many consecutive decode errors. It then reports `is_failed` and the
+    /// connection is closed. Like `on_first_frame`, this is raised by
+    /// decoding sinks.
+    fn on_stream_error(&mut self, _message: &str) {}
+
+    /// Whether the sink has given up on the stream. Checked after each video
+    /// frame; once true, the publish is ended and the connection closed.
+    fn is_failed(&self) -> bool {
+        false
+    }
+
+    /// Called when the publisher stops publishing (FCUnpublish, closeStream,
+    /// deleteStream) or the connection drops while a publish is active.
+    fn on_stream_end(&mut self) {}
+}
+
+/// The publish a `SinkFactory` is asked to create a sink for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionContext {
+    pub peer_addr: SocketAddr,
+    /// Empty for ingest protocols without app names (MPEG-TS, HTTP-FLV).
+    pub app_name: String,
+    pub stream_key: String,
+}
+
+/// Creates a VideoSink for each accepted publish, given who is publishing
+/// what. Returning an error rejects the publish.
+pub type SinkFactory =
+    Arc<dyn Fn(&ConnectionContext) -> io::Result<Box<dyn VideoSink>> + Send + Sync>;
+
+/// A publish accepted on this connection.
+struct ActivePublish {
+    stream_key: String,
+    sink: Box<dyn VideoSink>,
+    /// Last color description passed to the sink.
+    color_info: Option<ColorInfo>,
+    /// Set for `record` and `append` publishes when recording is enabled.
+    recorder: Option<FlvRecorder>,
+    /// Set up from the AAC sequence header.
+    #[cfg(feature = "audio-level")]
+    level_meter: Option<AacLevelMeter>,
 }
 
 /// Manages one RTMP publishing session.
 pub struct RtmpSession {
     session: ServerSession,
     allowed_key: Option<String>,
+    app_allowlist: Option<Arc<[String]>>,
+    peer_addr: SocketAddr,
+    publishers: PublisherRegistry,
+    sink_factory: SinkFactory,
+    recording_dir: Option<Arc<Path>>,
+    recorder_options: RecorderOptions,
+    /// Bytes received on the connection, reported through `publishers`.
+    ingest: IngestMeter,
+    /// Messages received on the connection by type, reported likewise.
+    messages: MessageCounter,
+    publishing: Option<ActivePublish>,
+    /// Query parameters of the app name the client connected with.
+    connect_params: HashMap<String, String>,
+    /// Set once the sink has failed and the connection should be closed.
+    close_requested: bool,
+    /// NAL length prefix size from the last sequence header (AVCC default: 4).
+    nalu_length_size: u8,
+    /// Chunk size ServerSession announced, which commands it has no API for
+    /// (keyframe requests) are sent with too.
+    chunk_size: usize,
+    /// Timestamp base of those commands, so they increase monotonically.
+    started: Instant,
 }
 
 impl RtmpSession {
     /// Create a new RTMP session and send initial protocol messages to the client.
     /// If `allowed_key` is `Some`, only clients publishing with that stream key are accepted.
-    pub async fn new(stream: &mut TcpStream, allowed_key: Option<String>) -> io::Result<Self> {
+    /// If `app_allowlist` is `Some`, connections to other app names are rejected.
+    /// Accepted publishes are recorded in `publishers` until they end, and
+    /// each gets its own sink from `sink_factory`.
+    /// If `recording_dir` is `Some`, publishes in `record` or `append` mode
+    /// are also written to `<stream key>.flv` there; `live` ones never are.
+    pub async fn new<S: AsyncWrite + Unpin>(
+        stream: &mut S,
+        peer_addr: SocketAddr,
+        allowed_key: Option<String>,
+        app_allowlist: Option<Arc<[String]>>,
+        publishers: PublisherRegistry,
+        sink_factory: SinkFactory,
+        recording_dir: Option<Arc<Path>>,
+    ) -> Result<Self, RtmpError> {
         let config = ServerSessionConfig::new();
-        let (session, initial_results) = ServerSession::new(config).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("failed to create ServerSession: {e:?}"),
-            )
-        })?;
+        let chunk_size = config.chunk_size as usize;
+        let (session, initial_results) = ServerSession::new(config)
+            .map_err(|e| session_error("failed to create ServerSession", e))?;
 
         // Send initial RTMP messages (chunk size, window ack, etc.)
         for result in initial_results {
@@ -46,23 +183,63 @@ impl RtmpSession {
         stream.flush().await?;
 
         debug!("RTMP session created, initial messages sent");
-        Ok(Self { session, allowed_key })
+        Ok(Self {
+            session,
+            allowed_key,
+            app_allowlist,
+            peer_addr,
+            publishers,
+            sink_factory,
+            recording_dir,
+            recorder_options: RecorderOptions::default(),
+            ingest: IngestMeter::new(),
+            messages: MessageCounter::new(),
+            publishing: None,
+            connect_params: HashMap::new(),
+            close_requested: false,
+            nalu_length_size: 4,
+            chunk_size,
+            started: Instant::now(),
+        })
+    }
+
+    /// Options for the recordings started by this session.
+    pub fn with_recorder_options(mut self, options: RecorderOptions) -> Self {
+        self.recorder_options = options;
+        self
+    }
+
+    /// Report the bitrate `ingest` measures for this connection while it
+    /// publishes. The caller counts what it reads into it.
+    pub fn with_ingest_meter(mut self, ingest: IngestMeter) -> Self {
+        self.ingest = ingest;
+        self
+    }
+
+    /// RTMP messages received so far, by type. Messages `ServerSession`
+    /// consumes without raising an event (e.g. `createStream`, window
+    /// acknowledgement size) aren't seen, so aren't counted.
+    pub fn message_counts(&self) -> MessageCounts {
+        self.messages.counts()
+    }
+
+    /// Whether the connection should be closed because the sink gave up on
+    /// the stream (`VideoSink::is_failed`).
+    pub fn close_requested(&self) -> bool {
+        self.close_requested
     }
 
     /// Process incoming RTMP data and dispatch events.
     /// Returns bytes to send back to the client.
-    pub async fn handle_input(
+    pub async fn handle_input<S: AsyncWrite + Unpin>(
         &mut self,
         data: &[u8],
-        stream: &mut TcpStream,
-        sink: &mut dyn VideoSink,
-    ) -> io::Result<()> {
-        let results = self.session.handle_input(data).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("session handle_input error: {e:?}"),
-            )
-        })?;
+        stream: &mut S,
+    ) -> Result<(), RtmpError> {
+        let results = self
+            .session
+            .handle_input(data)
+            .map_err(|e| session_error("handle_input", e))?;
 
         for result in results {
             match result {
@@ -70,29 +247,83 @@ impl RtmpSession {
                     stream.write_all(&packet.bytes).await?;
                 }
                 ServerSessionResult::RaisedEvent(event) => {
-                    self.handle_event(event, stream, sink).await?;
+                    self.messages.record(event_type_id(&event));
+                    self.handle_event(event, stream).await?;
                 }
                 ServerSessionResult::UnhandleableMessageReceived(msg) => {
-                    trace!("unhandled RTMP message: type_id={}", msg.type_id);
+                    self.messages.record(msg.type_id);
+                    match msg.type_id {
+                        TYPE_ID_AMF0_DATA => self.handle_data_message(&msg.data),
+                        TYPE_ID_AGGREGATE => {
+                            self.handle_aggregate_message(&msg.data, msg.timestamp.value)
+                        }
+                        type_id => trace!("unhandled RTMP message: type_id={type_id}"),
+                    }
                 }
             }
         }
+
+        let keyframe_requested = self.publishing.as_ref().is_some_and(|publish| {
+            self.publishers
+                .take_keyframe_request(&publish.stream_key, self.peer_addr)
+        });
+        if keyframe_requested {
+            self.send_keyframe_request(stream).await?;
+        }
         stream.flush().await?;
         Ok(())
     }
 
-    async fn handle_event(
+    /// Send `KEYFRAME_REQUEST_COMMAND` to the publisher. RTMP has no standard
+    /// way to ask for an IDR frame, so this only helps with publishers that
+    /// implement the command; others ignore unknown commands.
+    async fn send_keyframe_request<S: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+    ) -> Result<(), RtmpError> {
+        let Some(stream_key) = self.publishing.as_ref().map(|p| p.stream_key.clone()) else {
+            return Ok(());
+        };
+        let command = RtmpMessage::Amf0Command {
+            command_name: KEYFRAME_REQUEST_COMMAND.to_string(),
+            transaction_id: 0.0,
+            command_object: Amf0Value::Null,
+            additional_arguments: vec![Amf0Value::Utf8String(stream_key.clone())],
+        };
+        let timestamp = RtmpTimestamp::new(self.started.elapsed().as_millis() as u32);
+        let payload = command
+            .into_message_payload(timestamp, 0)
+            .map_err(|e| session_error("keyframe request serialization", e))?;
+        let chunks = chunk_message(&payload, COMMAND_CHUNK_STREAM_ID, self.chunk_size);
+        stream.write_all(&chunks).await?;
+        info!(stream_key, "requested keyframe from publisher");
+        Ok(())
+    }
+
+    async fn handle_event<S: AsyncWrite + Unpin>(
         &mut self,
         event: ServerSessionEvent,
-        stream: &mut TcpStream,
-        sink: &mut dyn VideoSink,
-    ) -> io::Result<()> {
+        stream: &mut S,
+    ) -> Result<(), RtmpError> {
         match event {
             ServerSessionEvent::ConnectionRequested {
                 request_id,
                 app_name,
             } => {
-                info!(app_name, "connection requested, accepting");
+                // rml_rtmp only hands over the connect command's `app`, not
+                // its `tcUrl`; encoders put the URL's query in both
+                let (app, params) = split_app_query(&app_name);
+                if !app_allowed(self.app_allowlist.as_deref(), app) {
+                    warn!(app_name, "connection rejected: unknown app");
+                    let results = self
+                        .session
+                        .reject_request(request_id, "NetConnection.Connect.Rejected", "unknown app")
+                        .map_err(|e| session_error("reject_request", e))?;
+                    self.send_results(results, stream).await?;
+                    return Err(RtmpError::Auth(format!("unknown app '{app_name}'")));
+                }
+                info!(app_name = app, params = params.len(), "connection requested, accepting");
+                self.connect_params = params;
                 let results = self.accept(request_id)?;
                 self.send_results(results, stream).await?;
             }
@@ -106,34 +337,50 @@ impl RtmpSession {
                 if let Some(ref expected) = self.allowed_key {
                     if stream_key != *expected {
                         warn!(app_name, stream_key, "publish rejected: invalid stream key");
-                        return Err(io::Error::new(
-                            io::ErrorKind::PermissionDenied,
-                            "invalid stream key",
-                        ));
+                        return Err(RtmpError::Auth("invalid stream key".to_string()));
                     }
                 }
+                let app_name = split_app_query(&app_name).0.to_string();
+                let context = ConnectionContext {
+                    peer_addr: self.peer_addr,
+                    app_name: app_name.clone(),
+                    stream_key: stream_key.clone(),
+                };
+                let mut sink = (self.sink_factory)(&context).map_err(|e| {
+                    warn!(app_name, stream_key, %e, "publish rejected: no sink available");
+                    e
+                })?;
+                if !self.connect_params.is_empty() {
+                    sink.on_connect_params(self.connect_params.clone());
+                }
                 info!(app_name, stream_key, ?mode, "publish requested, accepting");
                 let results = self.accept(request_id)?;
                 self.send_results(results, stream).await?;
+                self.end_publish();
+                let recorder = self.start_recording(&stream_key, &mode);
+                self.publishers.register_with_stats(
+                    &stream_key,
+                    &app_name,
+                    self.peer_addr,
+                    self.ingest.clone(),
+                    self.messages.clone(),
+                );
+                self.publishing = Some(ActivePublish {
+                    stream_key,
+                    sink,
+                    color_info: None,
+                    recorder,
+                    #[cfg(feature = "audio-level")]
+                    level_meter: None,
+                });
             }
 
             ServerSessionEvent::VideoDataReceived {
                 data, timestamp, ..
             } => {
-                let ts = timestamp.value as u32;
-                match flv::parse_video_data(&data, ts) {
-                    VideoPacket::SequenceHeader(config) => {
-                        info!("received AVC sequence header");
-                        sink.on_decoder_config(config);
-                    }
-                    VideoPacket::NaluData { avcc_payload, timestamp } => {
-                        sink.on_video_data(avcc_payload, timestamp);
-                    }
-                    VideoPacket::EndOfSequence => {
-                        info!("received end of sequence");
-                    }
-                    VideoPacket::Unsupported => {}
-                }
+                // The full 32-bit value: rml_rtmp applies the chunk's extended
+                // timestamp past 0xFFFFFF (~4.6 hours)
+                self.handle_video(data, timestamp.value);
             }
 
             ServerSessionEvent::StreamMetadataChanged {
@@ -141,12 +388,13 @@ impl RtmpSession {
                 stream_key,
                 metadata,
             } => {
-                info!(
+                debug!(
                     app_name,
                     stream_key,
                     ?metadata,
                     "stream metadata changed"
                 );
+                self.report_stream_info(StreamInfo::from(&metadata));
             }
 
             ServerSessionEvent::PublishStreamFinished {
@@ -154,11 +402,20 @@ impl RtmpSession {
                 stream_key,
             } => {
                 info!(app_name, stream_key, "publish finished");
+                self.end_publish();
+            }
+
+            ServerSessionEvent::UnhandleableAmf0Command { command_name, .. }
+                if is_stream_end_command(&command_name) =>
+            {
+                info!(command_name, "publisher closed stream");
+                self.end_publish();
             }
 
-            ServerSessionEvent::AudioDataReceived { .. } => {
-                // We only care about video
-                trace!("audio data received (ignored)");
+            ServerSessionEvent::AudioDataReceived {
+                data, timestamp, ..
+            } => {
+                self.handle_audio(&data, timestamp.value);
             }
 
             ServerSessionEvent::ReleaseStreamRequested { request_id, .. } => {
@@ -174,20 +431,179 @@ impl RtmpSession {
         Ok(())
     }
 
-    fn accept(&mut self, request_id: u32) -> io::Result<Vec<ServerSessionResult>> {
-        self.session.accept_request(request_id).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("accept_request error: {e:?}"),
-            )
-        })
+    fn handle_video(&mut self, data: Bytes, ts: u32) {
+        let Some(ActivePublish {
+            sink,
+            color_info,
+            recorder,
+            ..
+        }) = &mut self.publishing
+        else {
+            trace!("video data received before publish (ignored)");
+            return;
+        };
+        let packet = flv::parse_video_data(&data, ts, self.nalu_length_size);
+        record(recorder, |active| active.write_video(ts, &data, &packet));
+        match packet {
+            VideoPacket::SequenceHeader(config) => {
+                info!("received AVC sequence header");
+                self.nalu_length_size = config.nalu_length_size;
+                sink.on_decoder_config(config);
+            }
+            VideoPacket::NaluData {
+                avcc_payload,
+                timestamp,
+                ..
+            } => {
+                sei::forward_sei(sink.as_mut(), &avcc_payload, self.nalu_length_size);
+                sink.on_video_data(avcc_payload, timestamp);
+            }
+            VideoPacket::EndOfSequence => {
+                info!("received end of sequence");
+                sink.on_end_of_sequence();
+            }
+            VideoPacket::ColorInfo(info) => {
+                // Publishers resend it periodically, often with every keyframe
+                if color_info.as_ref() != Some(&info) {
+                    info!(?info, "received color info");
+                    *color_info = Some(info.clone());
+                    sink.on_color_info(info);
+                }
+            }
+            VideoPacket::Unsupported => {}
+        }
+        if sink.is_failed() && !self.close_requested {
+            warn!("sink gave up on the stream, closing connection");
+            self.close_requested = true;
+        }
+    }
+
+    fn handle_audio(&mut self, data: &[u8], ts: u32) {
+        // Audio is only recorded and, with `audio-level`, metered; the sink
+        // never gets the audio itself
+        let Some(publish) = &mut self.publishing else {
+            trace!("audio data received before publish (ignored)");
+            return;
+        };
+        record(&mut publish.recorder, |active| active.write_audio(ts, data));
+        #[cfg(feature = "audio-level")]
+        meter_audio(&mut publish.level_meter, publish.sink.as_mut(), data);
+    }
+
+    /// Deliver the tags bundled in an aggregate message as if each had
+    /// arrived as its own message. rml_rtmp passes these through unparsed.
+    fn handle_aggregate_message(&mut self, data: &Bytes, timestamp: u32) {
+        for tag in flv::split_aggregate(data, timestamp) {
+            match tag.tag_type {
+                TAG_TYPE_VIDEO => self.handle_video(tag.data, tag.timestamp),
+                TAG_TYPE_AUDIO => self.handle_audio(&tag.data, tag.timestamp),
+                TYPE_ID_AMF0_DATA => self.handle_data_message(&tag.data),
+                other => trace!(tag_type = other, "unhandled tag in aggregate message"),
+            }
+        }
+    }
+
+    /// Pick up `onMetaData` from data messages rml_rtmp didn't turn into a
+    /// `StreamMetadataChanged` event, such as a bare `onMetaData` or one
+    /// wrapped in `@setDataFrame` that it didn't unwrap.
+    fn handle_data_message(&mut self, data: &[u8]) {
+        let values = match amf0::deserialize(&mut Cursor::new(data)) {
+            Ok(values) => values,
+            Err(e) => {
+                debug!(?e, "failed to decode AMF0 data message");
+                return;
+            }
+        };
+        match StreamInfo::from_data_message(&values) {
+            Some(info) => {
+                debug!(?info, "stream metadata from data message");
+                self.report_stream_info(info);
+            }
+            None => trace!(?values, "unhandled AMF0 data message"),
+        }
+    }
+
+    fn report_stream_info(&mut self, info: StreamInfo) {
+        let Some(ActivePublish {
+            stream_key, sink, ..
+        }) = &mut self.publishing
+        else {
+            return;
+        };
+        match &info.video_codec {
+            Some(VideoCodec::Avc) | None => {}
+            Some(codec) => {
+                warn!(
+                    stream_key,
+                    ?codec,
+                    "publisher declares a non-AVC video codec; only H.264 is decoded"
+                );
+            }
+        }
+        sink.on_stream_info(info);
+    }
+
+    /// Notify the sink that publishing has ended and release it. Called on
+    /// close commands and on disconnect; only the first call per publish
+    /// reaches the sink.
+    pub fn end_publish(&mut self) {
+        if let Some(mut publish) = self.publishing.take() {
+            self.publishers.unregister(&publish.stream_key, self.peer_addr);
+            publish.sink.on_stream_end();
+            if let Some(recorder) = publish.recorder {
+                let path = recorder.path().to_path_buf();
+                match recorder.finish() {
+                    Ok(()) => info!(path = %path.display(), "recording finished"),
+                    Err(e) => warn!(path = %path.display(), %e, "failed to finish recording"),
+                }
+            }
+        }
+    }
+
+    /// Open the FLV recording for a publish in `mode`: truncated for
+    /// `record`, continued for `append`. `live` publishes, and all publishes
+    /// when no recording directory is set, aren't recorded. A recording that
+    /// can't be opened is skipped rather than failing the publish.
+    fn start_recording(&self, stream_key: &str, mode: &PublishMode) -> Option<FlvRecorder> {
+        let open = match mode {
+            PublishMode::Live => return None,
+            PublishMode::Record => FlvRecorder::create,
+            PublishMode::Append => FlvRecorder::append,
+        };
+        let Some(dir) = &self.recording_dir else {
+            debug!(stream_key, ?mode, "recording not enabled, publishing live only");
+            return None;
+        };
+        let path = dir.join(recording_file_name(stream_key));
+        match open(&path) {
+            Ok(recorder) => {
+                info!(
+                    stream_key,
+                    ?mode,
+                    keyframes_only = self.recorder_options.keyframes_only,
+                    path = %path.display(),
+                    "recording publish"
+                );
+                Some(recorder.with_options(self.recorder_options))
+            }
+            Err(e) => {
+                warn!(stream_key, path = %path.display(), %e, "failed to open recording");
+                None
+            }
+        }
+    }
+
+    fn accept(&mut self, request_id: u32) -> Result<Vec<ServerSessionResult>, RtmpError> {
+        self.session
+            .accept_request(request_id)
+            .map_err(|e| session_error("accept_request", e))
     }
 
-    async fn send_results(
-        &self,
+    async fn send_results<S: AsyncWrite + Unpin>(
+        &mut self,
         results: Vec<ServerSessionResult>,
-        stream: &mut TcpStream,
-    ) -> io::Result<()> {
+        stream: &mut S,
+    ) -> Result<(), RtmpError> {
         for result in results {
             if let ServerSessionResult::OutboundResponse(packet) = result {
                 stream.write_all(&packet.bytes).await?;
@@ -197,3 +613,240 @@ impl RtmpSession {
         Ok(())
     }
 }
+
+/// Measure an AAC frame and pass its level to the sink. The meter is
+/// (re)created from each AAC sequence header.
+#[cfg(feature = "audio-level")]
+fn meter_audio(meter: &mut Option<AacLevelMeter>, sink: &mut dyn VideoSink, data: &[u8]) {
+    match flv::parse_audio_data(data) {
+        AudioPacket::AacSequenceHeader(config) => match AacLevelMeter::new(config) {
+            Ok(created) => *meter = Some(created),
+            Err(e) => {
+                warn!(%e, "can't meter this stream's audio levels");
+                *meter = None;
+            }
+        },
+        AudioPacket::AacRaw(frame) => {
+            let Some(meter) = meter else { return };
+            match meter.measure(frame) {
+                Ok(Some((rms_db, peak_db))) => sink.on_audio_level(rms_db, peak_db),
+                Ok(None) => {}
+                Err(e) => debug!(%e, "failed to measure audio level"),
+            }
+        }
+        AudioPacket::Unsupported => {}
+    }
+}
+
+/// Write to the publish's recording, if any. A write error ends the
+/// recording; the publish carries on.
+fn record(
+    recorder: &mut Option<FlvRecorder>,
+    write: impl FnOnce(&mut FlvRecorder) -> io::Result<()>,
+) {
+    if let Some(active) = recorder {
+        if let Err(e) = write(active) {
+            warn!(path = %active.path().display(), %e, "recording failed, stopping it");
+            *recorder = None;
+        }
+    }
+}
+
+/// `stream_key` reduced to filename-safe characters: ASCII letters, digits,
+/// `-` and `_`, with anything else replaced by `_`. For naming files after a
+/// stream, since keys come from the client.
+pub fn sanitize_stream_key(stream_key: &str) -> String {
+    stream_key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Recording file name for `stream_key`.
+fn recording_file_name(stream_key: &str) -> String {
+    format!("{}.flv", sanitize_stream_key(stream_key))
+}
+
+fn session_error(context: &str, e: impl std::fmt::Debug) -> RtmpError {
+    RtmpError::Session(format!("{context}: {e:?}"))
+}
+
+/// Whether `app_name` may connect. Names are compared without surrounding
+/// slashes, so "/live" in the allowlist matches a client connecting to "live".
+fn app_allowed(allowlist: Option<&[String]>, app_name: &str) -> bool {
+    let Some(allowlist) = allowlist else { return true };
+    let app_name = app_name.trim_matches('/');
+    allowlist.iter().any(|app| app.trim_matches('/') == app_name)
+}
+
+/// Split an app name such as "live?token=xyz" into the app and its query
+/// parameters. Values are percent-decoded; a name without `=` maps to "".
+fn split_app_query(app_name: &str) -> (&str, HashMap<String, String>) {
+    let Some((app, query)) = app_name.split_once('?') else {
+        return (app_name, HashMap::new());
+    };
+    let params = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect();
+    (app, params)
+}
+
+/// Decode `%XX` escapes and `+` (space) in a URL query component. Malformed
+/// escapes are kept as they are.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let digit = |b: u8| (b as char).to_digit(16);
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| Some((digit(hex[0])? * 16 + digit(hex[1])?) as u8));
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                out.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Custom command sent on the connection's control stream to ask the
+/// publisher for a keyframe, with the stream key as its only argument.
+pub const KEYFRAME_REQUEST_COMMAND: &str = "requestKeyFrame";
+
+/// Chunk stream for commands sent outside `ServerSession`. Its serializer
+/// picks chunk streams 2 to 6 by message type, so the client's header state
+/// for this one is only ever set by our own messages, and a full header on
+/// each keeps it from affecting the session's.
+const COMMAND_CHUNK_STREAM_ID: u8 = 20;
+
+/// Split `payload` into chunks of at most `chunk_size` bytes on chunk stream
+/// `csid` (3 to 63): a type 0 chunk with the whole message header, then type
+/// 3 chunks for the rest.
+fn chunk_message(payload: &MessagePayload, csid: u8, chunk_size: usize) -> Vec<u8> {
+    let timestamp = payload.timestamp.value;
+    // Timestamps from 0xFFFFFF up go in an extended field after each header
+    let extended = (timestamp >= 0xFF_FFFF).then(|| timestamp.to_be_bytes());
+    let mut out = Vec::with_capacity(payload.data.len() + 16);
+    out.push(csid);
+    out.extend_from_slice(&timestamp.min(0xFF_FFFF).to_be_bytes()[1..]);
+    out.extend_from_slice(&(payload.data.len() as u32).to_be_bytes()[1..]);
+    out.push(payload.type_id);
+    out.extend_from_slice(&payload.message_stream_id.to_le_bytes());
+    out.extend(extended.iter().flatten());
+    for (i, chunk) in payload.data.chunks(chunk_size.max(1)).enumerate() {
+        if i > 0 {
+            out.push(0xC0 | csid);
+            out.extend(extended.iter().flatten());
+        }
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+/// The type of the RTMP message `ServerSession` raised `event` for.
+fn event_type_id(event: &ServerSessionEvent) -> u8 {
+    match event {
+        ServerSessionEvent::ClientChunkSizeChanged { .. } => TYPE_ID_SET_CHUNK_SIZE,
+        ServerSessionEvent::AcknowledgementReceived { .. } => TYPE_ID_ACKNOWLEDGEMENT,
+        ServerSessionEvent::PingResponseReceived { .. } => TYPE_ID_USER_CONTROL,
+        ServerSessionEvent::AudioDataReceived { .. } => TYPE_ID_AUDIO,
+        ServerSessionEvent::VideoDataReceived { .. } => TYPE_ID_VIDEO,
+        ServerSessionEvent::StreamMetadataChanged { .. } => TYPE_ID_AMF0_DATA,
+        ServerSessionEvent::ConnectionRequested { .. }
+        | ServerSessionEvent::ReleaseStreamRequested { .. }
+        | ServerSessionEvent::PublishStreamRequested { .. }
+        | ServerSessionEvent::PublishStreamFinished { .. }
+        | ServerSessionEvent::PlayStreamRequested { .. }
+        | ServerSessionEvent::PlayStreamFinished { .. }
+        | ServerSessionEvent::UnhandleableAmf0Command { .. } => TYPE_ID_AMF0_COMMAND,
+    }
+}
+
+/// AMF0 commands a publisher may send when it stops streaming, which
+/// rml_rtmp passes through as unhandleable commands. If it handles
+/// deleteStream itself it raises `PublishStreamFinished` instead; either
+/// way the publish ends once (`e2e_delete_stream_ends_publish`).
+fn is_stream_end_command(name: &str) -> bool {
+    matches!(name, "FCUnpublish" | "closeStream" | "deleteStream")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_message() {
+        let payload = MessagePayload {
+            timestamp: RtmpTimestamp::new(0x0102_0304),
+            type_id: TYPE_ID_AMF0_COMMAND,
+            message_stream_id: 1,
+            data: Bytes::from_static(&[1, 2, 3, 4, 5]),
+        };
+        let ts = [0x01, 0x02, 0x03, 0x04];
+        let chunks = chunk_message(&payload, COMMAND_CHUNK_STREAM_ID, 3);
+        let expected = [
+            &[COMMAND_CHUNK_STREAM_ID, 0xFF, 0xFF, 0xFF, 0, 0, 5, 20, 1, 0, 0, 0][..],
+            &ts,
+            &[1, 2, 3],
+            &[0xC0 | COMMAND_CHUNK_STREAM_ID],
+            &ts,
+            &[4, 5],
+        ]
+        .concat();
+        assert_eq!(chunks, expected);
+
+        // Timestamps below 0xFFFFFF fit in the header
+        let payload = MessagePayload {
+            timestamp: RtmpTimestamp::new(33),
+            ..payload
+        };
+        let chunks = chunk_message(&payload, COMMAND_CHUNK_STREAM_ID, 128);
+        assert_eq!(chunks[..4], [COMMAND_CHUNK_STREAM_ID, 0, 0, 33]);
+        assert_eq!(chunks.len(), 12 + 5);
+    }
+
+    #[test]
+    fn test_recording_file_name() {
+        assert_eq!(recording_file_name("cam-1_a"), "cam-1_a.flv");
+        assert_eq!(recording_file_name("../x y"), "___x_y.flv");
+    }
+
+    #[test]
+    fn test_app_allowlist() {
+        let allowlist = ["/live".to_string(), "preview".to_string()];
+        assert!(app_allowed(Some(&allowlist), "live"));
+        assert!(app_allowed(Some(&allowlist), "preview/"));
+        assert!(!app_allowed(Some(&allowlist), "bogus"));
+        assert!(app_allowed(None, "bogus"));
+    }
+
+    #[test]
+    fn test_split_app_query() {
+        let (app, params) = split_app_query("live?token=xyz&name=a%20b+c&flag");
+        assert_eq!(app, "live");
+        assert_eq!(params["token"], "xyz");
+        assert_eq!(params["name"], "a b c");
+        assert_eq!(params["flag"], "");
+
+        let (app, params) = split_app_query("live");
+        assert_eq!(app, "live");
+        assert!(params.is_empty());
+        assert_eq!(split_app_query("live?bad=%zz%4").1["bad"], "%zz%4");
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+/// Certificate and key an RTMPS listener presents to clients.
+///
+/// Cloning yields another handle to the same configuration.
+#[derive(Clone)]
+pub struct TlsConfig {
+    acceptor: TlsAcceptor,
+}
+
+impl TlsConfig {
+    /// Build from PEM files: `cert_path` holds the certificate chain, leaf
+    /// first, and `key_path` its private key (PKCS#8, PKCS#1 or SEC1).
+    pub fn from_pem_files(
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> io::Result<Self> {
+        let cert_path = cert_path.as_ref();
+        let key_path = key_path.as_ref();
+        let certs = CertificateDer::pem_file_iter(cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| pem_error(cert_path, e))?;
+        if certs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: no certificates found", cert_path.display()),
+            ));
+        }
+        let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| pem_error(key_path, e))?;
+        Self::new(certs, key)
+    }
+
+    /// Build from a DER certificate chain, leaf first, and its private key.
+    pub fn new(
+        certs: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> io::Result<Self> {
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(Self::from_server_config(Arc::new(config)))
+    }
+
+    /// Use a rustls configuration built elsewhere, e.g. one that asks for
+    /// client certificates.
+    pub fn from_server_config(config: Arc<ServerConfig>) -> Self {
+        Self {
+            acceptor: TlsAcceptor::from(config),
+        }
+    }
+
+    pub(crate) fn acceptor(&self) -> &TlsAcceptor {
+        &self.acceptor
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig").finish_non_exhaustive()
+    }
+}
+
+fn pem_error(path: &Path, e: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("rtmp-vcam-tls-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn test_loads_pem_files() {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = temp_path("cert.pem");
+        let key_path = temp_path("key.pem");
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+
+        let loaded = TlsConfig::from_pem_files(&cert_path, &key_path);
+        // A certificate isn't a key
+        let swapped = TlsConfig::from_pem_files(&cert_path, &cert_path);
+        std::fs::remove_file(&cert_path).ok();
+        std::fs::remove_file(&key_path).ok();
+
+        assert!(loaded.is_ok(), "{loaded:?}");
+        let err = swapped.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("cert.pem"), "{err}");
+    }
+
+    #[test]
+    fn test_rejects_missing_certificate() {
+        let err = TlsConfig::from_pem_files(temp_path("missing.pem"), temp_path("missing.key"))
+            .unwrap_err();
+        assert!(err.to_string().contains("missing.pem"), "{err}");
+    }
+}
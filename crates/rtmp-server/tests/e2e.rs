@@ -0,0 +1,1295 @@
+//! End-to-end test: a synthetic RTMP client publishes a hand-built H.264
+//! stream to a real server socket, and a recording sink checks what arrives.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use rml_rtmp::amf0::{self, Amf0Value};
+use rml_rtmp::chunk_io::ChunkSerializer;
+use rml_rtmp::handshake::{Handshake, HandshakeProcessResult, PeerType};
+use rml_rtmp::messages::MessagePayload;
+use rml_rtmp::sessions::{
+    ClientSession, ClientSessionConfig, ClientSessionEvent, ClientSessionResult, PublishRequestType,
+};
+use rml_rtmp::time::RtmpTimestamp;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+use rtmp_server::http_flv::FlvTagReader;
+use rtmp_server::message_counts::{TYPE_ID_AMF0_COMMAND, TYPE_ID_AUDIO, TYPE_ID_VIDEO};
+use rtmp_server::{
+    AvcDecoderConfig, ColorInfo, ConnectionContext, ConnectionInfo, Listener, Server, TlsConfig,
+    VideoSink, KEYFRAME_REQUEST_COMMAND,
+};
+
+const SPS: &[u8] = &[0x67, 0x64, 0x00, 0x1F, 0xAC, 0xD9];
+const PPS: &[u8] = &[0x68, 0xEB, 0xE3, 0xCB];
+
+/// How long a client script may take before the test fails.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+enum Event {
+    ConnectParams(HashMap<String, String>),
+    Config(AvcDecoderConfig),
+    Video(Bytes, u32),
+    ColorInfo(ColorInfo),
+    #[cfg(feature = "audio-level")]
+    AudioLevel(f32, f32),
+    EndOfSequence,
+    End,
+}
+
+/// Sink that records every callback for later inspection.
+struct RecordingSink {
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl VideoSink for RecordingSink {
+    fn on_connect_params(&mut self, params: HashMap<String, String>) {
+        self.events.lock().unwrap().push(Event::ConnectParams(params));
+    }
+
+    fn on_decoder_config(&mut self, config: AvcDecoderConfig) {
+        self.events.lock().unwrap().push(Event::Config(config));
+    }
+
+    fn on_video_data(&mut self, data: Bytes, timestamp: u32) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(Event::Video(data, timestamp));
+    }
+
+    fn on_color_info(&mut self, info: ColorInfo) {
+        self.events.lock().unwrap().push(Event::ColorInfo(info));
+    }
+
+    #[cfg(feature = "audio-level")]
+    fn on_audio_level(&mut self, rms_db: f32, peak_db: f32) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(Event::AudioLevel(rms_db, peak_db));
+    }
+
+    fn on_end_of_sequence(&mut self) {
+        self.events.lock().unwrap().push(Event::EndOfSequence);
+    }
+
+    fn on_stream_end(&mut self) {
+        self.events.lock().unwrap().push(Event::End);
+    }
+}
+
+/// Sink that gives up on the stream after `fail_after` video frames, like a
+/// decoding sink that hit too many decode errors.
+struct FailingSink {
+    events: Arc<Mutex<Vec<Event>>>,
+    frames: usize,
+    fail_after: usize,
+}
+
+impl VideoSink for FailingSink {
+    fn on_decoder_config(&mut self, _config: AvcDecoderConfig) {}
+
+    fn on_video_data(&mut self, data: Bytes, timestamp: u32) {
+        self.frames += 1;
+        self.events
+            .lock()
+            .unwrap()
+            .push(Event::Video(data, timestamp));
+    }
+
+    fn is_failed(&self) -> bool {
+        self.frames >= self.fail_after
+    }
+
+    fn on_stream_end(&mut self) {
+        self.events.lock().unwrap().push(Event::End);
+    }
+}
+
+/// Minimal publishing client built on rml_rtmp's client session.
+struct TestClient<S> {
+    stream: S,
+    session: ClientSession,
+    /// Chunk size the session sends with.
+    chunk_size: u32,
+    buf: Vec<u8>,
+}
+
+impl TestClient<TcpStream> {
+    async fn connect(addr: SocketAddr) -> io::Result<Self> {
+        Self::connect_with_config(addr, ClientSessionConfig::new()).await
+    }
+
+    async fn connect_with_config(
+        addr: SocketAddr,
+        config: ClientSessionConfig,
+    ) -> io::Result<Self> {
+        let stream = retry_connect(|| TcpStream::connect(addr)).await?;
+        Self::handshake(stream, config).await
+    }
+}
+
+impl TestClient<UnixStream> {
+    async fn connect_unix(path: &Path) -> io::Result<Self> {
+        let stream = retry_connect(|| UnixStream::connect(path)).await?;
+        Self::handshake(stream, ClientSessionConfig::new()).await
+    }
+}
+
+impl TestClient<TlsStream<TcpStream>> {
+    /// Connect over TLS as "localhost", trusting only `cert`.
+    async fn connect_tls(addr: SocketAddr, cert: CertificateDer<'static>) -> io::Result<Self> {
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).map_err(other)?;
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(other)?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let tcp = retry_connect(|| TcpStream::connect(addr)).await?;
+        let name = ServerName::try_from("localhost").map_err(other)?;
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(name, tcp)
+            .await?;
+        Self::handshake(stream, ClientSessionConfig::new()).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> TestClient<S> {
+    async fn handshake(mut stream: S, config: ClientSessionConfig) -> io::Result<Self> {
+        let mut handshake = Handshake::new(PeerType::Client);
+        let p0_p1 = handshake.generate_outbound_p0_and_p1().map_err(other)?;
+        stream.write_all(&p0_p1).await?;
+
+        let mut buf = vec![0u8; 4096];
+        let remaining = loop {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            match handshake.process_bytes(&buf[..n]).map_err(other)? {
+                HandshakeProcessResult::InProgress { response_bytes } => {
+                    stream.write_all(&response_bytes).await?;
+                }
+                HandshakeProcessResult::Completed {
+                    response_bytes,
+                    remaining_bytes,
+                } => {
+                    stream.write_all(&response_bytes).await?;
+                    break remaining_bytes;
+                }
+            }
+        };
+
+        let chunk_size = config.chunk_size;
+        let (session, results) = ClientSession::new(config).map_err(other)?;
+        let mut client = Self {
+            stream,
+            session,
+            chunk_size,
+            buf,
+        };
+        client.send(results).await?;
+        if !remaining.is_empty() {
+            let results = client.session.handle_input(&remaining).map_err(other)?;
+            client.send(results).await?;
+        }
+        Ok(client)
+    }
+
+    async fn send(
+        &mut self,
+        results: Vec<ClientSessionResult>,
+    ) -> io::Result<Vec<ClientSessionEvent>> {
+        let mut events = Vec::new();
+        for result in results {
+            match result {
+                ClientSessionResult::OutboundResponse(packet) => {
+                    self.stream.write_all(&packet.bytes).await?;
+                }
+                ClientSessionResult::RaisedEvent(event) => events.push(event),
+                ClientSessionResult::UnhandleableMessageReceived(_) => {}
+            }
+        }
+        self.stream.flush().await?;
+        Ok(events)
+    }
+
+    /// Read from the server until `wanted` is raised.
+    async fn wait_for(&mut self, wanted: ClientSessionEvent) -> io::Result<()> {
+        loop {
+            let n = self.stream.read(&mut self.buf).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let results = self.session.handle_input(&self.buf[..n]).map_err(other)?;
+            if self.send(results).await?.contains(&wanted) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Read from the server until it sends the AMF0 command `name`, returning
+    /// the command's arguments.
+    async fn wait_for_command(&mut self, name: &str) -> io::Result<Vec<Amf0Value>> {
+        loop {
+            let n = self.stream.read(&mut self.buf).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let results = self.session.handle_input(&self.buf[..n]).map_err(other)?;
+            for event in self.send(results).await? {
+                if let ClientSessionEvent::UnhandleableAmf0Command {
+                    command_name,
+                    additional_values,
+                    ..
+                } = event
+                {
+                    if command_name == name {
+                        return Ok(additional_values);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn publish(&mut self, app: &str, stream_key: &str) -> io::Result<()> {
+        self.publish_as(app, stream_key, PublishRequestType::Live)
+            .await
+    }
+
+    async fn publish_as(
+        &mut self,
+        app: &str,
+        stream_key: &str,
+        mode: PublishRequestType,
+    ) -> io::Result<()> {
+        let result = self
+            .session
+            .request_connection(app.to_string())
+            .map_err(other)?;
+        self.send(vec![result]).await?;
+        self.wait_for(ClientSessionEvent::ConnectionRequestAccepted)
+            .await?;
+
+        let result = self
+            .session
+            .request_publishing(stream_key.to_string(), mode)
+            .map_err(other)?;
+        self.send(vec![result]).await?;
+        self.wait_for(ClientSessionEvent::PublishRequestAccepted)
+            .await
+    }
+
+    async fn send_video(&mut self, tag: Vec<u8>, timestamp: u32) -> io::Result<()> {
+        let result = self
+            .session
+            .publish_video_data(Bytes::from(tag), RtmpTimestamp::new(timestamp), false)
+            .map_err(other)?;
+        self.send(vec![result]).await?;
+        Ok(())
+    }
+
+    async fn send_audio(&mut self, tag: Vec<u8>, timestamp: u32) -> io::Result<()> {
+        let result = self
+            .session
+            .publish_audio_data(Bytes::from(tag), RtmpTimestamp::new(timestamp), false)
+            .map_err(other)?;
+        self.send(vec![result]).await?;
+        Ok(())
+    }
+
+    /// Send FLV tags bundled in one aggregate message (type 22), which
+    /// ClientSession has no API for.
+    async fn send_aggregate(&mut self, tags: Vec<u8>, timestamp: u32) -> io::Result<()> {
+        let payload = MessagePayload {
+            timestamp: RtmpTimestamp::new(timestamp),
+            type_id: 22,
+            message_stream_id: 1,
+            data: Bytes::from(tags),
+        };
+        self.send_payload(&payload).await
+    }
+
+    /// Send an AMF0 command on the connection's control stream, with a
+    /// transaction ID of 0 and no command object, for commands
+    /// ClientSession has no API for.
+    async fn send_command(&mut self, name: &str, arguments: Vec<Amf0Value>) -> io::Result<()> {
+        let mut values = vec![
+            Amf0Value::Utf8String(name.to_string()),
+            Amf0Value::Number(0.0),
+            Amf0Value::Null,
+        ];
+        values.extend(arguments);
+        let payload = MessagePayload {
+            timestamp: RtmpTimestamp::new(0),
+            type_id: TYPE_ID_AMF0_COMMAND,
+            message_stream_id: 0,
+            data: Bytes::from(amf0::serialize(&values).map_err(other)?),
+        };
+        self.send_payload(&payload).await
+    }
+
+    async fn send_payload(&mut self, payload: &MessagePayload) -> io::Result<()> {
+        // A serializer of our own, at the session's chunk size; full chunk
+        // headers since it shares no chunk stream state with the session's
+        let mut serializer = ChunkSerializer::new();
+        serializer
+            .set_max_chunk_size(self.chunk_size, RtmpTimestamp::new(0))
+            .map_err(other)?;
+        let packet = serializer.serialize(payload, true, false).map_err(other)?;
+        self.stream.write_all(&packet.bytes).await?;
+        self.stream.flush().await
+    }
+
+    async fn stop(&mut self) -> io::Result<()> {
+        let results = self.session.stop_publishing().map_err(other)?;
+        self.send(results).await?;
+        Ok(())
+    }
+}
+
+fn other<E: std::fmt::Debug>(e: E) -> io::Error {
+    io::Error::other(format!("{e:?}"))
+}
+
+async fn retry_connect<S, F, Fut>(mut connect: F) -> io::Result<S>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = io::Result<S>>,
+{
+    let mut attempts = 0;
+    loop {
+        match connect().await {
+            Ok(stream) => return Ok(stream),
+            Err(e) if attempts >= 50 => return Err(e),
+            Err(_) => {
+                attempts += 1;
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+    }
+}
+
+fn free_port_addr() -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+/// Run `server` on a free local port, getting a sink for each publish from
+/// `factory`. Returns the address to connect to.
+fn spawn_server<F>(server: Server, factory: F) -> SocketAddr
+where
+    F: Fn(&ConnectionContext) -> io::Result<Box<dyn VideoSink>> + Send + Sync + 'static,
+{
+    let addr = free_port_addr();
+    tokio::spawn(async move { server.run(addr, factory, None).await });
+    addr
+}
+
+/// Run `server` on a free local port with a `RecordingSink` for every
+/// publish, all recording into the returned events.
+fn spawn_recording_server(server: Server) -> (SocketAddr, Arc<Mutex<Vec<Event>>>) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink_events = Arc::clone(&events);
+    let factory = move |_: &ConnectionContext| -> io::Result<Box<dyn VideoSink>> {
+        Ok(Box::new(RecordingSink {
+            events: Arc::clone(&sink_events),
+        }))
+    };
+    (spawn_server(server, factory), events)
+}
+
+/// Run a client script to completion, failing the test if it errors or
+/// takes longer than `CLIENT_TIMEOUT`.
+async fn run_client<T>(client: impl Future<Output = io::Result<T>>) -> T {
+    tokio::time::timeout(CLIENT_TIMEOUT, client)
+        .await
+        .expect("client timed out")
+        .expect("client failed")
+}
+
+/// Wait for a publish of `stream_key` to show up among the server's active
+/// publishers, and return its details.
+async fn wait_for_publisher(server: &Server, stream_key: &str) -> ConnectionInfo {
+    for _ in 0..100 {
+        let publishers = server.active_publishers();
+        if let Some((_, info)) = publishers.into_iter().find(|(key, _)| key == stream_key) {
+            return info;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("{stream_key} was never published");
+}
+
+/// A whole FLV tag: header, `data` and PreviousTagSize.
+fn flv_tag(tag_type: u8, timestamp: u32, data: &[u8]) -> Vec<u8> {
+    let mut tag = vec![tag_type];
+    tag.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+    tag.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+    tag.push((timestamp >> 24) as u8);
+    tag.extend_from_slice(&[0, 0, 0]);
+    tag.extend_from_slice(data);
+    tag.extend_from_slice(&(11 + data.len() as u32).to_be_bytes());
+    tag
+}
+
+/// FLV video tag carrying an AVCDecoderConfigurationRecord.
+fn sequence_header_tag() -> Vec<u8> {
+    let mut tag = vec![0x17, 0x00, 0x00, 0x00, 0x00];
+    tag.extend_from_slice(&[0x01, SPS[1], SPS[2], SPS[3], 0xFF, 0xE1]);
+    tag.extend_from_slice(&(SPS.len() as u16).to_be_bytes());
+    tag.extend_from_slice(SPS);
+    tag.push(0x01);
+    tag.extend_from_slice(&(PPS.len() as u16).to_be_bytes());
+    tag.extend_from_slice(PPS);
+    tag
+}
+
+/// FLV video tag with a single AVCC-framed NAL unit.
+fn nalu_tag(keyframe: bool, nal: &[u8]) -> Vec<u8> {
+    let frame_type = if keyframe { 0x17 } else { 0x27 };
+    let mut tag = vec![frame_type, 0x01, 0x00, 0x00, 0x00];
+    tag.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+    tag.extend_from_slice(nal);
+    tag
+}
+
+/// Enhanced-RTMP metadata video tag with HDR10 `colorInfo`.
+fn color_info_tag() -> Vec<u8> {
+    let numbers = |pairs: &[(&str, f64)]| {
+        Amf0Value::Object(
+            pairs
+                .iter()
+                .map(|(key, n)| (key.to_string(), Amf0Value::Number(*n)))
+                .collect(),
+        )
+    };
+    let color_info = HashMap::from([
+        (
+            "colorConfig".to_string(),
+            numbers(&[("bitDepth", 10.0), ("transferCharacteristics", 16.0)]),
+        ),
+        (
+            "hdrMdcv".to_string(),
+            numbers(&[
+                ("redX", 0.708),
+                ("redY", 0.292),
+                ("greenX", 0.17),
+                ("greenY", 0.797),
+                ("blueX", 0.131),
+                ("blueY", 0.046),
+                ("whitePointX", 0.3127),
+                ("whitePointY", 0.329),
+                ("maxLuminance", 1000.0),
+                ("minLuminance", 0.005),
+            ]),
+        ),
+    ]);
+    let mut tag = vec![0xD4, b'a', b'v', b'c', b'1'];
+    tag.extend(
+        amf0::serialize(&vec![
+            Amf0Value::Utf8String("colorInfo".into()),
+            Amf0Value::Object(color_info),
+        ])
+        .unwrap(),
+    );
+    tag
+}
+
+/// Wait for the server to pass the stream end to the sink.
+async fn wait_for_end(events: &Mutex<Vec<Event>>) {
+    for _ in 0..100 {
+        if matches!(events.lock().unwrap().last(), Some(Event::End)) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_publish_reaches_sink() {
+    let server = Server::new();
+    let handle = server.clone();
+    let (addr, events) = spawn_recording_server(server);
+
+    let frames: [(bool, &[u8]); 3] = [
+        (true, &[0x65, 0x88, 0x84, 0x00]),
+        (false, &[0x41, 0x9A, 0x02]),
+        (false, &[0x41, 0x9A, 0x04]),
+    ];
+
+    let (_client, publisher) = run_client(async {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live", "test").await?;
+        let publisher = wait_for_publisher(&handle, "test").await;
+        client.send_video(sequence_header_tag(), 0).await?;
+        for (i, (keyframe, nal)) in frames.iter().enumerate() {
+            client
+                .send_video(nalu_tag(*keyframe, nal), i as u32 * 33)
+                .await?;
+        }
+        client.stop().await?;
+        Ok((client, publisher))
+    })
+    .await;
+    assert_eq!(publisher.app_name, "live");
+    assert!(publisher.peer_addr.ip().is_loopback());
+
+    // The server processes input asynchronously; wait for the stream end
+    wait_for_end(&events).await;
+
+    let events = events.lock().unwrap();
+    assert!(
+        matches!(events.last(), Some(Event::End)),
+        "stream end not received: {events:?}"
+    );
+    match &events[0] {
+        Event::Config(config) => {
+            assert_eq!(config.sps, vec![SPS.to_vec()]);
+            assert_eq!(config.pps, vec![PPS.to_vec()]);
+            assert_eq!(config.nalu_length_size, 4);
+        }
+        other => panic!("expected decoder config first, got {other:?}"),
+    }
+
+    let video: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            Event::Video(data, ts) => Some((data.clone(), *ts)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(video.len(), frames.len());
+    for (i, ((data, ts), (_, nal))) in video.iter().zip(frames.iter()).enumerate() {
+        assert_eq!(*ts, i as u32 * 33);
+        assert_eq!(&data[..4], &(nal.len() as u32).to_be_bytes());
+        assert_eq!(&data[4..], *nal);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_large_client_chunk_size() {
+    let (addr, events) = spawn_recording_server(Server::new());
+
+    // Frames of several chunks and of many server reads each
+    let mut idr = vec![0x65, 0x88];
+    idr.resize(200_000, 0xAB);
+    let _client = run_client(async {
+        let mut config = ClientSessionConfig::new();
+        config.chunk_size = 65536;
+        let mut client = TestClient::connect_with_config(addr, config).await?;
+        client.publish("live", "test").await?;
+        client.send_video(sequence_header_tag(), 0).await?;
+        client.send_video(nalu_tag(true, &idr), 0).await?;
+        client.send_video(nalu_tag(false, &[0x41, 0x9A]), 33).await?;
+        client.stop().await?;
+        Ok(client)
+    })
+    .await;
+    wait_for_end(&events).await;
+
+    let received: Vec<(usize, u32)> = events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|e| match e {
+            Event::Video(data, ts) => Some((data.len(), *ts)),
+            _ => None,
+        })
+        .collect();
+    // Each payload carries a 4-byte length prefix
+    assert_eq!(received, [(idr.len() + 4, 0), (6, 33)]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_timestamps_past_24_bits() {
+    let (addr, events) = spawn_recording_server(Server::new());
+
+    // Around 4h39m of media time, where chunk headers switch to the
+    // extended timestamp field
+    let timestamps = [0xFF_FFDE, 0xFF_FFFF, 0x100_0000, 0x100_0021, 0x100_0042];
+    let _client = run_client(async {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live", "test").await?;
+        client.send_video(sequence_header_tag(), timestamps[0]).await?;
+        for &ts in &timestamps {
+            client.send_video(nalu_tag(false, &[0x41, 0x9A]), ts).await?;
+        }
+        client.stop().await?;
+        Ok(client)
+    })
+    .await;
+    wait_for_end(&events).await;
+
+    let received: Vec<u32> = events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|e| match e {
+            Event::Video(_, ts) => Some(*ts),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(received, timestamps);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_aggregate_message_reaches_sink() {
+    let (addr, events) = spawn_recording_server(Server::new());
+
+    // Sub-message timestamps are relative to the first one
+    let mut tags = Vec::new();
+    tags.extend(flv_tag(9, 500, &nalu_tag(true, &[0x65, 0x88])));
+    tags.extend(flv_tag(8, 510, &[0xAF, 0x01, 0x00]));
+    tags.extend(flv_tag(9, 533, &nalu_tag(false, &[0x41, 0x9A])));
+    let _client = run_client(async {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live", "test").await?;
+        client.send_video(sequence_header_tag(), 0).await?;
+        client.send_aggregate(tags, 1000).await?;
+        client.stop().await?;
+        Ok(client)
+    })
+    .await;
+    wait_for_end(&events).await;
+
+    let received: Vec<u32> = events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|e| match e {
+            Event::Video(_, ts) => Some(*ts),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(received, [1000, 1033]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_end_of_sequence_reaches_sink() {
+    let (addr, events) = spawn_recording_server(Server::new());
+
+    // An encoder reconfiguring mid-stream
+    let _client = run_client(async {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live", "test").await?;
+        client.send_video(sequence_header_tag(), 0).await?;
+        client.send_video(nalu_tag(true, &[0x65, 0x88]), 0).await?;
+        client.send_video(vec![0x17, 0x02, 0x00, 0x00, 0x00], 33).await?;
+        client.send_video(sequence_header_tag(), 33).await?;
+        client.send_video(nalu_tag(true, &[0x65, 0x88]), 33).await?;
+        client.stop().await?;
+        Ok(client)
+    })
+    .await;
+    wait_for_end(&events).await;
+
+    let kinds: Vec<&str> = events
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|e| match e {
+            Event::Config(_) => "config",
+            Event::Video(..) => "video",
+            Event::EndOfSequence => "end of sequence",
+            Event::End => "end",
+            _ => "other",
+        })
+        .collect();
+    assert_eq!(
+        kinds,
+        ["config", "video", "end of sequence", "config", "video", "end"]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_delete_stream_ends_publish() {
+    let (addr, events) = spawn_recording_server(Server::new());
+
+    // A publisher that deletes its stream without closeStream or
+    // FCUnpublish first, and keeps the connection open
+    let _client = run_client(async {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live", "test").await?;
+        client.send_video(sequence_header_tag(), 0).await?;
+        client
+            .send_command("deleteStream", vec![Amf0Value::Number(1.0)])
+            .await?;
+        Ok(client)
+    })
+    .await;
+    wait_for_end(&events).await;
+
+    let events = events.lock().unwrap();
+    let ends = events.iter().filter(|e| matches!(e, Event::End)).count();
+    assert_eq!(ends, 1, "{events:?}");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_failed_sink_closes_connection() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink_events = Arc::clone(&events);
+    let factory = move |_: &ConnectionContext| -> io::Result<Box<dyn VideoSink>> {
+        Ok(Box::new(FailingSink {
+            events: Arc::clone(&sink_events),
+            frames: 0,
+            fail_after: 3,
+        }))
+    };
+    let addr = spawn_server(Server::new(), factory);
+
+    let mut client = run_client(async {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live", "test").await?;
+        client.send_video(sequence_header_tag(), 0).await?;
+        for ts in 0..3 {
+            client.send_video(nalu_tag(false, &[0x41, 0xFF]), ts * 33).await?;
+        }
+        Ok(client)
+    })
+    .await;
+
+    // The server ends the publish and hangs up without the client stopping
+    wait_for_end(&events).await;
+    assert!(matches!(events.lock().unwrap().last(), Some(Event::End)));
+    let closed = async {
+        let mut buf = [0u8; 4096];
+        while let Ok(1..) = client.stream.read(&mut buf).await {}
+    };
+    tokio::time::timeout(Duration::from_secs(1), closed)
+        .await
+        .expect("connection still open");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_color_info_reaches_sink_once() {
+    let (addr, events) = spawn_recording_server(Server::new());
+
+    let _client = run_client(async {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live", "test").await?;
+        client.send_video(sequence_header_tag(), 0).await?;
+        // Sent again with each keyframe, as OBS does
+        for i in 0..2 {
+            client.send_video(color_info_tag(), i * 33).await?;
+            client
+                .send_video(nalu_tag(true, &[0x65, 0x88, 0x84, 0x00]), i * 33)
+                .await?;
+        }
+        client.stop().await?;
+        Ok(client)
+    })
+    .await;
+    wait_for_end(&events).await;
+
+    let events = events.lock().unwrap();
+    let color_info: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            Event::ColorInfo(info) => Some(info),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(color_info.len(), 1, "events: {events:?}");
+    assert_eq!(color_info[0].bit_depth, Some(10));
+    let mastering = color_info[0].mastering_display.unwrap();
+    assert_eq!(mastering.max_luminance, 1000.0);
+    assert_eq!(mastering.min_luminance, 0.005);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_record_mode_writes_flv() {
+    let dir = std::env::temp_dir().join(format!("rtmp-vcam-e2e-rec-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let server = Server::new().with_recording_dir(&dir);
+    let (addr, events) = spawn_recording_server(server);
+
+    for (key, mode) in [
+        ("recorded", PublishRequestType::Record),
+        ("live", PublishRequestType::Live),
+    ] {
+        let _client = run_client(async {
+            let mut client = TestClient::connect(addr).await?;
+            client.publish_as("live", key, mode).await?;
+            client.send_video(sequence_header_tag(), 0).await?;
+            client
+                .send_video(nalu_tag(true, &[0x65, 0x88, 0x84, 0x00]), 33)
+                .await?;
+            client.stop().await?;
+            Ok(client)
+        })
+        .await;
+        wait_for_end(&events).await;
+        events.lock().unwrap().clear();
+    }
+
+    let flv = std::fs::read(dir.join("recorded.flv")).unwrap();
+    let tags = FlvTagReader::new().push(&flv).unwrap();
+    let timestamps: Vec<_> = tags.iter().map(|tag| tag.timestamp).collect();
+    assert_eq!(timestamps, [0, 33]);
+    assert_eq!(tags[0].data[..], sequence_header_tag()[..]);
+    assert!(!dir.join("live.flv").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_connect_params_reach_sink() {
+    // The query doesn't stop the app from matching the allowlist
+    let server = Server::new().with_app_allowlist(["live"]);
+    let handle = server.clone();
+    let (addr, events) = spawn_recording_server(server);
+
+    let (_client, publisher) = run_client(async {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live?token=xyz", "test").await?;
+        let publisher = wait_for_publisher(&handle, "test").await;
+        client.send_video(sequence_header_tag(), 0).await?;
+        client.stop().await?;
+        Ok((client, publisher))
+    })
+    .await;
+    wait_for_end(&events).await;
+    assert_eq!(publisher.app_name, "live");
+
+    let events = events.lock().unwrap();
+    match &events[..] {
+        [Event::ConnectParams(params), Event::Config(_), .., Event::End] => {
+            assert_eq!(params.get("token").map(String::as_str), Some("xyz"));
+        }
+        other => panic!("unexpected events: {other:?}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_app_allowlist_rejects_unknown_app() {
+    let addr = free_port_addr();
+    let factory = |_: &ConnectionContext| -> io::Result<Box<dyn VideoSink>> {
+        Ok(Box::new(RecordingSink {
+            events: Arc::default(),
+        }))
+    };
+    let server = Server::new().with_app_allowlist(["live"]);
+    tokio::spawn(async move { server.run(addr, factory, None).await });
+
+    let publish = |app: &'static str| async move {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish(app, "test").await
+    };
+    let accepted = tokio::time::timeout(Duration::from_secs(5), publish("live")).await;
+    assert!(
+        matches!(accepted, Ok(Ok(()))),
+        "live rejected: {accepted:?}"
+    );
+
+    let rejected = tokio::time::timeout(Duration::from_secs(5), publish("bogus")).await;
+    assert!(
+        matches!(rejected, Ok(Err(_))),
+        "bogus accepted: {rejected:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_dual_stack_accepts_ipv4_and_ipv6() {
+    let port = free_port_addr().port();
+    let factory = |_: &ConnectionContext| -> io::Result<Box<dyn VideoSink>> {
+        Ok(Box::new(RecordingSink {
+            events: Arc::default(),
+        }))
+    };
+    let server = Server::new().with_dual_stack(true);
+    let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+    tokio::spawn(async move { server.run(addr, factory, None).await });
+
+    for client_addr in [
+        SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+        SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+    ] {
+        let publish = async {
+            let mut client = TestClient::connect(client_addr).await?;
+            client.publish("live", &format!("test-{}", client_addr.ip())).await
+        };
+        let result = tokio::time::timeout(Duration::from_secs(5), publish).await;
+        assert!(
+            matches!(result, Ok(Ok(()))),
+            "{client_addr} failed: {result:?}"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_listens_on_several_ports() {
+    let addrs = [free_port_addr(), free_port_addr()];
+    let factory = |_: &ConnectionContext| -> io::Result<Box<dyn VideoSink>> {
+        Ok(Box::new(RecordingSink {
+            events: Arc::default(),
+        }))
+    };
+    let server = Server::new();
+    let handle = server.clone();
+    tokio::spawn(async move { server.run_all(&addrs, factory, None).await });
+
+    // Both publishers stay connected at the same time
+    let publish = |addr: SocketAddr| async move {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live", &format!("port-{}", addr.port())).await?;
+        Ok::<_, io::Error>(client)
+    };
+    let clients = async { tokio::try_join!(publish(addrs[0]), publish(addrs[1])) };
+    let _clients = tokio::time::timeout(Duration::from_secs(5), clients)
+        .await
+        .expect("clients timed out")
+        .expect("publish failed");
+
+    let mut keys: Vec<String> = handle
+        .active_publishers()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    keys.sort();
+    let mut expected = addrs.map(|addr| format!("port-{}", addr.port()));
+    expected.sort();
+    assert_eq!(keys, expected);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_publishes_over_tls_and_tcp_at_once() {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert = certified.cert.der().clone();
+    let key = PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into());
+    let tls = TlsConfig::new(vec![cert.clone()], key).unwrap();
+
+    // One event list per stream key
+    let streams = Arc::new(Mutex::new(HashMap::<String, Arc<Mutex<Vec<Event>>>>::new()));
+    let sink_streams = Arc::clone(&streams);
+    let factory = move |context: &ConnectionContext| -> io::Result<Box<dyn VideoSink>> {
+        let events = Arc::clone(
+            sink_streams
+                .lock()
+                .unwrap()
+                .entry(context.stream_key.clone())
+                .or_default(),
+        );
+        Ok(Box::new(RecordingSink { events }))
+    };
+    let (tcp_addr, tls_addr) = (free_port_addr(), free_port_addr());
+    let listeners = [Listener::Tcp(tcp_addr), Listener::Tls(tls_addr, tls)];
+    let server = Server::new();
+    let handle = server.clone();
+    tokio::spawn(async move { server.serve(&listeners, factory, None).await });
+
+    // Both publishers are connected at the same time, then each sends a frame
+    let plain_nal: &[u8] = &[0x65, 0x88, 0x84, 0x00];
+    let secure_nal: &[u8] = &[0x65, 0x88, 0x84, 0x01];
+    let (mut tcp_client, mut tls_client) = run_client(async {
+        let mut tcp_client = TestClient::connect(tcp_addr).await?;
+        let mut tls_client = TestClient::connect_tls(tls_addr, cert).await?;
+        tcp_client.publish("live", "plain").await?;
+        tls_client.publish("live", "secure").await?;
+        Ok((tcp_client, tls_client))
+    })
+    .await;
+    wait_for_publisher(&handle, "plain").await;
+    wait_for_publisher(&handle, "secure").await;
+    run_client(async {
+        tcp_client.send_video(sequence_header_tag(), 0).await?;
+        tls_client.send_video(sequence_header_tag(), 0).await?;
+        tcp_client.send_video(nalu_tag(true, plain_nal), 0).await?;
+        tls_client.send_video(nalu_tag(true, secure_nal), 0).await?;
+        tcp_client.stop().await?;
+        tls_client.stop().await
+    })
+    .await;
+
+    for (stream_key, nal) in [("plain", plain_nal), ("secure", secure_nal)] {
+        let events = Arc::clone(&streams.lock().unwrap()[stream_key]);
+        wait_for_end(&events).await;
+        let events = events.lock().unwrap();
+        assert!(
+            matches!(events.first(), Some(Event::Config(_))),
+            "{stream_key}: {events:?}"
+        );
+        match events.get(1) {
+            Some(Event::Video(data, 0)) => assert_eq!(&data[4..], nal, "{stream_key}"),
+            other => panic!("{stream_key}: expected a frame, got {other:?}"),
+        }
+        assert!(
+            matches!(events.last(), Some(Event::End)),
+            "{stream_key}: {events:?}"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_publish_over_unix_socket() {
+    let path = std::env::temp_dir().join(format!("rtmp-vcam-e2e-{}.sock", std::process::id()));
+    let events = Arc::new(Mutex::new(Vec::new()));
+
+    let sink_events = Arc::clone(&events);
+    let factory = move |_: &ConnectionContext| -> io::Result<Box<dyn VideoSink>> {
+        Ok(Box::new(RecordingSink {
+            events: Arc::clone(&sink_events),
+        }))
+    };
+    let server_path = path.clone();
+    tokio::spawn(async move { rtmp_server::server::run_unix(server_path, factory, None).await });
+
+    let _client = run_client(async {
+        let mut client = TestClient::connect_unix(&path).await?;
+        client.publish("live", "local").await?;
+        client.send_video(sequence_header_tag(), 0).await?;
+        client
+            .send_video(nalu_tag(true, &[0x65, 0x88, 0x84, 0x00]), 0)
+            .await?;
+        client.stop().await?;
+        Ok(client)
+    })
+    .await;
+
+    wait_for_end(&events).await;
+    std::fs::remove_file(&path).ok();
+
+    let events = events.lock().unwrap();
+    assert!(
+        matches!(events.first(), Some(Event::Config(_))),
+        "{events:?}"
+    );
+    assert!(
+        matches!(events.get(1), Some(Event::Video(_, 0))),
+        "{events:?}"
+    );
+    assert!(matches!(events.last(), Some(Event::End)), "{events:?}");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_keyframe_request_reaches_publisher() {
+    let server = Server::new();
+    let handle = server.clone();
+    let (addr, _events) = spawn_recording_server(server);
+    assert!(!handle.request_keyframe("test"));
+
+    let args = run_client(async {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live", "test").await?;
+        client.send_video(sequence_header_tag(), 0).await?;
+        // Joined mid-GOP: only a P-frame so far
+        client
+            .send_video(nalu_tag(false, &[0x41, 0x9A, 0x02]), 0)
+            .await?;
+
+        // The publish is registered once the server has accepted it
+        for _ in 0..100 {
+            if handle.request_keyframe("test") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        // The request goes out when the server next handles input
+        client
+            .send_video(nalu_tag(false, &[0x41, 0x9A, 0x04]), 33)
+            .await?;
+        let args = client.wait_for_command(KEYFRAME_REQUEST_COMMAND).await?;
+
+        // The session's own messages still decode after the request: publish
+        // again, which is answered with onStatus on a new stream
+        client.stop().await?;
+        client.publish("live", "again").await?;
+        Ok(args)
+    })
+    .await;
+    assert_eq!(args, [Amf0Value::Utf8String("test".to_string())]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_shutdown_closes_active_connections() {
+    let addr = free_port_addr();
+    let events = Arc::new(Mutex::new(Vec::new()));
+
+    let sink_events = Arc::clone(&events);
+    let factory = move |_: &ConnectionContext| -> io::Result<Box<dyn VideoSink>> {
+        Ok(Box::new(RecordingSink {
+            events: Arc::clone(&sink_events),
+        }))
+    };
+    let server = Server::new();
+    let handle = server.clone();
+    let running = tokio::spawn(async move { server.run(addr, factory, None).await });
+
+    let mut clients = Vec::new();
+    for key in ["one", "two", "three"] {
+        let client = run_client(async {
+            let mut client = TestClient::connect(addr).await?;
+            client.publish("live", key).await?;
+            client.send_video(sequence_header_tag(), 0).await?;
+            Ok(client)
+        })
+        .await;
+        clients.push(client);
+    }
+    // Every publish has reached its sink
+    for _ in 0..100 {
+        if handle.active_publishers().len() == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(handle.active_publishers().len(), 3);
+
+    handle.shutdown();
+    let result = tokio::time::timeout(Duration::from_secs(2), running)
+        .await
+        .expect("server didn't stop")
+        .unwrap();
+    assert!(result.is_ok(), "{result:?}");
+
+    let ends = events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|event| matches!(event, Event::End))
+        .count();
+    assert_eq!(ends, 3);
+    assert!(handle.active_publishers().is_empty());
+
+    // The clients see their connections closed, after anything still unread
+    for mut client in clients {
+        let closed = async {
+            let mut buf = [0u8; 4096];
+            while let Ok(1..) = client.stream.read(&mut buf).await {}
+        };
+        tokio::time::timeout(Duration::from_secs(1), closed)
+            .await
+            .expect("connection still open");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_publisher_ingest_bitrate() {
+    let server = Server::new();
+    let handle = server.clone();
+    let (addr, _events) = spawn_recording_server(server);
+
+    let mut idr = vec![0x65, 0x88];
+    idr.resize(10_000, 0xAB);
+    let _client = run_client(async {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live", "test").await?;
+        client.send_video(sequence_header_tag(), 0).await?;
+        client.send_video(nalu_tag(true, &idr), 0).await?;
+        Ok(client)
+    })
+    .await;
+
+    // The bytes it sent are counted against the publish
+    let mut publisher = wait_for_publisher(&handle, "test").await;
+    for _ in 0..100 {
+        if publisher.ingest_kbps > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        publisher = wait_for_publisher(&handle, "test").await;
+    }
+    assert!(publisher.ingest_kbps > 0, "{publisher:?}");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_publisher_stats_count_messages_by_type() {
+    let server = Server::new();
+    let handle = server.clone();
+    let (addr, _events) = spawn_recording_server(server);
+
+    let _client = run_client(async {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live", "test").await?;
+        client.send_video(sequence_header_tag(), 0).await?;
+        client.send_video(nalu_tag(true, &[0x65, 0x88]), 0).await?;
+        client.send_video(nalu_tag(false, &[0x41, 0x9A]), 33).await?;
+        // AAC sequence header, then one raw frame
+        client.send_audio(vec![0xAF, 0x00, 0x12, 0x10], 0).await?;
+        client.send_audio(vec![0xAF, 0x01, 0x21], 23).await?;
+        Ok(client)
+    })
+    .await;
+
+    let mut messages = wait_for_publisher(&handle, "test").await.messages;
+    for _ in 0..100 {
+        if messages.get(TYPE_ID_VIDEO) == 3 && messages.get(TYPE_ID_AUDIO) == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        messages = wait_for_publisher(&handle, "test").await.messages;
+    }
+    assert_eq!(messages.get(TYPE_ID_VIDEO), 3, "{messages}");
+    assert_eq!(messages.get(TYPE_ID_AUDIO), 2, "{messages}");
+    // connect, releaseStream, publish and the like
+    assert!(messages.get(TYPE_ID_AMF0_COMMAND) > 0, "{messages}");
+}
+
+#[cfg(feature = "audio-level")]
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_audio_levels_reach_sink() {
+    use video_pipeline::audio::SILENCE_DB;
+
+    // Mono AAC-LC frame with a single spectral line in the lowest band, a
+    // steady tone whose level is set by the global gain: each step of 10 is
+    // 15 dB
+    let tone = |global_gain: u8| {
+        vec![0xAF, 0x01, global_gain >> 7, global_gain << 1, 0x00, 0x84, 0x21, 0x0E]
+    };
+    let silence = vec![0xAF, 0x01, 0x00, 0xC8, 0x00, 0x80, 0x23, 0x80];
+    let (addr, events) = spawn_recording_server(Server::new());
+
+    let _client = run_client(async {
+        let mut client = TestClient::connect(addr).await?;
+        client.publish("live", "test").await?;
+        // AAC-LC, 44.1 kHz, mono
+        client.send_audio(vec![0xAF, 0x00, 0x12, 0x08], 0).await?;
+        let frames = [(tone(176), 10), (tone(196), 10), (silence, 10)];
+        let mut timestamp = 0;
+        for (frame, count) in frames {
+            for _ in 0..count {
+                client.send_audio(frame.clone(), timestamp).await?;
+                timestamp += 23;
+            }
+        }
+        client.stop().await?;
+        Ok(client)
+    })
+    .await;
+    wait_for_end(&events).await;
+
+    let levels: Vec<(f32, f32)> = events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|e| match e {
+            Event::AudioLevel(rms_db, peak_db) => Some((*rms_db, *peak_db)),
+            _ => None,
+        })
+        .collect();
+    assert!(levels.iter().all(|(rms, peak)| rms <= peak), "{levels:?}");
+    let peaks: Vec<f32> = levels.iter().map(|(_, peak)| *peak).collect();
+    // The decoder's priming delay may hold back a frame or two
+    assert!(peaks.len() >= 25, "{peaks:?}");
+    let loud = peaks.iter().copied().fold(SILENCE_DB, f32::max);
+    assert!((-15.0..-3.0).contains(&loud), "{peaks:?}");
+    // The quiet tone, 30 dB down, held steady for several frames
+    let quiet = peaks.iter().filter(|p| (*p - (loud - 30.0)).abs() < 1.5);
+    assert!(quiet.count() >= 5, "{peaks:?}");
+    assert_eq!(peaks.last(), Some(&SILENCE_DB), "{peaks:?}");
+}
@@ -0,0 +1,72 @@
+//! `--config`: settings read from a TOML file at startup. Each key is
+//! named after its command-line flag, and flags given on the command line
+//! take precedence over the file.
+
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// Settings from a config file; anything left out keeps its default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub port: Option<u16>,
+    pub mode: Option<String>,
+    /// RTMP app names to accept, as repeated `--app` flags.
+    pub app: Vec<String>,
+    pub unix_socket: Option<PathBuf>,
+    pub dual_stack: Option<bool>,
+    pub tls_port: Option<u16>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub record_dir: Option<PathBuf>,
+    pub record_keyframes_only: Option<bool>,
+    pub stream_key: Option<String>,
+    pub decode_thread: Option<bool>,
+    pub pace: Option<u64>,
+    pub trace_timing: Option<bool>,
+    pub crc_frames: Option<bool>,
+    pub commit_policy: Option<String>,
+    pub log_file: Option<PathBuf>,
+    pub log_format: Option<String>,
+    pub verbose: Option<bool>,
+}
+
+impl Config {
+    /// Read and parse the config file at `path`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        Self::parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "port = 1936\n\
+             app = [\"live\", \"backup\"]\n\
+             stream-key = \"secret\"\n\
+             decode-thread = true\n",
+        )
+        .unwrap();
+        assert_eq!(config.port, Some(1936));
+        assert_eq!(config.app, ["live", "backup"]);
+        assert_eq!(config.stream_key.as_deref(), Some("secret"));
+        assert_eq!(config.decode_thread, Some(true));
+        assert_eq!(config.mode, None);
+
+        // Misspelt keys are errors rather than silently ignored
+        let err = Config::parse("prot = 1936\n").unwrap_err();
+        assert!(err.contains("prot"), "{err}");
+        assert!(Config::parse("port = \"1936\"\n").is_err());
+    }
+}
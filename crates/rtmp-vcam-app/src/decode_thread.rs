@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use bytes::Bytes;
+use tokio::runtime::Handle;
+use tracing::{debug, warn};
+
+use rtmp_server::{AvcDecoderConfig, ColorInfo, StreamInfo, VideoSink};
+
+/// Sink callbacks forwarded to the decode thread.
+pub(crate) enum SinkEvent {
+    Config(AvcDecoderConfig),
+    ConnectParams(HashMap<String, String>),
+    Video(Bytes, u32),
+    StreamInfo(StreamInfo),
+    ColorInfo(ColorInfo),
+    Sei(u32, Vec<u8>),
+    AudioLevel(f32, f32),
+    FirstFrame(usize, usize),
+    DecoderCreateError(i32, String),
+    EndOfSequence,
+    End,
+}
+
+/// VideoSink that hands every callback to another sink running on a
+/// dedicated thread, so a slow decode doesn't hold up the connection task's
+/// socket reads.
+///
+/// Events go through a bounded channel. When it is full the connection task
+/// waits for room, off the async worker, so the backpressure reaches the
+/// publisher through TCP flow control instead of stalling other tasks.
+pub struct ThreadedSink {
+    tx: Option<SyncSender<SinkEvent>>,
+    thread: Option<JoinHandle<()>>,
+    /// Times a send had to wait for the decode thread.
+    waits: u64,
+    /// Set once the inner sink reports it has given up on the stream.
+    failed: Arc<AtomicBool>,
+}
+
+impl ThreadedSink {
+    /// Run `inner` on a new thread behind a queue of `capacity` events.
+    /// Must be called from within a Tokio runtime, which the thread keeps
+    /// entered so `inner` can use the blocking pool.
+    pub fn spawn(inner: Box<dyn VideoSink>, capacity: usize) -> std::io::Result<Self> {
+        Self::spawn_with(inner, capacity, "decode", run)
+    }
+
+    /// Like `spawn`, but the thread, called `name`, runs `run` to take
+    /// events off the queue and deliver them to `inner`.
+    pub(crate) fn spawn_with<F>(
+        inner: Box<dyn VideoSink>,
+        capacity: usize,
+        name: &str,
+        run: F,
+    ) -> std::io::Result<Self>
+    where
+        F: FnOnce(Box<dyn VideoSink>, Receiver<SinkEvent>) + Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let failed = Arc::new(AtomicBool::new(false));
+        let inner = Box::new(FailureWatch {
+            inner,
+            failed: Arc::clone(&failed),
+        });
+        let handle = Handle::current();
+        let thread = std::thread::Builder::new()
+            .name(name.into())
+            .spawn(move || {
+                let _runtime = handle.enter();
+                run(inner, rx);
+            })?;
+        Ok(Self {
+            tx: Some(tx),
+            thread: Some(thread),
+            waits: 0,
+            failed,
+        })
+    }
+
+    fn send(&mut self, event: SinkEvent) {
+        let Some(tx) = &self.tx else { return };
+        let event = match tx.try_send(event) {
+            Ok(()) => return,
+            Err(TrySendError::Disconnected(_)) => {
+                warn!("decode thread exited, dropping video");
+                self.tx = None;
+                return;
+            }
+            Err(TrySendError::Full(event)) => event,
+        };
+
+        self.waits += 1;
+        if self.waits.is_power_of_two() {
+            debug!(
+                waits = self.waits,
+                "decode queue full, waiting for the decode thread"
+            );
+        }
+        let sent = tokio::task::block_in_place(|| tx.send(event));
+        if sent.is_err() {
+            warn!("decode thread exited, dropping video");
+            self.tx = None;
+        }
+    }
+}
+
+impl VideoSink for ThreadedSink {
+    fn on_decoder_config(&mut self, config: AvcDecoderConfig) {
+        self.send(SinkEvent::Config(config));
+    }
+
+    fn on_connect_params(&mut self, params: HashMap<String, String>) {
+        self.send(SinkEvent::ConnectParams(params));
+    }
+
+    fn on_video_data(&mut self, data: Bytes, timestamp: u32) {
+        self.send(SinkEvent::Video(data, timestamp));
+    }
+
+    fn on_stream_info(&mut self, info: StreamInfo) {
+        self.send(SinkEvent::StreamInfo(info));
+    }
+
+    fn on_color_info(&mut self, info: ColorInfo) {
+        self.send(SinkEvent::ColorInfo(info));
+    }
+
+    fn on_sei(&mut self, payload_type: u32, data: &[u8]) {
+        self.send(SinkEvent::Sei(payload_type, data.to_vec()));
+    }
+
+    fn on_audio_level(&mut self, rms_db: f32, peak_db: f32) {
+        self.send(SinkEvent::AudioLevel(rms_db, peak_db));
+    }
+
+    fn on_first_frame(&mut self, width: usize, height: usize) {
+        self.send(SinkEvent::FirstFrame(width, height));
+    }
+
+    fn on_decoder_create_error(&mut self, status: i32, message: &str) {
+        self.send(SinkEvent::DecoderCreateError(status, message.to_string()));
+    }
+
+    fn on_end_of_sequence(&mut self) {
+        self.send(SinkEvent::EndOfSequence);
+    }
+
+    fn on_stream_end(&mut self) {
+        self.send(SinkEvent::End);
+    }
+
+    fn is_failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// Runs on the decode thread in front of the inner sink and publishes its
+/// `is_failed` to the connection task.
+struct FailureWatch {
+    inner: Box<dyn VideoSink>,
+    failed: Arc<AtomicBool>,
+}
+
+impl VideoSink for FailureWatch {
+    fn on_decoder_config(&mut self, config: AvcDecoderConfig) {
+        self.inner.on_decoder_config(config);
+    }
+
+    fn on_connect_params(&mut self, params: HashMap<String, String>) {
+        self.inner.on_connect_params(params);
+    }
+
+    fn on_video_data(&mut self, data: Bytes, timestamp: u32) {
+        self.inner.on_video_data(data, timestamp);
+        if self.inner.is_failed() {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn on_stream_info(&mut self, info: StreamInfo) {
+        self.inner.on_stream_info(info);
+    }
+
+    fn on_color_info(&mut self, info: ColorInfo) {
+        self.inner.on_color_info(info);
+    }
+
+    fn on_sei(&mut self, payload_type: u32, data: &[u8]) {
+        self.inner.on_sei(payload_type, data);
+    }
+
+    fn on_audio_level(&mut self, rms_db: f32, peak_db: f32) {
+        self.inner.on_audio_level(rms_db, peak_db);
+    }
+
+    fn on_first_frame(&mut self, width: usize, height: usize) {
+        self.inner.on_first_frame(width, height);
+    }
+
+    fn on_decoder_create_error(&mut self, status: i32, message: &str) {
+        self.inner.on_decoder_create_error(status, message);
+    }
+
+    fn on_end_of_sequence(&mut self) {
+        self.inner.on_end_of_sequence();
+    }
+
+    fn on_stream_error(&mut self, message: &str) {
+        self.inner.on_stream_error(message);
+    }
+
+    fn on_stream_end(&mut self) {
+        self.inner.on_stream_end();
+    }
+}
+
+impl Drop for ThreadedSink {
+    fn drop(&mut self) {
+        // Closing the channel lets the thread finish what's queued and exit
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            if tokio::task::block_in_place(|| thread.join()).is_err() {
+                warn!("decode thread panicked");
+            }
+        }
+    }
+}
+
+impl SinkEvent {
+    /// Make the sink call this event stands for.
+    pub(crate) fn deliver(self, sink: &mut dyn VideoSink) {
+        match self {
+            SinkEvent::Config(config) => sink.on_decoder_config(config),
+            SinkEvent::ConnectParams(params) => sink.on_connect_params(params),
+            SinkEvent::Video(data, timestamp) => sink.on_video_data(data, timestamp),
+            SinkEvent::StreamInfo(info) => sink.on_stream_info(info),
+            SinkEvent::ColorInfo(info) => sink.on_color_info(info),
+            SinkEvent::Sei(payload_type, data) => sink.on_sei(payload_type, &data),
+            SinkEvent::AudioLevel(rms_db, peak_db) => sink.on_audio_level(rms_db, peak_db),
+            SinkEvent::FirstFrame(width, height) => sink.on_first_frame(width, height),
+            SinkEvent::DecoderCreateError(status, message) => {
+                sink.on_decoder_create_error(status, &message)
+            }
+            SinkEvent::EndOfSequence => sink.on_end_of_sequence(),
+            SinkEvent::End => sink.on_stream_end(),
+        }
+    }
+}
+
+fn run(mut sink: Box<dyn VideoSink>, rx: Receiver<SinkEvent>) {
+    for event in rx {
+        event.deliver(sink.as_mut());
+    }
+    debug!("decode thread finished");
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    struct SlowSink {
+        received: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl VideoSink for SlowSink {
+        fn on_decoder_config(&mut self, _config: AvcDecoderConfig) {}
+
+        fn on_video_data(&mut self, _data: Bytes, timestamp: u32) {
+            std::thread::sleep(Duration::from_millis(5));
+            self.received.lock().unwrap().push(timestamp);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_queues_and_applies_backpressure() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let inner = Box::new(SlowSink {
+            received: Arc::clone(&received),
+        });
+        let mut sink = ThreadedSink::spawn(inner, 2).unwrap();
+
+        // The reactor stays responsive while the sink is backed up
+        let ticker = tokio::spawn(async {
+            let mut ticks = 0;
+            for _ in 0..10 {
+                tokio::time::sleep(Duration::from_millis(2)).await;
+                ticks += 1;
+            }
+            ticks
+        });
+
+        for ts in 0..20 {
+            sink.on_video_data(Bytes::from_static(&[0, 0, 0, 1, 0x41]), ts);
+        }
+        assert!(sink.waits > 0);
+        drop(sink);
+
+        // Nothing dropped, order kept
+        assert_eq!(*received.lock().unwrap(), (0..20).collect::<Vec<_>>());
+        assert_eq!(ticker.await.unwrap(), 10);
+    }
+
+    struct FailingSink {
+        frames: usize,
+    }
+
+    impl VideoSink for FailingSink {
+        fn on_decoder_config(&mut self, _config: AvcDecoderConfig) {}
+
+        fn on_video_data(&mut self, _data: Bytes, _timestamp: u32) {
+            self.frames += 1;
+        }
+
+        fn is_failed(&self) -> bool {
+            self.frames >= 3
+        }
+    }
+
+    #[derive(Default)]
+    struct CreateErrors {
+        received: Arc<Mutex<Vec<(i32, String)>>>,
+    }
+
+    impl VideoSink for CreateErrors {
+        fn on_decoder_config(&mut self, _config: AvcDecoderConfig) {}
+
+        fn on_video_data(&mut self, _data: Bytes, _timestamp: u32) {}
+
+        fn on_decoder_create_error(&mut self, status: i32, message: &str) {
+            self.received
+                .lock()
+                .unwrap()
+                .push((status, message.to_string()));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_forwards_decoder_create_error() {
+        let inner = CreateErrors::default();
+        let received = Arc::clone(&inner.received);
+        let mut sink = ThreadedSink::spawn(Box::new(inner), 8).unwrap();
+        sink.on_decoder_create_error(-12710, "failed to create format description");
+        drop(sink);
+        assert_eq!(
+            *received.lock().unwrap(),
+            [(-12710, "failed to create format description".to_string())]
+        );
+    }
+
+    /// Records the callbacks the session or the decoder raise besides video.
+    #[derive(Default)]
+    pub(crate) struct Raised {
+        pub levels: Arc<Mutex<Vec<(f32, f32)>>>,
+        pub first_frames: Arc<Mutex<Vec<(usize, usize)>>>,
+    }
+
+    impl VideoSink for Raised {
+        fn on_decoder_config(&mut self, _config: AvcDecoderConfig) {}
+
+        fn on_video_data(&mut self, _data: Bytes, _timestamp: u32) {}
+
+        fn on_audio_level(&mut self, rms_db: f32, peak_db: f32) {
+            self.levels.lock().unwrap().push((rms_db, peak_db));
+        }
+
+        fn on_first_frame(&mut self, width: usize, height: usize) {
+            self.first_frames.lock().unwrap().push((width, height));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_forwards_audio_level_and_first_frame() {
+        let inner = Raised::default();
+        let (levels, first_frames) = (Arc::clone(&inner.levels), Arc::clone(&inner.first_frames));
+        let mut sink = ThreadedSink::spawn(Box::new(inner), 8).unwrap();
+        sink.on_audio_level(-20.5, -6.0);
+        sink.on_first_frame(1920, 1080);
+        drop(sink);
+        assert_eq!(*levels.lock().unwrap(), [(-20.5, -6.0)]);
+        assert_eq!(*first_frames.lock().unwrap(), [(1920, 1080)]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_reports_inner_failure() {
+        let mut sink = ThreadedSink::spawn(Box::new(FailingSink { frames: 0 }), 8).unwrap();
+        for ts in 0..3 {
+            assert!(!sink.is_failed());
+            sink.on_video_data(Bytes::from_static(&[0, 0, 0, 1, 0x41]), ts);
+        }
+        // The failure shows up once the decode thread has caught up
+        for _ in 0..100 {
+            if sink.is_failed() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("failure never reported");
+    }
+}
@@ -0,0 +1,143 @@
+//! `--dump-flv`: list the tags of an FLV file, for debugging captured
+//! streams. Video tags go through the same parser as ingest.
+
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::Path;
+
+use rtmp_server::flv::{parse_video_data, FlvTag};
+use rtmp_server::http_flv::FlvFileTags;
+use rtmp_server::recording::{TAG_TYPE_AUDIO, TAG_TYPE_SCRIPT, TAG_TYPE_VIDEO};
+use rtmp_server::VideoPacket;
+
+/// Print one line per tag of the FLV file at `path` to `out`. Returns the
+/// number of tags.
+pub fn run(path: &Path, out: &mut impl Write) -> io::Result<u64> {
+    let file = File::open(path)?;
+    dump(FlvFileTags::new(BufReader::new(file)), out)
+}
+
+fn dump(tags: impl Iterator<Item = io::Result<FlvTag>>, out: &mut impl Write) -> io::Result<u64> {
+    // Updated by each sequence header, as during ingest
+    let mut nalu_length_size = 4;
+    let mut count = 0;
+    for tag in tags {
+        let tag = tag?;
+        writeln!(
+            out,
+            "{count:>6}  {:<6} {:>10} ms {:>8} bytes  {}",
+            tag_type_name(tag.tag_type),
+            tag.timestamp,
+            tag.data.len(),
+            describe(&tag, &mut nalu_length_size)
+        )?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn tag_type_name(tag_type: u8) -> &'static str {
+    match tag_type {
+        TAG_TYPE_AUDIO => "audio",
+        TAG_TYPE_VIDEO => "video",
+        TAG_TYPE_SCRIPT => "script",
+        _ => "other",
+    }
+}
+
+/// Details of a tag's body: frame and AVC packet type for video.
+fn describe(tag: &FlvTag, nalu_length_size: &mut u8) -> String {
+    let Some(&first) = tag.data.first() else {
+        return "empty".to_string();
+    };
+    match tag.tag_type {
+        TAG_TYPE_VIDEO => describe_video(tag, first, nalu_length_size),
+        TAG_TYPE_AUDIO => format!("sound format {}", first >> 4),
+        _ => String::new(),
+    }
+}
+
+fn describe_video(tag: &FlvTag, first: u8, nalu_length_size: &mut u8) -> String {
+    if first & 0x80 != 0 {
+        return format!("enhanced, packet type {}", first & 0x0F);
+    }
+    let frame_type = match first >> 4 {
+        1 => "keyframe",
+        2 => "inter frame",
+        3 => "disposable inter frame",
+        4 => "generated keyframe",
+        5 => "video info",
+        _ => "unknown frame type",
+    };
+    let codec_id = first & 0x0F;
+    if codec_id != 7 {
+        return format!("{frame_type}, codec {codec_id}");
+    }
+    match parse_video_data(&tag.data, tag.timestamp, *nalu_length_size) {
+        VideoPacket::SequenceHeader(config) => {
+            *nalu_length_size = config.nalu_length_size;
+            format!(
+                "{frame_type}, AVC sequence header: {} SPS, {} PPS, {}-byte NAL lengths",
+                config.sps.len(),
+                config.pps.len(),
+                config.nalu_length_size
+            )
+        }
+        VideoPacket::NaluData {
+            composition_time,
+            is_keyframe,
+            ..
+        } => {
+            let idr = if is_keyframe { ", IDR" } else { "" };
+            format!("{frame_type}, AVC NALU, cts {composition_time} ms{idr}")
+        }
+        VideoPacket::EndOfSequence => format!("{frame_type}, AVC end of sequence"),
+        VideoPacket::ColorInfo(_) | VideoPacket::Unsupported => {
+            let packet_type = tag.data.get(1).copied().unwrap_or(0);
+            format!("{frame_type}, AVC packet type {packet_type} (not parsed)")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use rtmp_server::flv::{nalu_tag, sequence_header_tag};
+    use rtmp_server::AvcDecoderConfig;
+
+    #[test]
+    fn test_dump_lists_tags() {
+        let config = AvcDecoderConfig {
+            sps: vec![vec![0x67, 0x42, 0xC0, 0x0A]],
+            pps: vec![vec![0x68, 0xCE]],
+            nalu_length_size: 4,
+        };
+        let tag = |tag_type, timestamp, data: Bytes| {
+            Ok(FlvTag {
+                tag_type,
+                timestamp,
+                data,
+            })
+        };
+        let tags = vec![
+            tag(TAG_TYPE_SCRIPT, 0, Bytes::from_static(&[0x02])),
+            tag(TAG_TYPE_VIDEO, 0, sequence_header_tag(&config)),
+            tag(TAG_TYPE_VIDEO, 0, nalu_tag(&[0, 0, 0, 2, 0x65, 0x88], true)),
+            tag(TAG_TYPE_AUDIO, 10, Bytes::from_static(&[0xAF, 0x01])),
+            tag(TAG_TYPE_VIDEO, 0x0100_0021, nalu_tag(&[0, 0, 0, 2, 0x41, 0x9A], false)),
+        ];
+
+        let mut out = Vec::new();
+        assert_eq!(dump(tags.into_iter(), &mut out).unwrap(), 5);
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].contains("script"));
+        assert!(lines[1].contains("AVC sequence header: 1 SPS, 1 PPS, 4-byte NAL lengths"));
+        assert!(lines[2].ends_with("keyframe, AVC NALU, cts 0 ms, IDR"));
+        assert!(lines[3].contains("audio") && lines[3].contains("sound format 10"));
+        assert!(lines[4].contains(" 16777249 ms"));
+        assert!(lines[4].ends_with("inter frame, AVC NALU, cts 0 ms"));
+    }
+}
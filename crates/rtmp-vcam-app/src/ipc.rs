@@ -1,11 +1,21 @@
+use std::collections::HashMap;
 use std::ffi::CString;
 use std::io;
-use std::path::PathBuf;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
 use std::ptr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
 
-use tracing::info;
+use tracing::{error, info, warn};
 
-use video_pipeline::FRAME_SHM_SIZE;
+use rtmp_server::sanitize_stream_key;
+use video_pipeline::{
+    ColorHeader, FrameHeader, ReaderCaps, ReaderRegistry, FRAME_LAYOUT_VERSION, FRAME_SHM_SIZE,
+    READER_CAPS_NAME, READER_CAPS_SIZE, READER_REGISTRY_SIZE,
+};
 
 /// Ring buffer file path — must be accessible to both the Rust process (as user)
 /// and the sandboxed CMIO extension (as _cmiodalassistants).
@@ -13,22 +23,51 @@ use video_pipeline::FRAME_SHM_SIZE;
 /// so we use /Library/Application Support/RTMPVirtualCamera/.
 const RING_FILE_PATH: &str = "/Library/Application Support/RTMPVirtualCamera/rtmp_vcam_ring";
 
+/// Attempts to create the ring file at startup before giving up. On first
+/// launch the installer may still be creating the directory or fixing its
+/// permissions.
+const CREATE_ATTEMPTS: u32 = 5;
+const CREATE_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
 /// File-backed mmap shared memory for publishing decoded NV12 frames
 /// to the Swift Camera Extension.
 ///
-/// Layout (see video_pipeline::decoder for constants):
-///   Header (64 bytes):
-///     [0..8)   write_index (u64, atomic)
-///     [8..12)  width (u32)
-///     [12..16) height (u32)
-///     [16..64) reserved
+/// Layout (see video_pipeline::FrameHeader):
+///   Header (128 bytes):
+///     [0..8)    write_index (u64, atomic)
+///     [8..16)   unused (width/height in layout version 1)
+///     [16..20)  magic "RVCM"
+///     [20..22)  layout version (u16)
+///     [24..56)  slot 0 header, [56..88) slot 1 header:
+///       +0  timestamp_ms (u64), +8 width (u32), +12 height (u32),
+///       +16 pixel format "NV12" / "I420" / "P010" / "P10L" (zeros = NV12),
+///       +20 display width (u32), +24 display height (u32) (zeros = square pixels)
+///     [88..128) stream color description (video_pipeline::ColorHeader, zeros = none)
 ///   Frame data (double-buffered):
-///     [64 .. 64+MAX_FRAME_SIZE)              frame buffer 0
-///     [64+MAX_FRAME_SIZE .. 64+2*MAX_FRAME_SIZE) frame buffer 1
+///     [128 .. 128+MAX_FRAME_SIZE)                 frame buffer 0
+///     [128+MAX_FRAME_SIZE .. 128+2*MAX_FRAME_SIZE) frame buffer 1
+///
+/// The extension can't write to the file, so what it can take goes in the
+/// POSIX shared memory object `READER_CAPS_NAME` instead (see
+/// video_pipeline::ReaderCaps):
+///     [0..8)    largest frame it wants, width and height (u32; zeros = any)
+///     [8..12)   formats it reads besides NV12 (mask of OutputFormat::caps_bit)
+///     [12..16)  reserved
 pub struct SharedFrameBuffer {
     ptr: *mut u8,
-    fd: i32,
+    /// Descriptor of the file currently mapped at `ptr`. Replaced when the
+    /// file is recreated after being deleted externally.
+    fd: Mutex<i32>,
     path: PathBuf,
+    /// Readers registered to consume this buffer, mapped from
+    /// `readers_path(path)`. The Camera Extension can't register: its
+    /// sandbox only lets it read the files.
+    readers: ReaderRegistry,
+    readers_ptr: *mut u8,
+    readers_fd: i32,
+    /// The reader's `ReaderCaps`, if attached; null and -1 otherwise.
+    caps_ptr: *mut u8,
+    caps_fd: i32,
 }
 
 // SAFETY: The shared memory region uses atomic operations for synchronization.
@@ -36,83 +75,551 @@ unsafe impl Send for SharedFrameBuffer {}
 unsafe impl Sync for SharedFrameBuffer {}
 
 impl SharedFrameBuffer {
-    /// Create and map the shared frame buffer file.
+    /// Create and map the shared frame buffer file read by the Camera Extension,
+    /// with the extension's `ReaderCaps` attached. Retries with backoff while
+    /// the directory is missing or not yet writable.
     pub fn create() -> io::Result<Self> {
-        let ring_path = PathBuf::from(RING_FILE_PATH);
-
-        // Ensure parent directory exists
-        if let Some(parent) = ring_path.parent() {
-            std::fs::create_dir_all(parent)?;
+        let mut buffer = with_retries(CREATE_ATTEMPTS, CREATE_INITIAL_BACKOFF, || {
+            Self::create_at(Path::new(RING_FILE_PATH))
+        })?;
+        if let Err(e) = buffer.attach_reader_caps(READER_CAPS_NAME) {
+            warn!(name = READER_CAPS_NAME, %e, "reader capabilities unavailable, ignoring them");
         }
+        Ok(buffer)
+    }
 
-        let c_path = CString::new(RING_FILE_PATH)
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains null"))?;
+    /// Create and map a shared frame buffer file at `ring_path`.
+    pub fn create_at(ring_path: &Path) -> io::Result<Self> {
+        let ring_path = ring_path.to_path_buf();
+        let (readers_fd, readers_ptr) = map_file(&readers_path(&ring_path), READER_REGISTRY_SIZE)?;
+        let (fd, ptr) = match map_file(&ring_path, FRAME_SHM_SIZE) {
+            Ok(mapping) => mapping,
+            Err(e) => {
+                unsafe { unmap_file(readers_fd, readers_ptr, READER_REGISTRY_SIZE) };
+                return Err(e);
+            }
+        };
 
         unsafe {
-            let fd = libc::open(
-                c_path.as_ptr(),
-                libc::O_CREAT | libc::O_RDWR,
-                0o644,
+            FrameHeader::init(ptr as *mut FrameHeader);
+
+            info!(
+                path = %ring_path.display(),
+                size = FRAME_SHM_SIZE,
+                version = FRAME_LAYOUT_VERSION,
+                "frame buffer created"
             );
-            if fd < 0 {
-                return Err(io::Error::last_os_error());
-            }
+            Ok(SharedFrameBuffer {
+                ptr,
+                fd: Mutex::new(fd),
+                path: ring_path,
+                readers: ReaderRegistry::new(readers_ptr),
+                readers_ptr,
+                readers_fd,
+                caps_ptr: ptr::null_mut(),
+                caps_fd: -1,
+            })
+        }
+    }
 
-            // Set size for double-buffered frame data
-            if libc::ftruncate(fd, FRAME_SHM_SIZE as libc::off_t) != 0 {
-                let err = io::Error::last_os_error();
-                libc::close(fd);
-                return Err(err);
+    /// Get the raw pointer to the shared memory region.
+    /// The decoder callback writes directly to this pointer.
+    pub fn ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    /// Length of the mapping at `ptr()`.
+    pub fn len(&self) -> usize {
+        FRAME_SHM_SIZE
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Position of the slowest registered reader, after freeing the entries
+    /// of reader processes that exited without unregistering.
+    pub fn min_reader_cursor(&self) -> Option<u64> {
+        self.readers
+            .prune(|pid| unsafe { libc::kill(pid as libc::pid_t, 0) } == 0);
+        self.readers.min_cursor()
+    }
+
+    /// How many registered readers are behind the newest frame.
+    pub fn lagging_readers(&self) -> usize {
+        let header = unsafe { &*(self.ptr as *const FrameHeader) };
+        self.readers
+            .lagging(header.write_index.load(Ordering::Acquire))
+    }
+
+    /// A handle on this buffer's reader registry, for the decoder's
+    /// `ShmOutput` to check reader cursors before overwriting a slot.
+    pub fn reader_registry(&self) -> ReaderRegistry {
+        // SAFETY: the registry is mapped for as long as `self` lives, and
+        // decoders writing to this buffer already mustn't outlive it.
+        unsafe { ReaderRegistry::new(self.readers_ptr) }
+    }
+
+    /// Map the POSIX shared memory object `name`, creating it if the reader
+    /// hasn't yet, and follow the `ReaderCaps` the reader writes there.
+    pub fn attach_reader_caps(&mut self, name: &str) -> io::Result<()> {
+        let (fd, ptr) = map_shared_memory(name, READER_CAPS_SIZE)?;
+        unsafe { unmap_file(self.caps_fd, self.caps_ptr, READER_CAPS_SIZE) };
+        self.caps_fd = fd;
+        self.caps_ptr = ptr;
+        Ok(())
+    }
+
+    /// A handle on the reader's capabilities, if attached, for the
+    /// decoder's `ShmOutput` to scale and pick the pixel format by.
+    pub fn reader_caps(&self) -> Option<ReaderCaps> {
+        // SAFETY: mapped for as long as `self` lives, like the registry
+        (!self.caps_ptr.is_null()).then(|| unsafe { ReaderCaps::new(self.caps_ptr) })
+    }
+
+    /// Publish the stream's color description in the header.
+    pub fn write_color(&self, color: ColorHeader) {
+        unsafe { FrameHeader::write_color(self.ptr as *mut FrameHeader, color) };
+    }
+
+    /// Record in the header whether the stream is interlaced.
+    pub fn write_interlaced(&self, interlaced: bool) {
+        unsafe { FrameHeader::write_interlaced(self.ptr as *mut FrameHeader, interlaced) };
+    }
+
+    /// Write a packed NV12 frame through the same `ShmOutput` the decoder
+    /// callback uses, slot header and commit included, so the layout and
+    /// readers can be tested without VideoToolbox. `y` holds `height` rows
+    /// of `width` bytes and `uv` half as many. Not for use while a decoder
+    /// writes to this buffer.
+    #[cfg(test)]
+    pub fn write_frame(&self, y: &[u8], uv: &[u8], width: usize, height: usize, timestamp_ms: u64) {
+        use video_pipeline::{DecodedFrame, FrameOutput, ShmOutput};
+
+        let frame = DecodedFrame {
+            width,
+            height,
+            bytes_per_sample: 1,
+            y_plane: y,
+            y_stride: width,
+            uv_plane: uv,
+            uv_stride: width,
+            timestamp_ms,
+            sar: (1, 1),
+        };
+        ShmOutput::new(self.ptr, self.len()).write_frame(&frame);
+    }
+
+    /// Recreate the ring file if it was deleted or replaced since it was mapped.
+    ///
+    /// Our mapping would otherwise keep writing to the unlinked inode while a
+    /// reader opening the path sees a different, empty file. The new file is
+    /// mapped over the old mapping at the same address, so `ptr()` — which
+    /// decoders hold on to — stays valid. Returns true if the file was recreated.
+    pub fn ensure_file(&self) -> io::Result<bool> {
+        let mut fd = self.fd.lock().unwrap();
+
+        let mapped = fstat(*fd)?;
+        if let Ok(meta) = std::fs::metadata(&self.path) {
+            if meta.ino() == mapped.st_ino as u64 && meta.dev() == mapped.st_dev as u64 {
+                return Ok(false);
             }
+        }
 
-            // Map into our address space
+        warn!(path = %self.path.display(), "ring file missing or replaced, recreating");
+        let new_fd = open_ring_file(&self.path, FRAME_SHM_SIZE)?;
+        unsafe {
             let ptr = libc::mmap(
-                ptr::null_mut(),
+                self.ptr as *mut libc::c_void,
                 FRAME_SHM_SIZE,
                 libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_SHARED,
-                fd,
+                libc::MAP_SHARED | libc::MAP_FIXED,
+                new_fd,
                 0,
             );
             if ptr == libc::MAP_FAILED {
                 let err = io::Error::last_os_error();
+                libc::close(new_fd);
+                return Err(err);
+            }
+            FrameHeader::init(self.ptr as *mut FrameHeader);
+            libc::close(*fd);
+        }
+        *fd = new_fd;
+        info!(path = %self.path.display(), "frame buffer recreated");
+        Ok(true)
+    }
+}
+
+/// Path of the reader registry belonging to the ring file at `ring_path`:
+/// `<ring_path>+readers`. Per-stream ring file names never contain a `+`,
+/// so this can't collide with one.
+pub fn readers_path(ring_path: &Path) -> PathBuf {
+    let mut path = ring_path.as_os_str().to_owned();
+    path.push("+readers");
+    PathBuf::from(path)
+}
+
+/// Open (creating if needed) `path`, size it to `len` and map it read-write.
+fn map_file(path: &Path, len: usize) -> io::Result<(i32, *mut u8)> {
+    map_fd(open_ring_file(path, len)?, len)
+}
+
+/// Open (creating if needed) the POSIX shared memory object `name`, size it
+/// to `len` if nobody has yet and map it read-write. It's left writable by
+/// everyone, as the reader runs as another user.
+fn map_shared_memory(name: &str, len: usize) -> io::Result<(i32, *mut u8)> {
+    let c_name = CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name contains null"))?;
+    let fd = unsafe {
+        let fd = libc::shm_open(
+            c_name.as_ptr(),
+            libc::O_CREAT | libc::O_RDWR,
+            0o666 as libc::c_uint,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The mode passed to shm_open is masked by the umask
+        libc::fchmod(fd, 0o666);
+
+        // macOS only lets a shared memory object be sized once, so one the
+        // reader sized first is left alone
+        let sized = |fd| fstat(fd).is_ok_and(|st| st.st_size as usize >= len);
+        if !sized(fd) && libc::ftruncate(fd, len as libc::off_t) != 0 {
+            let err = io::Error::last_os_error();
+            if !sized(fd) {
                 libc::close(fd);
                 return Err(err);
             }
+        }
+        fd
+    };
+    map_fd(fd, len)
+}
 
-            // Zero-initialize header (frame data doesn't need zeroing)
-            ptr::write_bytes(ptr as *mut u8, 0, video_pipeline::FRAME_HEADER_SIZE);
+/// Map `len` bytes of `fd` read-write, closing it on failure.
+fn map_fd(fd: i32, len: usize) -> io::Result<(i32, *mut u8)> {
+    unsafe {
+        let ptr = libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        );
+        if ptr == libc::MAP_FAILED {
+            let err = io::Error::last_os_error();
+            libc::close(fd);
+            return Err(err);
+        }
+        Ok((fd, ptr as *mut u8))
+    }
+}
 
-            info!(
-                path = %ring_path.display(),
-                size = FRAME_SHM_SIZE,
-                "frame buffer created"
-            );
-            Ok(SharedFrameBuffer {
-                ptr: ptr as *mut u8,
-                fd,
-                path: ring_path,
-            })
+/// Undo `map_file` or `map_shared_memory`.
+unsafe fn unmap_file(fd: i32, ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        libc::munmap(ptr as *mut libc::c_void, len);
+    }
+    if fd >= 0 {
+        libc::close(fd);
+    }
+}
+
+/// Open (creating if needed) the ring file and size it to `len` bytes.
+fn open_ring_file(ring_path: &Path, len: usize) -> io::Result<i32> {
+    // Ensure parent directory exists (world-readable so the extension can open it)
+    if let Some(parent) = ring_path.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o755)
+            .create(parent)?;
+    }
+
+    let c_path = CString::new(ring_path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains null"))?;
+
+    unsafe {
+        let fd = libc::open(
+            c_path.as_ptr(),
+            libc::O_CREAT | libc::O_RDWR,
+            0o644,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Set size for double-buffered frame data
+        if libc::ftruncate(fd, len as libc::off_t) != 0 {
+            let err = io::Error::last_os_error();
+            libc::close(fd);
+            return Err(err);
         }
+        Ok(fd)
     }
+}
 
-    /// Get the raw pointer to the shared memory region.
-    /// The decoder callback writes directly to this pointer.
-    pub fn ptr(&self) -> *mut u8 {
-        self.ptr
+/// Run `f` up to `attempts` times, doubling the delay between attempts,
+/// for as long as it fails with an error that may clear up on its own.
+fn with_retries<T>(
+    attempts: u32,
+    initial_backoff: Duration,
+    mut f: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut backoff = initial_backoff;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < attempts && is_transient(&e) => {
+                warn!(
+                    attempt,
+                    attempts,
+                    %e,
+                    retry_in_ms = backoff.as_millis() as u64,
+                    "frame buffer not available yet, retrying"
+                );
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
     }
 }
 
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+    )
+}
+
+fn fstat(fd: i32) -> io::Result<libc::stat> {
+    unsafe {
+        let mut st: libc::stat = std::mem::zeroed();
+        if libc::fstat(fd, &mut st) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(st)
+    }
+}
+
+/// Hands out one frame buffer per publishing stream key, so concurrent
+/// publishers never write into each other's frames.
+///
+/// The buffer at `RING_FILE_PATH` (the one the Camera Extension reads) goes to
+/// whichever stream acquires it while it is free. Streams that arrive while it
+/// is taken get their own file next to it, named `rtmp_vcam_ring.<key>`.
+pub struct FrameBufferPool {
+    primary: Arc<SharedFrameBuffer>,
+    streams: Mutex<HashMap<String, Weak<SharedFrameBuffer>>>,
+}
+
+impl FrameBufferPool {
+    pub fn new(primary: Arc<SharedFrameBuffer>) -> Self {
+        Self {
+            primary,
+            streams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The buffer at `RING_FILE_PATH`, whichever stream is using it.
+    pub fn primary(&self) -> &SharedFrameBuffer {
+        &self.primary
+    }
+
+    /// Get the buffer for `stream_key`, allocating one if the key has none in use.
+    pub fn acquire(&self, stream_key: &str) -> io::Result<Arc<SharedFrameBuffer>> {
+        let mut streams = self.streams.lock().unwrap();
+        // The pool's own reference keeps the primary alive, so only a count
+        // above one means a stream is using it.
+        let primary_free = Arc::strong_count(&self.primary) == 1;
+
+        if let Some(buffer) = streams.get(stream_key).and_then(Weak::upgrade) {
+            if !Arc::ptr_eq(&buffer, &self.primary) || primary_free {
+                return Ok(buffer);
+            }
+        }
+
+        let buffer = if primary_free {
+            // Forget whichever key held the primary before
+            streams.retain(|_, w| !ptr::eq(w.as_ptr(), Arc::as_ptr(&self.primary)));
+            Arc::clone(&self.primary)
+        } else {
+            let path = stream_ring_path(stream_key);
+            Arc::new(SharedFrameBuffer::create_at(&path)?)
+        };
+        info!(stream_key, path = %buffer.path().display(), "frame buffer assigned to stream");
+
+        streams.retain(|_, w| w.strong_count() > 0);
+        streams.insert(stream_key.to_string(), Arc::downgrade(&buffer));
+        Ok(buffer)
+    }
+
+    /// Recreate any ring file that was deleted out from under us.
+    /// See [`SharedFrameBuffer::ensure_file`].
+    pub fn ensure_files(&self) {
+        if let Err(e) = self.primary.ensure_file() {
+            error!(path = %self.primary.path().display(), %e, "failed to recreate ring file");
+        }
+
+        // Don't upgrade the primary here: a second strong reference would make
+        // `acquire` think it's in use.
+        let others: Vec<_> = self
+            .streams
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|w| !ptr::eq(w.as_ptr(), Arc::as_ptr(&self.primary)))
+            .filter_map(Weak::upgrade)
+            .collect();
+        for buffer in others {
+            if let Err(e) = buffer.ensure_file() {
+                error!(path = %buffer.path().display(), %e, "failed to recreate ring file");
+            }
+        }
+    }
+}
+
+/// Per-stream ring file path. The key is reduced to filename-safe characters.
+fn stream_ring_path(stream_key: &str) -> PathBuf {
+    PathBuf::from(format!("{RING_FILE_PATH}.{}", sanitize_stream_key(stream_key)))
+}
+
 impl Drop for SharedFrameBuffer {
     fn drop(&mut self) {
+        let fd = *self.fd.get_mut().unwrap();
         unsafe {
-            if !self.ptr.is_null() {
-                libc::munmap(self.ptr as *mut libc::c_void, FRAME_SHM_SIZE);
-            }
-            if self.fd >= 0 {
-                libc::close(self.fd);
-            }
+            unmap_file(fd, self.ptr, FRAME_SHM_SIZE);
+            unmap_file(self.readers_fd, self.readers_ptr, READER_REGISTRY_SIZE);
+            unmap_file(self.caps_fd, self.caps_ptr, READER_CAPS_SIZE);
         }
         info!("frame buffer closed: {}", self.path.display());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_ring_path_sanitizes_key() {
+        let path = stream_ring_path("../cam 2/x");
+        assert_eq!(path.parent(), Path::new(RING_FILE_PATH).parent());
+        assert!(path.to_string_lossy().ends_with("rtmp_vcam_ring.___cam_2_x"));
+    }
+
+    #[test]
+    fn test_written_frame_round_trips() {
+        let path =
+            std::env::temp_dir().join(format!("rtmp-vcam-ring-write-{}", std::process::id()));
+        let shm = SharedFrameBuffer::create_at(&path).unwrap();
+        let reader = unsafe { video_pipeline::FrameReader::new(shm.ptr()) }.unwrap();
+        assert!(reader.latest_frame().is_none());
+
+        let y: Vec<u8> = (0..8).collect();
+        shm.write_frame(&y, &[0x80, 0x81, 0x82, 0x83], 4, 2, 33);
+        let frame = reader.latest_frame().unwrap();
+        assert_eq!((frame.width, frame.height, frame.timestamp_ms), (4, 2, 33));
+        assert_eq!(frame.data, [0, 1, 2, 3, 4, 5, 6, 7, 0x80, 0x81, 0x82, 0x83]);
+
+        // The next frame goes to the other slot
+        shm.write_frame(&[0x10; 16], &[0x80; 8], 4, 4, 66);
+        let frame = reader.latest_frame().unwrap();
+        assert_eq!((frame.width, frame.height, frame.timestamp_ms), (4, 4, 66));
+        assert_eq!(reader.write_index(), 2);
+
+        drop(shm);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(readers_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn test_follows_reader_caps_in_shared_memory() {
+        let path =
+            std::env::temp_dir().join(format!("rtmp-vcam-ring-caps-{}", std::process::id()));
+        let name = format!("rtmp-vcam-caps-{}", std::process::id());
+        let mut shm = SharedFrameBuffer::create_at(&path).unwrap();
+        assert!(shm.reader_caps().is_none());
+        shm.attach_reader_caps(&name).unwrap();
+        assert_eq!(shm.reader_caps().unwrap().max_size(), None);
+
+        // The reader writes through its own mapping
+        let (fd, ptr) = map_shared_memory(&name, READER_CAPS_SIZE).unwrap();
+        let caps = unsafe { ReaderCaps::new(ptr) };
+        caps.set_max_size(1280, 720);
+        caps.set_formats(video_pipeline::OutputFormat::P010.caps_bit());
+        let seen = shm.reader_caps().unwrap();
+        assert_eq!(seen.max_size(), Some((1280, 720)));
+        assert!(seen.supports(video_pipeline::OutputFormat::P010));
+
+        // Recreating the ring file leaves them alone
+        std::fs::remove_file(&path).unwrap();
+        assert!(shm.ensure_file().unwrap());
+        assert_eq!(shm.reader_caps().unwrap().max_size(), Some((1280, 720)));
+
+        unsafe { unmap_file(fd, ptr, READER_CAPS_SIZE) };
+        drop(shm);
+        let c_name = CString::new(name).unwrap();
+        unsafe { libc::shm_unlink(c_name.as_ptr()) };
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(readers_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn test_reports_slowest_registered_reader() {
+        let path = std::env::temp_dir().join(format!("rtmp-vcam-ring-{}", std::process::id()));
+        let shm = SharedFrameBuffer::create_at(&path).unwrap();
+        assert_eq!(shm.min_reader_cursor(), None);
+
+        let pid = std::process::id();
+        let first = shm.readers.register(pid, 0).unwrap();
+        let second = shm.readers.register(pid, 0).unwrap();
+        first.update(4);
+        second.update(3);
+        assert_eq!(shm.min_reader_cursor(), Some(3));
+        assert_eq!(shm.lagging_readers(), 0);
+
+        drop((first, second));
+        drop(shm);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(readers_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn test_with_retries_until_success() {
+        let mut calls = 0;
+        let result = with_retries(5, Duration::from_millis(1), || {
+            calls += 1;
+            if calls < 3 {
+                Err(io::Error::from(io::ErrorKind::PermissionDenied))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_with_retries_gives_up() {
+        let mut calls = 0;
+        let result: io::Result<()> = with_retries(3, Duration::from_millis(1), || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+
+        // Errors that won't clear up aren't retried
+        calls = 0;
+        let result: io::Result<()> = with_retries(3, Duration::from_millis(1), || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::InvalidInput))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
@@ -1,33 +1,223 @@
+mod config;
+mod decode_thread;
+mod dump_flv;
 mod ipc;
+mod pacing;
+mod self_test;
+mod snapshot;
+mod stats;
+mod watchdog;
 
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use bytes::Bytes;
-use tracing::{error, info};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::prelude::*;
 
-use rtmp_server::{AvcDecoderConfig, VideoSink};
-use video_pipeline::H264Decoder;
+use rtmp_server::{
+    AvcDecoderConfig, ColorInfo, ConnectionContext, Listener, RecorderOptions, RtmpError, Server,
+    Shutdown, StreamInfo, TlsConfig, VideoSink,
+};
+use video_pipeline::nal::{NAL_TYPE_PPS, NAL_TYPE_SPS};
+use video_pipeline::{
+    parse_sps, AvccNalIter, ColorHeader, CommitPolicy, DecoderError, DecoderOptions, H264Decoder,
+    ShmOutput,
+};
 
-use crate::ipc::SharedFrameBuffer;
+use crate::config::Config;
+use crate::decode_thread::ThreadedSink;
+use crate::ipc::{FrameBufferPool, SharedFrameBuffer};
+use crate::pacing::PacingSink;
+use crate::stats::{DecodeError, DecoderStats, StreamStatsRegistry};
+use crate::watchdog::Outcome;
+
+/// How long a single decode call may take before the decoder is considered wedged.
+const DECODE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Frames received without a sequence header before warning about it.
+const MISSING_CONFIG_WARN_FRAMES: u32 = 30;
+
+/// Consecutive decode errors, not counting benign ones, after which the
+/// stream is given up on and the publisher disconnected. At 30 fps that is a
+/// second of video without one good frame.
+const MAX_CONSECUTIVE_DECODE_ERRORS: u32 = 30;
+
+/// Sink events queued for the decode thread (`--decode-thread`) before the
+/// connection waits for it; about a second of video at 30 fps.
+const DECODE_QUEUE_EVENTS: usize = 30;
+
+/// How often ring files are checked for external deletion.
+const RING_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often each publisher's ingest bitrate is logged (at debug level).
+const INGEST_LOG_INTERVAL: Duration = Duration::from_secs(10);
 
 /// VideoSink implementation that decodes H.264 and copies pixel data to shared memory.
 struct DecoderSink {
-    decoder: Option<H264Decoder>,
+    decoder: Option<Arc<Mutex<H264Decoder>>>,
+    /// Last sequence header, kept so the decoder can be rebuilt after a stall.
+    config: Option<AvcDecoderConfig>,
+    /// NAL length size for in-band parameter sets, from a sequence header
+    /// that carried none itself.
+    in_band_length_size: u8,
+    /// A decode call that exceeded `DECODE_TIMEOUT` and hasn't returned yet.
+    stalled: Option<JoinHandle<Result<(), DecoderError>>>,
+    /// Publisher-declared metadata, if any was sent.
+    stream_info: Option<StreamInfo>,
+    /// Whether `on_first_frame` has fired for this publish.
+    first_frame_seen: bool,
+    /// Video frames received while no sequence header was configured.
+    frames_without_config: u32,
+    /// Video messages received on this stream, for numbering decode errors.
+    frame_seq: u64,
+    /// `H264Decoder::benign_errors` of the current decoder already counted in `stats`.
+    benign_errors: u64,
+    /// `H264Decoder::frames_dropped_by_vt` of the current decoder already counted in `stats`.
+    frames_dropped_by_vt: u64,
+    /// `H264Decoder::frames_decoded` of the current decoder already counted in `stats`.
+    frames_decoded: u64,
+    /// Decode errors since the last successful decode.
+    consecutive_errors: u32,
+    /// Set after `MAX_CONSECUTIVE_DECODE_ERRORS`; no more frames are decoded.
+    failed: bool,
+    /// Log per-frame decode timing (`--trace-timing`).
+    trace_timing: bool,
+    /// Log a per-frame Y-plane CRC (`--crc-frames`).
+    crc_frames: bool,
+    /// Whether frames wait for registered readers (`--commit-policy`).
+    commit_policy: CommitPolicy,
+    stats: Arc<DecoderStats>,
     shm: Arc<SharedFrameBuffer>,
 }
 
 impl DecoderSink {
-    fn new(shm: Arc<SharedFrameBuffer>) -> Self {
+    fn new(
+        shm: Arc<SharedFrameBuffer>,
+        stats: Arc<DecoderStats>,
+        trace_timing: bool,
+        crc_frames: bool,
+        commit_policy: CommitPolicy,
+    ) -> Self {
         Self {
             decoder: None,
+            config: None,
+            in_band_length_size: 4,
+            stalled: None,
+            stream_info: None,
+            first_frame_seen: false,
+            frames_without_config: 0,
+            frame_seq: 0,
+            benign_errors: 0,
+            frames_dropped_by_vt: 0,
+            frames_decoded: 0,
+            consecutive_errors: 0,
+            failed: false,
+            trace_timing,
+            crc_frames,
+            commit_policy,
+            stats,
             shm,
         }
     }
+
+    fn create_decoder(&mut self) {
+        let Some(config) = &self.config else { return };
+        let interlaced = config
+            .sps
+            .first()
+            .and_then(|sps| parse_sps(sps))
+            .is_some_and(|info| info.interlaced);
+        self.shm.write_interlaced(interlaced);
+        match H264Decoder::with_shm_output(
+            &config.sps,
+            &config.pps,
+            config.nalu_length_size,
+            self.shm_output(),
+        ) {
+            Ok(decoder) => {
+                // Frames VT can't decode yet (e.g. B-frames before the first
+                // IDR) are only counted, not reported as errors
+                let mut decoder = decoder.with_options(DecoderOptions {
+                    ignore_benign_errors: true,
+                    trace_timing: self.trace_timing,
+                    crc_frames: self.crc_frames,
+                    ..DecoderOptions::default()
+                });
+                if let Some(frame_rate) = self.stream_info.as_ref().and_then(|i| i.frame_rate) {
+                    decoder.set_frame_rate(frame_rate);
+                }
+                self.benign_errors = 0;
+                self.frames_dropped_by_vt = 0;
+                self.frames_decoded = 0;
+                self.consecutive_errors = 0;
+                self.decoder = Some(Arc::new(Mutex::new(decoder)));
+                info!("H264 decoder created successfully");
+            }
+            Err(e) => self.on_decoder_create_error(e.status.unwrap_or(0), &e.to_string()),
+        }
+    }
+
+    /// Count the frames `decoder` output since the last call towards
+    /// `stats`, and so towards --max-frames.
+    fn count_decoded_frames(&mut self, decoder: &H264Decoder) {
+        let decoded = decoder.frames_decoded();
+        if decoded > self.frames_decoded {
+            self.stats.record_frames(decoded - self.frames_decoded);
+            self.frames_decoded = decoded;
+        }
+    }
+
+    /// Output for the next decoder, writing to `shm` and following its
+    /// reader's capabilities, if it has any. The ring is laid out for NV12,
+    /// the only format the Camera Extension reads, so 10-bit streams are
+    /// decoded to NV12 too.
+    fn shm_output(&self) -> ShmOutput {
+        let output = ShmOutput::new(self.shm.ptr(), self.shm.len())
+            .with_commit_policy(self.commit_policy, self.shm.reader_registry());
+        match self.shm.reader_caps() {
+            Some(caps) => output.with_reader_caps(caps),
+            None => output,
+        }
+    }
 }
 
 impl VideoSink for DecoderSink {
+    fn on_connect_params(&mut self, params: HashMap<String, String>) {
+        // Values may be credentials; only their names are logged
+        let names: Vec<&String> = params.keys().collect();
+        debug!(?names, "connect parameters");
+    }
+
     fn on_decoder_config(&mut self, config: AvcDecoderConfig) {
+        // VT can't build a format description without an SPS and a PPS; wait
+        // for the ones the encoder sends in-band
+        if !config.has_parameter_sets() {
+            info!(
+                sps_count = config.sps.len(),
+                pps_count = config.pps.len(),
+                "sequence header has no parameter sets, waiting for in-band SPS/PPS"
+            );
+            self.in_band_length_size = config.nalu_length_size;
+            return;
+        }
+
+        // Encoders resend the sequence header periodically; rebuilding the
+        // VT session for an unchanged one would only cause a hiccup
+        if self.config.as_ref() == Some(&config)
+            && (self.decoder.is_some() || self.stalled.is_some())
+        {
+            debug!("sequence header unchanged, keeping decoder");
+            return;
+        }
+
         info!(
             sps_count = config.sps.len(),
             pps_count = config.pps.len(),
@@ -35,46 +225,460 @@ impl VideoSink for DecoderSink {
             "received decoder configuration, creating VT decoder"
         );
 
-        match H264Decoder::new(
-            &config.sps,
-            &config.pps,
-            config.nalu_length_size,
-            self.shm.ptr(),
-        ) {
-            Ok(decoder) => {
-                self.decoder = Some(decoder);
-                info!("H264 decoder created successfully");
-            }
-            Err(e) => {
-                error!(%e, "failed to create H264 decoder");
+        self.config = Some(config);
+        if self.stalled.is_none() {
+            self.create_decoder();
+        }
+    }
+
+    fn on_stream_info(&mut self, info: StreamInfo) {
+        if self.stream_info.as_ref() == Some(&info) {
+            return;
+        }
+        info!(
+            encoder = info.encoder.as_deref().unwrap_or("unknown"),
+            codec = ?info.video_codec,
+            width = info.width,
+            height = info.height,
+            frame_rate = info.frame_rate,
+            "publisher stream info"
+        );
+        // Sample buffer durations follow the advertised frame rate
+        if let (Some(frame_rate), Some(decoder)) = (info.frame_rate, &self.decoder) {
+            decoder.lock().unwrap().set_frame_rate(frame_rate);
+        }
+        self.stream_info = Some(info);
+    }
+
+    fn on_color_info(&mut self, info: ColorInfo) {
+        info!(
+            bit_depth = info.bit_depth,
+            primaries = info.color_primaries,
+            transfer = info.transfer_characteristics,
+            matrix = info.matrix_coefficients,
+            max_cll = info.max_cll,
+            max_fall = info.max_fall,
+            mastering_display = ?info.mastering_display,
+            "publisher color info"
+        );
+        self.shm.write_color(color_header(&info));
+    }
+
+    fn on_end_of_sequence(&mut self) {
+        // The encoder is reconfiguring: let the decoder output the frames it
+        // still holds, then wait for the next sequence header (or in-band
+        // SPS/PPS) so no reference frames carry over
+        info!("end of sequence, resetting H264 decoder");
+        self.config = None;
+        self.frames_without_config = 0;
+        let Some(decoder) = self.decoder.take() else { return };
+        let flushed = Arc::clone(&decoder);
+        match watchdog::run_with_timeout(DECODE_TIMEOUT, move || decoder.lock().unwrap().flush()) {
+            Outcome::Completed(Ok(())) => self.count_decoded_frames(&flushed.lock().unwrap()),
+            Outcome::Completed(Err(e)) => warn!(%e, "flush at end of sequence failed"),
+            Outcome::Panicked => error!("flush at end of sequence panicked"),
+            Outcome::TimedOut(task) => {
+                warn!(
+                    timeout_ms = DECODE_TIMEOUT.as_millis() as u64,
+                    "flush at end of sequence timed out, waiting for it to return"
+                );
+                self.stalled = Some(task);
             }
         }
     }
 
     fn on_video_data(&mut self, data: Bytes, timestamp: u32) {
-        if let Some(decoder) = &mut self.decoder {
-            if let Err(e) = decoder.decode_avcc(&data, timestamp) {
-                // Don't log every bad data error (common for B-frames before IDR)
-                if !e.contains("-12909") {
-                    tracing::warn!(%e, "decode error");
+        // Past --max-frames / --max-bytes: the app is shutting down
+        if self.stats.is_exhausted() || self.failed {
+            return;
+        }
+        self.stats.record_bytes(data.len());
+        let frame_seq = self.frame_seq;
+        self.frame_seq += 1;
+
+        if self.config.is_none() {
+            // Some encoders never send a sequence header but repeat SPS/PPS
+            // in-band with each keyframe; fall back to those
+            match in_band_config(&data, self.in_band_length_size) {
+                Some(config) => {
+                    info!("no sequence header received, using in-band SPS/PPS");
+                    self.on_decoder_config(config);
+                }
+                None => {
+                    self.frames_without_config += 1;
+                    if self.frames_without_config == MISSING_CONFIG_WARN_FRAMES {
+                        warn!(
+                            frames = self.frames_without_config,
+                            "receiving video but no AVC sequence header (SPS/PPS) was sent; \
+                             check the encoder sends one at stream start"
+                        );
+                    }
+                    return;
                 }
             }
         }
+
+        // While a wedged decode is still running, drop frames; once it
+        // returns, rebuild the decoder from the last sequence header.
+        if let Some(task) = &self.stalled {
+            if !task.is_finished() {
+                return;
+            }
+            self.stalled = None;
+            warn!("stalled decode returned, recreating H264 decoder");
+            self.create_decoder();
+        }
+
+        let Some(decoder) = &self.decoder else { return };
+        let decoder = Arc::clone(decoder);
+        let task_decoder = Arc::clone(&decoder);
+        let outcome = watchdog::run_with_timeout(DECODE_TIMEOUT, move || {
+            task_decoder.lock().unwrap().decode_avcc(&data, timestamp)
+        });
+
+        match outcome {
+            Outcome::Completed(Err(e)) => {
+                self.stats.record_error(DecodeError {
+                    status: e.status.unwrap_or(0),
+                    timestamp,
+                    frame_seq,
+                });
+                warn!(%e, "decode error");
+                self.consecutive_errors += 1;
+                if self.consecutive_errors == MAX_CONSECUTIVE_DECODE_ERRORS {
+                    self.failed = true;
+                    self.decoder = None;
+                    self.on_stream_error(&format!(
+                        "{} consecutive decode errors, last: {e}",
+                        self.consecutive_errors
+                    ));
+                }
+            }
+            Outcome::Completed(Ok(())) => {
+                self.consecutive_errors = 0;
+                self.count_decoded_frames(&decoder.lock().unwrap());
+                let benign_errors = decoder.lock().unwrap().benign_errors();
+                if benign_errors > self.benign_errors {
+                    self.stats.record_benign_errors(benign_errors - self.benign_errors);
+                    self.benign_errors = benign_errors;
+                    return;
+                }
+                let dropped = decoder.lock().unwrap().frames_dropped_by_vt();
+                if dropped > self.frames_dropped_by_vt {
+                    self.stats
+                        .record_frames_dropped_by_vt(dropped - self.frames_dropped_by_vt);
+                    self.frames_dropped_by_vt = dropped;
+                    return;
+                }
+                if !self.first_frame_seen {
+                    let size = decoder.lock().unwrap().first_frame_size();
+                    if let Some((width, height)) = size {
+                        self.first_frame_seen = true;
+                        self.on_first_frame(width, height);
+                    }
+                }
+            }
+            Outcome::Panicked => {
+                error!("decode panicked, dropping H264 decoder");
+                self.decoder = None;
+            }
+            Outcome::TimedOut(task) => {
+                warn!(
+                    timeout_ms = DECODE_TIMEOUT.as_millis() as u64,
+                    timestamp, "decode watchdog fired, decoder marked invalid until it returns"
+                );
+                self.decoder = None;
+                self.stalled = Some(task);
+            }
+        }
+    }
+
+    fn on_sei(&mut self, payload_type: u32, data: &[u8]) {
+        debug!(payload_type, len = data.len(), "SEI message");
+    }
+
+    fn on_audio_level(&mut self, rms_db: f32, peak_db: f32) {
+        self.stats.record_audio_level(rms_db, peak_db);
+    }
+
+    fn on_first_frame(&mut self, width: usize, height: usize) {
+        info!(width, height, "first frame decoded");
+    }
+
+    fn on_decoder_create_error(&mut self, status: i32, message: &str) {
+        error!(status, message, "failed to create H264 decoder");
+    }
+
+    fn on_stream_error(&mut self, message: &str) {
+        error!(message, "giving up on the stream, disconnecting the publisher");
+    }
+
+    fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    fn on_stream_end(&mut self) {
+        self.config = None;
+        self.in_band_length_size = 4;
+        self.shm.write_color(ColorHeader::default());
+        self.shm.write_interlaced(false);
+        if let Some(decoder) = self.decoder.take() {
+            self.count_decoded_frames(&decoder.lock().unwrap());
+            info!("stream ended, H264 decoder released");
+        }
     }
 }
 
-fn parse_args() -> (SocketAddr, bool, Option<String>) {
-    let mut port: u16 = 1935;
-    let mut verbose = false;
-    let mut stream_key: Option<String> = None;
+/// Convert a publisher's color description to the shared memory header's units.
+fn color_header(info: &ColorInfo) -> ColorHeader {
+    // Chromaticity in 0.00002 units, luminance in 0.0001 cd/m²
+    let chromaticity = |(x, y): (f32, f32)| {
+        [
+            (x * 50_000.0).round().clamp(0.0, 50_000.0) as u16,
+            (y * 50_000.0).round().clamp(0.0, 50_000.0) as u16,
+        ]
+    };
+    let luminance = |cd_m2: f32| (cd_m2 * 10_000.0).round() as u32;
 
+    let mut color = ColorHeader::default();
+    color.present = 1;
+    color.color_primaries = info.color_primaries.unwrap_or(0);
+    color.transfer_characteristics = info.transfer_characteristics.unwrap_or(0);
+    color.matrix_coefficients = info.matrix_coefficients.unwrap_or(0);
+    color.max_cll = info.max_cll.unwrap_or(0);
+    color.max_fall = info.max_fall.unwrap_or(0);
+    if let Some(display) = &info.mastering_display {
+        for (i, &primary) in display.primaries.iter().enumerate() {
+            color.display_primaries[2 * i..2 * i + 2].copy_from_slice(&chromaticity(primary));
+        }
+        color.white_point = chromaticity(display.white_point);
+        color.max_luminance = luminance(display.max_luminance);
+        color.min_luminance = luminance(display.min_luminance);
+    }
+    color
+}
+
+/// Build a decoder config from SPS/PPS NAL units carried in a video frame.
+/// `nalu_length_size` is 4 unless a sequence header without parameter sets
+/// said otherwise.
+fn in_band_config(data: &[u8], nalu_length_size: u8) -> Option<AvcDecoderConfig> {
+    let mut sps = Vec::new();
+    let mut pps = Vec::new();
+    for (nal_type, unit) in AvccNalIter::new(data, nalu_length_size) {
+        match nal_type {
+            NAL_TYPE_SPS => sps.push(unit.to_vec()),
+            NAL_TYPE_PPS => pps.push(unit.to_vec()),
+            _ => {}
+        }
+    }
+    if sps.is_empty() || pps.is_empty() {
+        return None;
+    }
+    Some(AvcDecoderConfig {
+        sps,
+        pps,
+        nalu_length_size,
+    })
+}
+
+/// Number of rotated log files kept when logging to a file.
+const MAX_LOG_FILES: usize = 7;
+
+/// Input protocol accepted on the listen port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Rtmp,
+    /// Raw MPEG-TS pushed over TCP (e.g. `ffmpeg -f mpegts tcp://host:port`).
+    MpegTs,
+    /// FLV POSTed over HTTP (e.g. `curl --data-binary @test.flv http://host:port/ingest`).
+    HttpFlv,
+}
+
+/// How log lines are written (`--log-format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    Text,
+    /// One JSON object per line, with the event's fields (`peer_addr`,
+    /// `stream_key`, ...) at the top level next to `timestamp`, `level`,
+    /// `target` and `message`.
+    Json,
+}
+
+struct Args {
+    /// Listen addresses, one per `--port`; only RTMP listens on more than one.
+    addrs: Vec<SocketAddr>,
+    /// RTMPS listen address (`--tls-port`), with its certificate and key.
+    tls_addr: Option<SocketAddr>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    mode: Mode,
+    apps: Vec<String>,
+    unix_socket: Option<PathBuf>,
+    dual_stack: bool,
+    record_dir: Option<PathBuf>,
+    record_keyframes_only: bool,
+    decode_thread: bool,
+    /// Pacing buffer depth in frames (`--pace`), if pacing is on.
+    pace: Option<u64>,
+    trace_timing: bool,
+    crc_frames: bool,
+    commit_policy: CommitPolicy,
+    max_frames: Option<u64>,
+    max_bytes: Option<u64>,
+    snapshot: Option<PathBuf>,
+    self_test: bool,
+    dump_flv: Option<PathBuf>,
+    verbose: bool,
+    stream_key: Option<String>,
+    log_file: Option<PathBuf>,
+    log_format: LogFormat,
+}
+
+fn parse_args() -> Args {
     let args: Vec<String> = std::env::args().collect();
+    parse_args_from(&args)
+}
+
+/// Parse `args`, program name first. Settings from the `--config` file, if
+/// one is given, apply unless the same flag is on the command line.
+fn parse_args_from(args: &[String]) -> Args {
+    let config = match args.iter().position(|arg| arg == "--config") {
+        Some(i) if i + 1 < args.len() => {
+            Config::load(Path::new(&args[i + 1])).unwrap_or_else(|e| {
+                eprintln!("invalid config file: {e}");
+                std::process::exit(2);
+            })
+        }
+        _ => Config::default(),
+    };
+
+    // Given on the command line, --port replaces the file's port
+    let mut ports: Vec<u16> = Vec::new();
+    let mut mode = config.mode.as_deref().map_or(Mode::Rtmp, parse_mode);
+    // Given on the command line, --app replaces the file's list
+    let mut apps: Vec<String> = Vec::new();
+    let mut unix_socket: Option<PathBuf> = config.unix_socket;
+    let mut dual_stack = config.dual_stack.unwrap_or(false);
+    let mut tls_port: Option<u16> = config.tls_port;
+    let mut tls_cert: Option<PathBuf> = config.tls_cert;
+    let mut tls_key: Option<PathBuf> = config.tls_key;
+    let mut record_dir: Option<PathBuf> = config.record_dir;
+    let mut record_keyframes_only = config.record_keyframes_only.unwrap_or(false);
+    let mut decode_thread = config.decode_thread.unwrap_or(false);
+    // `pace = 0` in the file turns pacing off
+    let mut pace: Option<u64> = config.pace.filter(|&depth| depth > 0);
+    let mut trace_timing = config.trace_timing.unwrap_or(false);
+    let mut crc_frames = config.crc_frames.unwrap_or(false);
+    let mut commit_policy = config
+        .commit_policy
+        .as_deref()
+        .map_or(CommitPolicy::DropOldest, parse_commit_policy);
+    let mut max_frames: Option<u64> = None;
+    let mut max_bytes: Option<u64> = None;
+    let mut snapshot: Option<PathBuf> = None;
+    let mut self_test = false;
+    let mut dump_flv: Option<PathBuf> = None;
+    let mut verbose = config.verbose.unwrap_or(false);
+    let mut stream_key: Option<String> = config.stream_key;
+    let mut log_file: Option<PathBuf> = config.log_file;
+    let mut log_format = config
+        .log_format
+        .as_deref()
+        .map_or(LogFormat::Text, parse_log_format);
+
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
             "--port" | "-p" => {
                 if i + 1 < args.len() {
-                    port = args[i + 1].parse().unwrap_or(1935);
+                    ports.push(parse_port(&args[i], &args[i + 1]));
+                    i += 1;
+                }
+            }
+            "--mode" => {
+                if i + 1 < args.len() {
+                    mode = parse_mode(&args[i + 1]);
+                    i += 1;
+                }
+            }
+            "--app" | "-a" => {
+                if i + 1 < args.len() {
+                    apps.push(args[i + 1].clone());
+                    i += 1;
+                }
+            }
+            "--unix-socket" => {
+                if i + 1 < args.len() {
+                    unix_socket = Some(PathBuf::from(&args[i + 1]));
+                    i += 1;
+                }
+            }
+            "--dual-stack" => {
+                dual_stack = true;
+            }
+            "--tls-port" => {
+                if i + 1 < args.len() {
+                    tls_port = Some(parse_port(&args[i], &args[i + 1]));
+                    i += 1;
+                }
+            }
+            "--tls-cert" => {
+                if i + 1 < args.len() {
+                    tls_cert = Some(PathBuf::from(&args[i + 1]));
+                    i += 1;
+                }
+            }
+            "--tls-key" => {
+                if i + 1 < args.len() {
+                    tls_key = Some(PathBuf::from(&args[i + 1]));
+                    i += 1;
+                }
+            }
+            "--record-dir" => {
+                if i + 1 < args.len() {
+                    record_dir = Some(PathBuf::from(&args[i + 1]));
+                    i += 1;
+                }
+            }
+            "--record-keyframes-only" => {
+                record_keyframes_only = true;
+            }
+            "--decode-thread" => {
+                decode_thread = true;
+            }
+            "--pace" => {
+                if i + 1 < args.len() {
+                    pace = Some(parse_limit("--pace", &args[i + 1]));
+                    i += 1;
+                }
+            }
+            "--trace-timing" => {
+                trace_timing = true;
+            }
+            "--crc-frames" => {
+                crc_frames = true;
+            }
+            "--commit-policy" => {
+                if i + 1 < args.len() {
+                    commit_policy = parse_commit_policy(&args[i + 1]);
+                    i += 1;
+                }
+            }
+            "--max-frames" => {
+                if i + 1 < args.len() {
+                    max_frames = Some(parse_limit("--max-frames", &args[i + 1]));
+                    i += 1;
+                }
+            }
+            "--max-bytes" => {
+                if i + 1 < args.len() {
+                    max_bytes = Some(parse_limit("--max-bytes", &args[i + 1]));
+                    i += 1;
+                }
+            }
+            "--snapshot" => {
+                if i + 1 < args.len() {
+                    snapshot = Some(PathBuf::from(&args[i + 1]));
                     i += 1;
                 }
             }
@@ -84,6 +688,31 @@ fn parse_args() -> (SocketAddr, bool, Option<String>) {
                     i += 1;
                 }
             }
+            "--log-file" => {
+                if i + 1 < args.len() {
+                    log_file = Some(PathBuf::from(&args[i + 1]));
+                    i += 1;
+                }
+            }
+            "--log-format" => {
+                if i + 1 < args.len() {
+                    log_format = parse_log_format(&args[i + 1]);
+                    i += 1;
+                }
+            }
+            "--config" => {
+                // Already loaded above
+                i += 1;
+            }
+            "--self-test" => {
+                self_test = true;
+            }
+            "--dump-flv" => {
+                if i + 1 < args.len() {
+                    dump_flv = Some(PathBuf::from(&args[i + 1]));
+                    i += 1;
+                }
+            }
             "--verbose" | "-v" => {
                 verbose = true;
             }
@@ -93,8 +722,30 @@ fn parse_args() -> (SocketAddr, bool, Option<String>) {
                 println!("Usage: rtmp-vcam-app [OPTIONS]");
                 println!();
                 println!("Options:");
-                println!("  -p, --port <PORT>          RTMP listen port (default: 1935)");
+                println!("  -p, --port <PORT>          Listen port (default: 1935; repeatable for RTMP)");
+                println!("      --mode <MODE>          Input protocol: rtmp, mpegts or http-flv (default: rtmp)");
+                println!("      --unix-socket <PATH>   Listen for RTMP on a Unix socket instead of TCP");
+                println!("      --dual-stack           Listen on [::], accepting both IPv6 and IPv4 clients");
+                println!("      --tls-port <PORT>      Also accept RTMPS on PORT, alongside plain RTMP");
+                println!("      --tls-cert <PATH>      PEM certificate chain for --tls-port");
+                println!("      --tls-key <PATH>       PEM private key for --tls-port");
+                println!("      --record-dir <PATH>    Save RTMP publishes made in record/append mode as FLV here");
+                println!("      --record-keyframes-only  Record only keyframes, without audio");
+                println!("  -a, --app <NAME>           Accept only this RTMP app name (repeatable)");
                 println!("  -k, --stream-key <KEY>     Require stream key for publishing");
+                println!("      --decode-thread        Decode each stream on its own thread");
+                println!("      --pace <FRAMES>        Buffer FRAMES frames to even out bursty input (adds latency)");
+                println!("      --trace-timing         Log timestamps, decode latency and write index per frame");
+                println!("      --crc-frames           Log a CRC of each decoded frame's Y plane, for golden-output tests");
+                println!("      --commit-policy <P>    drop-oldest (default) or block-briefly to wait for a slow reader");
+                println!("      --max-frames <N>       Exit after decoding N frames");
+                println!("      --max-bytes <N>        Exit after receiving N bytes of video");
+                println!("      --snapshot <PATH>      Save the next frame as a JPEG and exit");
+                println!("      --self-test            Check that hardware decoding works, then exit");
+                println!("      --dump-flv <PATH>      List the tags of an FLV file, then exit");
+                println!("      --log-file <PATH>      Write logs to PATH, rotated daily (stdout only with -v)");
+                println!("      --log-format <FORMAT>  Log as text (default) or json, one object per line");
+                println!("      --config <PATH>        Read settings from a TOML file; flags override it");
                 println!("  -v, --verbose              Enable debug logging");
                 println!("  -h, --help                 Show this help");
                 std::process::exit(0);
@@ -103,74 +754,746 @@ fn parse_args() -> (SocketAddr, bool, Option<String>) {
         }
         i += 1;
     }
+    if apps.is_empty() {
+        apps = config.app;
+    }
+    if ports.is_empty() {
+        ports.push(config.port.unwrap_or(1935));
+    }
 
-    let addr: SocketAddr = format!("0.0.0.0:{port}").parse().unwrap();
-    (addr, verbose, stream_key)
+    let listen_addr = |port| {
+        if dual_stack {
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))
+        } else {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))
+        }
+    };
+    let addrs = ports.into_iter().map(listen_addr).collect();
+    let tls_addr = tls_port.map(listen_addr);
+    Args {
+        addrs,
+        tls_addr,
+        tls_cert,
+        tls_key,
+        mode,
+        apps,
+        unix_socket,
+        dual_stack,
+        record_dir,
+        record_keyframes_only,
+        decode_thread,
+        pace,
+        trace_timing,
+        crc_frames,
+        commit_policy,
+        max_frames,
+        max_bytes,
+        snapshot,
+        self_test,
+        dump_flv,
+        verbose,
+        stream_key,
+        log_file,
+        log_format,
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    let (addr, verbose, stream_key) = parse_args();
+/// The certificate and key for `--tls-port`, exiting if either is missing
+/// or they can't be loaded.
+fn load_tls_config(cert: Option<&Path>, key: Option<&Path>) -> TlsConfig {
+    let (Some(cert), Some(key)) = (cert, key) else {
+        error!("--tls-port needs both --tls-cert and --tls-key");
+        std::process::exit(2);
+    };
+    TlsConfig::from_pem_files(cert, key).unwrap_or_else(|e| {
+        error!(%e, "failed to load TLS certificate");
+        std::process::exit(1);
+    })
+}
+
+/// The listen address for modes that take only one: the first `--port`.
+fn single_addr(addrs: &[SocketAddr]) -> SocketAddr {
+    if addrs.len() > 1 {
+        warn!(addr = %addrs[0], "only RTMP listens on several ports, using the first");
+    }
+    addrs[0]
+}
 
-    // Initialize tracing
-    let filter = if verbose {
-        "rtmp_server=debug,video_pipeline=debug,rtmp_vcam_app=debug"
+/// Parse a `--mode` value, exiting with a usage error if it isn't known.
+fn parse_mode(value: &str) -> Mode {
+    match value {
+        "rtmp" => Mode::Rtmp,
+        "mpegts" | "ts" => Mode::MpegTs,
+        "http-flv" => Mode::HttpFlv,
+        other => {
+            eprintln!("unknown mode '{other}' (expected rtmp, mpegts or http-flv)");
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Parse a `--log-format` value, exiting with a usage error if it isn't known.
+fn parse_log_format(value: &str) -> LogFormat {
+    match value {
+        "text" => LogFormat::Text,
+        "json" => LogFormat::Json,
+        other => {
+            eprintln!("unknown log format '{other}' (expected text or json)");
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Parse a `--commit-policy` value, exiting with a usage error if it isn't known.
+fn parse_commit_policy(value: &str) -> CommitPolicy {
+    match value {
+        "drop-oldest" => CommitPolicy::DropOldest,
+        "block-briefly" => CommitPolicy::BlockBriefly,
+        other => {
+            eprintln!("unknown commit policy '{other}' (expected drop-oldest or block-briefly)");
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Parse a `--port` / `--tls-port` value, exiting with a usage error if it
+/// isn't a port number.
+fn parse_port(flag: &str, value: &str) -> u16 {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("{flag} expects a port number, got '{value}'");
+        std::process::exit(2);
+    })
+}
+
+/// Parse a `--max-*` value, exiting with a usage error if it isn't a
+/// positive integer.
+fn parse_limit(flag: &str, value: &str) -> u64 {
+    match value.parse() {
+        Ok(n) if n > 0 => n,
+        _ => {
+            eprintln!("{flag} expects a positive integer, got '{value}'");
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Initialize tracing. Logs go to stdout, or to a daily-rotated file when
+/// `--log-file` is given (stdout is then only used with `--verbose`).
+/// The returned guard must be held for the file writer to keep flushing.
+fn init_logging(args: &Args) -> Option<WorkerGuard> {
+    let mut filter = if args.verbose {
+        "rtmp_server=debug,video_pipeline=debug,rtmp_vcam_app=debug".to_string()
     } else {
-        "rtmp_server=info,video_pipeline=info,rtmp_vcam_app=info"
+        "rtmp_server=info,video_pipeline=info,rtmp_vcam_app=info".to_string()
     };
+    if args.trace_timing {
+        filter.push_str(",video_pipeline::timing=trace");
+    }
+    if args.crc_frames {
+        filter.push_str(",video_pipeline::crc=debug");
+    }
+    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| filter.into());
+
+    let mut guard = None;
+    let file_layer = args.log_file.as_ref().and_then(|path| {
+        let dir = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| std::path::Path::new("."));
+        let prefix = path.file_name()?.to_string_lossy().into_owned();
+        let appender = match RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix(prefix)
+            .max_log_files(MAX_LOG_FILES)
+            .build(dir)
+        {
+            Ok(appender) => appender,
+            Err(e) => {
+                eprintln!("failed to open log file {}: {e}", path.display());
+                return None;
+            }
+        };
+        let (writer, g) = tracing_appender::non_blocking(appender);
+        guard = Some(g);
+        Some(fmt_layer(args.log_format, writer, false))
+    });
+
+    let use_stdout = file_layer.is_none() || args.verbose;
     let use_ansi = std::io::IsTerminal::is_terminal(&std::io::stderr());
-    tracing_subscriber::fmt()
-        .with_ansi(use_ansi)
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| filter.into()),
-        )
+    let stdout_layer =
+        use_stdout.then(|| fmt_layer(args.log_format, std::io::stdout, use_ansi));
+
+    let layers: Vec<_> = stdout_layer.into_iter().chain(file_layer).collect();
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(env_filter)
         .init();
 
+    guard
+}
+
+/// A log output layer writing to `writer` in `format`.
+fn fmt_layer<W>(
+    format: LogFormat,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn tracing_subscriber::Layer<tracing_subscriber::Registry> + Send + Sync>
+where
+    W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match format {
+        LogFormat::Text => layer.with_ansi(ansi).boxed(),
+        LogFormat::Json => layer.json().flatten_event(true).boxed(),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = parse_args();
+    let _log_guard = init_logging(&args);
+    if args.self_test {
+        match self_test::run() {
+            Ok((width, height)) => {
+                println!("decode OK {width}x{height}");
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("self-test failed: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &args.dump_flv {
+        match dump_flv::run(path, &mut std::io::stdout().lock()) {
+            Ok(_) => std::process::exit(0),
+            Err(e) => {
+                eprintln!("failed to dump {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        }
+    }
+    let Args {
+        addrs,
+        tls_addr,
+        tls_cert,
+        tls_key,
+        mode,
+        apps,
+        unix_socket,
+        dual_stack,
+        record_dir,
+        record_keyframes_only,
+        decode_thread,
+        pace,
+        trace_timing,
+        crc_frames,
+        commit_policy,
+        max_frames,
+        max_bytes,
+        snapshot,
+        stream_key,
+        ..
+    } = args;
+
     info!("rtmp-vcam starting");
 
-    // Create shared memory for IPC with the Camera Extension
-    let shm = match SharedFrameBuffer::create() {
-        Ok(shm) => Arc::new(shm),
+    // Load the RTMPS certificate up front, so a bad path fails at startup
+    let tls = tls_addr
+        .map(|addr| Listener::Tls(addr, load_tls_config(tls_cert.as_deref(), tls_key.as_deref())));
+
+    // Create shared memory for IPC with the Camera Extension.
+    // Each publishing stream gets its own buffer from the pool.
+    let pool = match SharedFrameBuffer::create() {
+        Ok(shm) => Arc::new(FrameBufferPool::new(Arc::new(shm))),
         Err(e) => {
             error!(%e, "failed to create shared memory");
             std::process::exit(1);
         }
     };
 
-    // Set up Ctrl+C handler to clean up shared memory
-    let shm_for_ctrlc = Arc::clone(&shm);
+    let stats = Arc::new(DecoderStats::with_limits(max_frames, max_bytes));
+    let stream_stats = Arc::new(StreamStatsRegistry::new(Arc::clone(&stats)));
+
+    // Shut down on Ctrl+C, once --max-frames / --max-bytes is reached, or
+    // when a task below asks to
+    let stop = StopRequest::default();
+    let stats_for_shutdown = Arc::clone(&stats);
+    let stop_for_shutdown = stop.clone();
+    let shutdown =
+        async move { shutdown_requested(&stats_for_shutdown, &stop_for_shutdown).await };
+
+    if let Some(path) = snapshot {
+        let pool = Arc::clone(&pool);
+        let stop = stop.clone();
+        tokio::spawn(async move {
+            info!(path = %path.display(), "waiting for a frame to snapshot");
+            match snapshot::capture(pool, path.clone()).await {
+                Ok(()) => {
+                    info!(path = %path.display(), "snapshot saved, shutting down");
+                    stop.stop(0);
+                }
+                Err(e) => {
+                    error!(path = %path.display(), %e, "failed to save snapshot, shutting down");
+                    stop.stop(1);
+                }
+            }
+        });
+    }
+
+    // Recreate ring files if they're deleted while we run, so a reader
+    // opening the path sees the same file we write to
+    let pool_for_check = Arc::clone(&pool);
     tokio::spawn(async move {
-        tokio::signal::ctrl_c().await.ok();
-        info!("shutting down...");
-        drop(shm_for_ctrlc);
-        std::process::exit(0);
+        let mut interval = tokio::time::interval(RING_CHECK_INTERVAL);
+        let mut lagging_readers = 0;
+        loop {
+            interval.tick().await;
+            let pool = Arc::clone(&pool_for_check);
+            tokio::task::spawn_blocking(move || pool.ensure_files()).await.ok();
+
+            // Note when registered readers fall behind or catch up
+            let primary = pool_for_check.primary();
+            let slowest = primary.min_reader_cursor();
+            let lagging = primary.lagging_readers();
+            if lagging != lagging_readers {
+                debug!(lagging, ?slowest, "frame buffer readers lagging");
+                lagging_readers = lagging;
+            }
+        }
     });
 
-    // Exit if parent process dies (orphan protection)
+    // Shut down if parent process dies (orphan protection)
+    let stop_for_orphan = stop.clone();
     tokio::spawn(async move {
         let original_ppid = unsafe { libc::getppid() };
         loop {
             tokio::time::sleep(std::time::Duration::from_secs(2)).await;
             let ppid = unsafe { libc::getppid() };
             if ppid != original_ppid {
-                tracing::info!("parent process died (ppid changed {original_ppid} -> {ppid}), exiting");
-                std::process::exit(0);
+                tracing::info!(
+                    "parent process died (ppid changed {original_ppid} -> {ppid}), shutting down"
+                );
+                stop_for_orphan.stop(0);
+                return;
             }
         }
     });
 
-    info!(%addr, "starting RTMP server");
+    let stream_stats_for_status = Arc::clone(&stream_stats);
+    let sink_factory = move |context: &ConnectionContext| -> std::io::Result<Box<dyn VideoSink>> {
+        info!(
+            peer_addr = %context.peer_addr,
+            app = context.app_name,
+            stream_key = context.stream_key,
+            "creating sink for publish"
+        );
+        let shm = pool.acquire(&context.stream_key)?;
+        let stats = stream_stats.register(&context.stream_key);
+        let sink = Box::new(DecoderSink::new(
+            shm,
+            stats,
+            trace_timing,
+            crc_frames,
+            commit_policy,
+        ));
+        // Pacing runs the decoder on its own thread too
+        if let Some(depth) = pace {
+            let sink = PacingSink::spawn(sink, depth as usize, DECODE_QUEUE_EVENTS)?;
+            return Ok(Box::new(sink));
+        }
+        if decode_thread {
+            return Ok(Box::new(ThreadedSink::spawn(sink, DECODE_QUEUE_EVENTS)?));
+        }
+        Ok(sink)
+    };
+    let result = match mode {
+        Mode::Rtmp => {
+            let mut server = Server::new().with_dual_stack(dual_stack);
+            if !apps.is_empty() {
+                info!(?apps, "accepting only listed RTMP apps");
+                server = server.with_app_allowlist(apps);
+            }
+            let status = server.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(INGEST_LOG_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    for (stream_key, info) in status.active_publishers() {
+                        let decoded = stream_stats_for_status.get(&stream_key);
+                        debug!(
+                            stream_key,
+                            peer_addr = %info.peer_addr,
+                            ingest_kbps = info.ingest_kbps,
+                            frames_decoded = decoded.as_ref().map_or(0, |s| s.frames()),
+                            frames_dropped = decoded.as_ref().map_or(0, |s| s.frames_dropped()),
+                            audio_level_db = ?decoded.as_ref().and_then(|s| s.audio_level()),
+                            messages = %info.messages,
+                            "publisher ingest"
+                        );
+                    }
+                }
+            });
+            if let Some(dir) = record_dir {
+                info!(dir = %dir.display(), "recording record/append publishes");
+                server = server
+                    .with_recording_dir(dir)
+                    .with_recorder_options(RecorderOptions {
+                        keyframes_only: record_keyframes_only,
+                    });
+            }
+            // Stop accepting and close the open connections, so the sinks
+            // release their decoders before we exit
+            let handle = server.clone();
+            tokio::spawn(async move {
+                shutdown.await;
+                handle.shutdown();
+            });
+            match unix_socket {
+                Some(path) => {
+                    if tls.is_some() {
+                        warn!("--tls-port has no effect with --unix-socket");
+                    }
+                    info!(path = %path.display(), "starting RTMP server on Unix socket");
+                    server.run_unix(path, sink_factory, stream_key).await
+                }
+                None => {
+                    let tls_addr = tls.as_ref().map(Listener::addr);
+                    info!(?addrs, ?tls_addr, "starting RTMP server");
+                    let mut listeners: Vec<Listener> =
+                        addrs.iter().map(|&addr| Listener::Tcp(addr)).collect();
+                    listeners.extend(tls);
+                    server.serve(&listeners, sink_factory, stream_key).await
+                }
+            }
+        }
+        Mode::MpegTs => {
+            if stream_key.is_some() || !apps.is_empty() || unix_socket.is_some() {
+                warn!("--stream-key, --app and --unix-socket have no effect in mpegts mode");
+            }
+            if tls.is_some() {
+                warn!("--tls-port has no effect in mpegts mode");
+            }
+            let addr = single_addr(&addrs);
+            info!(%addr, "starting MPEG-TS ingest");
+            tokio::select! {
+                result = rtmp_server::mpegts::run(addr, sink_factory) => {
+                    result.map_err(RtmpError::Io)
+                }
+                () = shutdown => Ok(()),
+            }
+        }
+        Mode::HttpFlv => {
+            if stream_key.is_some() || !apps.is_empty() || unix_socket.is_some() {
+                warn!("--stream-key, --app and --unix-socket have no effect in http-flv mode");
+            }
+            if tls.is_some() {
+                warn!("--tls-port has no effect in http-flv mode");
+            }
+            let addr = single_addr(&addrs);
+            info!(%addr, "starting HTTP-FLV ingest");
+            tokio::select! {
+                result = rtmp_server::http_flv::run(addr, sink_factory) => {
+                    result.map_err(RtmpError::Io)
+                }
+                () = shutdown => Ok(()),
+            }
+        }
+    };
+    if let Err(e) = result {
+        error!(%e, ?mode, "server error");
+        std::process::exit(1);
+    }
+    log_decode_summary(&stats);
+    info!("rtmp-vcam stopped");
+    let exit_code = stop.exit_code();
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+}
 
-    let shm_clone = Arc::clone(&shm);
+/// Lets a background task, such as the snapshot, stop the server the same
+/// way Ctrl+C does and choose the exit status once it has shut down.
+#[derive(Clone, Default)]
+struct StopRequest {
+    shutdown: Shutdown,
+    exit_code: Arc<AtomicI32>,
+}
 
-    // Start the RTMP server
-    if let Err(e) = rtmp_server::server::run(addr, move || {
-        Box::new(DecoderSink::new(Arc::clone(&shm_clone)))
-    }, stream_key)
-    .await
-    {
-        error!(%e, "RTMP server error");
-        std::process::exit(1);
+impl StopRequest {
+    /// Ask the server to shut down, then exit with `exit_code`. The first
+    /// request's status wins.
+    fn stop(&self, exit_code: i32) {
+        if !self.shutdown.is_triggered() {
+            self.exit_code.store(exit_code, Ordering::Relaxed);
+        }
+        self.shutdown.trigger();
+    }
+
+    fn exit_code(&self) -> i32 {
+        self.exit_code.load(Ordering::Relaxed)
+    }
+}
+
+/// Wait for Ctrl+C, for --max-frames / --max-bytes to be reached, or for
+/// `stop`.
+async fn shutdown_requested(stats: &DecoderStats, stop: &StopRequest) {
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("shutting down..."),
+        _ = stats.limit_reached() => info!(
+            frames = stats.frames(),
+            bytes = stats.bytes(),
+            "decode limit reached, shutting down"
+        ),
+        () = stop.shutdown.wait() => {}
+    }
+}
+
+/// Log the frames that couldn't be decoded, once the server has stopped.
+fn log_decode_summary(stats: &DecoderStats) {
+    let benign_errors = stats.benign_errors();
+    if benign_errors > 0 {
+        info!(benign_errors, "frames dropped as undecodable");
+    }
+    let frames_dropped_by_vt = stats.frames_dropped_by_vt();
+    if frames_dropped_by_vt > 0 {
+        info!(frames_dropped_by_vt, "frames dropped by VideoToolbox");
+    }
+    for e in stats.recent_errors() {
+        info!(
+            status = e.status,
+            timestamp = e.timestamp,
+            frame_seq = e.frame_seq,
+            "recent decode error"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_band_config() {
+        let frame = [
+            0x00, 0x00, 0x00, 0x04, 0x67, 0x64, 0x00, 0x1F, // SPS
+            0x00, 0x00, 0x00, 0x02, 0x68, 0xEB, // PPS
+            0x00, 0x00, 0x00, 0x02, 0x65, 0x88, // IDR
+        ];
+        let config = in_band_config(&frame, 4).unwrap();
+        assert_eq!(config.sps, vec![vec![0x67, 0x64, 0x00, 0x1F]]);
+        assert_eq!(config.pps, vec![vec![0x68, 0xEB]]);
+
+        // A frame without parameter sets
+        assert!(in_band_config(&frame[14..], 4).is_none());
+
+        // 2-byte lengths, as announced by a sequence header with no SPS/PPS
+        let frame = [
+            0x00, 0x04, 0x67, 0x64, 0x00, 0x1F, // SPS
+            0x00, 0x02, 0x68, 0xEB, // PPS
+            0x00, 0x02, 0x65, 0x88, // IDR
+        ];
+        let config = in_band_config(&frame, 2).unwrap();
+        assert_eq!(config.nalu_length_size, 2);
+        assert_eq!(config.pps, vec![vec![0x68, 0xEB]]);
+    }
+
+    #[test]
+    fn test_tiny_stream_through_flv_parser() {
+        use rtmp_server::flv::parse_video_data;
+        use rtmp_server::VideoPacket;
+
+        let stream = video_pipeline::test_vectors::tiny_stream();
+        let header = Bytes::from(stream.flv_sequence_header());
+        let VideoPacket::SequenceHeader(config) = parse_video_data(&header, 0, 4) else {
+            panic!("sequence header not recognised");
+        };
+        assert_eq!(config.sps, [stream.sps]);
+        assert_eq!(config.pps, [stream.pps]);
+        assert_eq!(config.nalu_length_size, stream.nalu_length_size);
+
+        for frame in &stream.frames {
+            let data = Bytes::from(frame.flv_video_data());
+            let packet = parse_video_data(&data, frame.timestamp_ms, config.nalu_length_size);
+            let VideoPacket::NaluData {
+                avcc_payload,
+                timestamp,
+                is_keyframe,
+                ..
+            } = packet
+            else {
+                panic!("frame at {} ms not parsed as NALU data", frame.timestamp_ms);
+            };
+            assert_eq!(avcc_payload, frame.avcc);
+            assert_eq!(timestamp, frame.timestamp_ms);
+            assert_eq!(is_keyframe, frame.keyframe);
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_raw_avcc_replay_decodes_every_frame() {
+        use rtmp_server::{RawAvccReader, RawAvccRecorder};
+
+        #[derive(Default)]
+        struct Collect {
+            config: Option<AvcDecoderConfig>,
+            frames: Vec<(Bytes, u32)>,
+        }
+        impl VideoSink for Collect {
+            fn on_decoder_config(&mut self, config: AvcDecoderConfig) {
+                self.config = Some(config);
+            }
+            fn on_video_data(&mut self, data: Bytes, timestamp: u32) {
+                self.frames.push((data, timestamp));
+            }
+        }
+
+        let stream = video_pipeline::test_vectors::tiny_stream();
+        let path = std::env::temp_dir().join(format!("rtmp-vcam-raw-{}.avcc", std::process::id()));
+        let mut recorder = RawAvccRecorder::create(&path).unwrap();
+        recorder.on_decoder_config(AvcDecoderConfig {
+            sps: vec![stream.sps.to_vec()],
+            pps: vec![stream.pps.to_vec()],
+            nalu_length_size: stream.nalu_length_size,
+        });
+        for frame in &stream.frames {
+            recorder.on_video_data(Bytes::from_static(frame.avcc), frame.timestamp_ms);
+        }
+        recorder.finish().unwrap();
+
+        let mut replayed = Collect::default();
+        RawAvccReader::open(&path).unwrap().replay(&mut replayed).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let config = replayed.config.unwrap();
+        let decoded = H264Decoder::decode_all(
+            &config.sps,
+            &config.pps,
+            config.nalu_length_size,
+            replayed.frames.iter().map(|(data, ts)| (&data[..], *ts)),
+        )
+        .unwrap();
+        assert_eq!(decoded.len(), stream.decode().unwrap().len());
+    }
+
+    #[cfg(target_os = "macos")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_frame_limit_counts_decoded_frames() {
+        let stream = video_pipeline::test_vectors::tiny_stream();
+        let path =
+            std::env::temp_dir().join(format!("rtmp-vcam-ring-counted-{}", std::process::id()));
+        let shm = Arc::new(SharedFrameBuffer::create_at(&path).unwrap());
+        let stats = Arc::new(DecoderStats::default());
+        let mut sink =
+            DecoderSink::new(shm, Arc::clone(&stats), false, false, CommitPolicy::default());
+        sink.on_decoder_config(AvcDecoderConfig {
+            sps: vec![stream.sps.to_vec()],
+            pps: vec![stream.pps.to_vec()],
+            nalu_length_size: stream.nalu_length_size,
+        });
+        for frame in &stream.frames {
+            sink.on_video_data(Bytes::from_static(frame.avcc), frame.timestamp_ms);
+        }
+        // Flushing counts the frames still held back
+        sink.on_end_of_sequence();
+        drop(sink);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(ipc::readers_path(&path)).unwrap();
+
+        assert_eq!(stats.frames(), stream.decode().unwrap().len() as u64);
+    }
+
+    #[test]
+    fn test_ten_bit_stream_commits_frames() {
+        use video_pipeline::{DecodedFrame, FrameOutput};
+
+        let path =
+            std::env::temp_dir().join(format!("rtmp-vcam-ring-10-bit-{}", std::process::id()));
+        let shm = Arc::new(SharedFrameBuffer::create_at(&path).unwrap());
+        let stats = Arc::new(DecoderStats::default());
+        let sink = DecoderSink::new(shm, stats, false, false, CommitPolicy::default());
+
+        // VideoToolbox is asked for NV12, as a full-size P010 frame is
+        // twice what the ring's slots hold
+        let mut output = sink.shm_output();
+        assert!(!output.accepts_p010());
+        let (width, height) = (1920, 1080);
+        let y = vec![0x80; width * height];
+        let uv = vec![0x80; width * height / 2];
+        for timestamp_ms in [0, 33] {
+            output.write_frame(&DecodedFrame {
+                width,
+                height,
+                bytes_per_sample: 1,
+                y_plane: &y,
+                y_stride: width,
+                uv_plane: &uv,
+                uv_stride: width,
+                timestamp_ms,
+                sar: (1, 1),
+            });
+        }
+        assert_eq!(output.write_index(), Some(2));
+
+        drop((output, sink));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(ipc::readers_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn test_color_header_units() {
+        let info = ColorInfo {
+            transfer_characteristics: Some(16),
+            max_cll: Some(1000),
+            mastering_display: Some(rtmp_server::MasteringDisplay {
+                primaries: [(0.708, 0.292), (0.17, 0.797), (0.131, 0.046)],
+                white_point: (0.3127, 0.329),
+                max_luminance: 1000.0,
+                min_luminance: 0.005,
+            }),
+            ..ColorInfo::default()
+        };
+        let color = color_header(&info);
+        assert_eq!(color.present, 1);
+        assert_eq!(color.transfer_characteristics, 16);
+        assert_eq!(color.color_primaries, 0);
+        assert_eq!(color.max_cll, 1000);
+        assert_eq!(color.display_primaries, [35400, 14600, 8500, 39850, 6550, 2300]);
+        assert_eq!(color.white_point, [15635, 16450]);
+        assert_eq!(color.max_luminance, 10_000_000);
+        assert_eq!(color.min_luminance, 50);
+    }
+
+    #[test]
+    fn test_flags_override_config_file() {
+        let path =
+            std::env::temp_dir().join(format!("rtmp-vcam-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "port = 1936\napp = [\"live\"]\ndecode-thread = true\n").unwrap();
+        let parse = |flags: &[&str]| {
+            let mut args = vec!["rtmp-vcam-app".to_string(), "--config".into()];
+            args.push(path.display().to_string());
+            args.extend(flags.iter().map(|flag| flag.to_string()));
+            parse_args_from(&args)
+        };
+
+        let args = parse(&[]);
+        assert_eq!(args.addrs, [SocketAddr::from((Ipv4Addr::UNSPECIFIED, 1936))]);
+        assert_eq!(args.apps, ["live"]);
+        assert!(args.decode_thread);
+
+        let args = parse(&["--port", "1937", "-p", "1938", "-a", "studio"]);
+        let ports: Vec<u16> = args.addrs.iter().map(SocketAddr::port).collect();
+        assert_eq!(ports, [1937, 1938]);
+        assert_eq!(args.apps, ["studio"]);
+        assert!(args.decode_thread);
+        assert_eq!(args.tls_addr, None);
+
+        let args = parse(&["--tls-port", "1939", "--tls-cert", "cert.pem", "--tls-key", "key.pem"]);
+        assert_eq!(args.tls_addr, Some(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 1939))));
+        assert_eq!(args.tls_cert.as_deref(), Some(Path::new("cert.pem")));
+        assert_eq!(args.tls_key.as_deref(), Some(Path::new("key.pem")));
+
+        std::fs::remove_file(&path).unwrap();
     }
 }
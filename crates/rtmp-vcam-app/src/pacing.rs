@@ -0,0 +1,285 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tracing::{debug, trace};
+
+use rtmp_server::{AvcDecoderConfig, ColorInfo, StreamInfo, VideoSink};
+
+use crate::decode_thread::{SinkEvent, ThreadedSink};
+
+/// Furthest ahead of the pacing clock a frame may be before it's taken as a
+/// timestamp jump and the clock restarts at it.
+const MAX_PACING_AHEAD: Duration = Duration::from_secs(1);
+
+/// VideoSink that smooths out bursty input (`--pace`): video is held back
+/// `depth` frames and handed to the inner sink when its timestamp comes due,
+/// so shm commits land at the stream's own frame rate however the frames
+/// arrived.
+///
+/// This adds `depth` frames of latency, 100 ms for a depth of 3 at 30 fps.
+/// Like `ThreadedSink`, the inner sink runs on its own thread.
+pub struct PacingSink {
+    thread: ThreadedSink,
+}
+
+impl PacingSink {
+    /// Run `inner` behind a pacing buffer of `depth` frames. Must be called
+    /// from within a Tokio runtime, as for `ThreadedSink::spawn`.
+    pub fn spawn(
+        inner: Box<dyn VideoSink>,
+        depth: usize,
+        capacity: usize,
+    ) -> std::io::Result<Self> {
+        let thread = ThreadedSink::spawn_with(inner, capacity, "pacing", move |sink, rx| {
+            run(sink, rx, depth)
+        })?;
+        Ok(Self { thread })
+    }
+}
+
+impl VideoSink for PacingSink {
+    fn on_decoder_config(&mut self, config: AvcDecoderConfig) {
+        self.thread.on_decoder_config(config);
+    }
+
+    fn on_connect_params(&mut self, params: HashMap<String, String>) {
+        self.thread.on_connect_params(params);
+    }
+
+    fn on_video_data(&mut self, data: Bytes, timestamp: u32) {
+        self.thread.on_video_data(data, timestamp);
+    }
+
+    fn on_stream_info(&mut self, info: StreamInfo) {
+        self.thread.on_stream_info(info);
+    }
+
+    fn on_color_info(&mut self, info: ColorInfo) {
+        self.thread.on_color_info(info);
+    }
+
+    fn on_sei(&mut self, payload_type: u32, data: &[u8]) {
+        self.thread.on_sei(payload_type, data);
+    }
+
+    fn on_audio_level(&mut self, rms_db: f32, peak_db: f32) {
+        self.thread.on_audio_level(rms_db, peak_db);
+    }
+
+    fn on_first_frame(&mut self, width: usize, height: usize) {
+        self.thread.on_first_frame(width, height);
+    }
+
+    fn on_decoder_create_error(&mut self, status: i32, message: &str) {
+        self.thread.on_decoder_create_error(status, message);
+    }
+
+    fn on_end_of_sequence(&mut self) {
+        self.thread.on_end_of_sequence();
+    }
+
+    fn on_stream_end(&mut self) {
+        self.thread.on_stream_end();
+    }
+
+    fn is_failed(&self) -> bool {
+        self.thread.is_failed()
+    }
+}
+
+/// Queue of sink events, releasing video frames on a clock driven by their
+/// timestamps. Other events keep their place and go out as soon as they
+/// reach the front.
+struct Pacer {
+    depth: usize,
+    queue: VecDeque<SinkEvent>,
+    /// Video frames in `queue`.
+    frames: usize,
+    /// When the frame with the given timestamp was released. Cleared when
+    /// the buffer runs dry and a frame comes in late, so it fills up to
+    /// `depth` again before the next release.
+    clock: Option<(Instant, u32)>,
+    /// Stream ends queued; everything before one goes out unpaced.
+    ends: usize,
+}
+
+impl Pacer {
+    fn new(depth: usize) -> Self {
+        Self {
+            depth: depth.max(1),
+            queue: VecDeque::new(),
+            frames: 0,
+            clock: None,
+            ends: 0,
+        }
+    }
+
+    fn push(&mut self, event: SinkEvent) {
+        match event {
+            SinkEvent::Video(..) => self.frames += 1,
+            SinkEvent::End => self.ends += 1,
+            _ => {}
+        }
+        self.queue.push_back(event);
+    }
+
+    /// When the front event is due, or `None` if the buffer is still filling.
+    fn next_due(&self, now: Instant) -> Option<Instant> {
+        let Some(SinkEvent::Video(_, timestamp)) = self.queue.front() else {
+            return (!self.queue.is_empty()).then_some(now);
+        };
+        // Past twice the depth the input is persistently faster than its
+        // timestamps; catch up rather than let latency grow
+        if self.ends > 0 || self.frames > 2 * self.depth {
+            return Some(now);
+        }
+        match self.clock {
+            Some(clock) => Some(due(clock, *timestamp, now).unwrap_or(now)),
+            None => (self.frames >= self.depth).then_some(now),
+        }
+    }
+
+    /// Take the front event if it's due at `now`.
+    fn pop(&mut self, now: Instant) -> Option<SinkEvent> {
+        if self.next_due(now)? > now {
+            return None;
+        }
+        let event = self.queue.pop_front()?;
+        match &event {
+            SinkEvent::Video(_, timestamp) => {
+                self.frames -= 1;
+                let due = self.clock.and_then(|clock| due(clock, *timestamp, now));
+                if due.is_none() {
+                    trace!(timestamp, "starting pacing clock");
+                    self.clock = Some((now, *timestamp));
+                }
+                if self.frames == 0 && due.is_some_and(|due| due < now) {
+                    debug!(timestamp, "pacing buffer ran dry, refilling");
+                    self.clock = None;
+                }
+            }
+            SinkEvent::End => {
+                self.ends -= 1;
+                self.clock = None;
+            }
+            _ => {}
+        }
+        Some(event)
+    }
+}
+
+/// When a frame at `timestamp` is due on `clock`, or `None` if the
+/// timestamp went backwards or jumped too far ahead to pace.
+fn due((start, start_timestamp): (Instant, u32), timestamp: u32, now: Instant) -> Option<Instant> {
+    let offset = timestamp.wrapping_sub(start_timestamp) as i32;
+    let due = start + Duration::from_millis(u64::try_from(offset).ok()?);
+    (due <= now + MAX_PACING_AHEAD).then_some(due)
+}
+
+fn run(mut sink: Box<dyn VideoSink>, rx: Receiver<SinkEvent>, depth: usize) {
+    let mut pacer = Pacer::new(depth);
+    loop {
+        let now = Instant::now();
+        while let Some(event) = pacer.pop(now) {
+            event.deliver(sink.as_mut());
+        }
+        let received = match pacer.next_due(now) {
+            Some(due) => rx.recv_timeout(due.saturating_duration_since(Instant::now())),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(event) => pacer.push(event),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    // The sink is going away: deliver what's left without waiting
+    for event in pacer.queue {
+        event.deliver(sink.as_mut());
+    }
+    debug!("pacing thread finished");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(timestamp: u32) -> SinkEvent {
+        SinkEvent::Video(Bytes::new(), timestamp)
+    }
+
+    /// Feed `arrivals` of (arrival ms, timestamp) through a pacer, stepping
+    /// a clock by 1 ms, and return when each frame was released.
+    fn release_times(depth: usize, arrivals: &[(u64, u32)]) -> Vec<(u64, u32)> {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(depth);
+        let mut released = Vec::new();
+        for ms in 0..1000 {
+            for &(_, timestamp) in arrivals.iter().filter(|(at, _)| *at == ms) {
+                pacer.push(video(timestamp));
+            }
+            let now = start + Duration::from_millis(ms);
+            while let Some(event) = pacer.pop(now) {
+                if let SinkEvent::Video(_, timestamp) = event {
+                    released.push((ms, timestamp));
+                }
+            }
+        }
+        released
+    }
+
+    #[test]
+    fn test_bursts_released_evenly() {
+        // 30 fps delivered two frames at a time every 66 ms
+        let arrivals: Vec<(u64, u32)> = (0..12).map(|i| (i / 2 * 66, i as u32 * 33)).collect();
+        let released = release_times(3, &arrivals);
+        assert_eq!(released.len(), 12);
+        // Held until three frames were buffered, then one every 33 ms
+        assert_eq!(released[0], (66, 0));
+        for pair in released.windows(2) {
+            assert_eq!(pair[1].0 - pair[0].0, 33, "{released:?}");
+        }
+    }
+
+    #[test]
+    fn test_timestamp_jump_restarts_clock() {
+        let released = release_times(2, &[(0, 0), (10, 33), (20, 50_000), (30, 50_033)]);
+        assert_eq!(released, [(10, 0), (43, 33), (43, 50_000), (76, 50_033)]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_forwards_audio_level_and_first_frame() {
+        use crate::decode_thread::tests::Raised;
+
+        let inner = Raised::default();
+        let (levels, first_frames) = (inner.levels.clone(), inner.first_frames.clone());
+        let mut sink = PacingSink::spawn(Box::new(inner), 3, 8).unwrap();
+        sink.on_audio_level(-20.5, -6.0);
+        sink.on_first_frame(1920, 1080);
+        drop(sink);
+        assert_eq!(*levels.lock().unwrap(), [(-20.5, -6.0)]);
+        assert_eq!(*first_frames.lock().unwrap(), [(1920, 1080)]);
+    }
+
+    #[test]
+    fn test_stream_end_flushes() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(4);
+        pacer.push(video(0));
+        pacer.push(video(33));
+        assert_eq!(pacer.next_due(start), None);
+
+        pacer.push(SinkEvent::End);
+        let events: Vec<_> = std::iter::from_fn(|| pacer.pop(start)).collect();
+        assert!(matches!(
+            events[..],
+            [
+                SinkEvent::Video(_, 0),
+                SinkEvent::Video(_, 33),
+                SinkEvent::End
+            ]
+        ));
+    }
+}
@@ -0,0 +1,59 @@
+//! `--self-test`: decode a built-in clip through VideoToolbox into a
+//! scratch frame buffer and check what lands there.
+
+use std::path::Path;
+
+use video_pipeline::test_vectors::{tiny_stream, TestStream};
+use video_pipeline::{FrameReader, H264Decoder};
+
+use crate::ipc::SharedFrameBuffer;
+
+/// Decode the clip and return the size of the frame read back, or why the
+/// check failed.
+pub fn run() -> Result<(usize, usize), String> {
+    let dir = std::env::temp_dir().join(format!("rtmp-vcam-self-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("failed to create {}: {e}", dir.display()))?;
+    let result = decode_into_buffer(&dir.join("ring"), &tiny_stream());
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+fn decode_into_buffer(ring_path: &Path, stream: &TestStream) -> Result<(usize, usize), String> {
+    let shm = SharedFrameBuffer::create_at(ring_path)
+        .map_err(|e| format!("failed to create frame buffer: {e}"))?;
+    let reader = unsafe { FrameReader::new(shm.ptr()) }?;
+    let mut decoder = H264Decoder::new(
+        &[stream.sps.to_vec()],
+        &[stream.pps.to_vec()],
+        stream.nalu_length_size,
+        shm.ptr(),
+        shm.len(),
+    )
+    .map_err(|e| format!("failed to create decoder: {e}"))?;
+    for frame in &stream.frames {
+        decoder
+            .decode_avcc(frame.avcc, frame.timestamp_ms)
+            .map_err(|e| format!("decode failed at {} ms: {e}", frame.timestamp_ms))?;
+    }
+    decoder.flush()?;
+
+    let frame = reader.latest_frame().ok_or("decoder produced no frame")?;
+    let expected = (stream.width as usize, stream.height as usize);
+    if (frame.width, frame.height) != expected {
+        return Err(format!(
+            "decoded {}x{}, expected {}x{}",
+            frame.width, frame.height, expected.0, expected.1
+        ));
+    }
+    // Compare the corners of the last frame's luma against the clip
+    let last = stream.frames.len() - 1;
+    for (x, y) in [(0, 0), (expected.0 - 1, 0), (0, expected.1 - 1)] {
+        let sample = frame.data[y * frame.width + x];
+        let want = stream.expected_luma(last, x, y);
+        if sample != want {
+            return Err(format!("luma at ({x}, {y}) is {sample}, expected {want}"));
+        }
+    }
+    Ok(expected)
+}
@@ -0,0 +1,76 @@
+//! `--snapshot`: save the next decoded frame as a JPEG.
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::RgbImage;
+use video_pipeline::{Frame, FrameReader};
+
+use crate::ipc::FrameBufferPool;
+
+const JPEG_QUALITY: u8 = 90;
+
+/// Wait for the next frame written to the pool's primary buffer (the one the
+/// camera shows) and save it to `path` as a JPEG at its display size.
+pub async fn capture(pool: Arc<FrameBufferPool>, path: PathBuf) -> io::Result<()> {
+    // The buffer was just created, so the first frame committed is the next one
+    let mut reader = unsafe { FrameReader::new(pool.primary().ptr()) }.map_err(io::Error::other)?;
+    let frame = reader.wait_for_frame_async().await;
+    tokio::task::spawn_blocking(move || write_jpeg(&frame, &path))
+        .await
+        .map_err(io::Error::other)?
+}
+
+fn write_jpeg(frame: &Frame, path: &Path) -> io::Result<()> {
+    let (width, height) = (frame.width as u32, frame.height as u32);
+    let mut image = RgbImage::from_raw(width, height, frame.to_rgb())
+        .expect("to_rgb returns width * height pixels");
+    let display = (frame.display_width as u32, frame.display_height as u32);
+    if display != (width, height) {
+        image = imageops::resize(&image, display.0, display.1, FilterType::Triangle);
+    }
+
+    let file = BufWriter::new(File::create(path)?);
+    JpegEncoder::new_with_quality(file, JPEG_QUALITY)
+        .encode_image(&image)
+        .map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use video_pipeline::OutputFormat;
+
+    #[test]
+    fn test_write_jpeg_at_display_size() {
+        // Grey 16x8 frame with 2:1 pixels
+        let (width, height) = (16, 8);
+        let mut data = vec![126u8; width * height];
+        data.extend(vec![128u8; width * height / 2]);
+        let frame = Frame {
+            width,
+            height,
+            timestamp_ms: 0,
+            format: OutputFormat::Nv12,
+            display_width: 32,
+            display_height: 8,
+            data,
+        };
+
+        let path =
+            std::env::temp_dir().join(format!("rtmp-vcam-snapshot-{}.jpg", std::process::id()));
+        write_jpeg(&frame, &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bytes[..3], [0xFF, 0xD8, 0xFF]); // JPEG SOI marker
+
+        let image = image::load_from_memory(&bytes).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (32, 8));
+        let pixel = image.get_pixel(16, 4);
+        assert!(pixel.0.iter().all(|&c| c.abs_diff(128) <= 4), "{pixel:?}");
+    }
+}
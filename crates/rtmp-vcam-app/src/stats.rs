@@ -0,0 +1,287 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use tokio::sync::Notify;
+
+/// Decode errors kept for diagnostics; older ones are discarded.
+const RECENT_ERRORS: usize = 16;
+
+/// A failed decode call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    /// OSStatus from VideoToolbox/CoreMedia, 0 if the error carried none.
+    pub status: i32,
+    /// Stream timestamp of the frame, in milliseconds.
+    pub timestamp: u32,
+    /// Position of the frame among the video messages of its stream.
+    pub frame_seq: u64,
+}
+
+/// Decode counters, with optional limits (`--max-frames`, `--max-bytes`)
+/// after which the app shuts down.
+///
+/// The app keeps one for all streams and gives each `DecoderSink` a child
+/// from `StreamStatsRegistry`, whose counts also go to the total.
+#[derive(Debug, Default)]
+pub struct DecoderStats {
+    frames: AtomicU64,
+    bytes: AtomicU64,
+    benign_errors: AtomicU64,
+    frames_dropped_by_vt: AtomicU64,
+    max_frames: Option<u64>,
+    max_bytes: Option<u64>,
+    exhausted: AtomicBool,
+    limit_reached: Notify,
+    recent_errors: Mutex<VecDeque<DecodeError>>,
+    /// Latest audio level, RMS and peak in dBFS. Not added to `parent`.
+    audio_level: Mutex<Option<(f32, f32)>>,
+    /// Totals that everything recorded here is also added to.
+    parent: Option<Arc<DecoderStats>>,
+}
+
+impl DecoderStats {
+    pub fn with_limits(max_frames: Option<u64>, max_bytes: Option<u64>) -> Self {
+        Self {
+            max_frames,
+            max_bytes,
+            ..Self::default()
+        }
+    }
+
+    /// Counters of their own, without limits, that also add to `parent`
+    /// and are exhausted when it is.
+    pub fn child(parent: &Arc<DecoderStats>) -> Self {
+        Self {
+            parent: Some(Arc::clone(parent)),
+            ..Self::default()
+        }
+    }
+
+    /// Count `len` bytes of video received.
+    pub fn record_bytes(&self, len: usize) {
+        let bytes = self.bytes.fetch_add(len as u64, Ordering::Relaxed) + len as u64;
+        if self.max_bytes.is_some_and(|max| bytes >= max) {
+            self.exhaust();
+        }
+        if let Some(parent) = &self.parent {
+            parent.record_bytes(len);
+        }
+    }
+
+    /// Count frames the decoder output.
+    pub fn record_frames(&self, count: u64) {
+        let frames = self.frames.fetch_add(count, Ordering::Relaxed) + count;
+        if self.max_frames.is_some_and(|max| frames >= max) {
+            self.exhaust();
+        }
+        if let Some(parent) = &self.parent {
+            parent.record_frames(count);
+        }
+    }
+
+    /// Count frames the decoder dropped as undecodable rather than failing
+    /// (`DecoderOptions::ignore_benign_errors`).
+    pub fn record_benign_errors(&self, count: u64) {
+        self.benign_errors.fetch_add(count, Ordering::Relaxed);
+        if let Some(parent) = &self.parent {
+            parent.record_benign_errors(count);
+        }
+    }
+
+    /// Count frames VideoToolbox dropped without producing an image.
+    pub fn record_frames_dropped_by_vt(&self, count: u64) {
+        self.frames_dropped_by_vt.fetch_add(count, Ordering::Relaxed);
+        if let Some(parent) = &self.parent {
+            parent.record_frames_dropped_by_vt(count);
+        }
+    }
+
+    /// Remember a failed decode, dropping the oldest past `RECENT_ERRORS`.
+    pub fn record_error(&self, error: DecodeError) {
+        {
+            let mut errors = self.recent_errors.lock().unwrap();
+            if errors.len() == RECENT_ERRORS {
+                errors.pop_front();
+            }
+            errors.push_back(error);
+        }
+        if let Some(parent) = &self.parent {
+            parent.record_error(error);
+        }
+    }
+
+    /// Remember the stream's latest audio level.
+    pub fn record_audio_level(&self, rms_db: f32, peak_db: f32) {
+        *self.audio_level.lock().unwrap() = Some((rms_db, peak_db));
+    }
+
+    /// The latest audio level, RMS and peak in dBFS, if any was measured.
+    pub fn audio_level(&self) -> Option<(f32, f32)> {
+        *self.audio_level.lock().unwrap()
+    }
+
+    /// The last `RECENT_ERRORS` decode errors, oldest first.
+    pub fn recent_errors(&self) -> Vec<DecodeError> {
+        self.recent_errors.lock().unwrap().iter().copied().collect()
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn benign_errors(&self) -> u64 {
+        self.benign_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn frames_dropped_by_vt(&self) -> u64 {
+        self.frames_dropped_by_vt.load(Ordering::Relaxed)
+    }
+
+    /// Frames dropped by either the decoder or VideoToolbox.
+    pub fn frames_dropped(&self) -> u64 {
+        self.benign_errors() + self.frames_dropped_by_vt()
+    }
+
+    /// Whether a limit has been reached; sinks stop decoding once it has.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Relaxed)
+            || self.parent.as_ref().is_some_and(|parent| parent.is_exhausted())
+    }
+
+    /// Resolves once a limit is reached. Never resolves without limits.
+    pub async fn limit_reached(&self) {
+        self.limit_reached.notified().await;
+    }
+
+    fn exhaust(&self) {
+        if !self.exhausted.swap(true, Ordering::Relaxed) {
+            self.limit_reached.notify_one();
+        }
+    }
+}
+
+/// Per-stream `DecoderStats`, keyed by stream key, for reporting each
+/// publish's counts separately. Entries live as long as the sink holding
+/// them.
+#[derive(Debug)]
+pub struct StreamStatsRegistry {
+    total: Arc<DecoderStats>,
+    streams: Mutex<HashMap<String, Weak<DecoderStats>>>,
+}
+
+impl StreamStatsRegistry {
+    /// Streams registered here also count towards `total`.
+    pub fn new(total: Arc<DecoderStats>) -> Self {
+        Self {
+            total,
+            streams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fresh counters for a publish of `stream_key`, replacing those of an
+    /// earlier publish with the same key.
+    pub fn register(&self, stream_key: &str) -> Arc<DecoderStats> {
+        let stats = Arc::new(DecoderStats::child(&self.total));
+        let mut streams = self.streams.lock().unwrap();
+        streams.retain(|_, stats| stats.strong_count() > 0);
+        streams.insert(stream_key.to_string(), Arc::downgrade(&stats));
+        stats
+    }
+
+    /// Counters of the publish of `stream_key`, if its sink is still alive.
+    pub fn get(&self, stream_key: &str) -> Option<Arc<DecoderStats>> {
+        self.streams.lock().unwrap().get(stream_key)?.upgrade()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frame_limit() {
+        let stats = DecoderStats::with_limits(Some(3), None);
+        stats.record_frames(2);
+        stats.record_bytes(1 << 20);
+        assert!(!stats.is_exhausted());
+
+        stats.record_frames(1);
+        assert!(stats.is_exhausted());
+        assert_eq!(stats.frames(), 3);
+        // The notification is kept for a later waiter
+        stats.limit_reached().await;
+    }
+
+    #[test]
+    fn test_byte_limit_and_no_limits() {
+        let stats = DecoderStats::with_limits(None, Some(1000));
+        stats.record_bytes(600);
+        assert!(!stats.is_exhausted());
+        stats.record_bytes(600);
+        assert!(stats.is_exhausted());
+        assert_eq!(stats.bytes(), 1200);
+
+        let unlimited = DecoderStats::default();
+        for _ in 0..1000 {
+            unlimited.record_frames(1);
+        }
+        // Dropped frames don't count towards --max-frames
+        unlimited.record_benign_errors(5);
+        unlimited.record_frames_dropped_by_vt(2);
+        assert!(!unlimited.is_exhausted());
+        assert_eq!(unlimited.benign_errors(), 5);
+        assert_eq!(unlimited.frames_dropped_by_vt(), 2);
+    }
+
+    #[test]
+    fn test_streams_counted_separately() {
+        let total = Arc::new(DecoderStats::with_limits(Some(5), None));
+        let registry = StreamStatsRegistry::new(Arc::clone(&total));
+        let cam1 = registry.register("cam1");
+        let cam2 = registry.register("cam2");
+        for _ in 0..3 {
+            cam1.record_frames(1);
+        }
+        cam2.record_frames(1);
+        cam2.record_benign_errors(2);
+
+        assert_eq!(registry.get("cam1").unwrap().frames(), 3);
+        assert_eq!(registry.get("cam2").unwrap().frames(), 1);
+        assert_eq!(registry.get("cam2").unwrap().frames_dropped(), 2);
+        assert_eq!(total.frames(), 4);
+        assert_eq!(total.frames_dropped(), 2);
+
+        // The shared limit stops every stream
+        assert!(!cam1.is_exhausted());
+        cam2.record_frames(1);
+        assert!(cam1.is_exhausted() && cam2.is_exhausted());
+
+        // A new publish starts from zero; an ended one is gone
+        let cam1 = registry.register("cam1");
+        assert_eq!(cam1.frames(), 0);
+        drop(cam2);
+        assert!(registry.get("cam2").is_none());
+    }
+
+    #[test]
+    fn test_recent_errors_keeps_latest() {
+        let stats = DecoderStats::default();
+        assert!(stats.recent_errors().is_empty());
+        for frame_seq in 0..20 {
+            stats.record_error(DecodeError {
+                status: -12909,
+                timestamp: frame_seq as u32 * 33,
+                frame_seq,
+            });
+        }
+        let errors = stats.recent_errors();
+        assert_eq!(errors.len(), RECENT_ERRORS);
+        assert_eq!(errors[0].frame_seq, 4);
+        assert_eq!(errors[RECENT_ERRORS - 1].frame_seq, 19);
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+/// Outcome of a call guarded by [`run_with_timeout`].
+pub enum Outcome<T> {
+    Completed(T),
+    Panicked,
+    /// The call is still running on the blocking pool. Synchronous FFI can't be
+    /// cancelled, so the handle is returned for the caller to poll.
+    TimedOut(JoinHandle<T>),
+}
+
+/// Run `f` on Tokio's blocking pool and wait at most `limit` for it.
+///
+/// Callable from synchronous code running on a multi-threaded runtime (such as
+/// a `VideoSink` callback); the calling worker thread blocks until `f`
+/// finishes or the limit expires.
+pub fn run_with_timeout<T, F>(limit: Duration, f: F) -> Outcome<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let mut task = tokio::task::spawn_blocking(f);
+    let result = tokio::task::block_in_place(|| {
+        Handle::current().block_on(tokio::time::timeout(limit, &mut task))
+    });
+    match result {
+        Ok(Ok(value)) => Outcome::Completed(value),
+        Ok(Err(_)) => Outcome::Panicked,
+        Err(_) => Outcome::TimedOut(task),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_completes_within_limit() {
+        let outcome = run_with_timeout(Duration::from_secs(1), || 42);
+        assert!(matches!(outcome, Outcome::Completed(42)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_times_out_and_recovers() {
+        let outcome = run_with_timeout(Duration::from_millis(10), || {
+            std::thread::sleep(Duration::from_millis(200));
+            7
+        });
+        let Outcome::TimedOut(task) = outcome else {
+            panic!("expected timeout");
+        };
+        assert!(!task.is_finished());
+        assert_eq!(task.await.unwrap(), 7);
+    }
+}
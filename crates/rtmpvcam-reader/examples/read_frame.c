@@ -0,0 +1,45 @@
+/*
+ * Print the newest frame in a ring file, as a check that the C API links
+ * and reads what the decoder wrote:
+ *
+ *   cargo build --release -p rtmpvcam-reader
+ *   cc -I crates/rtmpvcam-reader/include crates/rtmpvcam-reader/examples/read_frame.c \
+ *       target/release/librtmpvcam_reader.a -framework CoreFoundation \
+ *       -framework CoreMedia -framework CoreVideo -framework VideoToolbox \
+ *       -framework IOSurface -o read_frame
+ *   ./read_frame "/Library/Application Support/RTMPVirtualCamera/rtmp_vcam_ring"
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "rtmpvcam_reader.h"
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <ring file>\n", argv[0]);
+        return 2;
+    }
+    RtmpVcamReader *reader = rtmpvcam_reader_open(argv[1]);
+    if (!reader) {
+        fprintf(stderr, "%s: not a readable ring file\n", argv[1]);
+        return 1;
+    }
+
+    size_t len = rtmpvcam_max_frame_size();
+    uint8_t *data = malloc(len);
+    uint32_t width = 0, height = 0, fourcc = 0;
+    uint64_t timestamp = 0;
+    int status =
+        rtmpvcam_reader_latest(reader, data, len, &width, &height, &timestamp, &fourcc);
+    if (status == RTMPVCAM_OK) {
+        printf("frame %ux%u %.4s at %llu ms, first luma sample %u\n", width, height,
+               (const char *)&fourcc, (unsigned long long)timestamp, data[0]);
+    } else {
+        printf("no frame (status %d)\n", status);
+    }
+
+    free(data);
+    rtmpvcam_reader_close(reader);
+    return status == RTMPVCAM_OK ? 0 : 1;
+}
@@ -0,0 +1,78 @@
+/*
+ * C API for reading decoded frames from the rtmp-vcam ring file.
+ *
+ * Link against the rtmpvcam-reader static or dynamic library built by
+ * `cargo build --release -p rtmpvcam-reader` (librtmpvcam_reader.a / .dylib).
+ * Functions are implemented in crates/rtmpvcam-reader/src/lib.rs.
+ */
+
+#ifndef RTMPVCAM_READER_H
+#define RTMPVCAM_READER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Return values of rtmpvcam_reader_latest and rtmpvcam_set_reader_caps */
+#define RTMPVCAM_OK 1
+#define RTMPVCAM_NO_FRAME 0
+#define RTMPVCAM_BUFFER_TOO_SMALL (-1)
+#define RTMPVCAM_INVALID_ARGUMENT (-2)
+#define RTMPVCAM_IO_ERROR (-3)
+
+/* Bits of the `formats` mask passed to rtmpvcam_set_reader_caps */
+#define RTMPVCAM_FORMAT_NV12 0x1u
+#define RTMPVCAM_FORMAT_I420 0x2u
+#define RTMPVCAM_FORMAT_P010 0x4u
+#define RTMPVCAM_FORMAT_P010_LSB 0x8u
+
+/* Pixel formats rtmpvcam_reader_latest reports in `out_fourcc`: the four
+ * characters in memory order */
+#define RTMPVCAM_FOURCC(a, b, c, d) \
+    ((uint32_t)(a) | ((uint32_t)(b) << 8) | ((uint32_t)(c) << 16) | ((uint32_t)(d) << 24))
+#define RTMPVCAM_FOURCC_NV12 RTMPVCAM_FOURCC('N', 'V', '1', '2')
+#define RTMPVCAM_FOURCC_I420 RTMPVCAM_FOURCC('I', '4', '2', '0')
+#define RTMPVCAM_FOURCC_P010 RTMPVCAM_FOURCC('P', '0', '1', '0')
+#define RTMPVCAM_FOURCC_P010_LSB RTMPVCAM_FOURCC('P', '1', '0', 'L')
+
+/* Shared memory object rtmpvcam_set_reader_caps writes by default, named
+ * after the Camera Extension's app group so its sandbox allows it */
+#define RTMPVCAM_CAPS_NAME "EQWMDN3W3D.com.rtmpvcam/caps"
+
+typedef struct RtmpVcamReader RtmpVcamReader;
+
+/* Map the ring file at `path` read-only. Returns NULL if it can't be opened
+ * or isn't a ring file of the supported layout version. */
+RtmpVcamReader *rtmpvcam_reader_open(const char *path);
+
+/* Copy the newest frame's packed pixel data (NV12 by default) into
+ * `out_ptr` and store its width, height, timestamp in milliseconds and
+ * pixel format (an RTMPVCAM_FOURCC_* value). `out_w`, `out_h`, `out_ts` and
+ * `out_fourcc` may be NULL. The same frame is returned until a newer one is
+ * written. */
+int rtmpvcam_reader_latest(const RtmpVcamReader *reader, uint8_t *out_ptr, size_t out_len,
+                           uint32_t *out_w, uint32_t *out_h, uint64_t *out_ts,
+                           uint32_t *out_fourcc);
+
+/* Tell the writer what the reader can take: frames no larger than
+ * `max_width` x `max_height` (0 for either means any size) and the
+ * RTMPVCAM_FORMAT_* bits in `formats` (NV12 is assumed either way). Written
+ * to the shared memory object `name`, or RTMPVCAM_CAPS_NAME if NULL. Takes
+ * effect when the writer next creates a decoder. */
+int rtmpvcam_set_reader_caps(const char *name, uint32_t max_width, uint32_t max_height,
+                             uint32_t formats);
+
+/* Buffer size that holds any frame rtmpvcam_reader_latest returns. */
+size_t rtmpvcam_max_frame_size(void);
+
+/* Unmap and free a reader. NULL is ignored. */
+void rtmpvcam_reader_close(RtmpVcamReader *reader);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RTMPVCAM_READER_H */
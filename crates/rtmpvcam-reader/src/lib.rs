@@ -0,0 +1,398 @@
+//! C API for reading frames from the ring file, wrapping
+//! `video_pipeline::FrameReader` so the Swift Camera Extension can link it
+//! instead of reimplementing the layout. Declared in
+//! `include/rtmpvcam_reader.h`.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+
+use video_pipeline::{
+    FrameReader, OutputFormat, ReaderCaps, FRAME_SHM_SIZE, MAX_FRAME_SIZE, READER_CAPS_NAME,
+    READER_CAPS_SIZE,
+};
+
+/// `rtmpvcam_reader_latest` copied a frame.
+pub const RTMPVCAM_OK: c_int = 1;
+/// Nothing has been written yet.
+pub const RTMPVCAM_NO_FRAME: c_int = 0;
+/// The output buffer is smaller than the frame; the size outputs are set.
+pub const RTMPVCAM_BUFFER_TOO_SMALL: c_int = -1;
+/// A required pointer was null.
+pub const RTMPVCAM_INVALID_ARGUMENT: c_int = -2;
+/// The reader capabilities couldn't be opened or mapped for writing.
+pub const RTMPVCAM_IO_ERROR: c_int = -3;
+
+/// Bits of the `formats` mask passed to `rtmpvcam_set_reader_caps`.
+pub const RTMPVCAM_FORMAT_NV12: u32 = OutputFormat::Nv12.caps_bit();
+pub const RTMPVCAM_FORMAT_I420: u32 = OutputFormat::I420.caps_bit();
+pub const RTMPVCAM_FORMAT_P010: u32 = OutputFormat::P010.caps_bit();
+pub const RTMPVCAM_FORMAT_P010_LSB: u32 = OutputFormat::P010Lsb.caps_bit();
+
+/// Pixel formats `rtmpvcam_reader_latest` reports: the format's four
+/// characters in memory order, as in the slot header.
+pub const RTMPVCAM_FOURCC_NV12: u32 = u32::from_le_bytes(*b"NV12");
+pub const RTMPVCAM_FOURCC_I420: u32 = u32::from_le_bytes(*b"I420");
+pub const RTMPVCAM_FOURCC_P010: u32 = u32::from_le_bytes(*b"P010");
+pub const RTMPVCAM_FOURCC_P010_LSB: u32 = u32::from_le_bytes(*b"P10L");
+
+/// A read-only mapping of a ring file and the reader attached to it.
+pub struct RtmpVcamReader {
+    reader: FrameReader,
+    base: *mut libc::c_void,
+    fd: c_int,
+}
+
+impl RtmpVcamReader {
+    /// Map the ring file at `path` read-only and attach a reader to it.
+    fn open(path: &Path) -> Result<Self, String> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| "path contains a NUL byte".to_string())?;
+        unsafe {
+            let fd = libc::open(c_path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(format!("open failed: {}", std::io::Error::last_os_error()));
+            }
+            let mut stat: libc::stat = std::mem::zeroed();
+            if libc::fstat(fd, &mut stat) != 0 || (stat.st_size as usize) < FRAME_SHM_SIZE {
+                libc::close(fd);
+                return Err("not a ring file: too small".to_string());
+            }
+            let base = libc::mmap(
+                ptr::null_mut(),
+                FRAME_SHM_SIZE,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            );
+            if base == libc::MAP_FAILED {
+                let e = std::io::Error::last_os_error();
+                libc::close(fd);
+                return Err(format!("mmap failed: {e}"));
+            }
+            match FrameReader::new(base as *const u8) {
+                Ok(reader) => Ok(Self { reader, base, fd }),
+                Err(e) => {
+                    libc::munmap(base, FRAME_SHM_SIZE);
+                    libc::close(fd);
+                    Err(e)
+                }
+            }
+        }
+    }
+}
+
+/// Open (creating if the writer hasn't yet) the POSIX shared memory object
+/// `name` holding the reader's capabilities and map it read-write.
+fn map_caps(name: &CStr) -> Result<(c_int, *mut libc::c_void), String> {
+    unsafe {
+        let fd = libc::shm_open(
+            name.as_ptr(),
+            libc::O_CREAT | libc::O_RDWR | libc::O_CLOEXEC,
+            0o666 as libc::c_uint,
+        );
+        if fd < 0 {
+            return Err(format!("shm_open failed: {}", std::io::Error::last_os_error()));
+        }
+        // The writer runs as another user and must be able to open it too
+        libc::fchmod(fd, 0o666);
+
+        // macOS only lets a shared memory object be sized once
+        let sized = |fd| {
+            let mut stat: libc::stat = std::mem::zeroed();
+            libc::fstat(fd, &mut stat) == 0 && stat.st_size as usize >= READER_CAPS_SIZE
+        };
+        if !sized(fd) && libc::ftruncate(fd, READER_CAPS_SIZE as libc::off_t) != 0 {
+            let e = std::io::Error::last_os_error();
+            if !sized(fd) {
+                libc::close(fd);
+                return Err(format!("ftruncate failed: {e}"));
+            }
+        }
+        let base = libc::mmap(
+            ptr::null_mut(),
+            READER_CAPS_SIZE,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        );
+        if base == libc::MAP_FAILED {
+            let e = std::io::Error::last_os_error();
+            libc::close(fd);
+            return Err(format!("mmap failed: {e}"));
+        }
+        Ok((fd, base))
+    }
+}
+
+impl Drop for RtmpVcamReader {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base, FRAME_SHM_SIZE);
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Open the ring file at `path` (a NUL-terminated UTF-8 or native path).
+/// Returns null if it can't be mapped or isn't a ring file of this layout
+/// version. Free the reader with `rtmpvcam_reader_close`.
+///
+/// # Safety
+/// `path` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rtmpvcam_reader_open(path: *const c_char) -> *mut RtmpVcamReader {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+    let path = Path::new(std::ffi::OsStr::from_bytes(CStr::from_ptr(path).to_bytes()));
+    match RtmpVcamReader::open(path) {
+        Ok(reader) => Box::into_raw(Box::new(reader)),
+        Err(e) => {
+            tracing::warn!(path = %path.display(), %e, "failed to open ring file");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Copy the newest frame's packed pixel data into `out_ptr` and store its
+/// size, timestamp (milliseconds) and pixel format, one of the
+/// `RTMPVCAM_FOURCC_*` values. The data is NV12 unless the writer was set
+/// up for another format; a buffer of `rtmpvcam_max_frame_size()` bytes
+/// holds any frame.
+///
+/// Returns `RTMPVCAM_OK`, `RTMPVCAM_NO_FRAME`,
+/// `RTMPVCAM_BUFFER_TOO_SMALL` (with the other outputs still set) or
+/// `RTMPVCAM_INVALID_ARGUMENT`. The same frame is returned until a newer
+/// one is written; compare timestamps to skip repeats.
+///
+/// # Safety
+/// `reader` must come from `rtmpvcam_reader_open` and not be closed.
+/// `out_ptr` must be writable for `out_len` bytes, and the other outputs
+/// must each be null or valid.
+#[no_mangle]
+pub unsafe extern "C" fn rtmpvcam_reader_latest(
+    reader: *const RtmpVcamReader,
+    out_ptr: *mut u8,
+    out_len: usize,
+    out_w: *mut u32,
+    out_h: *mut u32,
+    out_ts: *mut u64,
+    out_fourcc: *mut u32,
+) -> c_int {
+    if reader.is_null() || out_ptr.is_null() {
+        return RTMPVCAM_INVALID_ARGUMENT;
+    }
+    let Some(frame) = (*reader).reader.latest_frame() else {
+        return RTMPVCAM_NO_FRAME;
+    };
+    if !out_w.is_null() {
+        *out_w = frame.width as u32;
+    }
+    if !out_h.is_null() {
+        *out_h = frame.height as u32;
+    }
+    if !out_ts.is_null() {
+        *out_ts = frame.timestamp_ms;
+    }
+    if !out_fourcc.is_null() {
+        *out_fourcc = u32::from_le_bytes(frame.format.fourcc());
+    }
+    if frame.data.len() > out_len {
+        return RTMPVCAM_BUFFER_TOO_SMALL;
+    }
+    ptr::copy_nonoverlapping(frame.data.as_ptr(), out_ptr, frame.data.len());
+    RTMPVCAM_OK
+}
+
+/// Tell the writer what the reader can take: frames no larger than
+/// `max_width` x `max_height` (0 for either means any size), which it
+/// scales down to, and the pixel formats in `formats`, a mask of
+/// `RTMPVCAM_FORMAT_*` bits (NV12 is assumed either way). Takes effect when
+/// the writer next creates a decoder, at the next publish or sequence
+/// header.
+///
+/// The values go in the POSIX shared memory object `name`, or
+/// `RTMPVCAM_CAPS_NAME` if null, which is named after the Camera
+/// Extension's app group so its sandbox lets it write there.
+///
+/// Returns `RTMPVCAM_OK` or `RTMPVCAM_IO_ERROR`.
+///
+/// # Safety
+/// `name` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rtmpvcam_set_reader_caps(
+    name: *const c_char,
+    max_width: u32,
+    max_height: u32,
+    formats: u32,
+) -> c_int {
+    let default_name;
+    let name = if name.is_null() {
+        default_name = CString::new(READER_CAPS_NAME).unwrap();
+        default_name.as_c_str()
+    } else {
+        CStr::from_ptr(name)
+    };
+    match map_caps(name) {
+        Ok((fd, base)) => {
+            let caps = ReaderCaps::new(base as *mut u8);
+            caps.set_max_size(max_width, max_height);
+            caps.set_formats(formats);
+            libc::munmap(base, READER_CAPS_SIZE);
+            libc::close(fd);
+            RTMPVCAM_OK
+        }
+        Err(e) => {
+            tracing::warn!(?name, %e, "failed to set the reader's capabilities");
+            RTMPVCAM_IO_ERROR
+        }
+    }
+}
+
+/// Size of the largest frame `rtmpvcam_reader_latest` can return.
+#[no_mangle]
+pub extern "C" fn rtmpvcam_max_frame_size() -> usize {
+    MAX_FRAME_SIZE
+}
+
+/// Unmap and free a reader. Null is ignored.
+///
+/// # Safety
+/// `reader` must be null or come from `rtmpvcam_reader_open`, and must not
+/// be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rtmpvcam_reader_close(reader: *mut RtmpVcamReader) {
+    if !reader.is_null() {
+        drop(Box::from_raw(reader));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use video_pipeline::{DecodedFrame, FrameHeader, FrameOutput, ShmOutput};
+
+    #[test]
+    fn test_reads_frame_from_ring_file() {
+        let path = std::env::temp_dir().join(format!("rtmpvcam-reader-{}", std::process::id()));
+        let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        // Not a ring file yet
+        std::fs::write(&path, b"RVCM").unwrap();
+        assert!(unsafe { rtmpvcam_reader_open(c_path.as_ptr()) }.is_null());
+
+        // Stand in for the app: stamp the header and write one frame
+        let mut region = vec![0u64; FRAME_SHM_SIZE.div_ceil(8)];
+        let base = region.as_mut_ptr() as *mut u8;
+        unsafe { FrameHeader::init(base as *mut FrameHeader) };
+        let y = [1, 2, 3, 4];
+        let uv = [5, 6];
+        ShmOutput::new(base, FRAME_SHM_SIZE).write_frame(&DecodedFrame {
+            width: 2,
+            height: 2,
+            bytes_per_sample: 1,
+            y_plane: &y,
+            y_stride: 2,
+            uv_plane: &uv,
+            uv_stride: 2,
+            timestamp_ms: 1234,
+            sar: (1, 1),
+        });
+        let bytes = unsafe { std::slice::from_raw_parts(base, FRAME_SHM_SIZE) };
+        std::fs::write(&path, bytes).unwrap();
+
+        unsafe {
+            let reader = rtmpvcam_reader_open(c_path.as_ptr());
+            assert!(!reader.is_null());
+            let (mut width, mut height, mut ts, mut fourcc) = (0, 0, 0, 0);
+            let mut small = [0u8; 4];
+            let status = rtmpvcam_reader_latest(
+                reader,
+                small.as_mut_ptr(),
+                small.len(),
+                &mut width,
+                &mut height,
+                &mut ts,
+                &mut fourcc,
+            );
+            assert_eq!(status, RTMPVCAM_BUFFER_TOO_SMALL);
+            assert_eq!((width, height, ts, fourcc), (2, 2, 1234, RTMPVCAM_FOURCC_NV12));
+
+            let mut data = vec![0u8; rtmpvcam_max_frame_size()];
+            let status = rtmpvcam_reader_latest(
+                reader,
+                data.as_mut_ptr(),
+                data.len(),
+                &mut width,
+                &mut height,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            assert_eq!(status, RTMPVCAM_OK);
+            assert_eq!(data[..6], [1, 2, 3, 4, 5, 6]);
+            rtmpvcam_reader_close(reader);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_fourccs_match_output_formats() {
+        let fourccs = [
+            (OutputFormat::Nv12, RTMPVCAM_FOURCC_NV12),
+            (OutputFormat::I420, RTMPVCAM_FOURCC_I420),
+            (OutputFormat::P010, RTMPVCAM_FOURCC_P010),
+            (OutputFormat::P010Lsb, RTMPVCAM_FOURCC_P010_LSB),
+        ];
+        for (format, fourcc) in fourccs {
+            assert_eq!(fourcc.to_le_bytes(), format.fourcc(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_caps_reach_decoder() {
+        let name = CString::new(format!("rtmpvcam-reader-caps-{}", std::process::id())).unwrap();
+        let formats = RTMPVCAM_FORMAT_P010 | RTMPVCAM_FORMAT_I420;
+        let status = unsafe { rtmpvcam_set_reader_caps(name.as_ptr(), 16, 16, formats) };
+        assert_eq!(status, RTMPVCAM_OK);
+
+        // What the writer maps
+        let (fd, base) = map_caps(&name).unwrap();
+        let caps = || unsafe { ReaderCaps::new(base as *mut u8) };
+        assert_eq!(caps().max_size(), Some((16, 16)));
+        assert!(caps().supports(OutputFormat::P010));
+        assert!(!caps().supports(OutputFormat::P010Lsb));
+
+        #[cfg(target_os = "macos")]
+        {
+            use video_pipeline::test_vectors::tiny_stream;
+            use video_pipeline::H264Decoder;
+
+            // The 32x32 test stream comes out scaled down to what was asked
+            let mut region = vec![0u64; FRAME_SHM_SIZE.div_ceil(8)];
+            let base = region.as_mut_ptr() as *mut u8;
+            unsafe { FrameHeader::init(base as *mut FrameHeader) };
+            let stream = tiny_stream();
+            let mut decoder = H264Decoder::with_shm_output(
+                &[stream.sps.to_vec()],
+                &[stream.pps.to_vec()],
+                stream.nalu_length_size,
+                ShmOutput::new(base, FRAME_SHM_SIZE).with_reader_caps(caps()),
+            )
+            .unwrap();
+            for frame in &stream.frames {
+                decoder.decode_avcc(frame.avcc, frame.timestamp_ms).unwrap();
+            }
+            decoder.flush().unwrap();
+            assert_eq!(decoder.first_frame_size(), Some((16, 16)));
+        }
+
+        unsafe {
+            libc::munmap(base, READER_CAPS_SIZE);
+            libc::close(fd);
+            libc::shm_unlink(name.as_ptr());
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Builds examples/read_frame.c against the library and runs it on a ring
+//! file, so the header, the example and the exported functions can't drift
+//! apart.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use video_pipeline::{DecodedFrame, FrameHeader, FrameOutput, ShmOutput, FRAME_SHM_SIZE};
+
+/// Where cargo put the library: the `deps` directory next to this test.
+fn library_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+/// Write a ring file holding one 2x2 NV12 frame, as the app would.
+fn write_ring_file(path: &Path) {
+    let mut region = vec![0u64; FRAME_SHM_SIZE.div_ceil(8)];
+    let base = region.as_mut_ptr() as *mut u8;
+    unsafe { FrameHeader::init(base as *mut FrameHeader) };
+    let y = [1, 2, 3, 4];
+    let uv = [5, 6];
+    ShmOutput::new(base, FRAME_SHM_SIZE).write_frame(&DecodedFrame {
+        width: 2,
+        height: 2,
+        bytes_per_sample: 1,
+        y_plane: &y,
+        y_stride: 2,
+        uv_plane: &uv,
+        uv_stride: 2,
+        timestamp_ms: 1234,
+        sar: (1, 1),
+    });
+    let bytes = unsafe { std::slice::from_raw_parts(base, FRAME_SHM_SIZE) };
+    std::fs::write(path, bytes).unwrap();
+}
+
+#[test]
+fn test_read_frame_example() {
+    let temp = std::env::temp_dir();
+    let ring = temp.join(format!("rtmpvcam-read-frame-{}", std::process::id()));
+    let program = temp.join(format!("rtmpvcam-read-frame-{}.out", std::process::id()));
+    write_ring_file(&ring);
+
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let lib_dir = library_dir();
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let compiled = Command::new(cc)
+        .args(["-Wall", "-Werror", "-I"])
+        .arg(crate_dir.join("include"))
+        .arg(crate_dir.join("examples/read_frame.c"))
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-lrtmpvcam_reader")
+        .arg("-o")
+        .arg(&program)
+        .output()
+        .unwrap();
+    let ran = compiled
+        .status
+        .success()
+        .then(|| Command::new(&program).arg(&ring).output().unwrap());
+    std::fs::remove_file(&ring).ok();
+    std::fs::remove_file(&program).ok();
+
+    assert!(compiled.status.success(), "{}", String::from_utf8_lossy(&compiled.stderr));
+    let ran = ran.unwrap();
+    assert!(ran.status.success(), "{ran:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&ran.stdout),
+        "frame 2x2 NV12 at 1234 ms, first luma sample 1\n"
+    );
+}
@@ -0,0 +1,60 @@
+//! Per-frame copy cost of a 4K NV12 frame, packed row by row versus kept at
+//! the decoder's stride (`ShmOutput::with_source_stride`).
+//!
+//! `cargo bench -p video-pipeline --bench frame_copy`
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use video_pipeline::DecodedFrame;
+
+const WIDTH: usize = 3840;
+const HEIGHT: usize = 2160;
+/// Stride of a decoder that pads rows, as VideoToolbox does for widths off
+/// its alignment.
+const PADDED_STRIDE: usize = WIDTH + 64;
+const ITERATIONS: u32 = 200;
+
+fn frame<'a>(y: &'a [u8], uv: &'a [u8], stride: usize) -> DecodedFrame<'a> {
+    DecodedFrame {
+        width: WIDTH,
+        height: HEIGHT,
+        bytes_per_sample: 1,
+        y_plane: y,
+        y_stride: stride,
+        uv_plane: uv,
+        uv_stride: stride,
+        timestamp_ms: 0,
+        sar: (1, 1),
+    }
+}
+
+fn time(name: &str, dst: &mut [u8], copy: impl Fn(&mut [u8])) {
+    // Fault the destination in before timing
+    copy(dst);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        copy(black_box(&mut *dst));
+    }
+    let per_frame = start.elapsed() / ITERATIONS;
+    println!(
+        "{name:<28} {:>8.3} ms/frame  {:>6.2} GB/s",
+        per_frame.as_secs_f64() * 1e3,
+        dst.len() as f64 / per_frame.max(Duration::from_nanos(1)).as_secs_f64() / 1e9,
+    );
+}
+
+fn main() {
+    for stride in [WIDTH, PADDED_STRIDE] {
+        let y = vec![0x80u8; stride * HEIGHT];
+        let uv = vec![0x80u8; stride * HEIGHT / 2];
+        let frame = frame(&y, &uv, stride);
+        println!("{WIDTH}x{HEIGHT} NV12, stride {stride}:");
+
+        let mut dst = vec![0u8; frame.packed_size()];
+        time("  packed", &mut dst, |dst| frame.copy_packed(dst));
+
+        let mut dst = vec![0u8; frame.strided_size().unwrap()];
+        time("  source stride", &mut dst, |dst| frame.copy_strided(dst));
+    }
+}
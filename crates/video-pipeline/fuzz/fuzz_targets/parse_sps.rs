@@ -0,0 +1,12 @@
+//! Arbitrary SPS NAL units through the VUI/dimension parser.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use video_pipeline::parse_sps;
+
+fuzz_target!(|nal: &[u8]| {
+    if let Some(info) = parse_sps(nal) {
+        info.display_size();
+    }
+});
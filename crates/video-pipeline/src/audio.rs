@@ -0,0 +1,269 @@
+//! AAC level metering for a VU display: decodes each AAC frame to PCM with
+//! AudioToolbox just to measure it. The audio itself is still not played.
+
+use std::ffi::c_void;
+
+use tracing::debug;
+
+use crate::ffi;
+
+/// Level reported for silence, in place of -inf dBFS.
+pub const SILENCE_DB: f32 = -100.0;
+
+/// Largest PCM frame count one AAC frame decodes to (HE-AAC; AAC-LC is 1024).
+const MAX_FRAMES_PER_PACKET: usize = 2048;
+
+/// Returned by the input callback once its single packet has been consumed.
+/// Any non-zero value works; AudioToolbox passes it back to us.
+const NO_MORE_INPUT: ffi::OSStatus = i32::from_be_bytes(*b"!dat");
+
+/// Sampling frequencies indexed by `samplingFrequencyIndex` (ISO 14496-3).
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// The fields of an AudioSpecificConfig needed to set up a decoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioSpecificConfig {
+    pub object_type: u8,
+    pub sample_rate: u32,
+    pub channels: u8,
+}
+
+impl AudioSpecificConfig {
+    /// Parse the AudioSpecificConfig carried in an FLV AAC sequence header.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut bits = data
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+        let mut read = |n: u32| -> Option<u32> {
+            (0..n).try_fold(0u32, |acc, _| Some((acc << 1) | bits.next()? as u32))
+        };
+
+        let mut object_type = read(5)?;
+        if object_type == 31 {
+            object_type = 32 + read(6)?;
+        }
+        let sample_rate = match read(4)? {
+            15 => read(24)?,
+            index => *SAMPLE_RATES.get(index as usize)?,
+        };
+        let channels = read(4)?;
+        // Channel configuration 0 defers to a program config element
+        if channels == 0 || object_type > u8::MAX as u32 {
+            return None;
+        }
+        Some(AudioSpecificConfig {
+            object_type: object_type as u8,
+            sample_rate,
+            channels: channels as u8,
+        })
+    }
+}
+
+/// RMS and peak level of interleaved float samples, in dBFS.
+pub fn levels(samples: &[f32]) -> (f32, f32) {
+    if samples.is_empty() {
+        return (SILENCE_DB, SILENCE_DB);
+    }
+    let (sum_squares, peak) = samples.iter().fold((0.0f64, 0.0f32), |(sum, peak), &s| {
+        (sum + (s as f64) * (s as f64), peak.max(s.abs()))
+    });
+    let rms = (sum_squares / samples.len() as f64).sqrt() as f32;
+    (to_db(rms), to_db(peak))
+}
+
+fn to_db(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        return SILENCE_DB;
+    }
+    (20.0 * amplitude.log10()).max(SILENCE_DB)
+}
+
+/// Decodes raw AAC frames (FLV `AACPacketType` 1 bodies) and reports their
+/// levels.
+pub struct AacLevelMeter {
+    converter: ffi::AudioConverterRef,
+    channels: u32,
+    pcm: Vec<f32>,
+}
+
+// SAFETY: the converter is only used through &mut self.
+unsafe impl Send for AacLevelMeter {}
+
+/// The packet handed to AudioToolbox by `supply_packet`.
+struct InputPacket {
+    data: *const u8,
+    len: u32,
+    channels: u32,
+    consumed: bool,
+    description: ffi::AudioStreamPacketDescription,
+}
+
+impl AacLevelMeter {
+    /// Create a meter for the stream described by `audio_specific_config`,
+    /// the body of the FLV AAC sequence header.
+    pub fn new(audio_specific_config: &[u8]) -> Result<Self, String> {
+        let config = AudioSpecificConfig::parse(audio_specific_config)
+            .ok_or("unsupported AudioSpecificConfig")?;
+        let channels = config.channels as u32;
+        let input = ffi::AudioStreamBasicDescription {
+            mSampleRate: config.sample_rate as f64,
+            mFormatID: ffi::kAudioFormatMPEG4AAC,
+            mFormatFlags: config.object_type as u32,
+            mFramesPerPacket: 1024,
+            mChannelsPerFrame: channels,
+            ..Default::default()
+        };
+        let output = ffi::AudioStreamBasicDescription {
+            mSampleRate: config.sample_rate as f64,
+            mFormatID: ffi::kAudioFormatLinearPCM,
+            mFormatFlags: ffi::kAudioFormatFlagIsFloat | ffi::kAudioFormatFlagIsPacked,
+            mBytesPerPacket: 4 * channels,
+            mFramesPerPacket: 1,
+            mBytesPerFrame: 4 * channels,
+            mChannelsPerFrame: channels,
+            mBitsPerChannel: 32,
+            mReserved: 0,
+        };
+
+        let mut converter: ffi::AudioConverterRef = std::ptr::null_mut();
+        let status = unsafe { ffi::AudioConverterNew(&input, &output, &mut converter) };
+        if status != 0 {
+            return Err(format!("AudioConverterNew failed: OSStatus {status}"));
+        }
+        debug!(?config, "AAC level meter created");
+        Ok(Self {
+            converter,
+            channels,
+            pcm: vec![0.0; MAX_FRAMES_PER_PACKET * channels as usize],
+        })
+    }
+
+    /// Decode one AAC frame and return its (RMS, peak) level in dBFS, or
+    /// `None` while the decoder is still priming and produced no samples.
+    pub fn measure(&mut self, aac_frame: &[u8]) -> Result<Option<(f32, f32)>, String> {
+        let mut packet = InputPacket {
+            data: aac_frame.as_ptr(),
+            len: aac_frame.len() as u32,
+            channels: self.channels,
+            consumed: false,
+            description: ffi::AudioStreamPacketDescription {
+                mStartOffset: 0,
+                mVariableFramesInPacket: 0,
+                mDataByteSize: aac_frame.len() as u32,
+            },
+        };
+        let mut output = ffi::AudioBufferList {
+            mNumberBuffers: 1,
+            mBuffers: [ffi::AudioBuffer {
+                mNumberChannels: self.channels,
+                mDataByteSize: (self.pcm.len() * 4) as u32,
+                mData: self.pcm.as_mut_ptr() as *mut c_void,
+            }],
+        };
+        let mut frames = MAX_FRAMES_PER_PACKET as u32;
+        let status = unsafe {
+            ffi::AudioConverterFillComplexBuffer(
+                self.converter,
+                supply_packet,
+                &mut packet as *mut InputPacket as *mut c_void,
+                &mut frames,
+                &mut output,
+                std::ptr::null_mut(),
+            )
+        };
+        if status != 0 && status != NO_MORE_INPUT {
+            // Leave the converter ready for the next frame
+            unsafe { ffi::AudioConverterReset(self.converter) };
+            return Err(format!(
+                "AudioConverterFillComplexBuffer failed: OSStatus {status}"
+            ));
+        }
+        if frames == 0 {
+            return Ok(None);
+        }
+        let samples = (frames * self.channels) as usize;
+        Ok(Some(levels(&self.pcm[..samples.min(self.pcm.len())])))
+    }
+}
+
+impl Drop for AacLevelMeter {
+    fn drop(&mut self) {
+        unsafe { ffi::AudioConverterDispose(self.converter) };
+    }
+}
+
+/// AudioConverter input callback: hands over the one packet in `user_data`,
+/// then reports that no more input is available.
+unsafe extern "C" fn supply_packet(
+    _converter: ffi::AudioConverterRef,
+    packets: *mut u32,
+    data: *mut ffi::AudioBufferList,
+    descriptions: *mut *mut ffi::AudioStreamPacketDescription,
+    user_data: *mut c_void,
+) -> ffi::OSStatus {
+    let packet = &mut *(user_data as *mut InputPacket);
+    if packet.consumed {
+        *packets = 0;
+        return NO_MORE_INPUT;
+    }
+    packet.consumed = true;
+
+    *packets = 1;
+    (*data).mNumberBuffers = 1;
+    (*data).mBuffers[0] = ffi::AudioBuffer {
+        mNumberChannels: packet.channels,
+        mDataByteSize: packet.len,
+        mData: packet.data as *mut c_void,
+    };
+    if !descriptions.is_null() {
+        *descriptions = &mut packet.description;
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_audio_specific_config() {
+        // AAC-LC, 44.1 kHz, stereo
+        assert_eq!(
+            AudioSpecificConfig::parse(&[0x12, 0x10]),
+            Some(AudioSpecificConfig {
+                object_type: 2,
+                sample_rate: 44100,
+                channels: 2,
+            })
+        );
+        // AAC-LC, 48 kHz, mono
+        assert_eq!(
+            AudioSpecificConfig::parse(&[0x11, 0x88]).map(|c| (c.sample_rate, c.channels)),
+            Some((48000, 1))
+        );
+        assert_eq!(AudioSpecificConfig::parse(&[0x12]), None);
+    }
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(levels(&[]), (SILENCE_DB, SILENCE_DB));
+        assert_eq!(levels(&[0.0; 64]), (SILENCE_DB, SILENCE_DB));
+
+        // Full-scale square wave: RMS and peak both 0 dBFS
+        let square: Vec<f32> = (0..64)
+            .map(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        let (rms, peak) = levels(&square);
+        assert!(rms.abs() < 0.01 && peak.abs() < 0.01);
+
+        // Half-scale sine: peak -6 dBFS, RMS 3 dB lower
+        let sine: Vec<f32> = (0..480)
+            .map(|i| 0.5 * (i as f32 * std::f32::consts::TAU / 48.0).sin())
+            .collect();
+        let (rms, peak) = levels(&sine);
+        assert!((peak + 6.02).abs() < 0.05, "peak {peak}");
+        assert!((rms + 9.03).abs() < 0.05, "rms {rms}");
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::output::OutputFormat;
+
+/// Size of the reader capabilities region, separate from the frame buffer.
+pub const READER_CAPS_SIZE: usize = 16;
+
+/// Name of the POSIX shared memory object holding the Camera Extension's
+/// `ReaderCaps`. The extension's sandbox only lets it open shared memory
+/// named after its app group, `$(TeamIdentifierPrefix)com.rtmpvcam`, and
+/// macOS limits the name to 31 bytes.
+pub const READER_CAPS_NAME: &str = "EQWMDN3W3D.com.rtmpvcam/caps";
+
+#[repr(C)]
+struct CapsRegion {
+    /// Largest frame the reader wants; zeros mean no preference.
+    max_width: AtomicU32,
+    max_height: AtomicU32,
+    /// `OutputFormat::caps_bit` of every format the reader can read.
+    formats: AtomicU32,
+    _reserved: u32,
+}
+
+const _: () = assert!(std::mem::size_of::<CapsRegion>() == READER_CAPS_SIZE);
+
+/// What the reader of a frame buffer can take, written by the reader and
+/// read by the writer each time it creates a decoder: the largest frame
+/// size it wants, which the decoder scales down to, and the pixel formats
+/// it reads besides NV12.
+///
+/// Lives in its own shared memory region because readers map the frame
+/// buffer read-only, and the Camera Extension can't write to files at all.
+/// A zero-filled region means no size preference and NV12 only.
+pub struct ReaderCaps {
+    caps: *const CapsRegion,
+}
+
+// SAFETY: the region is only accessed through atomics.
+unsafe impl Send for ReaderCaps {}
+unsafe impl Sync for ReaderCaps {}
+
+impl ReaderCaps {
+    /// # Safety
+    /// `base` must point to a mapping of at least `READER_CAPS_SIZE` bytes,
+    /// aligned to 4 bytes and valid for the lifetime of the handle, and
+    /// writable if the setters are used.
+    pub unsafe fn new(base: *mut u8) -> Self {
+        Self {
+            caps: base as *const CapsRegion,
+        }
+    }
+
+    fn region(&self) -> &CapsRegion {
+        unsafe { &*self.caps }
+    }
+
+    /// Ask for frames no larger than `width` x `height`, or pass 0 for
+    /// either to clear the request.
+    pub fn set_max_size(&self, width: u32, height: u32) {
+        self.region().max_width.store(width, Ordering::Relaxed);
+        self.region().max_height.store(height, Ordering::Relaxed);
+    }
+
+    /// The largest frame the reader asked for, if it set both dimensions.
+    pub fn max_size(&self) -> Option<(u32, u32)> {
+        let width = self.region().max_width.load(Ordering::Relaxed);
+        let height = self.region().max_height.load(Ordering::Relaxed);
+        (width > 0 && height > 0).then_some((width, height))
+    }
+
+    /// Advertise the formats the reader can read, as a mask of
+    /// `OutputFormat::caps_bit`s. NV12 is assumed either way.
+    pub fn set_formats(&self, formats: u32) {
+        self.region().formats.store(formats, Ordering::Relaxed);
+    }
+
+    /// The mask written by `set_formats`.
+    pub fn formats(&self) -> u32 {
+        self.region().formats.load(Ordering::Relaxed)
+    }
+
+    /// Whether the reader said it can read frames in `format`.
+    pub fn supports(&self, format: OutputFormat) -> bool {
+        format == OutputFormat::Nv12 || self.formats() & format.caps_bit() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caps_round_trip() {
+        let mut region = [0u32; READER_CAPS_SIZE / 4];
+        let caps = unsafe { ReaderCaps::new(region.as_mut_ptr() as *mut u8) };
+        assert_eq!(caps.max_size(), None);
+        assert!(caps.supports(OutputFormat::Nv12));
+        assert!(!caps.supports(OutputFormat::P010));
+
+        caps.set_max_size(1280, 720);
+        caps.set_formats(OutputFormat::P010.caps_bit() | OutputFormat::I420.caps_bit());
+        assert_eq!(caps.max_size(), Some((1280, 720)));
+        assert!(caps.supports(OutputFormat::P010));
+        assert!(caps.supports(OutputFormat::I420));
+        assert!(!caps.supports(OutputFormat::P010Lsb));
+
+        // Both dimensions are needed
+        caps.set_max_size(1280, 0);
+        assert_eq!(caps.max_size(), None);
+        assert_eq!(region[0], 1280);
+    }
+}
@@ -0,0 +1,169 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Number of readers that can register at once.
+pub const MAX_READERS: usize = 8;
+pub const READER_SLOT_SIZE: usize = 16;
+/// Size of the reader registry region, separate from the frame buffer.
+pub const READER_REGISTRY_SIZE: usize = MAX_READERS * READER_SLOT_SIZE;
+
+/// One registered reader. A zero `pid` marks the entry free.
+#[repr(C)]
+pub struct ReaderSlot {
+    pub pid: AtomicU32,
+    _pad: u32,
+    /// `write_index` of the last frame the reader consumed.
+    pub cursor: AtomicU64,
+}
+
+const _: () = assert!(std::mem::size_of::<ReaderSlot>() == READER_SLOT_SIZE);
+
+/// Registry of frame buffer readers and how far each has read, so the
+/// writer can tell how many are lagging and where the slowest one is.
+///
+/// Lives in its own shared memory region because the frame buffer is mapped
+/// read-only by readers; registering needs write access to this one.
+pub struct ReaderRegistry {
+    slots: *const ReaderSlot,
+}
+
+// SAFETY: the registry is only accessed through atomics.
+unsafe impl Send for ReaderRegistry {}
+unsafe impl Sync for ReaderRegistry {}
+
+impl ReaderRegistry {
+    /// # Safety
+    /// `base` must point to a writable mapping of at least
+    /// `READER_REGISTRY_SIZE` bytes, aligned to 8 bytes and valid for the
+    /// lifetime of the registry. A zero-filled region has no readers.
+    pub unsafe fn new(base: *mut u8) -> Self {
+        Self {
+            slots: base as *const ReaderSlot,
+        }
+    }
+
+    fn slots(&self) -> &[ReaderSlot] {
+        unsafe { std::slice::from_raw_parts(self.slots, MAX_READERS) }
+    }
+
+    /// Claim a free entry for the reader process `pid`, starting at `cursor`.
+    /// Returns `None` if `MAX_READERS` are already registered. The entry is
+    /// released when the returned cursor is dropped.
+    pub fn register(&self, pid: u32, cursor: u64) -> Option<ReaderCursor<'_>> {
+        let slot = self.slots().iter().find(|slot| {
+            slot.pid
+                .compare_exchange(0, pid, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        })?;
+        slot.cursor.store(cursor, Ordering::Release);
+        Some(ReaderCursor { slot })
+    }
+
+    /// Cursors of the registered readers.
+    pub fn cursors(&self) -> Vec<u64> {
+        self.slots()
+            .iter()
+            .filter(|slot| slot.pid.load(Ordering::Acquire) != 0)
+            .map(|slot| slot.cursor.load(Ordering::Acquire))
+            .collect()
+    }
+
+    /// Position of the slowest registered reader, or `None` if there are none.
+    pub fn min_cursor(&self) -> Option<u64> {
+        self.cursors().into_iter().min()
+    }
+
+    /// How many registered readers haven't consumed frame `write_index` yet.
+    pub fn lagging(&self, write_index: u64) -> usize {
+        self.cursors()
+            .into_iter()
+            .filter(|&cursor| cursor < write_index)
+            .count()
+    }
+
+    /// Free the entries of readers for which `is_alive` returns false, such
+    /// as processes that exited without unregistering. Returns how many
+    /// were freed.
+    pub fn prune(&self, is_alive: impl Fn(u32) -> bool) -> usize {
+        let mut freed = 0;
+        for slot in self.slots() {
+            let pid = slot.pid.load(Ordering::Acquire);
+            if pid != 0 && !is_alive(pid) {
+                slot.cursor.store(0, Ordering::Relaxed);
+                if slot
+                    .pid
+                    .compare_exchange(pid, 0, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    freed += 1;
+                }
+            }
+        }
+        freed
+    }
+}
+
+/// A reader's registry entry; see [`ReaderRegistry::register`].
+pub struct ReaderCursor<'a> {
+    slot: &'a ReaderSlot,
+}
+
+impl ReaderCursor<'_> {
+    /// Record that the reader has consumed frame `write_index`.
+    pub fn update(&self, write_index: u64) {
+        self.slot.cursor.store(write_index, Ordering::Release);
+    }
+}
+
+impl Drop for ReaderCursor<'_> {
+    fn drop(&mut self) {
+        self.slot.cursor.store(0, Ordering::Relaxed);
+        self.slot.pid.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region() -> Vec<u64> {
+        vec![0u64; READER_REGISTRY_SIZE / 8]
+    }
+
+    #[test]
+    fn test_reports_slowest_reader() {
+        let mut region = region();
+        let registry = unsafe { ReaderRegistry::new(region.as_mut_ptr() as *mut u8) };
+        assert_eq!(registry.min_cursor(), None);
+
+        let first = registry.register(100, 0).unwrap();
+        let second = registry.register(200, 0).unwrap();
+        first.update(10);
+        second.update(7);
+        assert_eq!(registry.min_cursor(), Some(7));
+        assert_eq!(registry.lagging(10), 1);
+        assert_eq!(registry.lagging(11), 2);
+
+        drop(second);
+        assert_eq!(registry.min_cursor(), Some(10));
+        assert_eq!(registry.lagging(10), 0);
+    }
+
+    #[test]
+    fn test_registry_full_and_prune() {
+        let mut region = region();
+        let registry = unsafe { ReaderRegistry::new(region.as_mut_ptr() as *mut u8) };
+        let cursors: Vec<_> = (1..=MAX_READERS as u32)
+            .map(|pid| registry.register(pid, 5).unwrap())
+            .collect();
+        assert!(registry.register(99, 0).is_none());
+
+        // A reader that exited without unregistering
+        std::mem::forget(cursors);
+        assert_eq!(registry.prune(|pid| pid != 3), 1);
+        let cursor = registry.register(99, 8).unwrap();
+        assert_eq!(registry.cursors().len(), MAX_READERS);
+        assert_eq!(registry.min_cursor(), Some(5));
+        cursor.update(2);
+        assert_eq!(registry.min_cursor(), Some(2));
+    }
+}
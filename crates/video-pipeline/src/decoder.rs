@@ -1,58 +1,536 @@
 use std::ffi::c_void;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
 
 use tracing::{debug, error, trace, warn};
 
 use crate::ffi;
-use crate::format::FormatDescription;
+use crate::format::{check_parameter_sets, FormatDescription};
+use crate::output::{
+    ChannelOutput, DecodedFrame, Frame, FrameLayout, FrameOutput, PixelBufferPoolOutput, ShmOutput,
+};
+use crate::sps::{parse_sps, SpsInfo};
 
 /// Shared frame buffer layout constants.
 /// Must match the Swift extension side.
-pub const FRAME_HEADER_SIZE: usize = 64;
+pub const FRAME_HEADER_SIZE: usize = 128;
 pub const MAX_WIDTH: usize = 1920;
 pub const MAX_HEIGHT: usize = 1080;
-pub const MAX_FRAME_SIZE: usize = MAX_WIDTH * MAX_HEIGHT * 3 / 2; // NV12
-pub const FRAME_SHM_SIZE: usize = FRAME_HEADER_SIZE + 2 * MAX_FRAME_SIZE; // double-buffered
+/// Slot and buffer sizes of the default NV12 layout; see `FrameLayout` for
+/// other output formats.
+pub const MAX_FRAME_SIZE: usize = FrameLayout::NV12.max_frame_size;
+pub const FRAME_SHM_SIZE: usize = FrameLayout::NV12.shm_size(); // double-buffered
+
+/// Header identification, stored in the reserved area after width/height.
+/// Readers must refuse to read a buffer whose magic or version doesn't match.
+pub const FRAME_MAGIC: [u8; 4] = *b"RVCM";
+pub const FRAME_MAGIC_OFFSET: usize = 16;
+/// Version 2 moved the frame description into per-slot headers.
+pub const FRAME_LAYOUT_VERSION: u16 = 2;
+pub const FRAME_VERSION_OFFSET: usize = 20;
+/// Offset of `FrameHeader::interlaced`.
+pub const FRAME_INTERLACED_OFFSET: usize = 22;
+/// Offset of `FrameHeader::slots`; each `SlotHeader` is `SLOT_HEADER_SIZE` bytes.
+pub const FRAME_SLOTS_OFFSET: usize = 24;
+pub const SLOT_HEADER_SIZE: usize = 32;
+/// Offset of `FrameHeader::color`, a `ColorHeader` of `COLOR_HEADER_SIZE` bytes.
+pub const FRAME_COLOR_OFFSET: usize = 88;
+pub const COLOR_HEADER_SIZE: usize = 40;
+
+/// Header at the start of the shared frame buffer. All header access goes
+/// through this struct rather than raw offsets.
+///
+/// The region is shared with another process, so fields other than
+/// `write_index` are accessed through raw pointers with volatile reads/writes.
+///
+/// `write_index` doubles as the sequence number of a seqlock over the two
+/// slots. The writer loads it, issues a Release fence so the previous
+/// commit is visible before any store into the slot it is about to reuse,
+/// writes the frame and its slot header, then increments it with Release
+/// ordering. A reader loads it with Acquire, copies slot
+/// `(write_index - 1) % 2`, issues an Acquire fence and loads it again: if
+/// it moved, the writer may have started on that slot and the copy is
+/// retried.
+#[repr(C)]
+pub struct FrameHeader {
+    /// Number of frames committed; the newest is in slot `(write_index - 1) % 2`.
+    /// See the struct docs for how writer and readers order around it.
+    pub write_index: AtomicU64,
+    /// Width and height of the newest frame in layout version 1; unused since.
+    _unused: [u32; 2],
+    /// `FRAME_MAGIC`.
+    pub magic: [u8; 4],
+    /// `FRAME_LAYOUT_VERSION`, little-endian.
+    pub version: u16,
+    /// Non-zero when the stream is interlaced (SPS `frame_mbs_only_flag`
+    /// 0). Frames are still full height: deinterlaced by VideoToolbox where
+    /// it supports that, otherwise both fields weaved.
+    pub interlaced: u8,
+    _pad: u8,
+    /// Description of the frame in each slot, written together with its pixels
+    /// so a reader never pairs one frame's data with another's dimensions.
+    pub slots: [SlotHeader; 2],
+    /// Color description of the stream, written when the publisher sends
+    /// one. All zeros means none was sent.
+    pub color: ColorHeader,
+}
+
+/// Describes the frame stored in one double-buffer slot.
+#[repr(C)]
+pub struct SlotHeader {
+    /// Presentation timestamp of the frame.
+    pub timestamp_ms: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Pixel layout of the frame data, `OutputFormat::fourcc`. All zeros
+    /// means NV12.
+    pub pixel_format: [u8; 4],
+    /// Size to show the frame at when the stream has non-square pixels
+    /// (SPS VUI sample aspect ratio). Zeros mean square pixels: use
+    /// `width`/`height`.
+    pub display_width: u32,
+    pub display_height: u32,
+    /// Bytes per row of every plane, when the writer kept the decoder's row
+    /// padding (`ShmOutput::with_source_stride`). Zero means tightly packed
+    /// rows of `width` samples.
+    pub stride: u32,
+}
+
+/// HDR color description of the stream, from the publisher's enhanced-RTMP
+/// `colorInfo`. Color code points are ISO/IEC 23091-4 (as in the SPS VUI);
+/// the mastering display values use the units of the HEVC mastering display
+/// SEI, so they can be passed on as-is.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ColorHeader {
+    /// Non-zero once a description has been written.
+    pub present: u8,
+    pub color_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    /// x, y of the red, green and blue primaries, in 0.00002 units. Zeros
+    /// when the publisher sent no mastering display.
+    pub display_primaries: [u16; 6],
+    /// x, y of the white point, in 0.00002 units.
+    pub white_point: [u16; 2],
+    /// Mastering display luminance range, in 0.0001 cd/m² units.
+    pub max_luminance: u32,
+    pub min_luminance: u32,
+    /// Content light levels in cd/m²; zero when unknown.
+    pub max_cll: u16,
+    pub max_fall: u16,
+    _reserved: [u8; 8],
+}
+
+const _: () = {
+    assert!(std::mem::size_of::<FrameHeader>() == FRAME_HEADER_SIZE);
+    assert!(std::mem::size_of::<SlotHeader>() == SLOT_HEADER_SIZE);
+    assert!(std::mem::offset_of!(FrameHeader, magic) == FRAME_MAGIC_OFFSET);
+    assert!(std::mem::offset_of!(FrameHeader, version) == FRAME_VERSION_OFFSET);
+    assert!(std::mem::offset_of!(FrameHeader, interlaced) == FRAME_INTERLACED_OFFSET);
+    assert!(std::mem::offset_of!(FrameHeader, slots) == FRAME_SLOTS_OFFSET);
+    assert!(std::mem::size_of::<ColorHeader>() == COLOR_HEADER_SIZE);
+    assert!(std::mem::offset_of!(FrameHeader, color) == FRAME_COLOR_OFFSET);
+};
+
+impl FrameHeader {
+    /// Zero the header and stamp the layout identification.
+    ///
+    /// # Safety
+    /// `header` must point to a writable, 8-byte aligned mapping of at least
+    /// `FRAME_HEADER_SIZE` bytes.
+    pub unsafe fn init(header: *mut FrameHeader) {
+        std::ptr::write_bytes(header as *mut u8, 0, FRAME_HEADER_SIZE);
+        std::ptr::addr_of_mut!((*header).magic).write_volatile(FRAME_MAGIC);
+        std::ptr::addr_of_mut!((*header).version).write_volatile(FRAME_LAYOUT_VERSION.to_le());
+    }
+
+    /// Check the magic and layout version stamped by `init`.
+    ///
+    /// # Safety
+    /// `header` must point to a readable mapping of at least `FRAME_HEADER_SIZE` bytes.
+    pub unsafe fn validate(header: *const FrameHeader) -> Result<(), String> {
+        let magic = std::ptr::addr_of!((*header).magic).read_volatile();
+        if magic != FRAME_MAGIC {
+            return Err(format!("frame buffer magic mismatch: {magic:02x?}"));
+        }
+        let version = u16::from_le(std::ptr::addr_of!((*header).version).read_volatile());
+        if version != FRAME_LAYOUT_VERSION {
+            return Err(format!(
+                "frame buffer layout version {version}, expected {FRAME_LAYOUT_VERSION}"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Replace the stream's color description. Pass
+    /// `ColorHeader::default()` to clear it.
+    ///
+    /// # Safety
+    /// `header` must point to a writable mapping of at least `FRAME_HEADER_SIZE` bytes.
+    pub unsafe fn write_color(header: *mut FrameHeader, color: ColorHeader) {
+        std::ptr::addr_of_mut!((*header).color).write_volatile(color);
+    }
+
+    /// The stream's color description, if one was written.
+    ///
+    /// # Safety
+    /// `header` must point to a readable mapping of at least `FRAME_HEADER_SIZE` bytes.
+    pub unsafe fn read_color(header: *const FrameHeader) -> Option<ColorHeader> {
+        let color = std::ptr::addr_of!((*header).color).read_volatile();
+        (color.present != 0).then_some(color)
+    }
+
+    /// Record whether the stream is interlaced.
+    ///
+    /// # Safety
+    /// `header` must point to a writable mapping of at least `FRAME_HEADER_SIZE` bytes.
+    pub unsafe fn write_interlaced(header: *mut FrameHeader, interlaced: bool) {
+        std::ptr::addr_of_mut!((*header).interlaced).write_volatile(interlaced as u8);
+    }
+
+    /// Whether the stream was recorded as interlaced.
+    ///
+    /// # Safety
+    /// `header` must point to a readable mapping of at least `FRAME_HEADER_SIZE` bytes.
+    pub unsafe fn read_interlaced(header: *const FrameHeader) -> bool {
+        std::ptr::addr_of!((*header).interlaced).read_volatile() != 0
+    }
+}
+
+/// VideoToolbox bad-data errors, returned for frames it can't decode such as
+/// B-frames that arrive before the first IDR (kVTVideoDecoderBadDataErr and
+/// the older codecBadDataErr).
+pub const BENIGN_DECODE_ERRORS: [i32; 2] = [-12909, -8969];
+
+/// Frame rate assumed for sample durations until `set_frame_rate` is called.
+const DEFAULT_FRAME_RATE: f32 = 30.0;
+
+/// Channel capacity used by `H264Decoder::decode_all`; a decode call outputs
+/// at most one frame, and a flush no more than the decoder holds back.
+const DECODE_ALL_CAPACITY: usize = 16;
+
+/// Decoder behavior switches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecoderOptions {
+    /// Treat `BENIGN_DECODE_ERRORS` as dropped frames: `decode_avcc` returns
+    /// `Ok(())` and the error is only counted in `benign_errors`.
+    pub ignore_benign_errors: bool,
+    /// Emit a TRACE event per decoded frame, with target
+    /// `video_pipeline::timing`: the input timestamp, the pixel buffer's
+    /// presentation timestamp, decode latency and the output's write index.
+    pub trace_timing: bool,
+    /// Hand only every Nth decoded frame to the output, e.g. 6 for a 5 fps
+    /// preview of a 30 fps stream. Every frame is still decoded, as later
+    /// ones reference them; the shm `write_index` only advances on the
+    /// frames handed over. 0 and 1 both mean every frame.
+    pub commit_every_n: u32,
+    /// Log a CRC-32 of each committed frame's Y plane at DEBUG, with target
+    /// `video_pipeline::crc`, for comparing decodes of the same clip.
+    pub crc_frames: bool,
+}
+
+/// Per-frame values handed to the decompression callback through
+/// `sourceFrameRefCon` under `DecoderOptions::trace_timing`.
+struct FrameTiming {
+    input_timestamp_ms: u32,
+    submitted: Instant,
+}
+
+/// Why a decoder couldn't be created or a frame couldn't be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecoderError {
+    /// OSStatus returned by CoreMedia or VideoToolbox, or `None` if the
+    /// input was rejected before reaching them (e.g. a missing SPS).
+    pub status: Option<i32>,
+    /// What was being attempted.
+    pub context: String,
+}
+
+impl DecoderError {
+    fn os_status(status: ffi::OSStatus, context: impl Into<String>) -> Self {
+        Self {
+            status: Some(status),
+            context: context.into(),
+        }
+    }
+}
+
+impl fmt::Display for DecoderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "{}: OSStatus {status}", self.context),
+            None => f.write_str(&self.context),
+        }
+    }
+}
+
+impl std::error::Error for DecoderError {}
+
+impl From<String> for DecoderError {
+    fn from(context: String) -> Self {
+        Self {
+            status: None,
+            context,
+        }
+    }
+}
+
+impl From<DecoderError> for String {
+    fn from(error: DecoderError) -> Self {
+        error.to_string()
+    }
+}
 
 /// H.264 hardware decoder using Apple VideoToolbox.
 ///
-/// Decodes H.264 NAL units into CVPixelBuffers and copies pixel data
-/// to a shared memory region for the Camera Extension to read.
+/// Decodes H.264 NAL units into CVPixelBuffers and hands the pixel data
+/// to a `FrameOutput` — by default the shared memory region read by the
+/// Camera Extension.
 pub struct H264Decoder {
     session: ffi::VTDecompressionSessionRef,
     format_desc: FormatDescription,
     _ctx: *mut CallbackContext, // prevent premature free
+    options: DecoderOptions,
+    /// Benign errors swallowed under `ignore_benign_errors`.
+    benign_errors: u64,
+    /// Duration given to each sample buffer, from the stream's frame rate.
+    frame_duration: ffi::CMTime,
 }
 
 /// Context passed to the VT decompression callback.
 struct CallbackContext {
-    shm_ptr: *mut u8,
+    output: Mutex<Box<dyn FrameOutput>>,
+    /// Dimensions of the first frame handed to `output`, set once by the callback.
+    first_frame: OnceLock<(usize, usize)>,
+    /// Sample aspect ratio from the first SPS, passed along with each frame.
+    sar: (u16, u16),
+    /// The first SPS, checked against the size of the first decoded frame.
+    sps_info: Option<SpsInfo>,
+    /// `DecoderOptions::commit_every_n`.
+    commit_every_n: AtomicU32,
+    /// `DecoderOptions::crc_frames`.
+    crc_frames: AtomicBool,
+    /// Frames decoded so far, committed or not.
+    decoded: AtomicU64,
+    /// Frames VideoToolbox reported as dropped through `infoFlags`.
+    frames_dropped_by_vt: AtomicU64,
+    /// Set through `H264Decoder::set_paused`: decode but commit nothing.
+    paused: AtomicBool,
 }
 
-// SAFETY: shm_ptr points to a memory-mapped region that is valid for the lifetime of the decoder.
-unsafe impl Send for CallbackContext {}
-unsafe impl Sync for CallbackContext {}
+impl CallbackContext {
+    /// Count a decoded frame and say whether it's one to hand to the output.
+    /// The first frame always is, unless paused.
+    fn next_frame_commits(&self) -> bool {
+        let every = self.commit_every_n.load(Ordering::Relaxed).max(1) as u64;
+        let due = self
+            .decoded
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(every);
+        due && !self.paused.load(Ordering::Relaxed)
+    }
+}
 
 impl H264Decoder {
     /// Create a new decoder from SPS/PPS parameter sets.
     ///
-    /// `shm_ptr` must point to a shared memory region of at least `FRAME_SHM_SIZE` bytes,
-    /// valid for the lifetime of the decoder.
+    /// `shm_ptr` must point to a shared memory region of `shm_len` bytes, valid
+    /// for the lifetime of the decoder. Frames are only written if they fit in
+    /// `shm_len`, which should be `FRAME_SHM_SIZE`.
     pub fn new(
         sps_list: &[Vec<u8>],
         pps_list: &[Vec<u8>],
         nalu_length_size: u8,
         shm_ptr: *mut u8,
-    ) -> Result<Self, String> {
+        shm_len: usize,
+    ) -> Result<Self, DecoderError> {
+        Self::with_shm_output(
+            sps_list,
+            pps_list,
+            nalu_length_size,
+            ShmOutput::new(shm_ptr, shm_len),
+        )
+    }
+
+    /// Like `new`, writing through an already configured `ShmOutput`, e.g.
+    /// one with a `CommitPolicy`, or `ReaderCaps` asking for smaller frames.
+    pub fn with_shm_output(
+        sps_list: &[Vec<u8>],
+        pps_list: &[Vec<u8>],
+        nalu_length_size: u8,
+        output: ShmOutput,
+    ) -> Result<Self, DecoderError> {
+        let max_size = output.reader_max_size();
+        Self::create(sps_list, pps_list, nalu_length_size, Box::new(output), max_size, None)
+    }
+
+    /// Create a decoder that sends packed NV12 frames on a bounded channel
+    /// instead of writing to shared memory. Frames are dropped when the
+    /// channel is full.
+    pub fn with_channel_output(
+        sps_list: &[Vec<u8>],
+        pps_list: &[Vec<u8>],
+        nalu_length_size: u8,
+        capacity: usize,
+    ) -> Result<(Self, Receiver<Frame>), DecoderError> {
+        let (output, rx) = ChannelOutput::new(capacity);
+        let decoder = Self::with_output(sps_list, pps_list, nalu_length_size, Box::new(output))?;
+        Ok((decoder, rx))
+    }
+
+    /// Decode a sequence of AVCC access units, given with their timestamps
+    /// in milliseconds, and return the pictures in output order as packed
+    /// NV12 (or P010) frames. For decoding frames from a file or another
+    /// source without setting up shared memory.
+    ///
+    /// Stops at the first frame VideoToolbox rejects.
+    pub fn decode_all<'a>(
+        sps_list: &[Vec<u8>],
+        pps_list: &[Vec<u8>],
+        nalu_length_size: u8,
+        frames: impl IntoIterator<Item = (&'a [u8], u32)>,
+    ) -> Result<Vec<Frame>, DecoderError> {
+        // Decoding is synchronous, so draining after every call keeps the
+        // channel from ever filling up and dropping frames
+        let (mut decoder, rx) =
+            Self::with_channel_output(sps_list, pps_list, nalu_length_size, DECODE_ALL_CAPACITY)?;
+        let mut decoded = Vec::new();
+        for (avcc_data, timestamp_ms) in frames {
+            decoder.decode_avcc(avcc_data, timestamp_ms)?;
+            decoded.extend(rx.try_iter());
+        }
+        decoder.flush()?;
+        drop(decoder);
+        decoded.extend(rx);
+        Ok(decoded)
+    }
+
+    /// Create a decoder that delivers decoded frames to a custom `FrameOutput`.
+    pub fn with_output(
+        sps_list: &[Vec<u8>],
+        pps_list: &[Vec<u8>],
+        nalu_length_size: u8,
+        output: Box<dyn FrameOutput>,
+    ) -> Result<Self, DecoderError> {
+        Self::create(sps_list, pps_list, nalu_length_size, output, None, None)
+    }
+
+    /// Create a decoder whose frames are delivered in CVPixelBuffers drawn
+    /// from `pool`, e.g. to share them with an AVSampleBufferDisplayLayer.
+    /// VideoToolbox decodes to the pool's pixel buffer attributes (size,
+    /// pixel format, IOSurface backing) and each frame is copied into a
+    /// buffer from the pool, as sessions can't allocate from a pool
+    /// directly. See `PixelBufferPoolOutput` for `on_frame`.
+    ///
+    /// # Safety
+    /// `pool` must be a valid `CVPixelBufferPoolRef`; it is retained for
+    /// the decoder's lifetime.
+    pub unsafe fn with_pixel_buffer_pool(
+        sps_list: &[Vec<u8>],
+        pps_list: &[Vec<u8>],
+        nalu_length_size: u8,
+        pool: ffi::CVPixelBufferPoolRef,
+        on_frame: impl FnMut(ffi::CVPixelBufferRef, u64) + Send + 'static,
+    ) -> Result<Self, DecoderError> {
+        if pool.is_null() {
+            return Err("pixel buffer pool is null".to_string().into());
+        }
+        let output = PixelBufferPoolOutput::new(pool, on_frame);
+        Self::create(
+            sps_list,
+            pps_list,
+            nalu_length_size,
+            Box::new(output),
+            None,
+            Some(pool),
+        )
+    }
+
+    /// Create a decoder whose frames are scaled down to fit within
+    /// `max_size`, if given, or match `pool`'s pixel buffer attributes.
+    fn create(
+        sps_list: &[Vec<u8>],
+        pps_list: &[Vec<u8>],
+        nalu_length_size: u8,
+        output: Box<dyn FrameOutput>,
+        max_size: Option<(u32, u32)>,
+        pool: Option<ffi::CVPixelBufferPoolRef>,
+    ) -> Result<Self, DecoderError> {
+        check_parameter_sets(sps_list, pps_list)?;
+        let sps_info = sps_list.first().and_then(|sps| parse_sps(sps));
         let format_desc =
             FormatDescription::from_h264_parameter_sets(sps_list, pps_list, nalu_length_size)
-                .map_err(|s| format!("failed to create format description: OSStatus {s}"))?;
+                .map_err(|s| {
+                    let profile = sps_info.map_or("unparsed SPS".to_string(), |info| {
+                        let level = (info.level_idc / 10, info.level_idc % 10);
+                        format!("{}, level {}.{}", info.profile_name(), level.0, level.1)
+                    });
+                    let context = format!("failed to create format description ({profile})");
+                    DecoderError::os_status(s, context)
+                })?;
+
+        // Output is always 4:2:0, so VideoToolbox has to convert 4:2:2 and
+        // 4:4:4 pictures, halving their chroma resolution, if it can at all
+        if let Some(info) = sps_info.filter(|info| matches!(info.chroma_format_idc, 2 | 3)) {
+            warn!(
+                chroma_format = info.chroma_format_name(),
+                profile = info.profile_name(),
+                "stream isn't 4:2:0; it will be converted, losing color detail, \
+                 or fail to decode. Encode with -pix_fmt yuv420p"
+            );
+        }
+
+        let sar = sps_info.map_or((1, 1), |info| info.sar);
+        if sar != (1, 1) {
+            debug!(sar_width = sar.0, sar_height = sar.1, "stream has non-square pixels");
+        }
+
+        let bit_depth = sps_info.map_or(8, |info| info.bit_depth);
+        let pixel_format = output_pixel_format(bit_depth, output.as_ref());
+
+        // Scale down to what the reader asked for; the first frame is then
+        // checked against the scaled size rather than the SPS's
+        let mut sps_info = sps_info;
+        let scaled = sps_info
+            .zip(max_size)
+            .and_then(|(info, max)| scaled_size((info.width, info.height), max));
+        if let (Some((width, height)), Some((max_width, max_height))) = (scaled, max_size) {
+            debug!(
+                width,
+                height,
+                max_width,
+                max_height,
+                "scaling output to the reader's maximum size"
+            );
+            sps_info = sps_info.map(|info| SpsInfo {
+                width,
+                height,
+                ..info
+            });
+        }
 
         // Build destination image buffer attributes
-        let dest_attrs = unsafe { create_destination_attributes() };
+        let dest_attrs = match pool {
+            Some(pool) => unsafe { pool_destination_attributes(pool) },
+            None => unsafe { create_destination_attributes(pixel_format, scaled) },
+        };
 
         // Build callback
-        let ctx = Box::new(CallbackContext { shm_ptr });
+        let ctx = Box::new(CallbackContext {
+            output: Mutex::new(output),
+            first_frame: OnceLock::new(),
+            sar,
+            sps_info,
+            commit_every_n: AtomicU32::new(1),
+            crc_frames: AtomicBool::new(false),
+            decoded: AtomicU64::new(0),
+            frames_dropped_by_vt: AtomicU64::new(0),
+            paused: AtomicBool::new(false),
+        });
         let ctx_ptr = Box::into_raw(ctx);
 
         let callback = ffi::DecompressionOutputCallbackRecord {
@@ -80,9 +558,11 @@ impl H264Decoder {
         if status != 0 {
             // Clean up the leaked context
             unsafe { drop(Box::from_raw(ctx_ptr)) };
-            return Err(format!(
-                "VTDecompressionSessionCreate failed: OSStatus {status}"
-            ));
+            return Err(DecoderError::os_status(status, "VTDecompressionSessionCreate failed"));
+        }
+
+        if sps_info.is_some_and(|info| info.interlaced) {
+            request_deinterlacing(session);
         }
 
         debug!("VTDecompressionSession created");
@@ -90,12 +570,68 @@ impl H264Decoder {
             session,
             format_desc,
             _ctx: ctx_ptr,
+            options: DecoderOptions::default(),
+            benign_errors: 0,
+            frame_duration: frame_duration(DEFAULT_FRAME_RATE),
         })
     }
 
+    /// Replace the default `DecoderOptions`.
+    pub fn with_options(mut self, options: DecoderOptions) -> Self {
+        self.options = options;
+        let ctx = unsafe { &*self._ctx };
+        ctx.commit_every_n.store(options.commit_every_n, Ordering::Relaxed);
+        ctx.crc_frames.store(options.crc_frames, Ordering::Relaxed);
+        self
+    }
+
+    /// Set the stream's frame rate, e.g. from its metadata, which sample
+    /// buffers take their duration from. 30 fps until set; rates that
+    /// aren't positive and finite are ignored.
+    pub fn set_frame_rate(&mut self, frame_rate: f32) {
+        if frame_rate.is_finite() && frame_rate > 0.0 {
+            self.frame_duration = frame_duration(frame_rate);
+        }
+    }
+
+    /// Number of benign decode errors swallowed because of
+    /// `DecoderOptions::ignore_benign_errors`.
+    pub fn benign_errors(&self) -> u64 {
+        self.benign_errors
+    }
+
+    /// Number of frames VideoToolbox dropped without producing an image,
+    /// as under load. Nothing is written to the output for them.
+    pub fn frames_dropped_by_vt(&self) -> u64 {
+        unsafe { &*self._ctx }
+            .frames_dropped_by_vt
+            .load(Ordering::Relaxed)
+    }
+
+    /// Number of frames VideoToolbox has output so far, whether or not they
+    /// were handed to the output (`commit_every_n`, `set_paused`). Frames
+    /// arrive asynchronously, so this can trail `decode_avcc` until `flush`.
+    pub fn frames_decoded(&self) -> u64 {
+        unsafe { &*self._ctx }.decoded.load(Ordering::Relaxed)
+    }
+
+    /// Stop handing decoded frames to the output, or start again. While
+    /// paused, frames are still decoded so later ones have their
+    /// references, but the shm `write_index` doesn't advance and readers
+    /// keep showing the last frame committed.
+    pub fn set_paused(&self, paused: bool) {
+        if unsafe { &*self._ctx }.paused.swap(paused, Ordering::Relaxed) != paused {
+            debug!(paused, "frame commits paused state changed");
+        }
+    }
+
     /// Decode AVCC-framed video data containing one or more NAL units.
     /// Data must be in AVCC format: [4-byte len][NAL1][4-byte len][NAL2]...
-    pub fn decode_avcc(&mut self, avcc_data: &[u8], timestamp_ms: u32) -> Result<(), String> {
+    pub fn decode_avcc(
+        &mut self,
+        avcc_data: &[u8],
+        timestamp_ms: u32,
+    ) -> Result<(), DecoderError> {
         // Create CMBlockBuffer — let CoreMedia allocate and own the memory,
         // then copy our data in, to avoid memory ownership issues.
         let mut block_buffer: ffi::CMBlockBufferRef = std::ptr::null_mut();
@@ -113,7 +649,10 @@ impl H264Decoder {
             )
         };
         if status != 0 {
-            return Err(format!("CMBlockBufferCreateWithMemoryBlock failed: {status}"));
+            return Err(DecoderError::os_status(
+                status,
+                "CMBlockBufferCreateWithMemoryBlock failed",
+            ));
         }
 
         // Copy AVCC data into the CoreMedia-owned block
@@ -127,12 +666,12 @@ impl H264Decoder {
         };
         if status != 0 {
             unsafe { ffi::CFRelease(block_buffer as *const c_void) };
-            return Err(format!("CMBlockBufferReplaceDataBytes failed: {status}"));
+            return Err(DecoderError::os_status(status, "CMBlockBufferReplaceDataBytes failed"));
         }
 
         // Create CMSampleBuffer
         let timing = ffi::CMSampleTimingInfo {
-            duration: ffi::CMTime::make(1, 30), // 1/30s
+            duration: self.frame_duration,
             presentationTimeStamp: ffi::CMTime::make(timestamp_ms as i64, 1000),
             decodeTimeStamp: ffi::CMTime::invalid(),
         };
@@ -157,9 +696,21 @@ impl H264Decoder {
         unsafe { ffi::CFRelease(block_buffer as *const c_void) };
 
         if status != 0 {
-            return Err(format!("CMSampleBufferCreateReady failed: {status}"));
+            return Err(DecoderError::os_status(status, "CMSampleBufferCreateReady failed"));
         }
 
+        // Decoding is synchronous, so the callback runs before
+        // DecodeFrame returns and `timing` outlives its use there
+        let timing = FrameTiming {
+            input_timestamp_ms: timestamp_ms,
+            submitted: Instant::now(),
+        };
+        let source_ref = if self.options.trace_timing {
+            &timing as *const FrameTiming as *mut c_void
+        } else {
+            std::ptr::null_mut()
+        };
+
         // Decode
         let mut info_flags: u32 = 0;
         let status = unsafe {
@@ -167,7 +718,7 @@ impl H264Decoder {
                 self.session,
                 sample_buffer,
                 0, // decodeFlags: synchronous
-                std::ptr::null_mut(), // sourceFrameRefCon
+                source_ref, // sourceFrameRefCon
                 &mut info_flags,
             )
         };
@@ -176,26 +727,40 @@ impl H264Decoder {
         unsafe { ffi::CFRelease(sample_buffer as *const c_void) };
 
         if status != 0 {
-            // -8969 = kVTVideoDecoderBadDataErr (common for incomplete frames)
-            if status == -8969 {
+            let benign = BENIGN_DECODE_ERRORS.contains(&status);
+            if benign && self.options.ignore_benign_errors {
+                self.benign_errors += 1;
+                trace!(status, timestamp_ms, "dropped undecodable frame");
+                return Ok(());
+            }
+            if benign {
                 trace!(status, "decode frame returned bad data (may be expected for partial frames)");
             } else {
                 warn!(status, "VTDecompressionSessionDecodeFrame failed");
             }
-            return Err(format!("VTDecompressionSessionDecodeFrame failed: {status}"));
+            return Err(DecoderError::os_status(
+                status,
+                "VTDecompressionSessionDecodeFrame failed",
+            ));
         }
 
         trace!(timestamp_ms, "decoded frame");
         Ok(())
     }
 
+    /// Width and height of the first frame this decoder delivered to its
+    /// output, or `None` if nothing has been decoded yet.
+    pub fn first_frame_size(&self) -> Option<(usize, usize)> {
+        unsafe { (*self._ctx).first_frame.get().copied() }
+    }
+
     /// Flush the decoder — wait for all pending frames.
-    pub fn flush(&self) -> Result<(), String> {
+    pub fn flush(&self) -> Result<(), DecoderError> {
         let status = unsafe {
             ffi::VTDecompressionSessionWaitForAsynchronousFrames(self.session)
         };
         if status != 0 {
-            return Err(format!("WaitForAsynchronousFrames failed: {status}"));
+            return Err(DecoderError::os_status(status, "WaitForAsynchronousFrames failed"));
         }
         Ok(())
     }
@@ -205,6 +770,9 @@ impl Drop for H264Decoder {
     fn drop(&mut self) {
         if !self.session.is_null() {
             unsafe {
+                // Frames still in flight call back into `_ctx`; let them
+                // finish before it's freed below
+                ffi::VTDecompressionSessionWaitForAsynchronousFrames(self.session);
                 ffi::VTDecompressionSessionInvalidate(self.session);
                 ffi::CFRelease(self.session as *const c_void);
             }
@@ -219,10 +787,48 @@ impl Drop for H264Decoder {
 // SAFETY: VTDecompressionSession is internally thread-safe for decode calls.
 unsafe impl Send for H264Decoder {}
 
+/// Pixel format to have VideoToolbox decode a `bit_depth` stream to: P010
+/// for 10-bit streams, so no precision is thrown away, if `output` can take
+/// it, and NV12 otherwise.
+fn output_pixel_format(bit_depth: u8, output: &dyn FrameOutput) -> u32 {
+    if bit_depth <= 8 {
+        return ffi::kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange;
+    }
+    if output.accepts_p010() {
+        debug!(bit_depth, "requesting P010 output");
+        ffi::kCVPixelFormatType_420YpCbCr10BiPlanarVideoRange
+    } else {
+        debug!(bit_depth, "output only takes 8-bit frames, requesting NV12");
+        ffi::kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange
+    }
+}
+
+/// Size to scale a `width` x `height` picture to so it fits within `max`,
+/// keeping its aspect ratio, or `None` if it already fits. Dimensions are
+/// kept even for 4:2:0 chroma.
+fn scaled_size((width, height): (u32, u32), max: (u32, u32)) -> Option<(u32, u32)> {
+    if width <= max.0 && height <= max.1 {
+        return None;
+    }
+    let scale = (max.0 as f64 / width as f64).min(max.1 as f64 / height as f64);
+    let even = |n: u32| ((n as f64 * scale) as u32 & !1).max(2);
+    Some((even(width), even(height)))
+}
+
+/// Duration of one frame at `frame_rate` fps, as 1000 units of a timescale
+/// of 1000 × fps, so rates like 29.97 are kept to three decimals.
+fn frame_duration(frame_rate: f32) -> ffi::CMTime {
+    ffi::CMTime::make(1000, (frame_rate * 1000.0).round() as i32)
+}
+
 /// Create destination pixel buffer attributes dictionary.
 ///
-/// Requests IOSurface-backed NV12 pixel buffers.
-unsafe fn create_destination_attributes() -> ffi::CFDictionaryRef {
+/// Requests IOSurface-backed pixel buffers in `pixel_format` (NV12 or P010),
+/// scaled to `size` if given.
+pub(crate) unsafe fn create_destination_attributes(
+    pixel_format: u32,
+    size: Option<(u32, u32)>,
+) -> ffi::CFDictionaryRef {
     let dict = ffi::CFDictionaryCreateMutable(
         ffi::kCFAllocatorDefault,
         4,
@@ -230,8 +836,7 @@ unsafe fn create_destination_attributes() -> ffi::CFDictionaryRef {
         &ffi::kCFTypeDictionaryValueCallBacks as *const _ as *const c_void,
     );
 
-    // Pixel format: NV12 (420v)
-    let pixel_format = ffi::kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange as i32;
+    let pixel_format = pixel_format as i32;
     let pixel_format_num = ffi::CFNumberCreate(
         ffi::kCFAllocatorDefault,
         ffi::kCFNumberSInt32Type,
@@ -244,6 +849,21 @@ unsafe fn create_destination_attributes() -> ffi::CFDictionaryRef {
     );
     ffi::CFRelease(pixel_format_num as *const c_void);
 
+    if let Some((width, height)) = size {
+        for (key, value) in [
+            (ffi::kCVPixelBufferWidthKey, width as i32),
+            (ffi::kCVPixelBufferHeightKey, height as i32),
+        ] {
+            let num = ffi::CFNumberCreate(
+                ffi::kCFAllocatorDefault,
+                ffi::kCFNumberSInt32Type,
+                &value as *const i32 as *const c_void,
+            );
+            ffi::CFDictionarySetValue(dict, key, num);
+            ffi::CFRelease(num);
+        }
+    }
+
     // IOSurface backing (empty dictionary = yes, use IOSurface)
     let io_surface_props = ffi::CFDictionaryCreateMutable(
         ffi::kCFAllocatorDefault,
@@ -261,19 +881,29 @@ unsafe fn create_destination_attributes() -> ffi::CFDictionaryRef {
     dict as ffi::CFDictionaryRef
 }
 
+/// Destination pixel buffer attributes matching the buffers `pool` vends,
+/// retained so they can be released like `create_destination_attributes`'.
+unsafe fn pool_destination_attributes(pool: ffi::CVPixelBufferPoolRef) -> ffi::CFDictionaryRef {
+    let attrs = ffi::CVPixelBufferPoolGetPixelBufferAttributes(pool);
+    if !attrs.is_null() {
+        ffi::CFRetain(attrs);
+    }
+    attrs
+}
+
 /// VTDecompressionSession output callback.
 ///
 /// Called by VideoToolbox when a frame has been decoded.
-/// Copies raw NV12 pixel data from the CVPixelBuffer into shared memory
-/// for the Camera Extension to read.
+/// Locks the CVPixelBuffer and hands its NV12 or P010 planes to the decoder's
+/// `FrameOutput` (shared memory for the Camera Extension by default).
 #[allow(non_snake_case)]
 unsafe extern "C" fn decompression_callback(
     decompressionOutputRefCon: *mut c_void,
-    _sourceFrameRefCon: *mut c_void,
+    sourceFrameRefCon: *mut c_void,
     status: ffi::OSStatus,
-    _infoFlags: u32,
+    infoFlags: u32,
     imageBuffer: ffi::CVImageBufferRef,
-    _presentationTimeStamp: ffi::CMTime,
+    presentationTimeStamp: ffi::CMTime,
     _presentationDuration: ffi::CMTime,
 ) {
     if status != 0 {
@@ -281,13 +911,22 @@ unsafe extern "C" fn decompression_callback(
         return;
     }
 
+    let ctx = &*(decompressionOutputRefCon as *const CallbackContext);
+    if infoFlags & ffi::kVTDecodeInfo_FrameDropped != 0 {
+        let dropped = ctx.frames_dropped_by_vt.fetch_add(1, Ordering::Relaxed) + 1;
+        trace!(dropped, "VideoToolbox dropped a frame");
+        return;
+    }
+
     if imageBuffer.is_null() {
         warn!("decompression callback received null imageBuffer");
         return;
     }
 
-    let ctx = &*(decompressionOutputRefCon as *const CallbackContext);
-    let shm = ctx.shm_ptr;
+    if !ctx.next_frame_commits() {
+        trace!("decoded frame not committed (commit_every_n or paused)");
+        return;
+    }
 
     // Lock the pixel buffer for read access
     let lock_status = ffi::CVPixelBufferLockBaseAddress(
@@ -301,75 +940,291 @@ unsafe extern "C" fn decompression_callback(
 
     let width = ffi::CVPixelBufferGetWidth(imageBuffer);
     let height = ffi::CVPixelBufferGetHeight(imageBuffer);
+    let pixel_format = ffi::CVPixelBufferGetPixelFormatType(imageBuffer);
+    let bytes_per_sample =
+        if pixel_format == ffi::kCVPixelFormatType_420YpCbCr10BiPlanarVideoRange {
+            2
+        } else {
+            1
+        };
+
+    let y_src = ffi::CVPixelBufferGetBaseAddressOfPlane(imageBuffer, 0);
+    let y_stride = ffi::CVPixelBufferGetBytesPerRowOfPlane(imageBuffer, 0);
+    let y_height = ffi::CVPixelBufferGetHeightOfPlane(imageBuffer, 0);
+    let uv_src = ffi::CVPixelBufferGetBaseAddressOfPlane(imageBuffer, 1);
+    let uv_stride = ffi::CVPixelBufferGetBytesPerRowOfPlane(imageBuffer, 1);
+    let uv_height = ffi::CVPixelBufferGetHeightOfPlane(imageBuffer, 1);
 
-    // Clamp to max supported resolution
-    if width > MAX_WIDTH || height > MAX_HEIGHT {
-        warn!(width, height, "frame exceeds max resolution, skipping");
+    if y_src.is_null() || uv_src.is_null() {
+        warn!("decoded pixel buffer has no plane base address");
         ffi::CVPixelBufferUnlockBaseAddress(imageBuffer, ffi::kCVPixelBufferLock_ReadOnly);
         return;
     }
 
-    let frame_size = width * height * 3 / 2; // NV12
-
-    // Determine which double-buffer slot to write to
-    let write_index_ptr = shm as *const AtomicU64;
-    let write_idx = (*write_index_ptr).load(Ordering::Relaxed);
-    let slot = (write_idx as usize) % 2;
-    let frame_offset = FRAME_HEADER_SIZE + slot * MAX_FRAME_SIZE;
-    let frame_dst = shm.add(frame_offset);
+    let timestamp_ms = if presentationTimeStamp.timescale > 0 {
+        (presentationTimeStamp.value * 1000 / presentationTimeStamp.timescale as i64) as u64
+    } else {
+        0
+    };
 
-    // Copy Y plane
-    let y_src = ffi::CVPixelBufferGetBaseAddressOfPlane(imageBuffer, 0);
-    let y_stride = ffi::CVPixelBufferGetBytesPerRowOfPlane(imageBuffer, 0);
-    let y_height = ffi::CVPixelBufferGetHeightOfPlane(imageBuffer, 0);
+    let frame = DecodedFrame {
+        width,
+        height,
+        bytes_per_sample,
+        y_plane: std::slice::from_raw_parts(y_src, y_stride * y_height),
+        y_stride,
+        uv_plane: std::slice::from_raw_parts(uv_src, uv_stride * uv_height),
+        uv_stride,
+        timestamp_ms,
+        sar: ctx.sar,
+    };
 
-    if !y_src.is_null() {
-        if y_stride == width {
-            // Fast path: stride matches width, single memcpy
-            std::ptr::copy_nonoverlapping(y_src, frame_dst, width * y_height);
-        } else {
-            // Row-by-row copy to strip padding
-            for row in 0..y_height {
-                std::ptr::copy_nonoverlapping(
-                    y_src.add(row * y_stride),
-                    frame_dst.add(row * width),
-                    width,
-                );
-            }
-        }
+    if ctx.crc_frames.load(Ordering::Relaxed) {
+        debug!(
+            target: "video_pipeline::crc",
+            frame = ctx.decoded.load(Ordering::Relaxed) - 1,
+            timestamp_ms,
+            y_crc = format_args!("{:08x}", frame.y_crc32()),
+            "frame crc"
+        );
     }
 
-    // Copy UV plane
-    let uv_src = ffi::CVPixelBufferGetBaseAddressOfPlane(imageBuffer, 1);
-    let uv_stride = ffi::CVPixelBufferGetBytesPerRowOfPlane(imageBuffer, 1);
-    let uv_height = ffi::CVPixelBufferGetHeightOfPlane(imageBuffer, 1);
-    let uv_dst = frame_dst.add(width * y_height);
-
-    if !uv_src.is_null() {
-        if uv_stride == width {
-            std::ptr::copy_nonoverlapping(uv_src, uv_dst, width * uv_height);
-        } else {
-            for row in 0..uv_height {
-                std::ptr::copy_nonoverlapping(
-                    uv_src.add(row * uv_stride),
-                    uv_dst.add(row * width),
-                    width,
-                );
-            }
+    if let Ok(mut output) = ctx.output.lock() {
+        output.write_frame(&frame);
+        if ctx.first_frame.set((width, height)).is_ok() {
+            check_first_frame_size(ctx.sps_info, width, height);
+        }
+        if !sourceFrameRefCon.is_null() {
+            let timing = &*(sourceFrameRefCon as *const FrameTiming);
+            trace!(
+                target: "video_pipeline::timing",
+                input_timestamp_ms = timing.input_timestamp_ms,
+                pts_ms = timestamp_ms,
+                decode_latency_us = timing.submitted.elapsed().as_micros() as u64,
+                write_index = output.write_index(),
+                "frame timing"
+            );
         }
     }
 
     // Unlock pixel buffer
     ffi::CVPixelBufferUnlockBaseAddress(imageBuffer, ffi::kCVPixelBufferLock_ReadOnly);
 
-    // Write dimensions to header
-    let width_ptr = shm.add(8) as *mut u32;
-    let height_ptr = shm.add(12) as *mut u32;
-    std::ptr::write_volatile(width_ptr, width as u32);
-    std::ptr::write_volatile(height_ptr, height as u32);
+    trace!(width, height, timestamp_ms, "frame delivered to output");
+}
 
-    // Increment write_index (atomic, Release ordering) — signals reader that a new frame is ready
-    (*write_index_ptr).fetch_add(1, Ordering::Release);
+/// Ask VideoToolbox to deinterlace an interlaced stream's fields into whole
+/// frames. Decoders that can't hand out the fields weaved instead, which
+/// are still full height, so a failure is only logged.
+fn request_deinterlacing(session: ffi::VTDecompressionSessionRef) {
+    let status = unsafe {
+        ffi::VTSessionSetProperty(
+            session,
+            ffi::kVTDecompressionPropertyKey_FieldMode,
+            ffi::kVTDecompressionProperty_FieldMode_DeinterlaceFields,
+        )
+    };
+    if status == 0 {
+        warn!("stream is interlaced, VideoToolbox will deinterlace it");
+    } else {
+        warn!(
+            status,
+            "stream is interlaced and can't be deinterlaced, frames will show both fields weaved"
+        );
+    }
+}
+
+/// Warn if the first decoded frame isn't the size the SPS declared, which
+/// points at the wrong parameter sets or a misbehaving decoder.
+fn check_first_frame_size(sps_info: Option<SpsInfo>, width: usize, height: usize) {
+    let Some(info) = sps_info else { return };
+    if info.interlaced && info.matches_decoded_size(width as u32, height as u32 * 2) {
+        warn!(
+            width,
+            height,
+            sps_height = info.height,
+            "decoder output single fields of an interlaced stream"
+        );
+    } else if !info.matches_decoded_size(width as u32, height as u32) {
+        warn!(
+            width,
+            height,
+            sps_width = info.width,
+            sps_height = info.height,
+            "decoded frame size doesn't match the SPS"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caps::{ReaderCaps, READER_CAPS_SIZE};
+    use crate::output::OutputFormat;
+
+    #[test]
+    fn test_commits_every_nth_frame() {
+        let (output, _rx) = ChannelOutput::new(1);
+        let ctx = CallbackContext {
+            output: Mutex::new(Box::new(output)),
+            first_frame: OnceLock::new(),
+            sar: (1, 1),
+            sps_info: None,
+            commit_every_n: AtomicU32::new(6),
+            crc_frames: AtomicBool::new(false),
+            decoded: AtomicU64::new(0),
+            frames_dropped_by_vt: AtomicU64::new(0),
+            paused: AtomicBool::new(false),
+        };
+        // One second of a 30 fps stream
+        let committed: Vec<usize> = (0..30).filter(|_| ctx.next_frame_commits()).collect();
+        assert_eq!(committed, [0, 6, 12, 18, 24]);
 
-    trace!(width, height, frame_size, slot, "copied frame to shm");
+        // 0 means every frame, like 1
+        ctx.commit_every_n.store(0, Ordering::Relaxed);
+        assert!((0..5).all(|_| ctx.next_frame_commits()));
+
+        // Paused frames are counted but none is committed
+        ctx.paused.store(true, Ordering::Relaxed);
+        assert!((0..5).all(|_| !ctx.next_frame_commits()));
+        ctx.paused.store(false, Ordering::Relaxed);
+        assert!(ctx.next_frame_commits());
+        assert_eq!(ctx.decoded.load(Ordering::Relaxed), 41);
+    }
+
+    #[test]
+    fn test_frame_duration() {
+        let seconds = |t: ffi::CMTime| t.value as f64 / t.timescale as f64;
+        assert_eq!(seconds(frame_duration(60.0)), 1.0 / 60.0);
+        assert_eq!(seconds(frame_duration(25.0)), 0.04);
+        let ntsc = frame_duration(29.97);
+        assert_eq!((ntsc.value, ntsc.timescale), (1000, 29970));
+    }
+
+    #[test]
+    fn test_scaled_size() {
+        assert_eq!(scaled_size((1920, 1080), (1280, 720)), Some((1280, 720)));
+        // Aspect kept; the tighter dimension wins
+        assert_eq!(scaled_size((1920, 1080), (1280, 1024)), Some((1280, 720)));
+        assert_eq!(scaled_size((1440, 1080), (1280, 720)), Some((960, 720)));
+        // Rounded down to even
+        assert_eq!(scaled_size((1918, 1078), (640, 640)), Some((640, 358)));
+        assert_eq!(scaled_size((1280, 720), (1280, 720)), None);
+        assert_eq!(scaled_size((640, 360), (1280, 720)), None);
+    }
+
+    #[test]
+    fn test_p010_only_requested_for_outputs_that_take_it() {
+        let nv12 = ffi::kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange;
+        let p010 = ffi::kCVPixelFormatType_420YpCbCr10BiPlanarVideoRange;
+        let mut caps_region = [0u32; READER_CAPS_SIZE / 4];
+        let caps_base = caps_region.as_mut_ptr() as *mut u8;
+        let caps = || unsafe { ReaderCaps::new(caps_base) };
+        caps().set_formats(OutputFormat::P010.caps_bit());
+
+        // Nothing is written, so the outputs need no mapping. An NV12
+        // ring's slots can't hold a full-size P010 frame
+        let output = ShmOutput::new(std::ptr::null_mut(), FRAME_SHM_SIZE);
+        assert_eq!(output_pixel_format(10, &output.with_reader_caps(caps())), nv12);
+        let len = FrameLayout::new(OutputFormat::P010).shm_size();
+        let output = ShmOutput::with_format(std::ptr::null_mut(), len, OutputFormat::P010);
+        let output = output.with_reader_caps(caps());
+        assert_eq!(output_pixel_format(10, &output), p010);
+        assert_eq!(output_pixel_format(8, &output), nv12);
+
+        // Nor unless the reader said it reads the format written
+        let output = output.with_p010_low_bits(true);
+        assert_eq!(output_pixel_format(10, &output), nv12);
+        caps().set_formats(0);
+        let output = ShmOutput::with_format(std::ptr::null_mut(), len, OutputFormat::P010);
+        assert_eq!(output_pixel_format(10, &output.with_reader_caps(caps())), nv12);
+
+        let (output, _rx) = ChannelOutput::new(1);
+        assert_eq!(output_pixel_format(10, &output), p010);
+    }
+
+    #[test]
+    fn test_rejects_missing_parameter_sets() {
+        let (output, _rx) = ChannelOutput::new(1);
+        let Err(err) = H264Decoder::with_output(&[], &[vec![0x68, 0xCE]], 4, Box::new(output))
+        else {
+            panic!("decoder created without an SPS");
+        };
+        // Caught before CoreMedia sees them, so there's no OSStatus
+        assert_eq!(err.status, None);
+        assert_eq!(err.to_string(), "need at least one SPS and one PPS, got 0 SPS and 1 PPS");
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_reports_status_for_bad_parameter_sets() {
+        let stream = crate::test_vectors::tiny_stream();
+        // Profile and level, then nothing CoreMedia can parse
+        let sps = vec![0x67, 0x42, 0x00, 0x1E];
+        let (output, _rx) = ChannelOutput::new(1);
+        let Err(err) =
+            H264Decoder::with_output(&[sps], &[stream.pps.to_vec()], 4, Box::new(output))
+        else {
+            panic!("decoder created from a truncated SPS");
+        };
+        assert!(matches!(err.status, Some(status) if status != 0), "{err:?}");
+        assert!(err.context.starts_with("failed to create format description"));
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_decodes_into_pixel_buffer_pool() {
+        let stream = crate::test_vectors::tiny_stream();
+        unsafe {
+            let attrs = create_destination_attributes(
+                ffi::kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange,
+                Some((stream.width, stream.height)),
+            );
+            let mut pool: ffi::CVPixelBufferPoolRef = std::ptr::null_mut();
+            let status = ffi::CVPixelBufferPoolCreate(
+                ffi::kCFAllocatorDefault,
+                std::ptr::null(),
+                attrs,
+                &mut pool,
+            );
+            ffi::CFRelease(attrs);
+            assert_eq!(status, ffi::kCVReturnSuccess);
+
+            // Keep every buffer so the pool has to vend a new one each frame
+            let buffers = std::sync::Arc::new(Mutex::new(Vec::new()));
+            let held = buffers.clone();
+            let mut decoder = H264Decoder::with_pixel_buffer_pool(
+                &[stream.sps.to_vec()],
+                &[stream.pps.to_vec()],
+                stream.nalu_length_size,
+                pool,
+                move |buffer, _| {
+                    ffi::CFRetain(buffer as *const c_void);
+                    held.lock().unwrap().push(buffer as usize);
+                },
+            )
+            .unwrap();
+            for frame in &stream.frames {
+                decoder.decode_avcc(frame.avcc, frame.timestamp_ms).unwrap();
+            }
+            decoder.flush().unwrap();
+            drop(decoder);
+
+            let buffers = buffers.lock().unwrap();
+            assert_eq!(buffers.len(), stream.frames.len());
+            let mut surface_ids = Vec::new();
+            for &buffer in buffers.iter() {
+                let buffer = buffer as ffi::CVPixelBufferRef;
+                assert_eq!(ffi::CVPixelBufferGetWidth(buffer), stream.width as usize);
+                assert_eq!(ffi::CVPixelBufferGetHeight(buffer), stream.height as usize);
+                surface_ids.push(ffi::IOSurfaceGetID(ffi::CVPixelBufferGetIOSurface(buffer)));
+                ffi::CFRelease(buffer as *const c_void);
+            }
+            // One distinct pool buffer out per frame
+            surface_ids.sort_unstable();
+            surface_ids.dedup();
+            assert_eq!(surface_ids.len(), stream.frames.len());
+            ffi::CFRelease(pool as *const c_void);
+        }
+    }
 }
@@ -1,4 +1,5 @@
-//! Raw FFI bindings to Apple frameworks: CoreMedia, VideoToolbox, CoreVideo, IOSurface.
+//! Raw FFI bindings to Apple frameworks: CoreMedia, VideoToolbox, CoreVideo, IOSurface,
+//! and AudioToolbox with the `audio-level` feature.
 //!
 //! These are stable C APIs — we bind them directly rather than going through objc2.
 
@@ -27,6 +28,7 @@ pub type VTDecompressionOutputCallbackRecord = DecompressionOutputCallbackRecord
 
 pub type CVPixelBufferRef = *mut c_void;
 pub type CVImageBufferRef = CVPixelBufferRef;
+pub type CVPixelBufferPoolRef = *mut c_void;
 
 pub type IOSurfaceRef = *mut c_void;
 pub type IOSurfaceID = u32;
@@ -83,6 +85,10 @@ pub type VTDecompressionOutputCallback = unsafe extern "C" fn(
     presentationDuration: CMTime,
 );
 
+/// VTDecodeInfoFlags bit set in `infoFlags` when the frame was dropped and
+/// no image buffer was produced.
+pub const kVTDecodeInfo_FrameDropped: u32 = 1 << 1;
+
 #[repr(C)]
 pub struct DecompressionOutputCallbackRecord {
     pub decompressionOutputCallback: VTDecompressionOutputCallback,
@@ -199,6 +205,16 @@ extern "C" {
 
     pub fn VTDecompressionSessionInvalidate(session: VTDecompressionSessionRef);
 
+    pub fn VTSessionSetProperty(
+        session: VTDecompressionSessionRef,
+        propertyKey: CFStringRef,
+        propertyValue: CFTypeRef,
+    ) -> OSStatus;
+
+    // Field handling for interlaced content
+    pub static kVTDecompressionPropertyKey_FieldMode: CFStringRef;
+    pub static kVTDecompressionProperty_FieldMode_DeinterlaceFields: CFStringRef;
+
     // Pixel buffer attributes keys
     pub static kCVPixelBufferPixelFormatTypeKey: CFStringRef;
     pub static kCVPixelBufferIOSurfacePropertiesKey: CFStringRef;
@@ -210,6 +226,8 @@ extern "C" {
 
 /// kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange = '420v' = 0x34323076
 pub const kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange: u32 = 0x34323076;
+/// kCVPixelFormatType_420YpCbCr10BiPlanarVideoRange = 'x420' = 0x78343230 (P010)
+pub const kCVPixelFormatType_420YpCbCr10BiPlanarVideoRange: u32 = 0x78343230;
 
 pub type CVReturn = i32;
 pub const kCVReturnSuccess: CVReturn = 0;
@@ -218,9 +236,18 @@ pub const kCVReturnSuccess: CVReturn = 0;
 pub const kCVPixelBufferLock_ReadOnly: u64 = 0x00000001;
 
 extern "C" {
+    pub fn CVPixelBufferCreate(
+        allocator: CFAllocatorRef,
+        width: usize,
+        height: usize,
+        pixelFormatType: u32,
+        pixelBufferAttributes: CFDictionaryRef,
+        pixelBufferOut: *mut CVPixelBufferRef,
+    ) -> CVReturn;
     pub fn CVPixelBufferGetIOSurface(pixelBuffer: CVPixelBufferRef) -> IOSurfaceRef;
     pub fn CVPixelBufferGetWidth(pixelBuffer: CVPixelBufferRef) -> usize;
     pub fn CVPixelBufferGetHeight(pixelBuffer: CVPixelBufferRef) -> usize;
+    pub fn CVPixelBufferGetPixelFormatType(pixelBuffer: CVPixelBufferRef) -> u32;
     pub fn CVPixelBufferLockBaseAddress(
         pixelBuffer: CVPixelBufferRef,
         lockFlags: u64,
@@ -241,6 +268,20 @@ extern "C" {
         pixelBuffer: CVPixelBufferRef,
         planeIndex: usize,
     ) -> usize;
+
+    pub fn CVPixelBufferPoolCreate(
+        allocator: CFAllocatorRef,
+        poolAttributes: CFDictionaryRef,
+        pixelBufferAttributes: CFDictionaryRef,
+        poolOut: *mut CVPixelBufferPoolRef,
+    ) -> CVReturn;
+    pub fn CVPixelBufferPoolCreatePixelBuffer(
+        allocator: CFAllocatorRef,
+        pixelBufferPool: CVPixelBufferPoolRef,
+        pixelBufferOut: *mut CVPixelBufferRef,
+    ) -> CVReturn;
+    /// Follows the Get rule: the dictionary is owned by the pool.
+    pub fn CVPixelBufferPoolGetPixelBufferAttributes(pool: CVPixelBufferPoolRef) -> CFDictionaryRef;
 }
 
 // ── IOSurface ──
@@ -250,6 +291,89 @@ extern "C" {
     pub fn IOSurfaceLookup(csid: IOSurfaceID) -> IOSurfaceRef;
 }
 
+// ── AudioToolbox ──
+
+#[cfg(feature = "audio-level")]
+pub type AudioConverterRef = *mut c_void;
+
+#[cfg(feature = "audio-level")]
+pub const kAudioFormatMPEG4AAC: u32 = u32::from_be_bytes(*b"aac ");
+#[cfg(feature = "audio-level")]
+pub const kAudioFormatLinearPCM: u32 = u32::from_be_bytes(*b"lpcm");
+#[cfg(feature = "audio-level")]
+pub const kAudioFormatFlagIsFloat: u32 = 1 << 0;
+#[cfg(feature = "audio-level")]
+pub const kAudioFormatFlagIsPacked: u32 = 1 << 3;
+
+#[cfg(feature = "audio-level")]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AudioStreamBasicDescription {
+    pub mSampleRate: f64,
+    pub mFormatID: u32,
+    pub mFormatFlags: u32,
+    pub mBytesPerPacket: u32,
+    pub mFramesPerPacket: u32,
+    pub mBytesPerFrame: u32,
+    pub mChannelsPerFrame: u32,
+    pub mBitsPerChannel: u32,
+    pub mReserved: u32,
+}
+
+#[cfg(feature = "audio-level")]
+#[repr(C)]
+pub struct AudioBuffer {
+    pub mNumberChannels: u32,
+    pub mDataByteSize: u32,
+    pub mData: *mut c_void,
+}
+
+#[cfg(feature = "audio-level")]
+#[repr(C)]
+pub struct AudioBufferList {
+    pub mNumberBuffers: u32,
+    pub mBuffers: [AudioBuffer; 1],
+}
+
+#[cfg(feature = "audio-level")]
+#[repr(C)]
+pub struct AudioStreamPacketDescription {
+    pub mStartOffset: i64,
+    pub mVariableFramesInPacket: u32,
+    pub mDataByteSize: u32,
+}
+
+#[cfg(feature = "audio-level")]
+pub type AudioConverterComplexInputDataProc = unsafe extern "C" fn(
+    inAudioConverter: AudioConverterRef,
+    ioNumberDataPackets: *mut u32,
+    ioData: *mut AudioBufferList,
+    outDataPacketDescription: *mut *mut AudioStreamPacketDescription,
+    inUserData: *mut c_void,
+) -> OSStatus;
+
+#[cfg(feature = "audio-level")]
+extern "C" {
+    pub fn AudioConverterNew(
+        inSourceFormat: *const AudioStreamBasicDescription,
+        inDestinationFormat: *const AudioStreamBasicDescription,
+        outAudioConverter: *mut AudioConverterRef,
+    ) -> OSStatus;
+
+    pub fn AudioConverterDispose(inAudioConverter: AudioConverterRef) -> OSStatus;
+
+    pub fn AudioConverterReset(inAudioConverter: AudioConverterRef) -> OSStatus;
+
+    pub fn AudioConverterFillComplexBuffer(
+        inAudioConverter: AudioConverterRef,
+        inInputDataProc: AudioConverterComplexInputDataProc,
+        inInputDataProcUserData: *mut c_void,
+        ioOutputDataPacketSize: *mut u32,
+        outOutputData: *mut AudioBufferList,
+        outPacketDescription: *mut AudioStreamPacketDescription,
+    ) -> OSStatus;
+}
+
 // ── Link directives ──
 
 #[link(name = "CoreFoundation", kind = "framework")]
@@ -266,3 +390,7 @@ extern "C" {}
 
 #[link(name = "IOSurface", kind = "framework")]
 extern "C" {}
+
+#[cfg(feature = "audio-level")]
+#[link(name = "AudioToolbox", kind = "framework")]
+extern "C" {}
@@ -3,6 +3,43 @@ use std::ffi::c_void;
 use tracing::debug;
 
 use crate::ffi;
+use crate::nal::{NAL_TYPE_PPS, NAL_TYPE_SPS};
+
+/// An SPS holds at least its NAL header, profile, constraint flags and level.
+const MIN_SPS_LEN: usize = 4;
+
+/// Check the parameter sets are usable before handing them to CoreMedia,
+/// whose `CMVideoFormatDescriptionCreateFromH264ParameterSets` only reports
+/// a bare OSStatus for malformed ones.
+pub fn check_parameter_sets(sps_list: &[Vec<u8>], pps_list: &[Vec<u8>]) -> Result<(), String> {
+    if sps_list.is_empty() || pps_list.is_empty() {
+        return Err(format!(
+            "need at least one SPS and one PPS, got {} SPS and {} PPS",
+            sps_list.len(),
+            pps_list.len()
+        ));
+    }
+    for (i, sps) in sps_list.iter().enumerate() {
+        if sps.len() < MIN_SPS_LEN {
+            return Err(format!(
+                "SPS {i} is {} bytes, too short to hold a profile and level",
+                sps.len()
+            ));
+        }
+        if sps[0] & 0x1F != NAL_TYPE_SPS {
+            return Err(format!("SPS {i} has NAL type {}", sps[0] & 0x1F));
+        }
+    }
+    for (i, pps) in pps_list.iter().enumerate() {
+        if pps.len() < 2 {
+            return Err(format!("PPS {i} is {} bytes, too short to be a PPS", pps.len()));
+        }
+        if pps[0] & 0x1F != NAL_TYPE_PPS {
+            return Err(format!("PPS {i} has NAL type {}", pps[0] & 0x1F));
+        }
+    }
+    Ok(())
+}
 
 /// Wraps a CMVideoFormatDescription created from H.264 SPS/PPS parameter sets.
 pub struct FormatDescription {
@@ -67,3 +104,21 @@ impl Drop for FormatDescription {
 // SAFETY: CMVideoFormatDescription is a CF type that is thread-safe for read access.
 unsafe impl Send for FormatDescription {}
 unsafe impl Sync for FormatDescription {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_parameter_sets() {
+        let sps = [vec![0x67, 0x42, 0xC0, 0x1F, 0xDA]];
+        let pps = [vec![0x68, 0xCE]];
+        assert!(check_parameter_sets(&sps, &pps).is_ok());
+
+        let err = check_parameter_sets(&[vec![0x67, 0x42, 0xC0]], &pps).unwrap_err();
+        assert!(err.contains("too short"), "{err}");
+        // Swapped parameter sets
+        assert!(check_parameter_sets(&pps, &sps).is_err());
+        assert!(check_parameter_sets(&sps, &[]).is_err());
+    }
+}
@@ -1,9 +1,33 @@
+#[cfg(feature = "audio-level")]
+pub mod audio;
+pub mod caps;
+pub mod cursors;
 pub mod decoder;
 pub mod format;
+pub mod nal;
+pub mod output;
+pub mod reader;
+pub mod sps;
 pub mod surface_pool;
+pub mod test_vectors;
 
 mod ffi;
 
-pub use decoder::{H264Decoder, FRAME_HEADER_SIZE, FRAME_SHM_SIZE, MAX_FRAME_SIZE, MAX_HEIGHT, MAX_WIDTH};
-pub use format::FormatDescription;
+pub use caps::{ReaderCaps, READER_CAPS_NAME, READER_CAPS_SIZE};
+pub use cursors::{ReaderCursor, ReaderRegistry, MAX_READERS, READER_REGISTRY_SIZE};
+pub use decoder::{
+    ColorHeader, DecoderError, DecoderOptions, FrameHeader, H264Decoder, SlotHeader,
+    BENIGN_DECODE_ERRORS, COLOR_HEADER_SIZE, FRAME_COLOR_OFFSET, FRAME_HEADER_SIZE,
+    FRAME_INTERLACED_OFFSET, FRAME_LAYOUT_VERSION, FRAME_MAGIC, FRAME_MAGIC_OFFSET,
+    FRAME_SHM_SIZE, FRAME_SLOTS_OFFSET, FRAME_VERSION_OFFSET, MAX_FRAME_SIZE, MAX_HEIGHT,
+    MAX_WIDTH, SLOT_HEADER_SIZE,
+};
+pub use format::{check_parameter_sets, FormatDescription};
+pub use nal::{avcc_to_annexb_inplace, AvccError, AvccNalIter};
+pub use output::{
+    ChannelOutput, CommitPolicy, DecodedFrame, Frame, FrameLayout, FrameOutput, FrameProcessor,
+    OutputFormat, PixelBufferPoolOutput, ShmOutput, COMMIT_MAX_WAIT,
+};
+pub use reader::FrameReader;
+pub use sps::{parse_sps, SpsInfo};
 pub use surface_pool::SurfaceRing;
@@ -0,0 +1,248 @@
+//! Helpers for walking H.264 NAL units in AVCC (length-prefixed) form.
+
+/// NAL unit types (ITU-T H.264 Table 7-1) used across the pipeline.
+pub const NAL_TYPE_SLICE: u8 = 1;
+pub const NAL_TYPE_IDR: u8 = 5;
+pub const NAL_TYPE_SEI: u8 = 6;
+pub const NAL_TYPE_SPS: u8 = 7;
+pub const NAL_TYPE_PPS: u8 = 8;
+pub const NAL_TYPE_AUD: u8 = 9;
+pub const NAL_TYPE_END_OF_SEQUENCE: u8 = 10;
+
+/// Why [`AvccNalIter`] stopped before the end of a payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AvccError {
+    /// `nalu_length_size` is not 1, 2 or 4.
+    InvalidLengthSize(u8),
+    /// The length prefix at `offset` itself is cut off.
+    TruncatedLength { offset: usize },
+    /// The NAL at `offset` declares more bytes than remain in the payload.
+    TruncatedNal {
+        offset: usize,
+        declared: usize,
+        available: usize,
+    },
+    /// The length prefix at `offset` is zero, as when a payload with wider
+    /// prefixes is read with narrower ones.
+    EmptyNal { offset: usize },
+}
+
+impl std::fmt::Display for AvccError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AvccError::InvalidLengthSize(size) => write!(f, "invalid NAL length size {size}"),
+            AvccError::TruncatedLength { offset } => {
+                write!(f, "truncated NAL length prefix at offset {offset}")
+            }
+            AvccError::TruncatedNal {
+                offset,
+                declared,
+                available,
+            } => write!(
+                f,
+                "NAL at offset {offset} declares {declared} bytes but only {available} remain"
+            ),
+            AvccError::EmptyNal { offset } => write!(f, "zero-length NAL at offset {offset}"),
+        }
+    }
+}
+
+/// Iterator over the NAL units of an AVCC payload
+/// (`[len][NAL1][len][NAL2]...`, `len` being `nalu_length_size` bytes big-endian).
+///
+/// Yields `(nal_type, unit)` where `unit` is the NAL body including its
+/// header byte but without the length prefix. Iteration stops at the first
+/// truncated or empty unit; check [`AvccNalIter::error`] afterwards to tell
+/// a clean end from a malformed payload.
+pub struct AvccNalIter<'a> {
+    data: &'a [u8],
+    length_size: usize,
+    pos: usize,
+    error: Option<AvccError>,
+}
+
+impl<'a> AvccNalIter<'a> {
+    /// `nalu_length_size` must be 1, 2 or 4; anything else yields nothing
+    /// and reports `AvccError::InvalidLengthSize` for a non-empty payload.
+    pub fn new(data: &'a [u8], nalu_length_size: u8) -> Self {
+        let valid = matches!(nalu_length_size, 1 | 2 | 4);
+        Self {
+            data,
+            length_size: nalu_length_size as usize,
+            pos: if valid { 0 } else { data.len() },
+            error: (!valid && !data.is_empty())
+                .then_some(AvccError::InvalidLengthSize(nalu_length_size)),
+        }
+    }
+
+    /// Why iteration stopped early, if the payload didn't split cleanly
+    /// into non-empty NAL units.
+    pub fn error(&self) -> Option<&AvccError> {
+        self.error.as_ref()
+    }
+
+    /// True if iteration stopped before the end of the payload; see `error`.
+    pub fn is_truncated(&self) -> bool {
+        self.error.is_some()
+    }
+
+    fn fail(&mut self, error: AvccError) -> Option<(u8, &'a [u8])> {
+        self.error = Some(error);
+        self.pos = self.data.len();
+        None
+    }
+}
+
+impl<'a> Iterator for AvccNalIter<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+
+        let offset = self.pos;
+        let start = offset + self.length_size;
+        if start > self.data.len() {
+            return self.fail(AvccError::TruncatedLength { offset });
+        }
+        let len = self.data[offset..start]
+            .iter()
+            .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+        if len == 0 {
+            return self.fail(AvccError::EmptyNal { offset });
+        }
+        let available = self.data.len() - start;
+        if len > available {
+            return self.fail(AvccError::TruncatedNal {
+                offset,
+                declared: len,
+                available,
+            });
+        }
+
+        let unit = &self.data[start..start + len];
+        self.pos = start + len;
+        Some((unit[0] & 0x1F, unit))
+    }
+}
+
+/// Start code that precedes each NAL unit in an Annex-B byte stream.
+pub const ANNEXB_START_CODE: [u8; 4] = [0x00, 0x00, 0x00, 0x01];
+
+/// Convert an AVCC payload to Annex-B by replacing each length prefix with
+/// `ANNEXB_START_CODE`.
+///
+/// 4-byte prefixes are the same size as the start code, so they are
+/// rewritten in place without allocating. Shorter prefixes don't leave room
+/// for one, and the payload is rebuilt in a copy instead. A truncated
+/// payload is an error and leaves `buf` unchanged.
+pub fn avcc_to_annexb_inplace(buf: &mut Vec<u8>, nalu_length_size: u8) -> Result<(), String> {
+    let mut units = AvccNalIter::new(buf, nalu_length_size);
+    let count = units.by_ref().count();
+    if units.is_truncated() {
+        return Err(format!(
+            "truncated AVCC payload ({} bytes, {nalu_length_size}-byte lengths)",
+            buf.len()
+        ));
+    }
+
+    if nalu_length_size != 4 {
+        let annexb = AvccNalIter::new(buf, nalu_length_size).fold(
+            Vec::with_capacity(buf.len() + count * ANNEXB_START_CODE.len()),
+            |mut out, (_, unit)| {
+                out.extend_from_slice(&ANNEXB_START_CODE);
+                out.extend_from_slice(unit);
+                out
+            },
+        );
+        *buf = annexb;
+        return Ok(());
+    }
+
+    let mut pos = 0;
+    while pos < buf.len() {
+        let len = u32::from_be_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]]);
+        buf[pos..pos + 4].copy_from_slice(&ANNEXB_START_CODE);
+        pos += 4 + len as usize;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iterates_units_with_types() {
+        let payload: &[u8] = &[
+            0x00, 0x00, 0x00, 0x05, 0x65, 0x88, 0x80, 0x40, 0x00, 0x00, 0x00, 0x00, 0x03, 0x06,
+            0x05, 0x00,
+        ];
+        let mut iter = AvccNalIter::new(payload, 4);
+        assert_eq!(
+            iter.next(),
+            Some((NAL_TYPE_IDR, &[0x65, 0x88, 0x80, 0x40, 0x00][..]))
+        );
+        assert_eq!(iter.next(), Some((NAL_TYPE_SEI, &[0x06, 0x05, 0x00][..])));
+        assert_eq!(iter.next(), None);
+        assert!(!iter.is_truncated());
+    }
+
+    #[test]
+    fn test_stops_on_truncation() {
+        let payload: &[u8] = &[0x00, 0x01, 0x09, 0x00, 0x05, 0x65, 0x88];
+        let mut iter = AvccNalIter::new(payload, 2);
+        assert_eq!(iter.next(), Some((NAL_TYPE_AUD, &[0x09][..])));
+        assert_eq!(iter.next(), None);
+        assert!(iter.is_truncated());
+        assert_eq!(
+            iter.error(),
+            Some(&AvccError::TruncatedNal {
+                offset: 3,
+                declared: 5,
+                available: 2
+            })
+        );
+
+        let payload: &[u8] = &[0x00, 0x00, 0x00, 0x01, 0x65, 0x00, 0x00];
+        let mut iter = AvccNalIter::new(payload, 4);
+        assert_eq!(iter.by_ref().count(), 1);
+        assert_eq!(iter.error(), Some(&AvccError::TruncatedLength { offset: 5 }));
+
+        // Zero lengths and unsupported prefix widths stop iteration too
+        let mut iter = AvccNalIter::new(&[0x00, 0x00, 0x00, 0x00], 2);
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.error(), Some(&AvccError::EmptyNal { offset: 0 }));
+        let iter = AvccNalIter::new(&[0x00, 0x01, 0x09], 3);
+        assert_eq!(iter.error(), Some(&AvccError::InvalidLengthSize(3)));
+    }
+
+    #[test]
+    fn test_avcc_to_annexb_in_place() {
+        let mut payload = vec![
+            0x00, 0x00, 0x00, 0x05, 0x65, 0x88, 0x80, 0x40, 0x00, 0x00, 0x00, 0x00, 0x03, 0x06,
+            0x05, 0x00,
+        ];
+        let capacity = payload.capacity();
+        avcc_to_annexb_inplace(&mut payload, 4).unwrap();
+        assert_eq!(
+            payload,
+            [
+                0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x80, 0x40, 0x00, 0x00, 0x00, 0x00, 0x01, 0x06,
+                0x05, 0x00,
+            ]
+        );
+        assert_eq!(payload.capacity(), capacity);
+
+        // Shorter prefixes need a copy
+        let mut payload = vec![0x00, 0x01, 0x09, 0x00, 0x02, 0x65, 0x88];
+        avcc_to_annexb_inplace(&mut payload, 2).unwrap();
+        assert_eq!(payload, [0, 0, 0, 1, 0x09, 0, 0, 0, 1, 0x65, 0x88]);
+
+        // Truncated payloads are left alone
+        let mut payload = vec![0x00, 0x00, 0x00, 0x05, 0x65];
+        assert!(avcc_to_annexb_inplace(&mut payload, 4).is_err());
+        assert_eq!(payload, [0x00, 0x00, 0x00, 0x05, 0x65]);
+    }
+}
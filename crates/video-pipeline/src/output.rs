@@ -0,0 +1,1219 @@
+use std::ffi::c_void;
+use std::sync::atomic::{fence, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, trace, warn};
+
+use crate::caps::ReaderCaps;
+use crate::cursors::ReaderRegistry;
+use crate::decoder::{FrameHeader, FRAME_HEADER_SIZE, MAX_HEIGHT, MAX_WIDTH};
+use crate::ffi;
+use crate::sps::display_size;
+
+/// A decoded NV12 or P010 picture, borrowed from a locked CVPixelBuffer.
+///
+/// Planes may carry row padding: each row is `*_stride` bytes, of which
+/// the first `width * bytes_per_sample` bytes are pixel data.
+pub struct DecodedFrame<'a> {
+    pub width: usize,
+    pub height: usize,
+    /// 1 for 8-bit NV12; 2 for 10-bit P010 (see `OutputFormat::P010`).
+    pub bytes_per_sample: usize,
+    pub y_plane: &'a [u8],
+    pub y_stride: usize,
+    pub uv_plane: &'a [u8],
+    pub uv_stride: usize,
+    pub timestamp_ms: u64,
+    /// Sample aspect ratio from the SPS VUI, (1, 1) for square pixels.
+    pub sar: (u16, u16),
+}
+
+impl DecodedFrame<'_> {
+    /// Size the frame should be shown at once `sar` is applied.
+    pub fn display_size(&self) -> (usize, usize) {
+        let (width, height) = display_size(self.width as u32, self.height as u32, self.sar);
+        (width as usize, height as usize)
+    }
+
+    /// Number of rows in the Y plane.
+    pub fn y_rows(&self) -> usize {
+        self.y_plane.len().checked_div(self.y_stride).unwrap_or(0)
+    }
+
+    /// Number of rows in the interleaved UV plane.
+    pub fn uv_rows(&self) -> usize {
+        self.uv_plane.len().checked_div(self.uv_stride).unwrap_or(0)
+    }
+
+    /// Bytes of pixel data in each row of either plane.
+    pub fn row_bytes(&self) -> usize {
+        self.width * self.bytes_per_sample
+    }
+
+    /// Size of the frame once row padding is stripped.
+    pub fn packed_size(&self) -> usize {
+        self.row_bytes() * (self.y_rows() + self.uv_rows())
+    }
+
+    /// The layout `copy_as` produces when asked for `format`. 10-bit frames
+    /// are only ever copied as P010 or P010Lsb, and 8-bit ones never are.
+    pub fn layout_for(&self, format: OutputFormat) -> OutputFormat {
+        match (self.bytes_per_sample, format) {
+            (2, OutputFormat::P010Lsb) => OutputFormat::P010Lsb,
+            (2, _) => OutputFormat::P010,
+            (_, OutputFormat::P010 | OutputFormat::P010Lsb) => OutputFormat::Nv12,
+            (_, format) => format,
+        }
+    }
+
+    /// Copy the frame into `dst` in `layout_for(format)`, with row padding
+    /// stripped. `dst` must be at least `packed_size()` bytes.
+    pub fn copy_as(&self, format: OutputFormat, dst: &mut [u8]) {
+        match self.layout_for(format) {
+            OutputFormat::Nv12 | OutputFormat::P010 => self.copy_packed(dst),
+            OutputFormat::I420 => self.copy_planar(dst),
+            OutputFormat::P010Lsb => {
+                self.copy_packed(dst);
+                shift_to_low_bits(&mut dst[..self.packed_size()]);
+            }
+        }
+    }
+
+    /// Copy the Y plane, then de-interleave UV into separate U and V planes.
+    /// 8-bit frames only. `dst` must be at least `packed_size()` bytes.
+    pub fn copy_planar(&self, dst: &mut [u8]) {
+        let u_offset = self.width * self.y_rows();
+        let chroma_width = self.width / 2;
+        let chroma_size = chroma_width * self.uv_rows();
+        let (y_dst, chroma) = dst.split_at_mut(u_offset);
+        copy_plane(self.y_plane, self.y_stride, self.width, y_dst);
+        if chroma_width == 0 {
+            return;
+        }
+
+        let (u_dst, v_dst) = chroma[..2 * chroma_size].split_at_mut(chroma_size);
+        let rows = self.uv_plane.chunks(self.uv_stride).take(self.uv_rows());
+        let dst_rows = u_dst
+            .chunks_mut(chroma_width)
+            .zip(v_dst.chunks_mut(chroma_width));
+        for (row, (u_row, v_row)) in rows.zip(dst_rows) {
+            for (i, pair) in row[..2 * chroma_width].chunks_exact(2).enumerate() {
+                u_row[i] = pair[0];
+                v_row[i] = pair[1];
+            }
+        }
+    }
+
+    /// Row stride shared by both planes, if they have the same one. Frames
+    /// whose planes differ can only be copied packed.
+    pub fn common_stride(&self) -> Option<usize> {
+        (self.y_stride == self.uv_stride && self.y_stride >= self.row_bytes())
+            .then_some(self.y_stride)
+    }
+
+    /// Size of the frame with its row padding kept, or `None` if the planes
+    /// have different strides.
+    pub fn strided_size(&self) -> Option<usize> {
+        self.common_stride()
+            .map(|stride| stride * (self.y_rows() + self.uv_rows()))
+    }
+
+    /// Copy both planes into `dst` as they are, padding included: one copy
+    /// per plane rather than one per row. `dst` must be at least
+    /// `strided_size()` bytes.
+    pub fn copy_strided(&self, dst: &mut [u8]) {
+        let (y_dst, uv_dst) = dst.split_at_mut(self.y_stride * self.y_rows());
+        y_dst.copy_from_slice(&self.y_plane[..y_dst.len()]);
+        let uv_len = self.uv_stride * self.uv_rows();
+        uv_dst[..uv_len].copy_from_slice(&self.uv_plane[..uv_len]);
+    }
+
+    /// Copy both planes into `dst` with row padding stripped.
+    /// `dst` must be at least `packed_size()` bytes.
+    pub fn copy_packed(&self, dst: &mut [u8]) {
+        let uv_offset = self.row_bytes() * self.y_rows();
+        copy_plane(
+            self.y_plane,
+            self.y_stride,
+            self.row_bytes(),
+            &mut dst[..uv_offset],
+        );
+        copy_plane(
+            self.uv_plane,
+            self.uv_stride,
+            self.row_bytes(),
+            &mut dst[uv_offset..],
+        );
+    }
+
+    /// CRC-32 (IEEE) of the Y plane's pixel data, row padding excluded, so
+    /// identical pictures match whatever stride the decoder picked.
+    pub fn y_crc32(&self) -> u32 {
+        let mut crc = !0u32;
+        for row in self.y_plane.chunks(self.y_stride.max(1)) {
+            crc = crc32_update(crc, &row[..self.row_bytes().min(row.len())]);
+        }
+        !crc
+    }
+}
+
+/// Fold `data` into a running CRC-32 (reflected, polynomial 0xEDB88320).
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+/// Turn P010 samples into P010Lsb ones in place: each little-endian u16
+/// moves its 10-bit value from the high bits to the low bits.
+fn shift_to_low_bits(samples: &mut [u8]) {
+    for sample in samples.chunks_exact_mut(2) {
+        let value = u16::from_le_bytes([sample[0], sample[1]]) >> 6;
+        sample.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn copy_plane(src: &[u8], stride: usize, width: usize, dst: &mut [u8]) {
+    if stride == width {
+        // Fast path: stride matches width, single memcpy
+        let len = src.len().min(dst.len());
+        dst[..len].copy_from_slice(&src[..len]);
+        return;
+    }
+    // Row-by-row copy to strip padding
+    for (src_row, dst_row) in src.chunks(stride).zip(dst.chunks_mut(width)) {
+        let len = width.min(src_row.len()).min(dst_row.len());
+        dst_row[..len].copy_from_slice(&src_row[..len]);
+    }
+}
+
+/// Pixel layout written to the shared memory frame slots.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Y plane followed by interleaved UV (what VideoToolbox produces).
+    #[default]
+    Nv12,
+    /// Y plane followed by separate U and V planes.
+    I420,
+    /// 10-bit NV12: the same planes with 16-bit samples. Used for every
+    /// 10-bit frame, whatever format was asked for, unless it was P010Lsb.
+    ///
+    /// Each sample is a little-endian u16 holding the 10-bit value in bits
+    /// 6-15, with bits 0-5 zero: value 940 (white) is stored as 0xEB00,
+    /// bytes `00 EB`. Reading the high byte alone gives the 8-bit value.
+    P010,
+    /// P010 with each 10-bit value in bits 0-9 instead, bits 10-15 zero:
+    /// 940 is stored as 0x03AC, bytes `AC 03`. For consumers that treat
+    /// samples as plain 10-bit numbers. Only produced for 10-bit frames.
+    P010Lsb,
+}
+
+impl OutputFormat {
+    /// Identifier stored in `FrameHeader::pixel_format`.
+    pub fn fourcc(self) -> [u8; 4] {
+        match self {
+            OutputFormat::Nv12 => *b"NV12",
+            OutputFormat::I420 => *b"I420",
+            OutputFormat::P010 => *b"P010",
+            OutputFormat::P010Lsb => *b"P10L",
+        }
+    }
+
+    /// Bytes per luma or chroma sample.
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            OutputFormat::P010 | OutputFormat::P010Lsb => 2,
+            OutputFormat::Nv12 | OutputFormat::I420 => 1,
+        }
+    }
+
+    /// Average bits per pixel of a packed frame, chroma included.
+    pub const fn bits_per_pixel(self) -> usize {
+        match self {
+            OutputFormat::P010 | OutputFormat::P010Lsb => 24,
+            OutputFormat::Nv12 | OutputFormat::I420 => 12,
+        }
+    }
+
+    /// Bit standing for this format in `ReaderCaps` format masks.
+    pub const fn caps_bit(self) -> u32 {
+        match self {
+            OutputFormat::Nv12 => 1,
+            OutputFormat::I420 => 2,
+            OutputFormat::P010 => 4,
+            OutputFormat::P010Lsb => 8,
+        }
+    }
+
+    /// Inverse of `fourcc`; an all-zero header field means NV12.
+    pub fn from_fourcc(fourcc: [u8; 4]) -> Option<Self> {
+        match &fourcc {
+            b"NV12" | [0, 0, 0, 0] => Some(OutputFormat::Nv12),
+            b"I420" => Some(OutputFormat::I420),
+            b"P010" => Some(OutputFormat::P010),
+            b"P10L" => Some(OutputFormat::P010Lsb),
+            _ => None,
+        }
+    }
+}
+
+/// Sizes of the double-buffered shared memory region for one output format:
+/// each slot holds a `MAX_WIDTH` x `MAX_HEIGHT` frame in that format.
+///
+/// The writer and its readers must agree on the layout; the Camera Extension
+/// only knows `FrameLayout::NV12`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLayout {
+    pub format: OutputFormat,
+    /// Size of each frame slot.
+    pub max_frame_size: usize,
+}
+
+impl FrameLayout {
+    pub const NV12: FrameLayout = FrameLayout::new(OutputFormat::Nv12);
+
+    pub const fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            max_frame_size: MAX_WIDTH * MAX_HEIGHT * format.bits_per_pixel() / 8,
+        }
+    }
+
+    /// Size of the whole region: the header and both slots.
+    pub const fn shm_size(&self) -> usize {
+        FRAME_HEADER_SIZE + 2 * self.max_frame_size
+    }
+
+    /// Offset of `slot`'s frame data from the start of the region.
+    pub const fn slot_offset(&self, slot: usize) -> usize {
+        FRAME_HEADER_SIZE + slot * self.max_frame_size
+    }
+}
+
+impl Default for FrameLayout {
+    fn default() -> Self {
+        Self::NV12
+    }
+}
+
+/// Destination for frames produced by the VT decompression callback.
+///
+/// Called on the VideoToolbox callback thread while the pixel buffer is locked,
+/// so implementations should copy what they need and return quickly.
+pub trait FrameOutput: Send {
+    fn write_frame(&mut self, frame: &DecodedFrame<'_>);
+
+    /// Number of frames committed so far, for outputs that count them.
+    fn write_index(&self) -> Option<u64> {
+        None
+    }
+
+    /// Whether 10-bit frames can be delivered as P010. If not, the decoder
+    /// has VideoToolbox convert them to 8-bit NV12 instead.
+    fn accepts_p010(&self) -> bool {
+        false
+    }
+}
+
+/// Modifies frames in place before `ShmOutput` commits them, e.g. to burn
+/// in a timestamp or watermark.
+///
+/// Runs on the VideoToolbox callback thread, after the frame is copied into
+/// its slot and before readers can see it. Both planes are tightly packed
+/// in the output format: `y` is `width * height` samples and `uv` holds the
+/// chroma (interleaved for NV12 and P010, U then V for I420). P010 samples
+/// are two bytes each, laid out as `OutputFormat` describes.
+pub trait FrameProcessor: Send {
+    fn process(&self, y: &mut [u8], uv: &mut [u8], width: usize, height: usize);
+}
+
+/// Longest `CommitPolicy::BlockBriefly` holds up the decode callback waiting
+/// for a reader, well under a frame interval so VideoToolbox isn't stalled.
+pub const COMMIT_MAX_WAIT: Duration = Duration::from_millis(10);
+
+/// What `ShmOutput` does when the slot it's about to write still holds a
+/// frame a registered reader hasn't consumed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommitPolicy {
+    /// Overwrite it; the reader skips to the newest frame. Lowest latency.
+    #[default]
+    DropOldest,
+    /// Spin for up to `COMMIT_MAX_WAIT` until every registered reader has
+    /// consumed it, then overwrite it regardless. For a single reader that
+    /// needs every frame more than it needs the newest one.
+    BlockBriefly,
+}
+
+/// Writes frames into the double-buffered shared memory region read by
+/// the Camera Extension.
+pub struct ShmOutput {
+    shm_ptr: *mut u8,
+    /// Length of the mapping at `shm_ptr`; writes past it are refused.
+    shm_len: usize,
+    format: OutputFormat,
+    /// Slot sizes for `format`.
+    layout: FrameLayout,
+    /// Keep the decoder's row padding instead of repacking row by row.
+    source_stride: bool,
+    /// Write 10-bit frames as P010Lsb rather than P010.
+    p010_low_bits: bool,
+    processor: Option<Box<dyn FrameProcessor>>,
+    commit_policy: CommitPolicy,
+    /// Consulted under `CommitPolicy::BlockBriefly`.
+    readers: Option<ReaderRegistry>,
+    /// Consulted when a decoder is created on this output.
+    reader_caps: Option<ReaderCaps>,
+}
+
+// SAFETY: shm_ptr points to a memory-mapped region that outlives the decoder.
+unsafe impl Send for ShmOutput {}
+
+impl ShmOutput {
+    /// `shm_ptr` must point to a writable shared memory region of `shm_len`
+    /// bytes, valid for the lifetime of the decoder. Frames that would not fit
+    /// in `shm_len` (anything under `FRAME_SHM_SIZE` can't hold both slots)
+    /// are skipped.
+    pub fn new(shm_ptr: *mut u8, shm_len: usize) -> Self {
+        Self::with_format(shm_ptr, shm_len, OutputFormat::Nv12)
+    }
+
+    /// Like `new`, writing frames in `format` instead of NV12. Slots are laid
+    /// out as `FrameLayout::new(format)`, so `shm_len` should be its
+    /// `shm_size()`.
+    pub fn with_format(shm_ptr: *mut u8, shm_len: usize, format: OutputFormat) -> Self {
+        Self {
+            shm_ptr,
+            shm_len,
+            format,
+            layout: FrameLayout::new(format),
+            source_stride: false,
+            p010_low_bits: false,
+            processor: None,
+            commit_policy: CommitPolicy::DropOldest,
+            readers: None,
+            reader_caps: None,
+        }
+    }
+
+    /// Run `processor` on every frame before it's committed. Frames are
+    /// then always packed, as processors expect.
+    pub fn with_processor(mut self, processor: impl FrameProcessor + 'static) -> Self {
+        self.processor = Some(Box::new(processor));
+        self
+    }
+
+    /// Copy NV12 and P010 frames with their source row stride, one copy per
+    /// plane, and record the stride in the slot header for readers to strip.
+    /// Cheaper than repacking rows when the decoder pads them, and rows keep
+    /// the pixel buffer's alignment, so a GPU consumer can upload a slot
+    /// without re-striding it. Frames whose planes have different strides,
+    /// or that wouldn't fit in a slot with their padding, are still packed.
+    pub fn with_source_stride(mut self, enabled: bool) -> Self {
+        self.source_stride = enabled;
+        self
+    }
+
+    /// Write 10-bit frames as `OutputFormat::P010Lsb`, with each sample's
+    /// value shifted down to the low bits. Slots keep the layout of
+    /// `format`, which has to be P010 or P010Lsb for 10-bit frames to be
+    /// decoded at all. Such frames are always packed.
+    pub fn with_p010_low_bits(mut self, enabled: bool) -> Self {
+        self.p010_low_bits = enabled;
+        self
+    }
+
+    /// The layout `frame` is written in.
+    fn layout_for(&self, frame: &DecodedFrame<'_>) -> OutputFormat {
+        match frame.layout_for(self.format) {
+            OutputFormat::P010 if self.p010_low_bits => OutputFormat::P010Lsb,
+            format => format,
+        }
+    }
+
+    /// Apply `policy` to frames not yet consumed by the readers in `readers`.
+    pub fn with_commit_policy(mut self, policy: CommitPolicy, readers: ReaderRegistry) -> Self {
+        self.commit_policy = policy;
+        self.readers = Some(readers);
+        self
+    }
+
+    /// Follow what the reader advertised in `caps` when a decoder is
+    /// created on this output: frames are scaled down to its maximum size,
+    /// and 10-bit ones are only decoded to P010 if it reads P010.
+    pub fn with_reader_caps(mut self, caps: ReaderCaps) -> Self {
+        self.reader_caps = Some(caps);
+        self
+    }
+
+    /// Maximum frame size the reader advertised, if any.
+    pub(crate) fn reader_max_size(&self) -> Option<(u32, u32)> {
+        self.reader_caps.as_ref()?.max_size()
+    }
+
+    /// Stride to store `frame` with when written as `format`, or `None` to
+    /// pack it.
+    fn stride_for(&self, frame: &DecodedFrame<'_>, format: OutputFormat) -> Option<usize> {
+        // Only NV12 and P010 are copied as they come from the decoder
+        if !self.source_stride
+            || self.processor.is_some()
+            || !matches!(format, OutputFormat::Nv12 | OutputFormat::P010)
+        {
+            return None;
+        }
+        let stride = frame.common_stride()?;
+        let fits = frame.strided_size()? <= self.layout.max_frame_size;
+        // A stride with no padding is just a packed frame
+        (fits && stride != frame.row_bytes()).then_some(stride)
+    }
+}
+
+impl FrameOutput for ShmOutput {
+    fn write_index(&self) -> Option<u64> {
+        if self.shm_len < FRAME_HEADER_SIZE {
+            return None;
+        }
+        let header = self.shm_ptr as *const FrameHeader;
+        Some(unsafe { (*header).write_index.load(Ordering::Acquire) })
+    }
+
+    fn accepts_p010(&self) -> bool {
+        // A full-size P010 frame is twice an NV12 one; only slots laid out
+        // for P010 hold it, and only readers that said so can read it
+        let format = match self.format {
+            OutputFormat::P010 if self.p010_low_bits => OutputFormat::P010Lsb,
+            OutputFormat::P010 | OutputFormat::P010Lsb => self.format,
+            OutputFormat::Nv12 | OutputFormat::I420 => return false,
+        };
+        self.reader_caps
+            .as_ref()
+            .is_some_and(|caps| caps.supports(format))
+    }
+
+    fn write_frame(&mut self, frame: &DecodedFrame<'_>) {
+        // Clamp to max supported resolution
+        if frame.width > MAX_WIDTH || frame.height > MAX_HEIGHT {
+            warn!(
+                width = frame.width,
+                height = frame.height,
+                "frame exceeds max resolution, skipping"
+            );
+            return;
+        }
+
+        // Plane heights come from the pixel buffer and needn't match `height`
+        // (e.g. unusual subsampling); never write past the slot
+        let format = self.layout_for(frame);
+        let stride = self.stride_for(frame, format);
+        let frame_size = stride
+            .and(frame.strided_size())
+            .unwrap_or_else(|| frame.packed_size());
+        if frame_size > self.layout.max_frame_size {
+            warn!(
+                width = frame.width,
+                height = frame.height,
+                y_rows = frame.y_rows(),
+                uv_rows = frame.uv_rows(),
+                frame_size,
+                "frame geometry exceeds frame slot, skipping"
+            );
+            return;
+        }
+
+        let shm = self.shm_ptr;
+        if self.shm_len < FRAME_HEADER_SIZE {
+            warn!(shm_len = self.shm_len, "frame buffer smaller than its header, skipping");
+            return;
+        }
+
+        unsafe {
+            let header = shm as *mut FrameHeader;
+
+            // Determine which double-buffer slot to write to
+            let write_idx = (*header).write_index.load(Ordering::Relaxed);
+            let slot = (write_idx as usize) % 2;
+            // The slot holds the frame committed as `write_idx - 1`
+            if let (CommitPolicy::BlockBriefly, Some(readers), Some(pending)) =
+                (self.commit_policy, &self.readers, write_idx.checked_sub(1))
+            {
+                if pending > 0 && !wait_for_readers(readers, pending, COMMIT_MAX_WAIT) {
+                    debug!(
+                        frame = pending,
+                        slowest = readers.min_cursor(),
+                        "reader still behind, overwriting its next frame"
+                    );
+                }
+            }
+            let frame_offset = self.layout.slot_offset(slot);
+            if frame_offset + frame_size > self.shm_len {
+                warn!(
+                    frame_offset,
+                    frame_size,
+                    shm_len = self.shm_len,
+                    "frame doesn't fit in the frame buffer, skipping"
+                );
+                return;
+            }
+            // Seqlock writer side, see `FrameHeader`: the last commit must
+            // be visible before anything lands in the slot it left behind
+            fence(Ordering::Release);
+            let frame_dst = std::slice::from_raw_parts_mut(shm.add(frame_offset), frame_size);
+
+            match stride {
+                Some(_) => frame.copy_strided(frame_dst),
+                None => frame.copy_as(format, frame_dst),
+            }
+            if let Some(processor) = &self.processor {
+                let (y, uv) = frame_dst.split_at_mut(frame.row_bytes() * frame.y_rows());
+                processor.process(y, uv, frame.width, frame.height);
+            }
+
+            // Describe the frame in its slot's header. Readers take the
+            // dimensions from the slot they copy, so these never apply to
+            // another frame's pixels
+            let slot_header = std::ptr::addr_of_mut!((*header).slots[slot]);
+            let (display_width, display_height) = frame.display_size();
+            std::ptr::addr_of_mut!((*slot_header).width).write_volatile(frame.width as u32);
+            std::ptr::addr_of_mut!((*slot_header).height).write_volatile(frame.height as u32);
+            std::ptr::addr_of_mut!((*slot_header).timestamp_ms).write_volatile(frame.timestamp_ms);
+            std::ptr::addr_of_mut!((*slot_header).pixel_format).write_volatile(format.fourcc());
+            std::ptr::addr_of_mut!((*slot_header).display_width)
+                .write_volatile(display_width as u32);
+            std::ptr::addr_of_mut!((*slot_header).display_height)
+                .write_volatile(display_height as u32);
+            std::ptr::addr_of_mut!((*slot_header).stride)
+                .write_volatile(stride.unwrap_or(0) as u32);
+
+            // Publish the frame
+            (*header).write_index.fetch_add(1, Ordering::Release);
+
+            trace!(
+                width = frame.width,
+                height = frame.height,
+                frame_size,
+                slot,
+                "copied frame to shm"
+            );
+        }
+    }
+}
+
+/// Wait up to `max_wait` for every registered reader to consume frame
+/// `index`, yielding in between. Returns whether they all did.
+fn wait_for_readers(readers: &ReaderRegistry, index: u64, max_wait: Duration) -> bool {
+    let deadline = Instant::now() + max_wait;
+    loop {
+        if readers.min_cursor().is_none_or(|cursor| cursor >= index) {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::yield_now();
+    }
+}
+
+/// An owned, tightly packed frame.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub timestamp_ms: u64,
+    pub format: OutputFormat,
+    /// Size to show the frame at; differs from `width`/`height` for
+    /// anamorphic streams.
+    pub display_width: usize,
+    pub display_height: usize,
+    /// Y plane (`width * height` samples) followed by the chroma planes
+    /// (`width * height / 2` in total), laid out according to `format`.
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    /// Convert to packed 8-bit RGB (`width * height * 3` bytes), assuming
+    /// BT.709 video-range YUV as VideoToolbox produces for HD streams.
+    /// 10-bit frames are reduced to 8 bits first.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        let (width, height) = (self.width, self.height);
+        let luma_size = width * height;
+        let chroma_width = width / 2;
+        let v_offset = luma_size + chroma_width * (height / 2);
+        // Samples by index; the high byte of a P010 sample is its top 8 bits.
+        // Missing samples (a truncated frame) come out as mid-grey
+        let sample = |i: usize| {
+            let byte = match self.format {
+                OutputFormat::P010 => self.data.get(2 * i + 1).copied(),
+                OutputFormat::P010Lsb => self
+                    .data
+                    .get(2 * i..2 * i + 2)
+                    .map(|s| (u16::from_le_bytes([s[0], s[1]]) >> 2) as u8),
+                OutputFormat::Nv12 | OutputFormat::I420 => self.data.get(i).copied(),
+            };
+            byte.unwrap_or(128) as i32
+        };
+
+        let mut rgb = Vec::with_capacity(luma_size * 3);
+        for y in 0..height {
+            for x in 0..width {
+                let (u, v) = match self.format {
+                    OutputFormat::Nv12 | OutputFormat::P010 | OutputFormat::P010Lsb => {
+                        let i = luma_size + (y / 2) * width + (x / 2) * 2;
+                        (sample(i), sample(i + 1))
+                    }
+                    OutputFormat::I420 => {
+                        let i = (y / 2) * chroma_width + x / 2;
+                        (sample(luma_size + i), sample(v_offset + i))
+                    }
+                };
+                let c = 298 * (sample(y * width + x) - 16);
+                let (d, e) = (u - 128, v - 128);
+                rgb.extend([
+                    (c + 459 * e + 128) >> 8,
+                    (c - 55 * d - 136 * e + 128) >> 8,
+                    (c + 541 * d + 128) >> 8,
+                ]
+                .map(|channel| channel.clamp(0, 255) as u8));
+            }
+        }
+        rgb
+    }
+}
+
+/// Sends a copy of each decoded frame on a bounded channel.
+///
+/// Frames are dropped if the receiver falls behind, so a slow consumer
+/// never stalls the decoder.
+pub struct ChannelOutput {
+    tx: SyncSender<Frame>,
+    dropped: u64,
+}
+
+impl ChannelOutput {
+    pub fn new(capacity: usize) -> (Self, Receiver<Frame>) {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        (Self { tx, dropped: 0 }, rx)
+    }
+
+    /// Number of frames dropped because the channel was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl FrameOutput for ChannelOutput {
+    fn write_frame(&mut self, frame: &DecodedFrame<'_>) {
+        let mut data = vec![0u8; frame.packed_size()];
+        frame.copy_packed(&mut data);
+        let (display_width, display_height) = frame.display_size();
+        let frame = Frame {
+            width: frame.width,
+            height: frame.height,
+            timestamp_ms: frame.timestamp_ms,
+            format: frame.layout_for(OutputFormat::Nv12),
+            display_width,
+            display_height,
+            data,
+        };
+        match self.tx.try_send(frame) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                trace!(dropped = self.dropped, "frame channel full, dropping frame");
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                trace!("frame channel receiver gone, dropping frame");
+            }
+        }
+    }
+
+    fn accepts_p010(&self) -> bool {
+        true
+    }
+}
+
+/// Copies each decoded frame into a CVPixelBuffer drawn from a
+/// caller-provided `CVPixelBufferPool`, for consumers that need buffers from
+/// a pool they manage, e.g. to enqueue on an AVSampleBufferDisplayLayer.
+///
+/// Set up by `H264Decoder::with_pixel_buffer_pool`, which has VideoToolbox
+/// decode to the pool's size and format so the copy is row for row. Frames
+/// that don't match the pool's buffers are skipped.
+pub struct PixelBufferPoolOutput {
+    pool: ffi::CVPixelBufferPoolRef,
+    on_frame: Box<dyn FnMut(ffi::CVPixelBufferRef, u64) + Send>,
+    /// Frames skipped because no matching buffer could be had from the pool.
+    skipped: u64,
+}
+
+// SAFETY: CVPixelBufferPool is thread-safe and the pool is retained.
+unsafe impl Send for PixelBufferPoolOutput {}
+
+impl PixelBufferPoolOutput {
+    /// Hand each frame to `on_frame` as a pool buffer along with its
+    /// timestamp in milliseconds. The buffer goes back to the pool when
+    /// `on_frame` returns unless it `CFRetain`s it.
+    ///
+    /// # Safety
+    /// `pool` must be a valid `CVPixelBufferPoolRef`; it is retained for
+    /// the output's lifetime.
+    pub unsafe fn new(
+        pool: ffi::CVPixelBufferPoolRef,
+        on_frame: impl FnMut(ffi::CVPixelBufferRef, u64) + Send + 'static,
+    ) -> Self {
+        ffi::CFRetain(pool as *const c_void);
+        Self {
+            pool,
+            on_frame: Box::new(on_frame),
+            skipped: 0,
+        }
+    }
+
+    /// Number of frames skipped, either because the pool couldn't vend a
+    /// buffer or because its buffers don't match the decoded frames.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    fn skip(&mut self, reason: &str) {
+        self.skipped += 1;
+        if self.skipped.is_power_of_two() {
+            warn!(
+                skipped = self.skipped,
+                reason,
+                "pixel buffer pool output skipping frames"
+            );
+        }
+    }
+
+    /// Copy `frame` into `buffer`, returning false if it doesn't fit.
+    unsafe fn copy_into(frame: &DecodedFrame<'_>, buffer: ffi::CVPixelBufferRef) -> bool {
+        let bytes_per_sample = match ffi::CVPixelBufferGetPixelFormatType(buffer) {
+            ffi::kCVPixelFormatType_420YpCbCr10BiPlanarVideoRange => 2,
+            _ => 1,
+        };
+        if ffi::CVPixelBufferGetWidth(buffer) != frame.width
+            || ffi::CVPixelBufferGetHeight(buffer) != frame.height
+            || bytes_per_sample != frame.bytes_per_sample
+        {
+            return false;
+        }
+        if ffi::CVPixelBufferLockBaseAddress(buffer, 0) != ffi::kCVReturnSuccess {
+            return false;
+        }
+        let planes = [
+            (frame.y_plane, frame.y_stride, frame.y_rows()),
+            (frame.uv_plane, frame.uv_stride, frame.uv_rows()),
+        ];
+        for (index, (src, src_stride, src_rows)) in planes.into_iter().enumerate() {
+            let dst = ffi::CVPixelBufferGetBaseAddressOfPlane(buffer, index) as *mut u8;
+            let dst_stride = ffi::CVPixelBufferGetBytesPerRowOfPlane(buffer, index);
+            let rows = src_rows.min(ffi::CVPixelBufferGetHeightOfPlane(buffer, index));
+            if dst.is_null() {
+                ffi::CVPixelBufferUnlockBaseAddress(buffer, 0);
+                return false;
+            }
+            let row_bytes = frame.row_bytes().min(dst_stride);
+            for row in 0..rows {
+                let src_row = &src[row * src_stride..row * src_stride + row_bytes];
+                let dst_row = dst.add(row * dst_stride);
+                std::ptr::copy_nonoverlapping(src_row.as_ptr(), dst_row, row_bytes);
+            }
+        }
+        ffi::CVPixelBufferUnlockBaseAddress(buffer, 0);
+        true
+    }
+}
+
+impl FrameOutput for PixelBufferPoolOutput {
+    fn write_frame(&mut self, frame: &DecodedFrame<'_>) {
+        let mut buffer: ffi::CVPixelBufferRef = std::ptr::null_mut();
+        let status = unsafe {
+            ffi::CVPixelBufferPoolCreatePixelBuffer(
+                ffi::kCFAllocatorDefault,
+                self.pool,
+                &mut buffer,
+            )
+        };
+        if status != ffi::kCVReturnSuccess || buffer.is_null() {
+            self.skip("pool has no buffer available");
+            return;
+        }
+        if unsafe { Self::copy_into(frame, buffer) } {
+            (self.on_frame)(buffer, frame.timestamp_ms);
+            trace!(width = frame.width, height = frame.height, "copied frame to pool buffer");
+        } else {
+            self.skip("frame doesn't match the pool's buffers");
+        }
+        unsafe { ffi::CFRelease(buffer as *const c_void) };
+    }
+}
+
+impl Drop for PixelBufferPoolOutput {
+    fn drop(&mut self) {
+        unsafe { ffi::CFRelease(self.pool as *const c_void) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn padded_frame<'a>(y: &'a [u8], uv: &'a [u8]) -> DecodedFrame<'a> {
+        // 2x2 frame with 4-byte strides
+        DecodedFrame {
+            width: 2,
+            height: 2,
+            bytes_per_sample: 1,
+            y_plane: y,
+            y_stride: 4,
+            uv_plane: uv,
+            uv_stride: 4,
+            timestamp_ms: 33,
+            sar: (1, 1),
+        }
+    }
+
+    #[test]
+    fn test_copy_packed_strips_padding() {
+        let y = [1, 2, 0, 0, 3, 4, 0, 0];
+        let uv = [5, 6, 0, 0];
+        let frame = padded_frame(&y, &uv);
+        assert_eq!(frame.packed_size(), 6);
+        let mut dst = vec![0u8; 6];
+        frame.copy_packed(&mut dst);
+        assert_eq!(dst, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_y_crc32_ignores_padding() {
+        assert_eq!(!crc32_update(!0, b"123456789"), 0xCBF4_3926);
+
+        let padded = padded_frame(&[1, 2, 0xAA, 0xAA, 3, 4, 0xBB, 0xBB], &[]);
+        let packed = DecodedFrame {
+            y_stride: 2,
+            ..padded_frame(&[1, 2, 3, 4], &[])
+        };
+        assert_eq!(padded.y_crc32(), packed.y_crc32());
+        assert_eq!(packed.y_crc32(), !crc32_update(!0, &[1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_channel_output_drops_when_full() {
+        let y = [1, 2, 0, 0, 3, 4, 0, 0];
+        let uv = [5, 6, 0, 0];
+        let (mut output, rx) = ChannelOutput::new(2);
+        for _ in 0..3 {
+            output.write_frame(&padded_frame(&y, &uv));
+        }
+        assert_eq!(output.dropped(), 1);
+
+        let frames: Vec<Frame> = rx.try_iter().collect();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].width, 2);
+        assert_eq!(frames[0].height, 2);
+        assert_eq!(frames[0].timestamp_ms, 33);
+        assert_eq!(frames[0].data, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_copy_planar_deinterleaves_chroma() {
+        // 4x2 frame: one chroma row of two UV pairs, padded to 6 bytes
+        let y = [1, 2, 3, 4, 0, 0, 5, 6, 7, 8, 0, 0];
+        let uv = [10, 20, 11, 21, 0, 0];
+        let frame = DecodedFrame {
+            width: 4,
+            height: 2,
+            bytes_per_sample: 1,
+            y_plane: &y,
+            y_stride: 6,
+            uv_plane: &uv,
+            uv_stride: 6,
+            timestamp_ms: 0,
+            sar: (1, 1),
+        };
+        let mut dst = vec![0u8; frame.packed_size()];
+        frame.copy_as(OutputFormat::I420, &mut dst);
+        assert_eq!(dst, [1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 20, 21]);
+
+        frame.copy_as(OutputFormat::Nv12, &mut dst);
+        assert_eq!(dst, [1, 2, 3, 4, 5, 6, 7, 8, 10, 20, 11, 21]);
+    }
+
+    #[test]
+    fn test_frame_to_rgb() {
+        // 4x2: black, white, then two BT.709 red pixels on each row
+        let y = [16, 235, 63, 63, 16, 235, 63, 63];
+        let frame = |format, chroma: &[u8]| Frame {
+            width: 4,
+            height: 2,
+            timestamp_ms: 0,
+            format,
+            display_width: 4,
+            display_height: 2,
+            data: [&y[..], chroma].concat(),
+        };
+        let nv12 = frame(OutputFormat::Nv12, &[128, 128, 102, 240]).to_rgb();
+        let i420 = frame(OutputFormat::I420, &[128, 102, 128, 240]).to_rgb();
+        assert_eq!(nv12, i420);
+        assert_eq!(nv12.len(), 4 * 2 * 3);
+        assert_eq!(nv12[..6], [0, 0, 0, 255, 255, 255]);
+        let red = &nv12[6..9];
+        assert!(red[0] == 255 && red[1] <= 2 && red[2] <= 2, "{red:?}");
+    }
+
+    #[test]
+    fn test_p010_frame_copied_with_16_bit_samples() {
+        // 2x2 P010: rows of two little-endian u16 samples, padded to 6 bytes
+        let y = [0x00, 0x40, 0x40, 0xEB, 0, 0, 0x00, 0x10, 0xC0, 0x3A, 0, 0];
+        let uv = [0x00, 0x80, 0x00, 0x80, 0, 0];
+        let frame = DecodedFrame {
+            width: 2,
+            height: 2,
+            bytes_per_sample: 2,
+            y_plane: &y,
+            y_stride: 6,
+            uv_plane: &uv,
+            uv_stride: 6,
+            timestamp_ms: 0,
+            sar: (1, 1),
+        };
+        assert_eq!(frame.packed_size(), 12);
+        // Planar output isn't available for 10-bit frames
+        assert_eq!(frame.layout_for(OutputFormat::I420), OutputFormat::P010);
+
+        let mut region = vec![0u64; crate::decoder::FRAME_SHM_SIZE.div_ceil(8)];
+        let base = region.as_mut_ptr() as *mut u8;
+        unsafe { FrameHeader::init(base as *mut FrameHeader) };
+        ShmOutput::with_format(base, crate::decoder::FRAME_SHM_SIZE, OutputFormat::I420)
+            .write_frame(&frame);
+        let read = unsafe { crate::FrameReader::new(base) }
+            .unwrap()
+            .latest_frame()
+            .unwrap();
+        assert_eq!(read.format, OutputFormat::P010);
+        assert_eq!(
+            read.data,
+            [0x00, 0x40, 0x40, 0xEB, 0x00, 0x10, 0xC0, 0x3A, 0x00, 0x80, 0x00, 0x80]
+        );
+
+        // 0xEB40 >> 8 is 235, white at 8 bits; 0x1000 >> 8 is 16, black
+        let rgb = read.to_rgb();
+        assert_eq!(rgb[3..6], [255, 255, 255]);
+        assert_eq!(rgb[6..9], [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_p010_low_bits_moves_samples_down() {
+        // 2x2 P010 at 10-bit white (940 = 0xEB00 >> 6) and black (64 = 0x1000 >> 6)
+        let y = [0x00, 0xEB, 0x00, 0x10, 0x00, 0xEB, 0x00, 0x10];
+        let uv = [0x00, 0x80, 0x00, 0x80];
+        let frame = DecodedFrame {
+            width: 2,
+            height: 2,
+            bytes_per_sample: 2,
+            y_plane: &y,
+            y_stride: 4,
+            uv_plane: &uv,
+            uv_stride: 4,
+            timestamp_ms: 0,
+            sar: (1, 1),
+        };
+
+        let mut region = vec![0u64; crate::decoder::FRAME_SHM_SIZE.div_ceil(8)];
+        let base = region.as_mut_ptr() as *mut u8;
+        unsafe { FrameHeader::init(base as *mut FrameHeader) };
+        ShmOutput::new(base, crate::decoder::FRAME_SHM_SIZE)
+            .with_source_stride(true)
+            .with_p010_low_bits(true)
+            .write_frame(&frame);
+        let read = unsafe { crate::FrameReader::new(base) }
+            .unwrap()
+            .latest_frame()
+            .unwrap();
+        assert_eq!(read.format, OutputFormat::P010Lsb);
+        let samples: Vec<u16> = read
+            .data
+            .chunks_exact(2)
+            .map(|s| u16::from_le_bytes([s[0], s[1]]))
+            .collect();
+        assert_eq!(samples, [940, 64, 940, 64, 512, 512]);
+
+        // Reduced to 8 bits the same as P010
+        let rgb = read.to_rgb();
+        assert_eq!(rgb[..3], [255, 255, 255]);
+        assert_eq!(rgb[3..6], [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_output_format_fourcc_round_trip() {
+        for format in [
+            OutputFormat::Nv12,
+            OutputFormat::I420,
+            OutputFormat::P010,
+            OutputFormat::P010Lsb,
+        ] {
+            assert_eq!(OutputFormat::from_fourcc(format.fourcc()), Some(format));
+        }
+        assert_eq!(OutputFormat::from_fourcc([0; 4]), Some(OutputFormat::Nv12));
+        assert_eq!(OutputFormat::from_fourcc(*b"BGRA"), None);
+    }
+
+    #[test]
+    fn test_shm_output_stays_within_mapping() {
+        let y = [1, 2, 0, 0, 3, 4, 0, 0];
+        let uv = [5, 6, 0, 0];
+
+        // Room for the header and part of the first slot only
+        let len = FRAME_HEADER_SIZE + 64;
+        let mut region = vec![0u64; len / 8];
+        let base = region.as_mut_ptr() as *mut u8;
+        let header = base as *const FrameHeader;
+        let mut output = ShmOutput::new(base, len);
+        output.write_frame(&padded_frame(&y, &uv));
+        assert_eq!(unsafe { (*header).write_index.load(Ordering::Acquire) }, 1);
+        // The second slot lies past the end of the mapping
+        output.write_frame(&padded_frame(&y, &uv));
+        assert_eq!(output.write_index(), Some(1));
+
+        // Too small for even the header
+        let mut region = vec![0u64; 8];
+        let mut output = ShmOutput::new(region.as_mut_ptr() as *mut u8, 64);
+        output.write_frame(&padded_frame(&y, &uv));
+        assert_eq!(output.write_index(), None);
+        assert!(region.iter().all(|&word| word == 0));
+    }
+
+    #[test]
+    fn test_block_briefly_waits_for_reader() {
+        let y = [1, 2, 0, 0, 3, 4, 0, 0];
+        let uv = [5, 6, 0, 0];
+        let mut region = vec![0u64; crate::decoder::FRAME_SHM_SIZE.div_ceil(8)];
+        let base = region.as_mut_ptr() as *mut u8;
+        let mut cursors = vec![0u64; crate::READER_REGISTRY_SIZE / 8];
+        let cursors_base = cursors.as_mut_ptr() as *mut u8;
+        let registry = unsafe { ReaderRegistry::new(cursors_base) };
+        let reader = registry.register(1, 0).unwrap();
+        let mut output = ShmOutput::new(base, crate::decoder::FRAME_SHM_SIZE).with_commit_policy(
+            CommitPolicy::BlockBriefly,
+            unsafe { ReaderRegistry::new(cursors_base) },
+        );
+
+        // Both slots are free at first
+        output.write_frame(&padded_frame(&y, &uv));
+        output.write_frame(&padded_frame(&y, &uv));
+
+        // The third frame would overwrite frame 1, which the reader hasn't read
+        let started = Instant::now();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(2));
+                reader.update(1);
+            });
+            output.write_frame(&padded_frame(&y, &uv));
+        });
+        assert!(started.elapsed() >= Duration::from_millis(2));
+        assert_eq!(output.write_index(), Some(3));
+
+        // A reader that doesn't catch up only delays the write
+        let started = Instant::now();
+        output.write_frame(&padded_frame(&y, &uv));
+        assert!(started.elapsed() >= COMMIT_MAX_WAIT);
+        assert_eq!(output.write_index(), Some(4));
+
+        // Without readers there's nothing to wait for
+        drop(reader);
+        assert!(wait_for_readers(&registry, 10, Duration::ZERO));
+    }
+
+    #[test]
+    fn test_shm_output_refuses_oversized_geometry() {
+        let mut region = vec![0u64; crate::decoder::FRAME_SHM_SIZE.div_ceil(8)];
+        let base = region.as_mut_ptr() as *mut u8;
+        let mut output = ShmOutput::new(base, crate::decoder::FRAME_SHM_SIZE);
+
+        // Within MAX_WIDTH x MAX_HEIGHT, but the planes have more rows than
+        // `height` and together overflow the slot
+        let y = vec![1u8; MAX_WIDTH * (MAX_HEIGHT + 120)];
+        let uv = vec![2u8; MAX_WIDTH * (MAX_HEIGHT / 2 + 60)];
+        output.write_frame(&DecodedFrame {
+            width: MAX_WIDTH,
+            height: MAX_HEIGHT,
+            bytes_per_sample: 1,
+            y_plane: &y,
+            y_stride: MAX_WIDTH,
+            uv_plane: &uv,
+            uv_stride: MAX_WIDTH,
+            timestamp_ms: 0,
+            sar: (1, 1),
+        });
+
+        let header = base as *const FrameHeader;
+        assert_eq!(unsafe { (*header).write_index.load(Ordering::Acquire) }, 0);
+        // The second slot (just past the first) is untouched
+        let second_slot = unsafe { *base.add(FrameLayout::NV12.slot_offset(1)) };
+        assert_eq!(second_slot, 0);
+    }
+
+    #[test]
+    fn test_processor_modifies_frame_before_commit() {
+        struct WhiteBar;
+        impl FrameProcessor for WhiteBar {
+            fn process(&self, y: &mut [u8], _uv: &mut [u8], width: usize, _height: usize) {
+                y[..width].fill(0xFF);
+            }
+        }
+
+        let y = [1, 2, 0, 0, 3, 4, 0, 0];
+        let uv = [5, 6, 0, 0];
+        let len = crate::decoder::FRAME_SHM_SIZE;
+        let mut region = vec![0u64; len.div_ceil(8)];
+        let base = region.as_mut_ptr() as *mut u8;
+        unsafe { FrameHeader::init(base as *mut FrameHeader) };
+        // Source stride is ignored while a processor is set
+        let mut output = ShmOutput::new(base, len)
+            .with_source_stride(true)
+            .with_processor(WhiteBar);
+        output.write_frame(&padded_frame(&y, &uv));
+
+        let reader = unsafe { crate::reader::FrameReader::new(base) }.unwrap();
+        assert_eq!(reader.latest_frame().unwrap().data, [0xFF, 0xFF, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_frame_layout_sized_for_format() {
+        let p010 = FrameLayout::new(OutputFormat::P010);
+        assert_eq!(p010.max_frame_size, MAX_WIDTH * MAX_HEIGHT * 3);
+        assert_eq!(p010.shm_size(), FRAME_HEADER_SIZE + 2 * MAX_WIDTH * MAX_HEIGHT * 3);
+        assert_eq!(FrameLayout::NV12.shm_size(), crate::decoder::FRAME_SHM_SIZE);
+
+        // A full-size 10-bit frame only fits the P010 layout's slots
+        let y = vec![1u8; MAX_WIDTH * 2 * MAX_HEIGHT];
+        let uv = vec![2u8; MAX_WIDTH * 2 * MAX_HEIGHT / 2];
+        let frame = DecodedFrame {
+            width: MAX_WIDTH,
+            height: MAX_HEIGHT,
+            bytes_per_sample: 2,
+            y_plane: &y,
+            y_stride: MAX_WIDTH * 2,
+            uv_plane: &uv,
+            uv_stride: MAX_WIDTH * 2,
+            timestamp_ms: 0,
+            sar: (1, 1),
+        };
+        for (format, written) in [(OutputFormat::Nv12, 0), (OutputFormat::P010, 2)] {
+            let len = FrameLayout::new(format).shm_size();
+            let mut region = vec![0u64; len.div_ceil(8)];
+            let base = region.as_mut_ptr() as *mut u8;
+            let mut output = ShmOutput::with_format(base, len, format);
+            output.write_frame(&frame);
+            output.write_frame(&frame);
+            let header = base as *const FrameHeader;
+            let index = unsafe { (*header).write_index.load(Ordering::Acquire) };
+            assert_eq!(index, written, "{format:?}");
+        }
+    }
+}
@@ -0,0 +1,482 @@
+use std::sync::atomic::{fence, Ordering};
+use std::time::{Duration, Instant};
+
+use tracing::trace;
+
+use crate::decoder::{ColorHeader, FrameHeader, MAX_HEIGHT, MAX_WIDTH};
+use crate::output::{Frame, FrameLayout, OutputFormat};
+
+/// How many times a read is retried when the writer overwrites the slot mid-copy.
+const MAX_READ_ATTEMPTS: usize = 3;
+
+/// How often the waits check `write_index`; see `FrameReader::wait_for_frame`.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Reads frames from the double-buffered shared memory region written by
+/// `ShmOutput` — the Rust counterpart of the Camera Extension's reader.
+pub struct FrameReader {
+    base: *const u8,
+    /// Slot sizes the writer uses.
+    layout: FrameLayout,
+    /// `write_index` of the last frame returned by `drain_to_latest`.
+    last_index: u64,
+}
+
+// SAFETY: the region is only read, and `write_index` is accessed atomically.
+unsafe impl Send for FrameReader {}
+
+impl FrameReader {
+    /// Attach to a frame buffer, checking its magic and layout version.
+    ///
+    /// # Safety
+    /// `base` must point to a mapping of at least `FRAME_SHM_SIZE` bytes,
+    /// aligned to 8 bytes and valid for the lifetime of the reader.
+    pub unsafe fn new(base: *const u8) -> Result<Self, String> {
+        Self::with_layout(base, FrameLayout::NV12)
+    }
+
+    /// Like `new`, for a buffer whose writer uses `layout` rather than the
+    /// default NV12 one.
+    ///
+    /// # Safety
+    /// As for `new`, with a mapping of at least `layout.shm_size()` bytes.
+    pub unsafe fn with_layout(base: *const u8, layout: FrameLayout) -> Result<Self, String> {
+        FrameHeader::validate(base as *const FrameHeader)?;
+        Ok(Self {
+            base,
+            layout,
+            last_index: 0,
+        })
+    }
+
+    /// Number of frames the writer has committed so far.
+    pub fn write_index(&self) -> u64 {
+        self.header().write_index.load(Ordering::Acquire)
+    }
+
+    /// How many frames the writer has committed since the last one returned
+    /// by `drain_to_latest` or the waits.
+    pub fn frames_behind(&self) -> u64 {
+        self.write_index().saturating_sub(self.last_index)
+    }
+
+    /// The newest committed frame, whether or not it was returned before.
+    pub fn latest_frame(&self) -> Option<Frame> {
+        self.read_latest().map(|(frame, _)| frame)
+    }
+
+    /// The newest frame not yet returned by this method, skipping any older
+    /// ones the reader fell behind on. Returns the frame and how many frames
+    /// were skipped, or `None` if nothing new was written.
+    pub fn drain_to_latest(&mut self) -> Option<(Frame, u64)> {
+        if self.write_index() == self.last_index {
+            return None;
+        }
+        let (frame, index) = self.read_latest()?;
+        let skipped = index.saturating_sub(self.last_index + 1);
+        if skipped > 0 {
+            trace!(skipped, "reader fell behind, skipping to latest frame");
+        }
+        self.last_index = index;
+        Some((frame, skipped))
+    }
+
+    /// Block until a frame newer than the last one returned is committed,
+    /// or `timeout` passes. As with `drain_to_latest`, frames the reader
+    /// fell behind on are skipped.
+    ///
+    /// This polls rather than sleeping on a notification: the writer only
+    /// bumps `write_index` in shared memory, and macOS has no public
+    /// cross-process futex to wait on it. The thread wakes every 1 ms
+    /// (`WAIT_POLL_INTERVAL`), about 1000 wakeups a second while waiting,
+    /// and sees a new frame up to 1 ms after it is committed. Readers that
+    /// can't afford either should call `drain_to_latest` from their own
+    /// frame clock instead.
+    pub fn wait_for_frame(&mut self, timeout: Duration) -> Option<Frame> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some((frame, _)) = self.drain_to_latest() {
+                return Some(frame);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+            std::thread::sleep(WAIT_POLL_INTERVAL.min(remaining));
+        }
+    }
+
+    /// Async `wait_for_frame` without a timeout; wrap it in
+    /// `tokio::time::timeout` to bound the wait. Polls the same way, on a
+    /// 1 ms tokio interval.
+    #[cfg(feature = "tokio")]
+    pub async fn wait_for_frame_async(&mut self) -> Frame {
+        let mut interval = tokio::time::interval(WAIT_POLL_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if let Some((frame, _)) = self.drain_to_latest() {
+                return frame;
+            }
+        }
+    }
+
+    /// The stream's HDR color description, if the publisher sent one.
+    pub fn color(&self) -> Option<ColorHeader> {
+        unsafe { FrameHeader::read_color(self.base as *const FrameHeader) }
+    }
+
+    /// Whether the stream is interlaced. Frames are full height either way.
+    pub fn interlaced(&self) -> bool {
+        unsafe { FrameHeader::read_interlaced(self.base as *const FrameHeader) }
+    }
+
+    fn header(&self) -> &FrameHeader {
+        unsafe { &*(self.base as *const FrameHeader) }
+    }
+
+    /// Copy out the newest frame along with its `write_index`, retrying if
+    /// the writer moved on to its slot while copying.
+    fn read_latest(&self) -> Option<(Frame, u64)> {
+        for _ in 0..MAX_READ_ATTEMPTS {
+            let index = self.write_index();
+            if index == 0 {
+                return None;
+            }
+
+            // Everything describing the frame comes from its own slot header
+            let slot = ((index - 1) % 2) as usize;
+            let slot_header = unsafe {
+                std::ptr::addr_of!((*(self.base as *const FrameHeader)).slots[slot])
+            };
+            let (width, height, timestamp_ms, fourcc, display_width, display_height) = unsafe {
+                (
+                    std::ptr::addr_of!((*slot_header).width).read_volatile() as usize,
+                    std::ptr::addr_of!((*slot_header).height).read_volatile() as usize,
+                    std::ptr::addr_of!((*slot_header).timestamp_ms).read_volatile(),
+                    std::ptr::addr_of!((*slot_header).pixel_format).read_volatile(),
+                    std::ptr::addr_of!((*slot_header).display_width).read_volatile() as usize,
+                    std::ptr::addr_of!((*slot_header).display_height).read_volatile() as usize,
+                )
+            };
+            let stride =
+                unsafe { std::ptr::addr_of!((*slot_header).stride).read_volatile() } as usize;
+            let format = OutputFormat::from_fourcc(fourcc);
+            let row_bytes = width * format.map_or(1, |format| format.bytes_per_sample());
+            let valid_size = width > 0 && height > 0 && width <= MAX_WIDTH && height <= MAX_HEIGHT;
+            let valid_stride = stride == 0 || stride >= row_bytes;
+            let (Some(format), true) = (format, valid_size && valid_stride) else {
+                if self.write_index() > index {
+                    continue; // slot header was being rewritten
+                }
+                trace!(?fourcc, width, height, stride, "invalid slot header");
+                return None;
+            };
+
+            let size = width * height * format.bits_per_pixel() / 8;
+            let src = unsafe { self.base.add(self.layout.slot_offset(slot)) };
+            let data = if stride == 0 {
+                let size = size.min(self.layout.max_frame_size);
+                unsafe { std::slice::from_raw_parts(src, size) }.to_vec()
+            } else {
+                // Rows kept their padding; strip it while copying out
+                let rows = size / row_bytes;
+                let len = (stride * rows).min(self.layout.max_frame_size);
+                let slot_data = unsafe { std::slice::from_raw_parts(src, len) };
+                let mut data = Vec::with_capacity(size);
+                for row in slot_data.chunks(stride) {
+                    data.extend_from_slice(&row[..row_bytes.min(row.len())]);
+                }
+                data
+            };
+
+            // Seqlock reader side, see `FrameHeader`: the writer only reuses
+            // this slot once it has committed the next frame, so an index
+            // that didn't move means a clean copy. The fence keeps the copy
+            // above from being reordered past the check.
+            fence(Ordering::Acquire);
+            if self.write_index() <= index {
+                let frame = Frame {
+                    width,
+                    height,
+                    timestamp_ms,
+                    format,
+                    // Zeros mean square pixels
+                    display_width: if display_width == 0 { width } else { display_width },
+                    display_height: if display_height == 0 { height } else { display_height },
+                    data,
+                };
+                return Some((frame, index));
+            }
+        }
+        trace!("writer kept overwriting the slot being read, giving up");
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::FRAME_SHM_SIZE;
+    use crate::output::{DecodedFrame, FrameOutput, ShmOutput};
+
+    /// 8-byte aligned stand-in for the mapped ring file, with its header stamped.
+    fn region() -> Vec<u64> {
+        let mut region = vec![0u64; FRAME_SHM_SIZE.div_ceil(8)];
+        unsafe { FrameHeader::init(region.as_mut_ptr() as *mut FrameHeader) };
+        region
+    }
+
+    fn write_frame(output: &mut ShmOutput, value: u8) {
+        write_frame_with_sar(output, value, (1, 1));
+    }
+
+    fn write_frame_with_sar(output: &mut ShmOutput, value: u8, sar: (u16, u16)) {
+        let timestamp_ms = value as u64 * 33;
+        let y = [value; 4];
+        let uv = [value; 2];
+        output.write_frame(&DecodedFrame {
+            width: 2,
+            height: 2,
+            bytes_per_sample: 1,
+            y_plane: &y,
+            y_stride: 2,
+            uv_plane: &uv,
+            uv_stride: 2,
+            timestamp_ms,
+            sar,
+        });
+    }
+
+    #[test]
+    fn test_reads_frames_written_with_source_stride() {
+        let mut region = region();
+        let base = region.as_mut_ptr() as *mut u8;
+        let mut output = ShmOutput::new(base, FRAME_SHM_SIZE).with_source_stride(true);
+        let mut reader = unsafe { FrameReader::new(base) }.unwrap();
+        let stride = |reader: &FrameReader, slot: usize| unsafe {
+            std::ptr::addr_of!((*(reader.base as *const FrameHeader)).slots[slot].stride)
+                .read_volatile()
+        };
+
+        // 2x2 frame in rows padded to 4 bytes; the padding is 0xFF
+        let y = [1, 2, 0xFF, 0xFF, 3, 4, 0xFF, 0xFF];
+        let uv = [5, 6, 0xFF, 0xFF];
+        output.write_frame(&DecodedFrame {
+            width: 2,
+            height: 2,
+            bytes_per_sample: 1,
+            y_plane: &y,
+            y_stride: 4,
+            uv_plane: &uv,
+            uv_stride: 4,
+            timestamp_ms: 0,
+            sar: (1, 1),
+        });
+        assert_eq!(stride(&reader, 0), 4);
+        let (frame, _) = reader.drain_to_latest().unwrap();
+        assert_eq!(frame.data, [1, 2, 3, 4, 5, 6]);
+
+        // Unpadded rows are written packed, with no stride recorded
+        write_frame(&mut output, 7);
+        assert_eq!(stride(&reader, 1), 0);
+        assert_eq!(reader.drain_to_latest().unwrap().0.data, [7; 6]);
+    }
+
+    #[test]
+    fn test_drain_to_latest_skips_stale_frames() {
+        let mut region = region();
+        let base = region.as_mut_ptr() as *mut u8;
+        let mut output = ShmOutput::new(base, FRAME_SHM_SIZE);
+        let mut reader = unsafe { FrameReader::new(base) }.unwrap();
+        assert!(reader.drain_to_latest().is_none());
+
+        for value in 1..=5 {
+            write_frame(&mut output, value);
+        }
+        let (frame, skipped) = reader.drain_to_latest().unwrap();
+        assert_eq!(skipped, 4);
+        assert_eq!((frame.width, frame.height), (2, 2));
+        assert_eq!(frame.data, [5; 6]);
+        assert_eq!(frame.timestamp_ms, 165);
+
+        // Nothing new since
+        assert!(reader.drain_to_latest().is_none());
+        assert_eq!(reader.latest_frame().unwrap().data, [5; 6]);
+
+        write_frame(&mut output, 6);
+        let (frame, skipped) = reader.drain_to_latest().unwrap();
+        assert_eq!(skipped, 0);
+        assert_eq!(frame.data, [6; 6]);
+    }
+
+    #[test]
+    fn test_frames_behind() {
+        let mut region = region();
+        let base = region.as_mut_ptr() as *mut u8;
+        let mut output = ShmOutput::new(base, FRAME_SHM_SIZE);
+        let mut reader = unsafe { FrameReader::new(base) }.unwrap();
+        assert_eq!(reader.frames_behind(), 0);
+
+        for value in 1..=10 {
+            write_frame(&mut output, value);
+        }
+        assert_eq!(reader.frames_behind(), 10);
+
+        reader.drain_to_latest().unwrap();
+        assert_eq!(reader.frames_behind(), 0);
+        write_frame(&mut output, 11);
+        assert_eq!(reader.frames_behind(), 1);
+    }
+
+    #[test]
+    fn test_wait_for_frame() {
+        let mut region = region();
+        let base = region.as_mut_ptr() as *mut u8;
+        let mut reader = unsafe { FrameReader::new(base) }.unwrap();
+
+        let base_addr = base as usize;
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            write_frame(&mut ShmOutput::new(base_addr as *mut u8, FRAME_SHM_SIZE), 7);
+        });
+        let start = Instant::now();
+        let frame = reader.wait_for_frame(Duration::from_secs(5)).unwrap();
+        assert_eq!(frame.data, [7; 6]);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        writer.join().unwrap();
+
+        // Nothing further is written
+        let start = Instant::now();
+        assert!(reader.wait_for_frame(Duration::from_millis(20)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_wait_for_frame_async() {
+        let mut region = region();
+        let base = region.as_mut_ptr() as *mut u8;
+        let mut reader = unsafe { FrameReader::new(base) }.unwrap();
+
+        let base_addr = base as usize;
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            write_frame(&mut ShmOutput::new(base_addr as *mut u8, FRAME_SHM_SIZE), 9);
+        });
+        let wait = tokio::time::timeout(Duration::from_secs(5), reader.wait_for_frame_async());
+        assert_eq!(wait.await.unwrap().data, [9; 6]);
+        writer.join().unwrap();
+
+        let wait = tokio::time::timeout(Duration::from_millis(20), reader.wait_for_frame_async());
+        assert!(wait.await.is_err());
+    }
+
+    #[test]
+    fn test_reports_display_size() {
+        let mut region = region();
+        let base = region.as_mut_ptr() as *mut u8;
+        let mut output = ShmOutput::new(base, FRAME_SHM_SIZE);
+        let reader = unsafe { FrameReader::new(base) }.unwrap();
+
+        write_frame(&mut output, 1);
+        let frame = reader.latest_frame().unwrap();
+        assert_eq!((frame.display_width, frame.display_height), (2, 2));
+
+        // 2:1 pixels are shown twice as wide as they are coded
+        write_frame_with_sar(&mut output, 2, (2, 1));
+        let frame = reader.latest_frame().unwrap();
+        assert_eq!((frame.width, frame.height), (2, 2));
+        assert_eq!((frame.display_width, frame.display_height), (4, 2));
+    }
+
+    #[test]
+    fn test_reads_color_description() {
+        let mut region = region();
+        let header = region.as_mut_ptr() as *mut FrameHeader;
+        let reader = unsafe { FrameReader::new(header as *const u8) }.unwrap();
+        assert_eq!(reader.color(), None);
+
+        let mut color = ColorHeader::default();
+        color.present = 1;
+        color.transfer_characteristics = 16;
+        color.white_point = [15635, 16450];
+        color.max_luminance = 10_000_000;
+        unsafe { FrameHeader::write_color(header, color) };
+        assert_eq!(reader.color(), Some(color));
+        // Frames written afterwards leave it alone
+        write_frame(&mut ShmOutput::new(header as *mut u8, FRAME_SHM_SIZE), 1);
+        assert_eq!(reader.color(), Some(color));
+
+        unsafe { FrameHeader::write_color(header, ColorHeader::default()) };
+        assert_eq!(reader.color(), None);
+    }
+
+    #[test]
+    fn test_reads_interlaced_flag() {
+        let mut region = region();
+        let header = region.as_mut_ptr() as *mut FrameHeader;
+        let reader = unsafe { FrameReader::new(header as *const u8) }.unwrap();
+        assert!(!reader.interlaced());
+
+        unsafe { FrameHeader::write_interlaced(header, true) };
+        assert!(reader.interlaced());
+        // Still a valid header for readers that don't know the flag
+        assert!(unsafe { FrameHeader::validate(header) }.is_ok());
+        unsafe { FrameHeader::write_interlaced(header, false) };
+        assert!(!reader.interlaced());
+    }
+
+    #[test]
+    fn test_dimensions_match_pixels_under_concurrent_writes() {
+        let mut region = region();
+        let base = region.as_mut_ptr() as *mut u8;
+        let reader = unsafe { FrameReader::new(base) }.unwrap();
+
+        // Each frame is filled with its own width, so a frame read with the
+        // dimensions of another shows up as a mismatch
+        let base_addr = base as usize;
+        let writer = std::thread::spawn(move || {
+            let mut output = ShmOutput::new(base_addr as *mut u8, FRAME_SHM_SIZE);
+            for i in 0..20_000usize {
+                let (width, height) = [(2, 2), (4, 2), (8, 4)][i % 3];
+                let y = vec![width as u8; width * height];
+                let uv = vec![width as u8; width * height / 2];
+                output.write_frame(&DecodedFrame {
+                    width,
+                    height,
+                    bytes_per_sample: 1,
+                    y_plane: &y,
+                    y_stride: width,
+                    uv_plane: &uv,
+                    uv_stride: width,
+                    timestamp_ms: i as u64,
+                    sar: (1, 1),
+                });
+            }
+        });
+
+        let mut frames = 0;
+        while !writer.is_finished() {
+            if let Some(frame) = reader.latest_frame() {
+                assert_eq!(frame.data.len(), frame.width * frame.height * 3 / 2);
+                assert!(
+                    frame.data.iter().all(|&b| b as usize == frame.width),
+                    "{}x{} frame holds pixels of another frame",
+                    frame.width,
+                    frame.height
+                );
+                frames += 1;
+            }
+        }
+        writer.join().unwrap();
+        assert!(frames > 0);
+    }
+
+    #[test]
+    fn test_rejects_unstamped_region() {
+        let mut region = vec![0u64; FRAME_SHM_SIZE.div_ceil(8)];
+        let result = unsafe { FrameReader::new(region.as_mut_ptr() as *const u8) };
+        assert!(result.is_err());
+    }
+}
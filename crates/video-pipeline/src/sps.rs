@@ -0,0 +1,437 @@
+//! Minimal H.264 SPS parsing: profile and level, coded picture size, luma bit
+//! depth, scan type and the VUI sample aspect ratio, so readers can show
+//! anamorphic streams at their display aspect.
+
+/// Macroblock size; a decoder may hand out pictures padded up to it.
+const MB_SIZE: u32 = 16;
+
+/// `aspect_ratio_idc` value signalling an explicit `sar_width`/`sar_height`.
+const EXTENDED_SAR: u8 = 255;
+
+/// Sample aspect ratios for `aspect_ratio_idc` 1..=16 (H.264 Table E-1).
+const SAR_TABLE: [(u16, u16); 16] = [
+    (1, 1),
+    (12, 11),
+    (10, 11),
+    (16, 11),
+    (40, 33),
+    (24, 11),
+    (20, 11),
+    (32, 11),
+    (80, 33),
+    (18, 11),
+    (15, 11),
+    (64, 33),
+    (160, 99),
+    (4, 3),
+    (3, 2),
+    (2, 1),
+];
+
+/// What the pipeline needs from a sequence parameter set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpsInfo {
+    pub profile_idc: u8,
+    /// `constraint_set0_flag` to `constraint_set5_flag`, from the top bit down.
+    pub constraint_flags: u8,
+    /// Level times ten, e.g. 31 for level 3.1.
+    pub level_idc: u8,
+    /// Coded picture size after frame cropping.
+    pub width: u32,
+    pub height: u32,
+    /// Luma bit depth; 8 unless a High 10/4:2:2/4:4:4 profile says otherwise.
+    pub bit_depth: u8,
+    /// 0 monochrome, 1 4:2:0, 2 4:2:2, 3 4:4:4. Only High and later profiles
+    /// can signal anything but 4:2:0.
+    pub chroma_format_idc: u32,
+    /// Sample (pixel) aspect ratio from the VUI; 1:1 when absent or unspecified.
+    pub sar: (u16, u16),
+    /// Whether pictures may be coded as fields (`frame_mbs_only_flag` 0).
+    /// `width`/`height` are still those of the whole frame.
+    pub interlaced: bool,
+}
+
+impl SpsInfo {
+    /// Name of the profile, telling Constrained Baseline (as mobile encoders
+    /// send) apart from plain Baseline.
+    pub fn profile_name(&self) -> &'static str {
+        match self.profile_idc {
+            66 if self.constraint_flags & 0x40 != 0 => "Constrained Baseline",
+            66 => "Baseline",
+            77 => "Main",
+            88 => "Extended",
+            100 => "High",
+            110 => "High 10",
+            122 => "High 4:2:2",
+            244 => "High 4:4:4 Predictive",
+            _ => "unknown profile",
+        }
+    }
+
+    /// Chroma subsampling as commonly written, e.g. "4:2:2".
+    pub fn chroma_format_name(&self) -> &'static str {
+        match self.chroma_format_idc {
+            0 => "4:0:0",
+            1 => "4:2:0",
+            2 => "4:2:2",
+            3 => "4:4:4",
+            _ => "unknown chroma format",
+        }
+    }
+
+    /// Size the picture should be shown at: the width stretched by the
+    /// sample aspect ratio, the height unchanged.
+    pub fn display_size(&self) -> (u32, u32) {
+        display_size(self.width, self.height, self.sar)
+    }
+
+    /// Whether a decoded picture of `width` x `height` is this SPS's picture:
+    /// either the cropped size or within a macroblock of it, as when the
+    /// decoder skips the crop.
+    pub fn matches_decoded_size(&self, width: u32, height: u32) -> bool {
+        width.abs_diff(self.width) < MB_SIZE && height.abs_diff(self.height) < MB_SIZE
+    }
+}
+
+/// Scale `width` by `sar`. An unset ratio (either term 0) means square pixels.
+pub fn display_size(width: u32, height: u32, (sar_width, sar_height): (u16, u16)) -> (u32, u32) {
+    if sar_width == 0 || sar_height == 0 {
+        return (width, height);
+    }
+    let scaled = (width as u64 * sar_width as u64 + sar_height as u64 / 2) / sar_height as u64;
+    (scaled.min(u32::MAX as u64) as u32, height)
+}
+
+/// Parse an SPS NAL unit (including its one-byte header). Returns `None` if
+/// the unit is truncated or uses syntax this parser doesn't follow.
+pub fn parse_sps(nal: &[u8]) -> Option<SpsInfo> {
+    let rbsp = unescape_rbsp(nal.get(1..)?);
+    let mut r = BitReader::new(&rbsp);
+
+    let profile_idc = r.bits(8)? as u8;
+    let constraint_flags = r.bits(8)? as u8;
+    let level_idc = r.bits(8)? as u8;
+    r.ue()?; // seq_parameter_set_id
+
+    let mut chroma_format_idc = 1;
+    let mut bit_depth = 8;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = r.ue()?;
+        if chroma_format_idc == 3 {
+            r.skip(1)?; // separate_color_plane_flag
+        }
+        bit_depth = u8::try_from(r.ue()?).ok()?.checked_add(8)?; // bit_depth_luma_minus8
+        r.ue()?; // bit_depth_chroma_minus8
+        r.skip(1)?; // qpprime_y_zero_transform_bypass_flag
+        if r.flag()? {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if r.flag()? {
+                    skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    r.ue()?; // log2_max_frame_num_minus4
+    match r.ue()? {
+        0 => {
+            r.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            r.skip(1)?; // delta_pic_order_always_zero_flag
+            r.se()?; // offset_for_non_ref_pic
+            r.se()?; // offset_for_top_to_bottom_field
+            for _ in 0..r.ue()? {
+                r.se()?; // offset_for_ref_frame
+            }
+        }
+        _ => {}
+    }
+    r.ue()?; // max_num_ref_frames
+    r.skip(1)?; // gaps_in_frame_num_value_allowed_flag
+    let width_in_mbs = r.ue()?.checked_add(1)?;
+    let height_in_map_units = r.ue()?.checked_add(1)?;
+    let frame_mbs_only = r.flag()?;
+    if !frame_mbs_only {
+        r.skip(1)?; // mb_adaptive_frame_field_flag
+    }
+    r.skip(1)?; // direct_8x8_inference_flag
+
+    let field_factor = if frame_mbs_only { 1 } else { 2 };
+    let mut width = width_in_mbs.checked_mul(16)?;
+    let mut height = height_in_map_units.checked_mul(16 * field_factor)?;
+    if r.flag()? {
+        let (crop_x, crop_y) = match chroma_format_idc {
+            1 => (2, 2 * field_factor),
+            2 => (2, field_factor),
+            _ => (1, field_factor),
+        };
+        let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+        let crop_width = left.checked_add(right)?.checked_mul(crop_x)?;
+        let crop_height = top.checked_add(bottom)?.checked_mul(crop_y)?;
+        width = width.checked_sub(crop_width)?;
+        height = height.checked_sub(crop_height)?;
+    }
+
+    let mut sar = (1, 1);
+    if r.flag()? && r.flag()? {
+        // vui_parameters_present_flag, aspect_ratio_info_present_flag
+        match r.bits(8)? as u8 {
+            EXTENDED_SAR => sar = (r.bits(16)? as u16, r.bits(16)? as u16),
+            idc @ 1..=16 => sar = SAR_TABLE[idc as usize - 1],
+            _ => {} // 0 = unspecified, others reserved
+        }
+    }
+
+    Some(SpsInfo {
+        profile_idc,
+        constraint_flags,
+        level_idc,
+        width,
+        height,
+        bit_depth,
+        chroma_format_idc,
+        sar,
+        interlaced: !frame_mbs_only,
+    })
+}
+
+fn skip_scaling_list(r: &mut BitReader<'_>, size: usize) -> Option<()> {
+    let mut last_scale = 8i64;
+    let mut next_scale = 8i64;
+    for _ in 0..size {
+        if next_scale != 0 {
+            next_scale = (last_scale + r.se()? + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+    Some(())
+}
+
+/// Remove emulation prevention bytes (00 00 03 -> 00 00).
+fn unescape_rbsp(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+/// MSB-first bit reader with Exp-Golomb decoding.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bit(&mut self) -> Option<u32> {
+        let byte = *self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+
+    fn flag(&mut self) -> Option<bool> {
+        Some(self.bit()? == 1)
+    }
+
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        (0..n).try_fold(0, |acc, _| Some((acc << 1) | self.bit()?))
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.pos += n;
+        (self.pos <= self.data.len() * 8).then_some(())
+    }
+
+    fn ue(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while self.bit()? == 0 {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << leading_zeros) - 1 + self.bits(leading_zeros)?)
+    }
+
+    fn se(&mut self) -> Option<i64> {
+        let k = self.ue()? as i64;
+        Some(if k % 2 == 1 { (k + 1) / 2 } else { -(k / 2) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds RBSP bit strings for test SPS units.
+    #[derive(Default)]
+    struct BitWriter {
+        bits: Vec<bool>,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, n: u32, value: u32) -> &mut Self {
+            for i in (0..n).rev() {
+                self.bits.push((value >> i) & 1 == 1);
+            }
+            self
+        }
+
+        fn ue(&mut self, value: u32) -> &mut Self {
+            let coded = value + 1;
+            let len = 32 - coded.leading_zeros();
+            self.bits(len - 1, 0).bits(len, coded)
+        }
+
+        /// NAL header, payload and rbsp trailing bits (no emulation prevention).
+        fn into_nal(mut self) -> Vec<u8> {
+            self.bits.push(true);
+            while !self.bits.len().is_multiple_of(8) {
+                self.bits.push(false);
+            }
+            let mut nal = vec![0x67];
+            nal.extend(
+                self.bits
+                    .chunks(8)
+                    .map(|byte| byte.iter().fold(0u8, |acc, &b| (acc << 1) | b as u8)),
+            );
+            nal
+        }
+    }
+
+    /// Baseline SPS for `width_mbs` x `height_mbs` macroblocks with
+    /// `crop_bottom` rows of 2-line cropping, ending before the VUI flag.
+    fn baseline_sps(width_mbs: u32, height_mbs: u32, crop_bottom: u32) -> BitWriter {
+        let mut w = BitWriter::default();
+        w.bits(8, 66).bits(8, 0).bits(8, 31).ue(0);
+        w.ue(0).ue(0).ue(0); // log2_max_frame_num, poc type 0, log2_max_poc_lsb
+        w.ue(1).bits(1, 0); // max_num_ref_frames, gaps
+        w.ue(width_mbs - 1).ue(height_mbs - 1);
+        w.bits(1, 1).bits(1, 1); // frame_mbs_only, direct_8x8
+        if crop_bottom > 0 {
+            w.bits(1, 1).ue(0).ue(0).ue(0).ue(crop_bottom);
+        } else {
+            w.bits(1, 0);
+        }
+        w
+    }
+
+    #[test]
+    fn test_parse_sps_without_vui() {
+        let mut w = baseline_sps(80, 45, 0);
+        w.bits(1, 0);
+        let info = parse_sps(&w.into_nal()).unwrap();
+        assert_eq!((info.width, info.height), (1280, 720));
+        assert_eq!(info.sar, (1, 1));
+        assert_eq!(info.bit_depth, 8);
+        assert_eq!((info.profile_name(), info.level_idc), ("Baseline", 31));
+        assert!(!info.interlaced);
+        assert_eq!(info.display_size(), (1280, 720));
+    }
+
+    #[test]
+    fn test_parse_sps_interlaced() {
+        // Main profile 1080i: 34 field map units of 32 lines, cropped by 8
+        let mut w = BitWriter::default();
+        w.bits(8, 77).bits(8, 0).bits(8, 40).ue(0);
+        w.ue(0).ue(0).ue(0).ue(1).bits(1, 0);
+        w.ue(119).ue(33);
+        w.bits(1, 0).bits(1, 1).bits(1, 1); // frame_mbs_only, mb_adaptive, direct_8x8
+        w.bits(1, 1).ue(0).ue(0).ue(0).ue(2);
+        w.bits(1, 0);
+        let info = parse_sps(&w.into_nal()).unwrap();
+        assert!(info.interlaced);
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert!(info.matches_decoded_size(1920, 1080));
+        assert!(!info.matches_decoded_size(1920, 540));
+    }
+
+    #[test]
+    fn test_parse_sps_high10() {
+        // High 10 (profile 110), 4:2:0, 10-bit luma and chroma
+        let mut w = BitWriter::default();
+        w.bits(8, 110).bits(8, 0).bits(8, 40).ue(0);
+        w.ue(1).ue(2).ue(2).bits(1, 0).bits(1, 0); // chroma, bit depths, bypass, no matrices
+        w.ue(0).ue(0).ue(0).ue(1).bits(1, 0);
+        w.ue(119).ue(67).bits(1, 1).bits(1, 1).bits(1, 1).ue(0).ue(0).ue(0).ue(4);
+        w.bits(1, 0);
+        let info = parse_sps(&w.into_nal()).unwrap();
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert_eq!(info.bit_depth, 10);
+        assert_eq!(info.profile_name(), "High 10");
+        assert_eq!(info.chroma_format_name(), "4:2:0");
+    }
+
+    #[test]
+    fn test_parse_sps_high422() {
+        // High 4:2:2 (profile 122), 8-bit; vertical crop units are single lines
+        let mut w = BitWriter::default();
+        w.bits(8, 122).bits(8, 0).bits(8, 40).ue(0);
+        w.ue(2).ue(0).ue(0).bits(1, 0).bits(1, 0); // chroma, bit depths, bypass, no matrices
+        w.ue(0).ue(0).ue(0).ue(1).bits(1, 0);
+        w.ue(119).ue(67).bits(1, 1).bits(1, 1).bits(1, 1).ue(0).ue(0).ue(0).ue(8);
+        w.bits(1, 0);
+        let info = parse_sps(&w.into_nal()).unwrap();
+        assert_eq!(info.chroma_format_idc, 2);
+        assert_eq!(info.chroma_format_name(), "4:2:2");
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert_eq!(info.profile_name(), "High 4:2:2");
+    }
+
+    #[test]
+    fn test_parse_sps_anamorphic() {
+        // 1440x1088 coded, cropped to 1080, PAR 4:3 (aspect_ratio_idc 14)
+        let mut w = baseline_sps(90, 68, 4);
+        w.bits(1, 1).bits(1, 1).bits(8, 14);
+        let info = parse_sps(&w.into_nal()).unwrap();
+        assert_eq!((info.width, info.height), (1440, 1080));
+        assert_eq!(info.sar, (4, 3));
+        assert_eq!(info.display_size(), (1920, 1080));
+
+        // Same via Extended_SAR
+        let mut w = baseline_sps(90, 68, 4);
+        w.bits(1, 1).bits(1, 1).bits(8, 255).bits(16, 4).bits(16, 3);
+        assert_eq!(
+            parse_sps(&w.into_nal()).unwrap().display_size(),
+            (1920, 1080)
+        );
+    }
+
+    #[test]
+    fn test_matches_decoded_size() {
+        let mut w = baseline_sps(120, 68, 4);
+        w.bits(1, 0);
+        let info = parse_sps(&w.into_nal()).unwrap();
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert!(info.matches_decoded_size(1920, 1080));
+        // Coded size, macroblock-aligned
+        assert!(info.matches_decoded_size(1920, 1088));
+        assert!(!info.matches_decoded_size(1280, 720));
+        assert!(!info.matches_decoded_size(1920, 1104));
+    }
+
+    #[test]
+    fn test_parse_sps_truncated() {
+        let nal = baseline_sps(80, 45, 0).into_nal();
+        assert!(parse_sps(&nal[..4]).is_none());
+        assert_eq!(display_size(640, 480, (0, 0)), (640, 480));
+    }
+}
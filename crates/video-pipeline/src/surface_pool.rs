@@ -65,11 +65,22 @@ impl SurfaceRing {
     /// Read the latest surface ID and timestamp.
     /// Returns None if no frames have been written yet.
     pub fn latest(&self) -> Option<(ffi::IOSurfaceID, u64)> {
+        self.get(0)
+    }
+
+    /// Read the surface ID and timestamp of the frame `n_back` positions
+    /// behind the latest, for replaying recent frames; `get(0)` is `latest`.
+    /// Returns None if fewer than `n_back + 1` frames have been written or
+    /// `n_back` is past the ring's capacity of `RING_SIZE` frames.
+    ///
+    /// The oldest slot is the next one `push` overwrites, so a frame near the
+    /// capacity should be read promptly.
+    pub fn get(&self, n_back: usize) -> Option<(ffi::IOSurfaceID, u64)> {
         let write_idx = self.inner.write_index.load(Ordering::Acquire);
-        if write_idx == 0 {
+        if n_back >= RING_SIZE || n_back as u64 >= write_idx {
             return None;
         }
-        let idx = (write_idx - 1) as usize % RING_SIZE;
+        let idx = (write_idx - 1 - n_back as u64) as usize % RING_SIZE;
         let surface_id = self.inner.surface_ids[idx].load(Ordering::Acquire);
         let timestamp = self.inner.timestamps[idx].load(Ordering::Acquire);
         if surface_id == 0 {
@@ -78,6 +89,27 @@ impl SurfaceRing {
         Some((surface_id, timestamp))
     }
 
+    /// Get a retained reference to the latest IOSurface, for in-process
+    /// zero-copy consumers (e.g. wrapping it in a Metal texture).
+    ///
+    /// Ownership: the returned ref carries its own `CFRetain`, so it stays
+    /// valid after the ring overwrites the slot. The caller owns it and must
+    /// `CFRelease` it exactly once when done.
+    pub fn latest_surface_ref(&self) -> Option<ffi::IOSurfaceRef> {
+        let surfaces = self.inner.retained_surfaces.lock().unwrap();
+        let write_idx = self.inner.write_index.load(Ordering::Acquire);
+        if write_idx == 0 {
+            return None;
+        }
+        let surface = surfaces[(write_idx - 1) as usize % RING_SIZE];
+        if surface.is_null() {
+            return None;
+        }
+        // Retain while holding the lock so `push` can't release it first
+        unsafe { ffi::CFRetain(surface as *const c_void) };
+        Some(surface)
+    }
+
     /// Get the current write count (for detecting new frames).
     pub fn write_count(&self) -> u64 {
         self.inner.write_index.load(Ordering::Acquire)
@@ -138,4 +170,66 @@ mod tests {
         assert_eq!(ts, 20 * 33);
         assert_eq!(ring.write_count(), 20);
     }
+
+    #[test]
+    fn test_ring_get_back() {
+        let ring = SurfaceRing::new();
+        assert!(ring.get(0).is_none());
+        for i in 0..8u32 {
+            ring.push(i + 1, (i as u64 + 1) * 33, std::ptr::null_mut());
+        }
+        assert_eq!(ring.get(0), ring.latest());
+        assert_eq!(ring.get(3), Some((5, 5 * 33)));
+        assert_eq!(ring.get(7), Some((1, 33)));
+        assert!(ring.get(8).is_none());
+
+        // Only as far back as has been written
+        let ring = SurfaceRing::new();
+        ring.push(1, 33, std::ptr::null_mut());
+        ring.push(2, 66, std::ptr::null_mut());
+        assert_eq!(ring.get(1), Some((1, 33)));
+        assert!(ring.get(2).is_none());
+    }
+
+    #[test]
+    fn test_latest_surface_ref_empty() {
+        let ring = SurfaceRing::new();
+        assert!(ring.latest_surface_ref().is_none());
+        ring.push(42, 1000, std::ptr::null_mut());
+        assert!(ring.latest_surface_ref().is_none());
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_latest_surface_ref_matches_pushed() {
+        unsafe {
+            let attrs = crate::decoder::create_destination_attributes(
+                ffi::kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange,
+                None,
+            );
+            let mut pixel_buffer: ffi::CVPixelBufferRef = std::ptr::null_mut();
+            let status = ffi::CVPixelBufferCreate(
+                ffi::kCFAllocatorDefault,
+                64,
+                64,
+                ffi::kCVPixelFormatType_420YpCbCr8BiPlanarVideoRange,
+                attrs,
+                &mut pixel_buffer,
+            );
+            ffi::CFRelease(attrs);
+            assert_eq!(status, ffi::kCVReturnSuccess);
+
+            let surface = ffi::CVPixelBufferGetIOSurface(pixel_buffer);
+            assert!(!surface.is_null());
+            let surface_id = ffi::IOSurfaceGetID(surface);
+
+            let ring = SurfaceRing::new();
+            ring.push(surface_id, 33, surface);
+            let surface_ref = ring.latest_surface_ref().unwrap();
+            assert_eq!(ffi::IOSurfaceGetID(surface_ref), surface_id);
+
+            ffi::CFRelease(surface_ref as *const c_void);
+            ffi::CFRelease(pixel_buffer as *const c_void);
+        }
+    }
 }
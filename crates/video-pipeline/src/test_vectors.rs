@@ -0,0 +1,176 @@
+//! Small H.264 streams for exercising the decode path in tests.
+//!
+//! The bitstreams under `testdata/` are produced by the generator script
+//! beside them and embedded here, so tests don't depend on an encoder.
+
+use std::sync::mpsc::Receiver;
+
+use crate::{DecoderError, Frame, H264Decoder};
+
+/// A test stream: its parameter sets and AVCC-framed access units.
+#[derive(Debug, Clone)]
+pub struct TestStream {
+    /// SPS and PPS NAL units, without length prefixes.
+    pub sps: &'static [u8],
+    pub pps: &'static [u8],
+    pub nalu_length_size: u8,
+    /// Picture size the SPS declares, which every frame decodes to.
+    pub width: u32,
+    pub height: u32,
+    pub frames: Vec<TestFrame>,
+}
+
+/// One access unit of a `TestStream`.
+#[derive(Debug, Clone, Copy)]
+pub struct TestFrame {
+    /// NAL units with `nalu_length_size`-byte length prefixes.
+    pub avcc: &'static [u8],
+    pub timestamp_ms: u32,
+    pub keyframe: bool,
+}
+
+/// 32x32 Constrained Baseline (profile 66, constraint_set0 and 1, level 1)
+/// at 30 fps: IDR, P, P, IDR, P.
+///
+/// The IDR frames are coded losslessly (I_PCM) and every P frame skips all
+/// macroblocks, so the decoded pictures are known exactly; see
+/// [`TestStream::expected_luma`]. Chroma is a flat 128 throughout.
+pub fn tiny_stream() -> TestStream {
+    const FRAMES: [(&[u8], bool); 5] = [
+        (include_bytes!("../testdata/tiny/frame_0.avcc"), true),
+        (include_bytes!("../testdata/tiny/frame_1.avcc"), false),
+        (include_bytes!("../testdata/tiny/frame_2.avcc"), false),
+        (include_bytes!("../testdata/tiny/frame_3.avcc"), true),
+        (include_bytes!("../testdata/tiny/frame_4.avcc"), false),
+    ];
+    TestStream {
+        sps: include_bytes!("../testdata/tiny/sps.bin"),
+        pps: include_bytes!("../testdata/tiny/pps.bin"),
+        nalu_length_size: 4,
+        width: 32,
+        height: 32,
+        frames: FRAMES
+            .iter()
+            .enumerate()
+            .map(|(i, &(avcc, keyframe))| TestFrame {
+                avcc,
+                timestamp_ms: i as u32 * 1000 / 30,
+                keyframe,
+            })
+            .collect(),
+    }
+}
+
+impl TestStream {
+    /// Luma sample at (`x`, `y`) of decoded frame `index` of `tiny_stream`.
+    /// The first IDR is a diagonal ramp from 16 upwards, the second the
+    /// same ramp mirrored down from 235; P frames repeat the IDR before them.
+    pub fn expected_luma(&self, index: usize, x: usize, y: usize) -> u8 {
+        let idr = self.frames[..=index]
+            .iter()
+            .filter(|frame| frame.keyframe)
+            .count();
+        let ramp = 16 + 3 * (x + y) as u32;
+        (if idr == 1 { ramp } else { 235 + 16 - ramp }) as u8
+    }
+
+    /// The AVCDecoderConfigurationRecord for the stream, as carried in an
+    /// FLV sequence header.
+    pub fn decoder_config_record(&self) -> Vec<u8> {
+        let mut record = vec![
+            1,
+            self.sps[1],
+            self.sps[2],
+            self.sps[3],
+            0xFC | (self.nalu_length_size - 1),
+            0xE0 | 1,
+        ];
+        record.extend_from_slice(&(self.sps.len() as u16).to_be_bytes());
+        record.extend_from_slice(self.sps);
+        record.push(1);
+        record.extend_from_slice(&(self.pps.len() as u16).to_be_bytes());
+        record.extend_from_slice(self.pps);
+        record
+    }
+
+    /// FLV video tag body (RTMP video message) of the AVC sequence header.
+    pub fn flv_sequence_header(&self) -> Vec<u8> {
+        let mut tag = vec![0x17, 0, 0, 0, 0];
+        tag.extend_from_slice(&self.decoder_config_record());
+        tag
+    }
+
+    /// Create a decoder for the stream that delivers frames on a channel
+    /// with room for all of them.
+    pub fn decoder(&self) -> Result<(H264Decoder, Receiver<Frame>), DecoderError> {
+        H264Decoder::with_channel_output(
+            &[self.sps.to_vec()],
+            &[self.pps.to_vec()],
+            self.nalu_length_size,
+            self.frames.len(),
+        )
+    }
+
+    /// Decode every frame of the stream and return the pictures, in output
+    /// order.
+    pub fn decode(&self) -> Result<Vec<Frame>, DecoderError> {
+        H264Decoder::decode_all(
+            &[self.sps.to_vec()],
+            &[self.pps.to_vec()],
+            self.nalu_length_size,
+            self.frames
+                .iter()
+                .map(|frame| (frame.avcc, frame.timestamp_ms)),
+        )
+    }
+}
+
+impl TestFrame {
+    /// FLV video tag body (RTMP video message) carrying the frame as an AVC
+    /// NALU packet with no composition offset.
+    pub fn flv_video_data(&self) -> Vec<u8> {
+        let frame_type = if self.keyframe { 0x10 } else { 0x20 };
+        let mut tag = vec![frame_type | 7, 1, 0, 0, 0];
+        tag.extend_from_slice(self.avcc);
+        tag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_sps, AvccNalIter};
+
+    #[test]
+    fn test_tiny_stream_is_well_formed() {
+        let stream = tiny_stream();
+        let sps = parse_sps(stream.sps).unwrap();
+        assert_eq!((sps.width, sps.height), (stream.width, stream.height));
+        assert_eq!(sps.bit_depth, 8);
+        // As mobile encoders send: profile 66 with constraint_set0/1
+        assert_eq!(sps.profile_name(), "Constrained Baseline");
+        assert_eq!(sps.constraint_flags, 0xC0);
+
+        for frame in &stream.frames {
+            let nalus = AvccNalIter::new(frame.avcc, stream.nalu_length_size);
+            let types: Vec<u8> = nalus.map(|(nal_type, _)| nal_type).collect();
+            assert_eq!(types, [if frame.keyframe { 5 } else { 1 }]);
+        }
+        assert_eq!(stream.expected_luma(2, 0, 0), 16);
+        assert_eq!(stream.expected_luma(4, 0, 0), 235);
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_tiny_stream_decodes() {
+        let stream = tiny_stream();
+        let frames = stream.decode().unwrap();
+        assert_eq!(frames.len(), stream.frames.len());
+        for (index, frame) in frames.iter().enumerate() {
+            assert_eq!((frame.width, frame.height), (32, 32));
+            for (x, y) in [(0, 0), (31, 0), (17, 9), (31, 31)] {
+                assert_eq!(frame.data[y * 32 + x], stream.expected_luma(index, x, y));
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+#!/usr/bin/env python3
+"""Generate the `tiny` H.264 test stream used by `test_vectors::tiny_stream`.
+
+32x32 Constrained Baseline, CAVLC, 2x2 macroblocks: two IDR frames coded
+entirely as I_PCM macroblocks (so their decoded pixels are known exactly),
+each followed by P frames that skip every macroblock and so repeat them.
+
+Writes sps.bin and pps.bin (bare NAL units) and frame_N.avcc (access
+units with 4-byte NAL length prefixes) into testdata/tiny/.
+"""
+
+import os
+
+WIDTH_MBS = 2
+HEIGHT_MBS = 2
+MBS = WIDTH_MBS * HEIGHT_MBS
+OUT = os.path.join(os.path.dirname(os.path.abspath(__file__)), "tiny")
+
+
+class Bits:
+    def __init__(self):
+        self.bits = []
+
+    def u(self, n, value):
+        self.bits += [(value >> i) & 1 for i in reversed(range(n))]
+        return self
+
+    def ue(self, value):
+        value += 1
+        n = value.bit_length()
+        return self.u(n - 1, 0).u(n, value)
+
+    def se(self, value):
+        return self.ue(2 * value - 1 if value > 0 else -2 * value)
+
+    def align_zero(self):
+        while len(self.bits) % 8:
+            self.bits.append(0)
+        return self
+
+    def bytes(self, data):
+        assert len(self.bits) % 8 == 0
+        for byte in data:
+            self.u(8, byte)
+        return self
+
+    def rbsp(self):
+        self.bits.append(1)  # rbsp_stop_one_bit
+        self.align_zero()
+        return bytes(
+            int("".join(map(str, self.bits[i : i + 8])), 2) for i in range(0, len(self.bits), 8)
+        )
+
+
+def nal(header, rbsp):
+    # Emulation prevention
+    out = bytearray([header])
+    zeros = 0
+    for byte in rbsp:
+        if zeros >= 2 and byte <= 3:
+            out.append(3)
+            zeros = 0
+        out.append(byte)
+        zeros = zeros + 1 if byte == 0 else 0
+    return bytes(out)
+
+
+def sps():
+    b = Bits()
+    b.u(8, 66).u(8, 0xC0).u(8, 10)  # Constrained Baseline, level 1
+    b.ue(0)  # seq_parameter_set_id
+    b.ue(0)  # log2_max_frame_num_minus4
+    b.ue(2)  # pic_order_cnt_type: output in decode order
+    b.ue(1)  # max_num_ref_frames
+    b.u(1, 0)  # gaps_in_frame_num_value_allowed_flag
+    b.ue(WIDTH_MBS - 1).ue(HEIGHT_MBS - 1)
+    b.u(1, 1)  # frame_mbs_only_flag
+    b.u(1, 1)  # direct_8x8_inference_flag
+    b.u(1, 0)  # frame_cropping_flag
+    b.u(1, 0)  # vui_parameters_present_flag
+    return nal(0x67, b.rbsp())
+
+
+def pps():
+    b = Bits()
+    b.ue(0).ue(0)  # pic_parameter_set_id, seq_parameter_set_id
+    b.u(1, 0)  # entropy_coding_mode_flag: CAVLC
+    b.u(1, 0)  # bottom_field_pic_order_in_frame_present_flag
+    b.ue(0)  # num_slice_groups_minus1
+    b.ue(0).ue(0)  # num_ref_idx_l0/l1_default_active_minus1
+    b.u(1, 0).u(2, 0)  # weighted_pred_flag, weighted_bipred_idc
+    b.se(0).se(0).se(0)  # pic_init_qp/qs_minus26, chroma_qp_index_offset
+    b.u(1, 0)  # deblocking_filter_control_present_flag
+    b.u(1, 0)  # constrained_intra_pred_flag
+    b.u(1, 0)  # redundant_pic_cnt_present_flag
+    return nal(0x68, b.rbsp())
+
+
+def luma(idr_pic_id, x, y):
+    """Pixel value of IDR frame `idr_pic_id`; never 0, so no emulation prevention."""
+    value = 16 + 3 * (x + y)
+    return value if idr_pic_id == 0 else 235 + 16 - value
+
+
+def idr(idr_pic_id):
+    b = Bits()
+    b.ue(0)  # first_mb_in_slice
+    b.ue(7)  # slice_type: I, all slices
+    b.ue(0)  # pic_parameter_set_id
+    b.u(4, 0)  # frame_num
+    b.ue(idr_pic_id)
+    b.u(1, 0).u(1, 0)  # no_output_of_prior_pics_flag, long_term_reference_flag
+    b.se(0)  # slice_qp_delta
+    for mb in range(MBS):
+        mb_x, mb_y = mb % WIDTH_MBS * 16, mb // WIDTH_MBS * 16
+        b.ue(25)  # mb_type: I_PCM
+        b.align_zero()
+        b.bytes(luma(idr_pic_id, mb_x + i % 16, mb_y + i // 16) for i in range(256))
+        b.bytes([128] * 128)  # Cb, Cr
+    return nal(0x65, b.rbsp())
+
+
+def p_skip(frame_num):
+    b = Bits()
+    b.ue(0)  # first_mb_in_slice
+    b.ue(5)  # slice_type: P, all slices
+    b.ue(0)  # pic_parameter_set_id
+    b.u(4, frame_num)
+    b.u(1, 0)  # num_ref_idx_active_override_flag
+    b.u(1, 0)  # ref_pic_list_modification_flag_l0
+    b.u(1, 0)  # adaptive_ref_pic_marking_mode_flag
+    b.se(0)  # slice_qp_delta
+    b.ue(MBS)  # mb_skip_run: every macroblock
+    return nal(0x41, b.rbsp())
+
+
+def avcc(*units):
+    return b"".join(len(unit).to_bytes(4, "big") + unit for unit in units)
+
+
+def main():
+    os.makedirs(OUT, exist_ok=True)
+    frames = [idr(0), p_skip(1), p_skip(2), idr(1), p_skip(1)]
+    files = {"sps.bin": sps(), "pps.bin": pps()}
+    files.update((f"frame_{i}.avcc", avcc(frame)) for i, frame in enumerate(frames))
+    for name, data in files.items():
+        with open(os.path.join(OUT, name), "wb") as f:
+            f.write(data)
+
+
+if __name__ == "__main__":
+    main()
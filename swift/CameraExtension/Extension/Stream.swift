@@ -8,23 +8,49 @@ private let logger = Logger(subsystem: "com.rtmpvcam.host.camera-extension", cat
 /// Shared memory layout for IPC with the Rust process.
 /// Must match the Rust side exactly (video_pipeline::decoder constants).
 ///
-/// Header (64 bytes):
+/// Header (128 bytes):
 ///   [0..8)    write_index (u64, little-endian, atomic)
-///   [8..12)   width (u32)
-///   [12..16)  height (u32)
-///   [16..64)  reserved
+///   [8..16)   reader max width, height (u32 each; written by the reader, 0 = no preference)
+///   [16..20)  magic "RVCM"
+///   [20..22)  layout version (u16, little-endian)
+///   [22]      interlaced (u8; non-zero = interlaced source, still full height; not read here)
+///   [24..56)  slot 0 header, [56..88) slot 1 header:
+///     +0   timestamp_ms (u64)
+///     +8   width (u32)
+///     +12  height (u32)
+///     +16  pixel format "NV12" / "I420" / "P010" / "P10L" (zeros = NV12; only NV12 is read here)
+///     +20  display width (u32; 0 = square pixels, use width)
+///     +24  display height (u32; 0 = square pixels, use height)
+///     +28  stride (u32; bytes per row of both planes, 0 = packed rows of width bytes)
+///   [88..128) stream color description (HDR; zeros = none, not read here)
 ///
 /// Frame data (double-buffered):
-///   [64 .. 64+MAX_FRAME_SIZE)                   frame buffer 0
-///   [64+MAX_FRAME_SIZE .. 64+2*MAX_FRAME_SIZE)  frame buffer 1
+///   [128 .. 128+MAX_FRAME_SIZE)                   frame buffer 0
+///   [128+MAX_FRAME_SIZE .. 128+2*MAX_FRAME_SIZE)  frame buffer 1
 ///
-/// Each frame is NV12: Y plane (width*height) + UV plane (width*height/2)
-private let kHeaderSize = 64
+/// Each frame is NV12: Y plane (width*height) + UV plane (width*height/2),
+/// described by the slot header of the same index.
+private let kHeaderSize = 128
 private let kMaxWidth = 1920
 private let kMaxHeight = 1080
 private let kMaxFrameSize = kMaxWidth * kMaxHeight * 3 / 2  // NV12
 private let kShmSize = kHeaderSize + 2 * kMaxFrameSize       // ~6.2MB
 
+/// Header identification — must match video_pipeline::FRAME_MAGIC / FRAME_LAYOUT_VERSION.
+private let kMagicOffset = 16
+private let kMagic: [UInt8] = Array("RVCM".utf8)
+private let kVersionOffset = 20
+private let kLayoutVersion: UInt16 = 2
+
+/// Per-slot frame description — must match video_pipeline::SlotHeader.
+private let kSlotsOffset = 24
+private let kSlotHeaderSize = 32
+private let kSlotWidthOffset = 8
+private let kSlotHeightOffset = 12
+private let kSlotPixelFormatOffset = 16
+private let kSlotStrideOffset = 28
+private let kPixelFormatNV12: [UInt8] = Array("NV12".utf8)
+
 /// Ring buffer file path — must match the Rust side.
 /// The cmioextension sandbox allows: (allow file-read* (subpath "/Library"))
 private let kRingFilePath = "/Library/Application Support/RTMPVirtualCamera/rtmp_vcam_ring"
@@ -47,6 +73,9 @@ class CameraStreamSource: NSObject, CMIOExtensionStreamSource {
     private var sequenceNumber: UInt64 = 0
     // Track last write_index to detect new frames
     private var lastWriteIndex: UInt64 = 0
+    // Set once a layout mismatch has been logged, to avoid log spam
+    private var loggedLayoutMismatch = false
+    private var loggedPixelFormatMismatch = false
 
     override init() {
         super.init()
@@ -154,26 +183,60 @@ class CameraStreamSource: NSObject, CMIOExtensionStreamSource {
         }
     }
 
+    /// Check that the header was written by a Rust process using the same layout.
+    private func validateHeader(_ ptr: UnsafeMutableRawPointer) -> Bool {
+        let magic = (0..<4).map { ptr.load(fromByteOffset: kMagicOffset + $0, as: UInt8.self) }
+        let version = UInt16(littleEndian: ptr.loadUnaligned(fromByteOffset: kVersionOffset, as: UInt16.self))
+        guard magic == kMagic, version == kLayoutVersion else {
+            if !loggedLayoutMismatch {
+                loggedLayoutMismatch = true
+                let found = String(decoding: magic, as: UTF8.self)
+                logger.error("Frame buffer layout mismatch: magic='\(found, privacy: .public)' version=\(version, privacy: .public), expected 'RVCM' version=\(kLayoutVersion, privacy: .public) — refusing to read")
+            }
+            return false
+        }
+        loggedLayoutMismatch = false
+        return true
+    }
+
     /// Read the latest frame data from shared memory into a CVPixelBuffer.
     private func readLatestFrame() -> CVPixelBuffer? {
         guard let ptr = shmPointer else { return nil }
+        guard validateHeader(ptr) else { return nil }
 
         // Read write_index atomically
         let writeIndex = ptr.load(fromByteOffset: 0, as: UInt64.self)
         guard writeIndex > 0 else { return nil }
 
-        // Read dimensions from header
-        let frameWidth = Int(ptr.load(fromByteOffset: 8, as: UInt32.self))
-        let frameHeight = Int(ptr.load(fromByteOffset: 12, as: UInt32.self))
+        // Determine which double-buffer slot to read from
+        // Reader reads from the most recently completed slot
+        let slot = Int((writeIndex - 1) % 2)
+
+        // Read dimensions and format from that slot's header, never another's
+        let slotHeader = kSlotsOffset + slot * kSlotHeaderSize
+        let frameWidth = Int(ptr.load(fromByteOffset: slotHeader + kSlotWidthOffset, as: UInt32.self))
+        let frameHeight = Int(ptr.load(fromByteOffset: slotHeader + kSlotHeightOffset, as: UInt32.self))
 
         guard frameWidth > 0, frameHeight > 0,
               frameWidth <= kMaxWidth, frameHeight <= kMaxHeight else { return nil }
 
-        // Determine which double-buffer slot to read from
-        // Reader reads from the most recently completed slot
-        let slot = Int((writeIndex - 1) % 2)
+        let pixelFormat = (0..<4).map { ptr.load(fromByteOffset: slotHeader + kSlotPixelFormatOffset + $0, as: UInt8.self) }
+        guard pixelFormat == kPixelFormatNV12 || pixelFormat == [0, 0, 0, 0] else {
+            if !loggedPixelFormatMismatch {
+                loggedPixelFormatMismatch = true
+                let found = String(decoding: pixelFormat, as: UTF8.self)
+                logger.error("Frame buffer pixel format '\(found, privacy: .public)' is not NV12 — refusing to read")
+            }
+            return nil
+        }
+        loggedPixelFormatMismatch = false
+
+        // Rows keep the writer's padding when it recorded a stride
+        let stride = Int(ptr.load(fromByteOffset: slotHeader + kSlotStrideOffset, as: UInt32.self))
+        let srcStride = stride == 0 ? frameWidth : stride
+        guard srcStride >= frameWidth, srcStride * frameHeight * 3 / 2 <= kMaxFrameSize else { return nil }
+
         let frameOffset = kHeaderSize + slot * kMaxFrameSize
-        let frameSize = frameWidth * frameHeight * 3 / 2
 
         // Create a CVPixelBuffer and copy data into it
         var pixelBuffer: CVPixelBuffer?
@@ -201,15 +264,15 @@ class CameraStreamSource: NSObject, CMIOExtensionStreamSource {
         if let yDst = CVPixelBufferGetBaseAddressOfPlane(pixelBuffer, 0) {
             let yDstStride = CVPixelBufferGetBytesPerRowOfPlane(pixelBuffer, 0)
             let yHeight = CVPixelBufferGetHeightOfPlane(pixelBuffer, 0)
-            if yDstStride == frameWidth {
+            if yDstStride == srcStride {
                 // Fast path
-                memcpy(yDst, srcBase, frameWidth * yHeight)
+                memcpy(yDst, srcBase, srcStride * yHeight)
             } else {
                 // Row by row
                 for row in 0..<yHeight {
                     memcpy(
                         yDst.advanced(by: row * yDstStride),
-                        srcBase.advanced(by: row * frameWidth),
+                        srcBase.advanced(by: row * srcStride),
                         frameWidth
                     )
                 }
@@ -217,17 +280,17 @@ class CameraStreamSource: NSObject, CMIOExtensionStreamSource {
         }
 
         // Copy UV plane
-        let uvSrcOffset = frameWidth * frameHeight
+        let uvSrcOffset = srcStride * frameHeight
         if let uvDst = CVPixelBufferGetBaseAddressOfPlane(pixelBuffer, 1) {
             let uvDstStride = CVPixelBufferGetBytesPerRowOfPlane(pixelBuffer, 1)
             let uvHeight = CVPixelBufferGetHeightOfPlane(pixelBuffer, 1)
-            if uvDstStride == frameWidth {
-                memcpy(uvDst, srcBase.advanced(by: uvSrcOffset), frameWidth * uvHeight)
+            if uvDstStride == srcStride {
+                memcpy(uvDst, srcBase.advanced(by: uvSrcOffset), srcStride * uvHeight)
             } else {
                 for row in 0..<uvHeight {
                     memcpy(
                         uvDst.advanced(by: row * uvDstStride),
-                        srcBase.advanced(by: uvSrcOffset + row * frameWidth),
+                        srcBase.advanced(by: uvSrcOffset + row * srcStride),
                         frameWidth
                     )
                 }